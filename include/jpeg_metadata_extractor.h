@@ -0,0 +1,56 @@
+/* Generated with cbindgen from src/ffi.rs; regenerate with:
+ *   cbindgen --config cbindgen.toml --output include/jpeg_metadata_extractor.h
+ */
+
+#ifndef JPEG_METADATA_EXTRACTOR_H
+#define JPEG_METADATA_EXTRACTOR_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Extraction succeeded and `*out` holds the metadata JSON
+ */
+#define JME_OK 0
+
+/**
+ * A required pointer argument was null
+ */
+#define JME_ERR_NULL_ARGUMENT 1
+
+/**
+ * Extraction failed and `*out` holds `{"error": "..."}`
+ */
+#define JME_ERR_EXTRACTION 2
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Extract metadata from `len` bytes at `data` and store a JSON string in `*out`.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes and `out` must be a valid pointer.
+ * The string written to `*out` must be freed with [`free_metadata_json`].
+ */
+int extract_metadata_json(const uint8_t *data, size_t len, char **out);
+
+/**
+ * Release a string returned by [`extract_metadata_json`]. Null is ignored.
+ *
+ * # Safety
+ *
+ * `json` must be null or a pointer previously returned through `out` and not yet freed.
+ */
+void free_metadata_json(char *json);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* JPEG_METADATA_EXTRACTOR_H */
@@ -0,0 +1,303 @@
+use crate::drone::DJI_NS;
+use crate::error::{ExtractError, Result};
+use crate::exif_write;
+use crate::jpeg::{self, Segment, SOI};
+use crate::xmp::{AUX_NS, DC_NS};
+use crate::xmp_write;
+use exif::{Context, Exif, Field, In, Reader, Tag, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Prefix marking a pseudonymised value
+pub const PREFIX: &str = "anon:";
+
+/// A group of identifying fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    /// Body and lens serial numbers, and the owner and creator names kept with them
+    CameraSerial,
+    /// Location: GPS fields and drone position data
+    Gps,
+    /// Camera make, model and lens model
+    Body,
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "camera_serial" => Ok(Category::CameraSerial),
+            "gps" => Ok(Category::Gps),
+            "body" => Ok(Category::Body),
+            _ => Err(format!("unknown category '{}' (expected camera_serial, gps or body)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Category::CameraSerial => "camera_serial",
+            Category::Gps => "gps",
+            Category::Body => "body",
+        })
+    }
+}
+
+/// EXIF tags pseudonymised for each string-valued category
+const SERIAL_TAGS: [Tag; 4] = [Tag::BodySerialNumber, Tag::LensSerialNumber, Tag::CameraOwnerName, Tag::Artist];
+const BODY_TAGS: [Tag; 4] = [Tag::Make, Tag::Model, Tag::LensMake, Tag::LensModel];
+
+const TIFF_NS: &str = "http://ns.adobe.com/tiff/1.0/";
+const EXIF_NS: &str = "http://ns.adobe.com/exif/1.0/";
+const EXIF_EX_NS: &str = "http://cipa.jp/exif/1.0/";
+
+/// XMP properties removed for each string-valued category
+const SERIAL_PROPERTIES: &[(&str, &str)] = &[
+    (AUX_NS, "SerialNumber"), (AUX_NS, "LensSerialNumber"), (AUX_NS, "OwnerName"),
+    (EXIF_EX_NS, "BodySerialNumber"), (EXIF_EX_NS, "LensSerialNumber"), (EXIF_EX_NS, "CameraOwnerName"),
+    (DC_NS, "creator"),
+];
+/// IPTC By-line, the photographer's name, removed with the serial numbers as dc:creator is
+const IPTC_BYLINE: (u8, u8) = (2, 80);
+/// Signature of the APP13 segment holding Photoshop image resources
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+/// Image resource holding the IPTC-IIM record
+const IPTC_RESOURCE: u16 = 0x0404;
+
+const BODY_PROPERTIES: &[(&str, &str)] = &[
+    (TIFF_NS, "Make"), (TIFF_NS, "Model"), (AUX_NS, "Lens"), (AUX_NS, "LensID"),
+    (EXIF_EX_NS, "LensMake"), (EXIF_EX_NS, "LensModel"),
+];
+
+/// Replaces identifying values with salted SHA-256 pseudonyms. The same value
+/// and salt always give the same pseudonym, so images from one device can still
+/// be grouped, but without the salt the original cannot be recovered by
+/// enumerating likely serial numbers.
+#[derive(Clone, Debug)]
+pub struct Anonymizer {
+    salt: Vec<u8>,
+    categories: BTreeSet<Category>,
+}
+
+impl Anonymizer {
+    pub fn new(salt: &[u8], categories: impl IntoIterator<Item = Category>) -> Self {
+        Anonymizer { salt: salt.to_vec(), categories: categories.into_iter().collect() }
+    }
+
+    pub fn covers(&self, category: Category) -> bool {
+        self.categories.contains(&category)
+    }
+
+    /// Pseudonym for `value`: the prefix and 16 hex digits of a salted hash
+    pub fn pseudonym(&self, value: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(&self.salt)
+            .chain_update([0])
+            .chain_update(value.as_bytes())
+            .finalize();
+        let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}{}", PREFIX, hex)
+    }
+
+    /// Category an EXIF tag belongs to, if it is identifying
+    pub fn category_of(tag: Tag) -> Option<Category> {
+        if SERIAL_TAGS.contains(&tag) {
+            Some(Category::CameraSerial)
+        } else if BODY_TAGS.contains(&tag) {
+            Some(Category::Body)
+        } else if tag.context() == Context::Gps {
+            Some(Category::Gps)
+        } else {
+            None
+        }
+    }
+
+    /// Edits that anonymise a file's EXIF: replacement fields carrying
+    /// pseudonyms, and whether an existing field should be kept. GPS fields
+    /// are dropped rather than hashed, since a hashed coordinate is useless, and
+    /// so is the maker note, which repeats the serial number, owner and model in
+    /// vendor formats that cannot be rewritten field by field.
+    pub fn exif_edits(&self, exif: &Exif) -> (Vec<Field>, impl Fn(&Field) -> bool + '_) {
+        let replacements = exif.fields()
+            .filter(|f| f.ifd_num == In::PRIMARY)
+            .filter(|f| Self::category_of(f.tag).is_some_and(|c| c != Category::Gps && self.covers(c)))
+            .filter_map(|f| match &f.value {
+                Value::Ascii(values) => {
+                    let text = String::from_utf8_lossy(values.first()?);
+                    let text = text.trim_end_matches('\0').trim();
+                    Some(Field {
+                        tag: f.tag,
+                        ifd_num: f.ifd_num,
+                        value: Value::Ascii(vec![self.pseudonym(text).into_bytes()]),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        let drop_maker_note = self.covers(Category::CameraSerial) || self.covers(Category::Body);
+        let keep = move |f: &Field| {
+            let gps = self.covers(Category::Gps) && f.tag.context() == Context::Gps;
+            let maker_note = drop_maker_note && f.tag == Tag::MakerNote;
+            !(gps || maker_note)
+        };
+        (replacements, keep)
+    }
+
+    /// Whether an XMP property holds identifying data of a covered category.
+    /// XMP copies are removed rather than hashed: the EXIF fields keep the pseudonyms.
+    pub fn covers_xmp(&self, namespace: &str, name: &str) -> bool {
+        let listed = |properties: &[(&str, &str)]| properties.contains(&(namespace, name));
+        (self.covers(Category::CameraSerial) && listed(SERIAL_PROPERTIES))
+            || (self.covers(Category::Body) && listed(BODY_PROPERTIES))
+            || (self.covers(Category::Gps) && ((namespace == EXIF_NS && name.starts_with("GPS")) || namespace == DJI_NS))
+    }
+
+    /// A JPEG with its identifying EXIF fields pseudonymised, its maker note
+    /// dropped and the matching XMP properties removed. Fails when the file has
+    /// XMP that cannot be rewritten, rather than leave copies of the values behind.
+    pub fn rewrite(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let exif = Reader::new().read_from_container(&mut Cursor::new(bytes))
+            .map_err(|_| ExtractError::Encode("no EXIF data".to_string()))?;
+        let (fields, keep) = self.exif_edits(&exif);
+        let rewritten = exif_write::rewrite_with(bytes, &fields, None, keep)?;
+        let rewritten = xmp_write::remove_jpeg_properties(&rewritten, |namespace, name| self.covers_xmp(namespace, name))?;
+        if !self.covers(Category::CameraSerial) {
+            return Ok(rewritten);
+        }
+        remove_iptc_datasets(&rewritten, &[IPTC_BYLINE])
+    }
+}
+
+/// A JPEG without the given IPTC-IIM datasets in its Photoshop APP13 segments
+fn remove_iptc_datasets(bytes: &[u8], datasets: &[(u8, u8)]) -> Result<Vec<u8>> {
+    let segments = jpeg::read_segments(&mut Cursor::new(bytes))?;
+    if !segments.iter().any(|s| s.is_app(13, PHOTOSHOP_SIGNATURE)) {
+        return Ok(bytes.to_vec());
+    }
+    let mut out = vec![0xFF, SOI];
+    for segment in &segments {
+        if segment.is_app(13, PHOTOSHOP_SIGNATURE) {
+            let resources = rewrite_resources(&segment.data[PHOTOSHOP_SIGNATURE.len()..], datasets)
+                .ok_or_else(|| ExtractError::Encode("IPTC data: the Photoshop resources could not be parsed".to_string()))?;
+            let data = [PHOTOSHOP_SIGNATURE, &resources].concat();
+            jpeg::push_segment(&mut out, &Segment { marker: segment.marker, data, offset: None })?;
+        } else {
+            jpeg::push_segment(&mut out, segment)?;
+        }
+    }
+    out.extend(&bytes[jpeg::header_len(&segments) as usize..]);
+    Ok(out)
+}
+
+/// Photoshop image resources with `datasets` removed from the IPTC record, or
+/// `None` if they are malformed
+fn rewrite_resources(mut data: &[u8], datasets: &[(u8, u8)]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    while !data.is_empty() {
+        let (header, rest) = data.split_at_checked(6)?;
+        if &header[..4] != b"8BIM" {
+            return None;
+        }
+        let id = u16::from_be_bytes([header[4], header[5]]);
+        // A Pascal string name, padded to an even length
+        let name_len = (1 + *rest.first()? as usize).next_multiple_of(2);
+        let (name, rest) = rest.split_at_checked(name_len)?;
+        let (size, rest) = rest.split_at_checked(4)?;
+        let size = u32::from_be_bytes(size.try_into().ok()?) as usize;
+        let value = rest.get(..size)?;
+        data = rest.get(size.next_multiple_of(2)..).unwrap_or_default();
+
+        let value = match id {
+            IPTC_RESOURCE => remove_datasets(value, datasets)?,
+            _ => value.to_vec(),
+        };
+        out.extend(header);
+        out.extend(name);
+        out.extend((value.len() as u32).to_be_bytes());
+        out.extend(&value);
+        if value.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+/// An IPTC-IIM record without `datasets`, given as (record, dataset) numbers
+fn remove_datasets(mut record: &[u8], datasets: &[(u8, u8)]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    // Resources are padded to an even length, so a zero byte may follow the last dataset
+    while record.first() == Some(&0x1C) {
+        let (header, rest) = record.split_at_checked(5)?;
+        let len = u16::from_be_bytes([header[3], header[4]]);
+        // Extended lengths are only used for objects over 32 KiB, never for names
+        if len & 0x8000 != 0 {
+            return None;
+        }
+        let (value, rest) = rest.split_at_checked(len as usize)?;
+        if !datasets.contains(&(header[1], header[2])) {
+            out.extend(header);
+            out.extend(value);
+        }
+        record = rest;
+    }
+    record.iter().all(|&b| b == 0).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jpeg, xmp};
+
+    #[test]
+    fn test_pseudonym() {
+        let a = Anonymizer::new(b"salt", [Category::CameraSerial]);
+        let b = Anonymizer::new(b"pepper", [Category::CameraSerial]);
+        assert_eq!(a.pseudonym("025021000535"), a.pseudonym("025021000535"));
+        assert_ne!(a.pseudonym("025021000535"), b.pseudonym("025021000535"));
+        assert!(a.pseudonym("x").starts_with(PREFIX));
+        assert_eq!(a.pseudonym("x").len(), PREFIX.len() + 16);
+        assert!("lens".parse::<Category>().is_err());
+    }
+
+    #[test]
+    fn test_exif_edits() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let exif = Reader::new().read_from_container(&mut Cursor::new(&bytes)).unwrap();
+        let anonymizer = Anonymizer::new(b"salt", [Category::CameraSerial, Category::Gps]);
+        let (fields, keep) = anonymizer.exif_edits(&exif);
+        assert!(fields.iter().any(|f| f.tag == Tag::BodySerialNumber));
+        assert!(fields.iter().all(|f| f.tag != Tag::Model));
+        let gps = Field { tag: Tag::GPSLatitude, ifd_num: In::PRIMARY, value: Value::Byte(vec![]) };
+        assert!(!keep(&gps));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let contains = |bytes: &[u8], text: &str| bytes.windows(text.len()).any(|w| w == text.as_bytes());
+        assert!(contains(&bytes, "025021000535") && contains(&bytes, "James Broadbent"));
+        let anonymizer = Anonymizer::new(b"salt", [Category::CameraSerial, Category::Body]);
+        let rewritten = anonymizer.rewrite(&bytes).unwrap();
+
+        // No copy of the serial numbers or the owner is left anywhere in the file
+        for text in ["025021000535", "4200000392", "James Broadbent"] {
+            assert!(!contains(&rewritten, text), "{} is still in the file", text);
+        }
+        let exif = Reader::new().read_from_container(&mut Cursor::new(&rewritten)).unwrap();
+        assert!(exif.get_field(Tag::MakerNote, In::PRIMARY).is_none());
+        let serial = exif.get_field(Tag::BodySerialNumber, In::PRIMARY).unwrap();
+        assert_eq!(serial.display_value().to_string(), format!("\"{}\"", anonymizer.pseudonym("025021000535")));
+        let segments = jpeg::read_segments(&mut Cursor::new(&rewritten)).unwrap();
+        let packet = xmp::packet(&segments).unwrap();
+        let doc = xmp::parse(&packet).unwrap();
+        assert_eq!(xmp::property(&doc, AUX_NS, "SerialNumber"), None);
+        assert_eq!(xmp::property(&doc, AUX_NS, "Lens"), None);
+        assert_eq!(xmp::property(&doc, AUX_NS, "Firmware").as_deref(), Some("Firmware Version 1.0.1"));
+        // The image data is untouched
+        assert!(rewritten.ends_with(&bytes[jpeg::header_len(&jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap()) as usize..]));
+    }
+}
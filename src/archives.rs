@@ -0,0 +1,249 @@
+use crate::throttle::{Throttle, Throttled};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use jpeg_metadata_extractor::filesystem::FilesystemMetadata;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+
+/// Separates an archive from a file inside it, as in `shoot.zip!day1/IMG_0001.jpg`
+const MEMBER_SEPARATOR: char = '!';
+
+/// Largest member read into memory. Sizes in entry headers are not checked
+/// against the data, so a compressed member can expand well past what it declares.
+const MAX_MEMBER_SIZE: u64 = 1 << 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    const ALL: [ArchiveKind; 2] = [ArchiveKind::Zip, ArchiveKind::Tar];
+
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveKind::Zip => "zip",
+            ArchiveKind::Tar => "tar",
+        }
+    }
+
+    fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Self::ALL.into_iter().find(|kind| extension.eq_ignore_ascii_case(kind.extension()))
+    }
+}
+
+/// Extensions of the archives whose members can be given as inputs
+pub fn archive_extensions() -> impl Iterator<Item = &'static str> {
+    ArchiveKind::ALL.into_iter().map(ArchiveKind::extension)
+}
+
+/// A file read from an archive, held in memory until it has been processed
+pub struct Member {
+    /// Path inside the archive, `/`-separated
+    pub name: String,
+    pub bytes: Vec<u8>,
+    /// Size, times and ownership from the archive's entry header
+    pub metadata: FilesystemMetadata,
+}
+
+/// The archive an input refers to, and the single member it addresses if any.
+/// Plain `.zip` and `.tar` files address every member.
+pub fn archive_input(path: &Path) -> Option<(PathBuf, Option<String>)> {
+    if ArchiveKind::of(path).is_some() && path.is_file() {
+        return Some((path.to_path_buf(), None));
+    }
+    let text = path.to_str()?;
+    // The first separator after an existing archive, since directory names may contain one too
+    text.match_indices(MEMBER_SEPARATOR).find_map(|(i, _)| {
+        let archive = Path::new(&text[..i]);
+        (ArchiveKind::of(archive).is_some() && archive.is_file())
+            .then(|| (archive.to_path_buf(), Some(text[i + 1..].to_string())))
+    })
+}
+
+/// How a member is addressed on the command line and in output
+pub fn member_path(archive: &Path, name: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(MEMBER_SEPARATOR.to_string());
+    path.push(name);
+    PathBuf::from(path)
+}
+
+/// Where sidecars for `path` are written, before the extension is replaced: a
+/// member's go under a directory named after its archive, e.g. `shoot/day1/IMG_0001.jpg`
+pub fn output_base(path: &Path) -> PathBuf {
+    match archive_input(path) {
+        Some((archive, Some(name))) => archive.with_extension("").join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Stream through an archive, reading each regular file that `wanted` accepts
+/// (by name, size and modification time) and passing it to `visit`, until it
+/// breaks, which is returned so the caller can tell a finished archive. Only one
+/// member is in memory at a time and nothing is extracted to disk. Members
+/// larger than [`MAX_MEMBER_SIZE`] are reported and skipped.
+pub fn for_each_member(
+    archive: &Path,
+    throttle: Option<&Throttle>,
+    wanted: impl Fn(&str, u64, Option<DateTime<Utc>>) -> bool,
+    mut visit: impl FnMut(Member) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>> {
+    let file = File::open(archive)
+        .with_context(|| format!("Failed to open archive {}", archive.display()))?;
+    let file = Throttled::new(file, throttle);
+    let read = || format!("Failed to read archive {}", archive.display());
+    match ArchiveKind::of(archive) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file)).with_context(read)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).with_context(read)?;
+                // Names that would escape the archive root are not addressable
+                if !entry.is_file() || entry.enclosed_name().is_none() {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                let modified = entry.last_modified().and_then(zip_time);
+                if !wanted(&name, entry.size(), modified) {
+                    continue;
+                }
+                let metadata = member_metadata(entry.size(), modified, entry.unix_mode(), None);
+                if let Some(bytes) = read_member(&mut entry, archive, &name, MAX_MEMBER_SIZE)? {
+                    if visit(Member { bytes, name, metadata }).is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+            }
+        }
+        Some(ArchiveKind::Tar) => {
+            let mut tar = tar::Archive::new(BufReader::new(file));
+            for entry in tar.entries().with_context(read)? {
+                let mut entry = entry.with_context(read)?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let path = entry.path().with_context(read)?.into_owned();
+                if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+                    continue;
+                }
+                let name = path.components()
+                    .filter(|c| *c != Component::CurDir)
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let header = entry.header();
+                let modified = header.mtime().ok().and_then(|t| DateTime::from_timestamp(t as i64, 0));
+                if !wanted(&name, entry.size(), modified) {
+                    continue;
+                }
+                let owner = header.uid().ok().zip(header.gid().ok());
+                let metadata = member_metadata(entry.size(), modified, header.mode().ok(), owner);
+                if let Some(bytes) = read_member(&mut entry, archive, &name, MAX_MEMBER_SIZE)? {
+                    if visit(Member { bytes, name, metadata }).is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+            }
+        }
+        None => anyhow::bail!("Not a .zip or .tar archive: {}", archive.display()),
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// A member's contents, or `None` (reported) when there are more than `limit` bytes
+fn read_member(entry: &mut impl Read, archive: &Path, name: &str, limit: u64) -> Result<Option<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    entry.take(limit + 1).read_to_end(&mut bytes)
+        .with_context(|| format!("Failed to read archive member {}", name))?;
+    if bytes.len() as u64 > limit {
+        eprintln!("Skipped (larger than {} bytes): {}", limit, member_path(archive, name).display());
+        return Ok(None);
+    }
+    Ok(Some(bytes))
+}
+
+/// Zip timestamps are local time without a zone, so like EXIF times they are reported as if UTC
+fn zip_time(time: zip::DateTime) -> Option<DateTime<Utc>> {
+    NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?
+        .and_hms_opt(time.hour().into(), time.minute().into(), time.second().into())
+        .map(|t| t.and_utc())
+}
+
+fn member_metadata(size: u64, modified: Option<DateTime<Utc>>, mode: Option<u32>, owner: Option<(u64, u64)>) -> FilesystemMetadata {
+    FilesystemMetadata {
+        canonical_path: None,
+        volume_id: None,
+        size,
+        created_time: None,
+        modified_time: modified.unwrap_or(DateTime::UNIX_EPOCH),
+        is_symlink: false,
+        link_target: None,
+        inode: None,
+        device: None,
+        uid: owner.and_then(|(uid, _)| uid.try_into().ok()),
+        gid: owner.and_then(|(_, gid)| gid.try_into().ok()),
+        mode: mode.map(|m| format!("{:04o}", m & 0o7777)),
+        readonly: mode.is_some_and(|m| m & 0o200 == 0),
+        xattrs: BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_zip_and_tar_members() {
+        let dir = std::env::temp_dir().join(format!("jme-archives-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let jpeg = std::fs::read("images/JAM26284.jpg").unwrap();
+
+        let zip_path = dir.join("shoot.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for name in ["day1/a.jpg", "notes.txt"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(&jpeg).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let tar_path = dir.join("shoot.tar");
+        let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(jpeg.len() as u64);
+        header.set_mode(0o444);
+        header.set_mtime(1_700_000_000);
+        tar.append_data(&mut header, "b.jpg", jpeg.as_slice()).unwrap();
+        tar.into_inner().unwrap();
+
+        let mut names = Vec::new();
+        for archive in [&zip_path, &tar_path] {
+            for_each_member(archive, None, |name, _, _| name.ends_with(".jpg"), |member| {
+                assert_eq!(member.bytes, jpeg);
+                names.push((member.name, member.metadata.readonly));
+                ControlFlow::Continue(())
+            }).unwrap().continue_value().unwrap();
+        }
+        let mut visited = 0;
+        let stopped = for_each_member(&zip_path, None, |_, _, _| true, |_| {
+            visited += 1;
+            ControlFlow::Break(())
+        }).unwrap();
+        assert_eq!((stopped, visited), (ControlFlow::Break(()), 1));
+        let addressed = archive_input(&member_path(&zip_path, "day1/a.jpg"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names, [("day1/a.jpg".to_string(), false), ("b.jpg".to_string(), true)]);
+        assert_eq!(addressed, Some((zip_path, Some("day1/a.jpg".to_string()))));
+        assert_eq!(output_base(Path::new("missing.zip!a.jpg")), Path::new("missing.zip!a.jpg"));
+
+        let archive = Path::new("shoot.zip");
+        assert_eq!(read_member(&mut jpeg.as_slice(), archive, "a.jpg", jpeg.len() as u64).unwrap(), Some(jpeg.clone()));
+        assert_eq!(read_member(&mut jpeg.as_slice(), archive, "a.jpg", 1024).unwrap(), None);
+    }
+}
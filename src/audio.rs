@@ -0,0 +1,183 @@
+use crate::jpeg::{self, Segment};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How far into an APPn payload the RIFF header may start, after the vendor's signature
+const MAX_SIGNATURE_LEN: usize = 64;
+
+/// A voice memo attached to a photo
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AudioNote {
+    pub source: AudioSource,
+    /// The APPn segment holding embedded audio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+    /// File name of a paired WAV file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Size of the WAV data in bytes
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioSource {
+    /// Stored in APPn segments of the JPEG
+    Embedded,
+    /// A WAV file next to the JPEG with the same name, as written by many compacts
+    Sidecar,
+}
+
+/// Format and length from a WAV header
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WavInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Seconds, rounded to milliseconds
+    pub duration: Option<f64>,
+}
+
+/// Parse the RIFF chunks of a WAV file. `bytes` may stop anywhere after the
+/// `data` chunk header; `size` is the whole file's length, used when the data
+/// chunk's length is left unset as by recorders that never finalized it.
+pub fn parse_wav(bytes: &[u8], size: u64) -> Option<WavInfo> {
+    if bytes.get(..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let u16_at = |at: usize| bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let mut info = WavInfo::default();
+    let mut byte_rate = 0;
+    let mut at = 12;
+    while let (Some(id), Some(len)) = (bytes.get(at..at + 4), u32_at(at + 4)) {
+        match id {
+            b"fmt " => {
+                info.channels = u16_at(at + 10)?;
+                info.sample_rate = u32_at(at + 12)?;
+                byte_rate = u32_at(at + 16)?;
+            }
+            b"data" => {
+                let available = size.saturating_sub(at as u64 + 8);
+                let len = if len == 0 || len == u32::MAX { available } else { (len as u64).min(available) };
+                info.duration = (byte_rate > 0).then(|| (len as f64 / byte_rate as f64 * 1000.0).round() / 1000.0);
+                break;
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        at += 8 + len as usize + (len as usize & 1);
+    }
+    (info.sample_rate > 0).then_some(info)
+}
+
+/// Index of an APPn segment carrying a WAV file, and where its RIFF header starts
+pub fn wav_segment(segments: &[Segment]) -> Option<(usize, usize)> {
+    segments.iter().enumerate()
+        .filter(|(_, s)| (0xE0..=0xEF).contains(&s.marker))
+        .find_map(|(index, s)| {
+            let window = &s.data[..s.data.len().min(MAX_SIGNATURE_LEN + 12)];
+            let start = window.windows(4).position(|w| w == b"RIFF")?;
+            (window.get(start + 8..start + 12) == Some(b"WAVE")).then_some((index, start))
+        })
+}
+
+/// The embedded WAV file. Audio too large for one segment continues in the
+/// following segments with the same marker, each starting with the same signature.
+pub fn embedded_wav(segments: &[Segment]) -> Option<Vec<u8>> {
+    let (index, start) = wav_segment(segments)?;
+    let first = &segments[index];
+    let signature = &first.data[..start];
+    let mut wav = first.data[start..].to_vec();
+    for segment in segments[index + 1..].iter().take_while(|s| s.marker == first.marker && s.data.starts_with(signature)) {
+        wav.extend(&segment.data[start..]);
+    }
+    // The RIFF length covers everything after its first 8 bytes
+    let riff_len = u32::from_le_bytes(wav[4..8].try_into().ok()?) as usize;
+    wav.truncate(riff_len.saturating_add(8));
+    Some(wav)
+}
+
+/// Embedded audio, if the image has any
+pub fn from_segments(segments: &[Segment]) -> Option<AudioNote> {
+    let (index, _) = wav_segment(segments)?;
+    let wav = embedded_wav(segments)?;
+    let info = parse_wav(&wav, wav.len() as u64)?;
+    Some(AudioNote {
+        source: AudioSource::Embedded,
+        segment: Some(jpeg::marker_name(segments[index].marker)),
+        file: None,
+        size: wav.len() as u64,
+        duration: info.duration,
+        sample_rate: Some(info.sample_rate),
+        channels: Some(info.channels),
+    })
+}
+
+/// A paired WAV file's details, from its first bytes and full size
+pub fn from_sidecar(name: &str, header: &[u8], size: u64) -> AudioNote {
+    let info = parse_wav(header, size);
+    AudioNote {
+        source: AudioSource::Sidecar,
+        segment: None,
+        file: Some(name.to_string()),
+        size,
+        duration: info.and_then(|i| i.duration),
+        sample_rate: info.map(|i| i.sample_rate),
+        channels: info.map(|i| i.channels),
+    }
+}
+
+/// The WAV file next to `image` with the same stem, e.g. `P1010001.WAV` for `P1010001.JPG`
+pub fn find_sidecar(image: &Path) -> Option<PathBuf> {
+    ["WAV", "wav", "Wav"].iter()
+        .map(|ext| image.with_extension(ext))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16-bit mono PCM at 8 kHz with `samples` samples
+    fn wav(samples: usize) -> Vec<u8> {
+        let data_len = samples as u32 * 2;
+        let mut wav = b"RIFF".to_vec();
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend(16000u32.to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        wav.extend(vec![0; data_len as usize]);
+        wav
+    }
+
+    #[test]
+    fn test_embedded_wav_across_segments() {
+        let wav = wav(12000);
+        let signature = b"AUDIO\0";
+        let segments: Vec<Segment> = wav.chunks(10000)
+            .map(|chunk| Segment { marker: 0xE9, data: [signature.as_slice(), chunk].concat(), offset: None })
+            .collect();
+        assert_eq!(embedded_wav(&segments).unwrap(), wav);
+        let note = from_segments(&segments).unwrap();
+        assert_eq!(note.segment.as_deref(), Some("APP9"));
+        assert_eq!((note.duration, note.sample_rate, note.channels), (Some(1.5), Some(8000), Some(1)));
+
+        // A header read on its own still gives the duration from the file size
+        let sidecar = from_sidecar("P1010001.WAV", &wav[..64], wav.len() as u64);
+        assert_eq!(sidecar.duration, Some(1.5));
+        assert_eq!(from_segments(&[Segment { marker: 0xE1, data: b"Exif\0\0".to_vec(), offset: None }]), None);
+    }
+}
@@ -0,0 +1,120 @@
+use chrono::{DateTime, Duration, Utc};
+use std::path::Path;
+
+/// What burst detection needs to know about one image
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Frame<'a> {
+    /// Model and serial number; frames only group with frames from the same camera
+    pub camera: (Option<&'a str>, Option<&'a str>),
+    pub capture_time: Option<DateTime<Utc>>,
+    /// Frame number recorded by the camera
+    pub sequence_number: Option<u32>,
+    /// Number at the end of the file name, see [`file_number`]
+    pub file_number: Option<u64>,
+}
+
+/// Trailing number of a file name's stem, e.g. 1234 for `IMG_1234.JPG`
+pub fn file_number(filename: &str) -> Option<u64> {
+    let stem = Path::new(filename).file_stem()?.to_str()?;
+    let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    stem[prefix.len()..].parse().ok()
+}
+
+/// Group frames into bursts: runs from one camera whose capture times are at
+/// most `max_gap` apart and, where both neighbours have them, whose sequence
+/// numbers (or failing those, file numbers) are consecutive.
+///
+/// Returns a group id per frame, `None` for frames not in a burst. Ids are
+/// `burst-1`, `burst-2`, ... in order of each burst's first capture time.
+pub fn detect(frames: &[Frame], max_gap: Duration) -> Vec<Option<String>> {
+    let mut order: Vec<usize> = (0..frames.len()).filter(|&i| frames[i].capture_time.is_some()).collect();
+    order.sort_by_key(|&i| {
+        let f = &frames[i];
+        (f.camera, f.capture_time, f.sequence_number, f.file_number, i)
+    });
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+    for i in order {
+        if let Some(&prev) = current.last() {
+            if !continues(&frames[prev], &frames[i], max_gap) {
+                groups.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(i);
+    }
+    groups.push(current);
+    groups.retain(|group| group.len() > 1);
+    groups.sort_by_key(|group| (frames[group[0]].capture_time, group[0]));
+
+    let mut ids = vec![None; frames.len()];
+    for (n, group) in groups.iter().enumerate() {
+        for &i in group {
+            ids[i] = Some(format!("burst-{}", n + 1));
+        }
+    }
+    ids
+}
+
+/// Whether `next` directly follows `prev` in the same burst
+fn continues(prev: &Frame, next: &Frame, max_gap: Duration) -> bool {
+    let (Some(prev_time), Some(next_time)) = (prev.capture_time, next.capture_time) else {
+        return false;
+    };
+    if prev.camera != next.camera || next_time - prev_time > max_gap {
+        return false;
+    }
+    match (prev.sequence_number, next.sequence_number) {
+        (Some(a), Some(b)) => a.checked_add(1) == Some(b),
+        _ => match (prev.file_number, next.file_number) {
+            (Some(a), Some(b)) => a.checked_add(1) == Some(b),
+            _ => true,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn frame<'a>(camera: &'a str, millis: i64, name: &str) -> Frame<'a> {
+        Frame {
+            camera: (Some(camera), None),
+            capture_time: Some(Utc.timestamp_millis_opt(1_700_000_000_000 + millis).unwrap()),
+            sequence_number: None,
+            file_number: file_number(name),
+        }
+    }
+
+    #[test]
+    fn test_file_number() {
+        assert_eq!(file_number("IMG_1234.JPG"), Some(1234));
+        assert_eq!(file_number("DSC00001.jpg"), Some(1));
+        assert_eq!(file_number("holiday.jpg"), None);
+    }
+
+    #[test]
+    fn test_detect() {
+        let frames = [
+            frame("A", 2000, "IMG_0012.JPG"),
+            frame("A", 0, "IMG_0010.JPG"),
+            frame("A", 100, "IMG_0011.JPG"),
+            // Same time but another camera
+            frame("B", 50, "DSC_0500.JPG"),
+            // Close in time but not the next file
+            frame("A", 2100, "IMG_0020.JPG"),
+            frame("B", 5000, "DSC_0501.JPG"),
+            Frame { capture_time: None, ..frame("A", 0, "IMG_0013.JPG") },
+        ];
+        let ids = detect(&frames, Duration::milliseconds(500));
+        let burst = Some("burst-1".to_string());
+        assert_eq!(ids, [None, burst.clone(), burst, None, None, None, None]);
+
+        let ids = detect(&frames, Duration::seconds(10));
+        assert_eq!(ids[0], ids[1]);
+        assert_eq!(ids[3], ids[5]);
+        assert_ne!(ids[0], ids[3]);
+        assert_eq!((ids[4].as_ref(), ids[6].as_ref()), (None, None));
+    }
+}
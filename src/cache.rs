@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use jpeg_metadata_extractor::content::{ContentMetadata, ExtractOptions};
+use jpeg_metadata_extractor::filesystem::FilesystemMetadata;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A cached extraction result and the file state it was computed from
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    /// Tool version; entries from other versions are ignored
+    version: String,
+    path: PathBuf,
+    size: u64,
+    modified_time: DateTime<Utc>,
+    /// Options the content was extracted with; entries for other options are ignored
+    options: ExtractOptions,
+    content: T,
+}
+
+/// On-disk cache of content metadata keyed by (path, size, mtime)
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    /// `$XDG_CACHE_HOME/jpeg-metadata-extractor`, falling back to `~/.cache`
+    /// (or `%LOCALAPPDATA%` on Windows)
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+        Some(base.join("jpeg-metadata-extractor"))
+    }
+
+    /// Cached content for `path`, if present and the file is unchanged
+    pub fn get(&self, path: &Path, fs_metadata: &FilesystemMetadata, options: &ExtractOptions) -> Option<ContentMetadata> {
+        let key = cache_key(path)?;
+        let json = fs::read_to_string(self.dir.join(entry_name(&key))).ok()?;
+        let entry: CacheEntry<ContentMetadata> = serde_json::from_str(&json).ok()?;
+        let fresh = entry.version == env!("CARGO_PKG_VERSION")
+            && entry.path == key
+            && entry.size == fs_metadata.size
+            && entry.modified_time == fs_metadata.modified_time
+            && entry.options == *options;
+        fresh.then_some(entry.content)
+    }
+
+    pub fn put(
+        &self,
+        path: &Path,
+        fs_metadata: &FilesystemMetadata,
+        options: &ExtractOptions,
+        content: &ContentMetadata,
+    ) -> Result<()> {
+        let key = cache_key(path).context("Failed to resolve path for caching")?;
+        let entry = CacheEntry {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            path: key.clone(),
+            size: fs_metadata.size,
+            modified_time: fs_metadata.modified_time,
+            options: options.clone(),
+            content,
+        };
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory {}", self.dir.display()))?;
+        fs::write(self.dir.join(entry_name(&key)), serde_json::to_string(&entry)?)
+            .context("Failed to write cache entry")
+    }
+
+    /// Remove every cached entry
+    pub fn clear(&self) -> Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to clear cache {}", self.dir.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Absolute path identifying a file regardless of how it was named on the command line
+fn cache_key(path: &Path) -> Option<PathBuf> {
+    fs::canonicalize(path).ok()
+}
+
+/// File name for a cache entry: FNV-1a hash of the key, which is stable across builds
+fn entry_name(key: &Path) -> String {
+    let hash = key.as_os_str().as_encoded_bytes().iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}.json", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jpeg_metadata_extractor::content::extract_from_bytes;
+    use jpeg_metadata_extractor::filesystem::extract_filesystem_metadata;
+
+    #[test]
+    fn test_cache_roundtrip_and_invalidation() {
+        let dir = std::env::temp_dir().join(format!("jme-cache-{}", std::process::id()));
+        let cache = Cache::new(dir.clone());
+        let path = Path::new("images/JAM19896.jpg");
+        let mut fs_metadata = extract_filesystem_metadata(path).unwrap();
+        let options = ExtractOptions::default();
+        let content = extract_from_bytes(&fs::read(path).unwrap(), &options).unwrap();
+
+        assert!(cache.get(path, &fs_metadata, &options).is_none());
+        cache.put(path, &fs_metadata, &options, &content).unwrap();
+        let cached = cache.get(path, &fs_metadata, &options).unwrap();
+        assert_eq!(serde_json::to_value(&cached).unwrap(), serde_json::to_value(&content).unwrap());
+
+        let with_tags = ExtractOptions { tags: vec![0x9286], ..Default::default() };
+        assert!(cache.get(path, &fs_metadata, &with_tags).is_none());
+        fs_metadata.size += 1;
+        assert!(cache.get(path, &fs_metadata, &options).is_none());
+
+        cache.clear().unwrap();
+        assert!(!dir.exists());
+        cache.clear().unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use crate::error::{ExtractError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Diagonal of a 35mm film frame, which crop factors are relative to
+const FULL_FRAME_DIAGONAL: f64 = 43.267;
+
+/// Physical sensor dimensions in millimetres
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sensor {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Sensor {
+    pub fn diagonal(&self) -> f64 {
+        self.width.hypot(self.height)
+    }
+
+    /// Ratio of the 35mm frame diagonal to this sensor's
+    pub fn crop_factor(&self) -> f64 {
+        FULL_FRAME_DIAGONAL / self.diagonal()
+    }
+}
+
+/// What the camera database records about one model
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraSpec {
+    /// Sensor width in millimetres
+    pub sensor_width: f64,
+    /// Sensor height in millimetres
+    pub sensor_height: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub megapixels: Option<f64>,
+}
+
+impl CameraSpec {
+    pub fn sensor(&self) -> Sensor {
+        Sensor { width: self.sensor_width, height: self.sensor_height }
+    }
+}
+
+/// Derived values from the camera database, for the `enrichment` section
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Enrichment {
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub crop_factor: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub megapixels: Option<f64>,
+    /// Focal length times crop factor, in millimetres
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_length_35mm: Option<f64>,
+}
+
+impl Enrichment {
+    pub fn new(spec: &CameraSpec, focal_length: Option<f64>) -> Self {
+        let crop_factor = spec.sensor().crop_factor();
+        Enrichment {
+            sensor_width: spec.sensor_width,
+            sensor_height: spec.sensor_height,
+            crop_factor: (crop_factor * 100.0).round() / 100.0,
+            megapixels: spec.megapixels,
+            focal_length_35mm: focal_length.map(|f| (f * crop_factor * 10.0).round() / 10.0),
+        }
+    }
+}
+
+const fn spec(sensor_width: f64, sensor_height: f64, megapixels: f64) -> CameraSpec {
+    CameraSpec { sensor_width, sensor_height, megapixels: Some(megapixels) }
+}
+
+/// Built-in camera database, keyed by EXIF Model as cameras write it
+const CAMERAS: &[(&str, CameraSpec)] = &[
+    ("Canon EOS 5D Mark III", spec(36.0, 24.0, 22.3)),
+    ("Canon EOS 5D Mark IV", spec(36.0, 24.0, 30.4)),
+    ("Canon EOS 6D", spec(35.8, 23.9, 20.2)),
+    ("Canon EOS 6D Mark II", spec(35.9, 24.0, 26.2)),
+    ("Canon EOS R", spec(36.0, 24.0, 30.3)),
+    ("Canon EOS R5", spec(36.0, 24.0, 45.0)),
+    ("Canon EOS R6", spec(35.9, 23.9, 20.1)),
+    ("Canon EOS 90D", spec(22.3, 14.8, 32.5)),
+    ("Canon EOS 80D", spec(22.3, 14.9, 24.2)),
+    ("Canon EOS R7", spec(22.3, 14.8, 32.5)),
+    ("NIKON D750", spec(35.9, 24.0, 24.3)),
+    ("NIKON D850", spec(35.9, 23.9, 45.7)),
+    ("NIKON Z 6", spec(35.9, 23.9, 24.5)),
+    ("NIKON Z 7", spec(35.9, 23.9, 45.7)),
+    ("NIKON D7500", spec(23.5, 15.7, 20.9)),
+    ("NIKON Z 50", spec(23.5, 15.7, 20.9)),
+    ("ILCE-7M3", spec(35.6, 23.8, 24.2)),
+    ("ILCE-7RM4", spec(35.7, 23.8, 61.0)),
+    ("ILCE-7M4", spec(35.9, 23.9, 33.0)),
+    ("ILCE-6400", spec(23.5, 15.6, 24.2)),
+    ("ILCE-6600", spec(23.5, 15.6, 24.2)),
+    ("X-T4", spec(23.5, 15.6, 26.1)),
+    ("X-T5", spec(23.5, 15.6, 40.2)),
+    ("GFX 50S", spec(43.8, 32.9, 51.4)),
+    ("GFX100S", spec(43.8, 32.9, 102.0)),
+    ("E-M1MarkIII", spec(17.4, 13.0, 20.4)),
+    ("DC-GH5", spec(17.3, 13.0, 20.3)),
+    ("DSC-RX100M7", spec(13.2, 8.8, 20.1)),
+    ("FC3170", spec(6.4, 4.8, 48.0)),
+    ("L1D-20c", spec(13.2, 8.8, 20.0)),
+];
+
+/// Camera entries from a TOML file, one table per model:
+///
+/// ```toml
+/// ["Canon EOS R5"]
+/// sensor_width = 36.0
+/// sensor_height = 24.0
+/// megapixels = 45.0
+/// ```
+pub fn parse_overrides(toml: &str) -> Result<BTreeMap<String, CameraSpec>> {
+    toml::from_str(toml).map_err(|e| ExtractError::parse_from("camera database", e))
+}
+
+/// Database entry for an EXIF camera model, from `overrides` first and then the
+/// built-in table, matched ignoring case and surrounding space
+pub fn lookup(model: &str, overrides: &BTreeMap<String, CameraSpec>) -> Option<CameraSpec> {
+    let model = model.trim();
+    overrides.iter()
+        .map(|(name, spec)| (name.as_str(), spec))
+        .chain(CAMERAS.iter().map(|(name, spec)| (*name, spec)))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(model))
+        .map(|(_, spec)| *spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let none = BTreeMap::new();
+        let full_frame = lookup("Canon EOS 5D Mark IV", &none).unwrap();
+        assert_eq!(full_frame.megapixels, Some(30.4));
+        assert!((full_frame.sensor().crop_factor() - 1.0).abs() < 0.01);
+        assert_eq!(lookup(" nikon z 50 ", &none).map(|s| s.sensor_width), Some(23.5));
+        assert_eq!(lookup("Unknown Cam", &none), None);
+
+        let overrides = parse_overrides("[\"nikon z 50\"]\nsensor_width = 24.0\nsensor_height = 16.0\n").unwrap();
+        let custom = lookup("NIKON Z 50", &overrides).unwrap();
+        assert_eq!((custom.sensor_width, custom.megapixels), (24.0, None));
+        assert!(parse_overrides("[cam]\nsensor_width = \"wide\"\n").is_err());
+    }
+
+    #[test]
+    fn test_enrichment() {
+        let enrichment = Enrichment::new(&spec(23.5, 15.6, 24.2), Some(35.0));
+        assert_eq!(enrichment.crop_factor, 1.53);
+        assert_eq!(enrichment.focal_length_35mm, Some(53.7));
+    }
+}
@@ -0,0 +1,68 @@
+use clap::{CommandFactory, ValueEnum};
+use jpeg_metadata_extractor::detect::ImageFormat;
+use jpeg_metadata_extractor::extractor::ExtractorRegistry;
+use serde_json::{json, Map, Value};
+
+use crate::{archives, layout, Args, OutputFormat, OutputLayout, FEATURES};
+
+/// Version of the `--capabilities` document, raised when a key changes meaning or is removed
+const CAPABILITIES_VERSION: u32 = 1;
+
+/// What this build can do, for `--capabilities`: the formats it reads and writes,
+/// the field groups a record is divided into, the subcommands and the Cargo
+/// features compiled in. Names are those accepted on the command line and used in
+/// the output, so a caller can check a request against them before sending work.
+pub fn capabilities(registry: &ExtractorRegistry) -> Value {
+    let input_formats: Vec<Value> = ImageFormat::SUPPORTED.iter()
+        .map(|format| json!({"name": format, "description": format.to_string()}))
+        .collect();
+    let field_groups: Map<String, Value> = layout::sections().iter()
+        .map(|(section, fields)| (section.to_string(), json!(fields)))
+        .collect();
+    let subcommands: Vec<String> = Args::command().get_subcommands()
+        .map(|command| command.get_name().to_string())
+        .collect();
+    let features: Map<String, Value> = FEATURES.iter()
+        .map(|(name, enabled)| (name.to_string(), Value::Bool(*enabled)))
+        .collect();
+
+    json!({
+        "capabilities_version": CAPABILITIES_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+        "input_formats": input_formats,
+        "archive_formats": archives::archive_extensions().collect::<Vec<_>>(),
+        "output_formats": value_names::<OutputFormat>(),
+        "output_layouts": value_names::<OutputLayout>(),
+        "field_groups": field_groups,
+        "extractors": registry.names().collect::<Vec<_>>(),
+        "subcommands": subcommands,
+        "features": features,
+    })
+}
+
+/// The command line names of an option's values
+fn value_names<T: ValueEnum>() -> Vec<String> {
+    T::value_variants().iter()
+        .filter_map(ValueEnum::to_possible_value)
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let document = capabilities(&ExtractorRegistry::new());
+        assert_eq!(document["input_formats"][0], json!({"name": "jfif", "description": "JPEG (JFIF)"}));
+        assert!(document["input_formats"].as_array().unwrap().iter().all(|f| f["name"] != "unknown"));
+        assert_eq!(document["archive_formats"], json!(["zip", "tar"]));
+        assert_eq!(document["output_formats"], json!(["json", "table", "jsonl", "exiftool", "xmp"]));
+        assert_eq!(document["output_layouts"], json!(["sidecar", "cas"]));
+        assert!(document["field_groups"]["image"].as_array().unwrap().contains(&json!("color_space")));
+        assert!(document["subcommands"].as_array().unwrap().contains(&json!("audit")));
+        assert_eq!(document["features"]["decode"], cfg!(feature = "decode"));
+        assert_eq!(document["features"]["sqlite"], cfg!(feature = "sqlite"));
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the index inside the store, mapping input paths to hashes
+pub const INDEX_FILE: &str = "index.json";
+
+/// Lowercase hex SHA-256 of `bytes`
+pub fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compact JSON of `value` with every object's keys sorted, so a record hashes
+/// the same however it is printed, compressed or signed
+pub fn canonical(value: &Value) -> Vec<u8> {
+    fn sorted(value: &Value) -> Value {
+        match value {
+            Value::Object(object) => {
+                let entries: BTreeMap<&String, Value> = object.iter().map(|(key, value)| (key, sorted(value))).collect();
+                Value::Object(entries.into_iter().map(|(key, value)| (key.clone(), value)).collect())
+            }
+            Value::Array(values) => Value::Array(values.iter().map(sorted).collect()),
+            _ => value.clone(),
+        }
+    }
+    serde_json::to_vec(&sorted(value)).expect("a JSON value always serializes")
+}
+
+/// Where an object is stored: `<root>/ab/cd/abcd….<extension>`, fanned out by
+/// the first two bytes of its hash so no directory grows too large
+pub fn object_path(root: &Path, hash: &str, extension: &str) -> PathBuf {
+    root.join(&hash[..2]).join(&hash[2..4]).join(format!("{}.{}", hash, extension))
+}
+
+/// The index of a content-addressed store. Entries from earlier runs are kept,
+/// and an input written again points at its new object.
+#[derive(Debug)]
+pub struct Index {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl Index {
+    pub fn open(root: &Path) -> Result<Self> {
+        let path = root.join(INDEX_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Failed to parse store index {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Index { path, entries })
+    }
+
+    pub fn insert(&mut self, input: String, hash: String) {
+        self.entries.insert(input, hash);
+    }
+
+    /// Objects no longer referenced by the index are left in place; another
+    /// index or an archive snapshot may still refer to them
+    pub fn write(&self, durable: bool) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        let temp_path = crate::write_temp(&self.path, json.as_bytes(), durable)?;
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        if durable {
+            crate::sync_parent(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_path_and_index() {
+        let digest = hash(b"{}");
+        assert_eq!(digest, "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a");
+        assert_eq!(
+            object_path(Path::new("store"), &digest, "json.gz"),
+            Path::new("store/44/13/44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a.json.gz"),
+        );
+
+        let root = std::env::temp_dir().join(format!("jme-cas-{}", std::process::id()));
+        let mut index = Index::open(&root).unwrap();
+        index.insert("a.jpg".to_string(), digest.clone());
+        index.write(false).unwrap();
+        let mut reopened = Index::open(&root).unwrap();
+        reopened.insert("b.jpg".to_string(), digest.clone());
+        reopened.write(false).unwrap();
+        let written = fs::read_to_string(root.join(INDEX_FILE)).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let entries: BTreeMap<String, String> = serde_json::from_str(&written).unwrap();
+        assert_eq!(entries.keys().collect::<Vec<_>>(), ["a.jpg", "b.jpg"]);
+    }
+
+    #[test]
+    fn test_canonical() {
+        let a: Value = serde_json::from_str(r#"{"b": 1, "a": {"y": [{"d": 2, "c": 3}], "x": null}}"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{"a": {"x": null, "y": [{"c": 3, "d": 2}]}, "b": 1}"#).unwrap();
+        assert_eq!(canonical(&a), br#"{"a":{"x":null,"y":[{"c":3,"d":2}]},"b":1}"#);
+        assert_eq!(hash(&canonical(&a)), hash(&canonical(&b)));
+    }
+}
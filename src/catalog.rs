@@ -0,0 +1,313 @@
+use crate::archives;
+use crate::registry::Camera;
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Records are written in batches of this many rows
+const BATCH_SIZE: usize = 200;
+
+/// A database that stores one JSON record per input path, replacing any earlier record
+pub trait CatalogBackend: Send {
+    /// Insert or replace `records` as (path, record) pairs in one round trip
+    fn upsert(&mut self, records: &[(String, Value)]) -> Result<()>;
+
+    /// Insert `cameras` into the cameras table of --camera-registry, keeping
+    /// any already there: a camera's ID is derived from its fields
+    fn upsert_cameras(&mut self, cameras: &[Camera]) -> Result<()>;
+}
+
+/// Buffers records for a backend and writes them in batches
+pub struct Catalog {
+    backend: Box<dyn CatalogBackend>,
+    pending: Vec<(String, Value)>,
+    /// Cameras first referred to by the pending records
+    cameras: Vec<Camera>,
+}
+
+impl Catalog {
+    pub fn new(backend: Box<dyn CatalogBackend>) -> Self {
+        Catalog { backend, pending: Vec::new(), cameras: Vec::new() }
+    }
+
+    /// Open the backend for a `--db` URL, creating its schema if needed
+    #[cfg_attr(not(all(feature = "postgres", feature = "sqlite")), allow(unreachable_code, unused_variables))]
+    pub fn open(url: &str) -> Result<Self> {
+        let backend: Box<dyn CatalogBackend> = match url.split_once("://") {
+            #[cfg(feature = "postgres")]
+            Some(("postgres" | "postgresql", _)) => Box::new(pg::PostgresCatalog::connect(url)?),
+            #[cfg(not(feature = "postgres"))]
+            Some(("postgres" | "postgresql", _)) => bail!("--db {} needs a build with the postgres feature", url),
+            #[cfg(feature = "sqlite")]
+            Some(("sqlite", path)) => Box::new(sqlite::SqliteCatalog::open(Path::new(path))?),
+            #[cfg(not(feature = "sqlite"))]
+            Some(("sqlite", _)) => bail!("--db {} needs a build with the sqlite feature", url),
+            _ => bail!("Unsupported catalog URL {} (expected postgres://... or sqlite://...)", url),
+        };
+        Ok(Catalog::new(backend))
+    }
+
+    pub fn add(&mut self, path: String, record: Value) -> Result<()> {
+        self.pending.push((path, record));
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Add a camera, written with the next batch of records, ahead of them
+    pub fn add_camera(&mut self, camera: Camera) {
+        self.cameras.push(camera);
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.cameras.is_empty() {
+            self.backend.upsert_cameras(&self.cameras)?;
+            self.cameras.clear();
+        }
+        if !self.pending.is_empty() {
+            self.backend.upsert(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Only checks the scheme, so a bad URL is reported before any file is read
+pub fn parse_url(url: &str) -> Result<String, String> {
+    match url.split_once("://") {
+        Some(("postgres" | "postgresql", _)) => Ok(url.to_string()),
+        Some(("sqlite", path)) if !path.is_empty() => Ok(url.to_string()),
+        _ => Err(format!("unsupported catalog URL '{}' (expected postgres://... or sqlite://...)", url)),
+    }
+}
+
+/// The input path a sidecar was written for, which is its catalog key: the image
+/// next to it, or the archive member, named by the record's `filename` and `archive`
+/// fields (flat or in the `file` section). `None` if the JSON is not a record.
+pub fn sidecar_key(sidecar: &Path, record: &Value) -> Option<PathBuf> {
+    let field = |name: &str| record.get(name).or_else(|| record.get("file")?.get(name))?.as_str();
+    let filename = field("filename")?;
+    Some(match field("archive") {
+        Some(archive) => archives::member_path(Path::new(archive), filename),
+        None => sidecar.with_file_name(filename),
+    })
+}
+
+#[cfg(feature = "postgres")]
+mod pg {
+    use super::CatalogBackend;
+    use crate::registry::Camera;
+    use anyhow::{Context, Result};
+    use postgres::types::ToSql;
+    use postgres::{Client, NoTls};
+    use serde_json::Value;
+
+    /// Serializes schema creation between workers starting at the same time
+    const SCHEMA_LOCK: i64 = 0x6a70_6567_6d65_7461;
+
+    const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS image_metadata (
+        path TEXT PRIMARY KEY,
+        metadata JSONB NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE TABLE IF NOT EXISTS cameras (
+        id TEXT PRIMARY KEY,
+        model TEXT,
+        serial TEXT,
+        firmware TEXT
+    )";
+
+    pub struct PostgresCatalog {
+        client: Client,
+    }
+
+    impl PostgresCatalog {
+        pub fn connect(url: &str) -> Result<Self> {
+            let mut client = Client::connect(url, NoTls).with_context(|| format!("Failed to connect to {}", url))?;
+            let mut transaction = client.transaction()?;
+            transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&SCHEMA_LOCK])?;
+            transaction.batch_execute(SCHEMA).context("Failed to create the catalog tables")?;
+            transaction.commit()?;
+            Ok(PostgresCatalog { client })
+        }
+    }
+
+    impl CatalogBackend for PostgresCatalog {
+        fn upsert(&mut self, records: &[(String, Value)]) -> Result<()> {
+            // ON CONFLICT fails if one statement updates a row twice, so only
+            // the last record for a path in a batch is kept
+            let mut rows: Vec<(&str, String)> = Vec::new();
+            for (path, record) in records {
+                rows.retain(|(p, _)| p != path);
+                rows.push((path, record.to_string()));
+            }
+            let placeholders: Vec<String> = (0..rows.len())
+                .map(|i| format!("(${}, ${}::text::jsonb, now())", 2 * i + 1, 2 * i + 2))
+                .collect();
+            let query = format!(
+                "INSERT INTO image_metadata (path, metadata, updated_at) VALUES {} \
+                 ON CONFLICT (path) DO UPDATE SET metadata = EXCLUDED.metadata, updated_at = EXCLUDED.updated_at",
+                placeholders.join(", "),
+            );
+            let params: Vec<&(dyn ToSql + Sync)> = rows.iter()
+                .flat_map(|(path, json)| [path as &(dyn ToSql + Sync), json as &(dyn ToSql + Sync)])
+                .collect();
+            self.client.execute(&query, &params).context("Failed to write to the image_metadata table")?;
+            Ok(())
+        }
+
+        fn upsert_cameras(&mut self, cameras: &[Camera]) -> Result<()> {
+            let placeholders: Vec<String> = (0..cameras.len())
+                .map(|i| format!("(${}, ${}, ${}, ${})", 4 * i + 1, 4 * i + 2, 4 * i + 3, 4 * i + 4))
+                .collect();
+            let query = format!(
+                "INSERT INTO cameras (id, model, serial, firmware) VALUES {} ON CONFLICT (id) DO NOTHING",
+                placeholders.join(", "),
+            );
+            let params: Vec<&(dyn ToSql + Sync)> = cameras.iter()
+                .flat_map(|c| [&c.id as &(dyn ToSql + Sync), &c.model, &c.serial, &c.firmware])
+                .collect();
+            self.client.execute(&query, &params).context("Failed to write to the cameras table")?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::CatalogBackend;
+    use crate::registry::Camera;
+    use anyhow::{Context, Result};
+    use rusqlite::Connection;
+    use serde_json::Value;
+    use std::path::Path;
+    use std::time::Duration;
+
+    const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS image_metadata (
+        path TEXT PRIMARY KEY,
+        metadata TEXT NOT NULL,
+        updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE TABLE IF NOT EXISTS cameras (
+        id TEXT PRIMARY KEY,
+        model TEXT,
+        serial TEXT,
+        firmware TEXT
+    )";
+
+    /// A catalog in a local SQLite file, with records stored as JSON text
+    pub struct SqliteCatalog {
+        connection: Connection,
+    }
+
+    impl SqliteCatalog {
+        pub fn open(path: &Path) -> Result<Self> {
+            let connection = Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+            // Other processes writing the same file wait rather than fail
+            connection.busy_timeout(Duration::from_secs(30))?;
+            connection.execute_batch(SCHEMA).context("Failed to create the catalog tables")?;
+            Ok(SqliteCatalog { connection })
+        }
+    }
+
+    impl CatalogBackend for SqliteCatalog {
+        fn upsert(&mut self, records: &[(String, Value)]) -> Result<()> {
+            let transaction = self.connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(
+                    "INSERT INTO image_metadata (path, metadata, updated_at) VALUES (?1, ?2, CURRENT_TIMESTAMP) \
+                     ON CONFLICT (path) DO UPDATE SET metadata = excluded.metadata, updated_at = excluded.updated_at",
+                )?;
+                for (path, record) in records {
+                    statement.execute((path, record.to_string()))?;
+                }
+            }
+            transaction.commit().context("Failed to write to the image_metadata table")
+        }
+
+        fn upsert_cameras(&mut self, cameras: &[Camera]) -> Result<()> {
+            let transaction = self.connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(
+                    "INSERT INTO cameras (id, model, serial, firmware) VALUES (?1, ?2, ?3, ?4) ON CONFLICT (id) DO NOTHING",
+                )?;
+                for camera in cameras {
+                    statement.execute((&camera.id, &camera.model, &camera.serial, &camera.firmware))?;
+                }
+            }
+            transaction.commit().context("Failed to write to the cameras table")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<usize>>>);
+
+    impl CatalogBackend for Recorder {
+        fn upsert(&mut self, records: &[(String, Value)]) -> Result<()> {
+            self.0.lock().unwrap().push(records.len());
+            Ok(())
+        }
+
+        fn upsert_cameras(&mut self, _cameras: &[Camera]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let mut catalog = Catalog::new(Box::new(Recorder(batches.clone())));
+        for i in 0..BATCH_SIZE + 5 {
+            catalog.add(format!("{}.jpg", i), serde_json::json!({"size": i})).unwrap();
+        }
+        catalog.flush().unwrap();
+        catalog.flush().unwrap();
+        assert_eq!(*batches.lock().unwrap(), [BATCH_SIZE, 5]);
+
+        assert!(parse_url("postgresql://u@db/photos").is_ok());
+        assert!(parse_url("sqlite://photos.db").is_ok());
+        assert!(parse_url("sqlite://").is_err());
+        assert!(parse_url("mysql://db/photos").is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite() {
+        let path = std::env::temp_dir().join(format!("jme-catalog-{}.db", std::process::id()));
+        let mut catalog = Catalog::open(&format!("sqlite://{}", path.display())).unwrap();
+        catalog.add("a.jpg".to_string(), serde_json::json!({"size": 1})).unwrap();
+        catalog.add("a.jpg".to_string(), serde_json::json!({"size": 2})).unwrap();
+        catalog.add("b.jpg".to_string(), serde_json::json!({"size": 3})).unwrap();
+        let camera = Camera { id: "c0ffee".to_string(), model: Some("X-T5".to_string()), serial: None, firmware: Some("2.0".to_string()) };
+        catalog.add_camera(camera.clone());
+        catalog.flush().unwrap();
+        catalog.add_camera(camera);
+        catalog.flush().unwrap();
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let rows: Vec<(String, String)> = connection.prepare("SELECT path, metadata FROM image_metadata ORDER BY path").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        let cameras: Vec<(String, Option<String>, Option<String>)> = connection.prepare("SELECT id, model, serial FROM cameras").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows, [("a.jpg".to_string(), r#"{"size":2}"#.to_string()), ("b.jpg".to_string(), r#"{"size":3}"#.to_string())]);
+        assert_eq!(cameras, [("c0ffee".to_string(), Some("X-T5".to_string()), None)]);
+    }
+
+    #[test]
+    fn test_sidecar_key() {
+        let sidecar = Path::new("shoot/IMG_0001.json");
+        let nested = serde_json::json!({"file": {"filename": "IMG_0001.JPG"}, "image": {}});
+        assert_eq!(sidecar_key(sidecar, &nested), Some(PathBuf::from("shoot/IMG_0001.JPG")));
+        let member = serde_json::json!({"filename": "day1/IMG_0002.jpg", "archive": "shoot.zip"});
+        assert_eq!(sidecar_key(sidecar, &member), Some(archives::member_path(Path::new("shoot.zip"), "day1/IMG_0002.jpg")));
+        assert_eq!(sidecar_key(sidecar, &serde_json::json!({"files": 3})), None);
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Character set of nominally-ASCII EXIF text. The standard allows only ASCII,
+/// but older cameras and editors wrote their locale's encoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Charset {
+    /// UTF-8 when valid, else Shift_JIS when the text reads as Japanese, else Latin-1
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// ISO 8859-1 with the Windows-1252 additions, as Western European cameras wrote it
+    #[serde(rename = "latin-1")]
+    Latin1,
+    /// As written by Japanese cameras and phones
+    #[serde(rename = "shift-jis")]
+    ShiftJis,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Charset::Auto),
+            "utf-8" | "utf8" => Ok(Charset::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" | "windows-1252" | "cp1252" => Ok(Charset::Latin1),
+            "shift-jis" | "shift_jis" | "sjis" | "cp932" => Ok(Charset::ShiftJis),
+            _ => Err(format!("unknown charset '{}' (expected auto, utf-8, latin-1 or shift-jis)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Charset::Auto => "auto",
+            Charset::Utf8 => "utf-8",
+            Charset::Latin1 => "latin-1",
+            Charset::ShiftJis => "shift-jis",
+        })
+    }
+}
+
+impl Charset {
+    /// The character set `bytes` are decoded from: this one, or for `Auto` the
+    /// best guess. Latin-1 text can form valid Shift_JIS byte pairs, so Shift_JIS
+    /// is only guessed when every non-ASCII character decodes to Japanese.
+    pub fn resolve(self, bytes: &[u8]) -> Charset {
+        if self != Charset::Auto {
+            return self;
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            return Charset::Utf8;
+        }
+        let (text, had_errors) = encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes);
+        if !had_errors && text.chars().filter(|c| !c.is_ascii()).all(is_japanese) {
+            Charset::ShiftJis
+        } else {
+            Charset::Latin1
+        }
+    }
+
+    /// Decode `bytes` to UTF-8, replacing anything the character set cannot represent
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self.resolve(bytes) {
+            Charset::Latin1 => encoding_rs::WINDOWS_1252.decode_without_bom_handling(bytes).0.into_owned(),
+            Charset::ShiftJis => encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes).0.into_owned(),
+            _ => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+
+    /// The character set `bytes` had to be transcoded from, or `None` for
+    /// ASCII and UTF-8, which are used as they are
+    pub fn transcoded_from(self, bytes: &[u8]) -> Option<Charset> {
+        Some(self.resolve(bytes)).filter(|&charset| charset != Charset::Utf8 && !bytes.is_ascii())
+    }
+}
+
+/// Kana, CJK ideographs, and the full-width forms and punctuation used with them.
+/// Half-width katakana are left out: as Shift_JIS they are single bytes that
+/// Latin-1 accented capitals also produce.
+fn is_japanese(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' | '\u{4E00}'..='\u{9FFF}' | '\u{FF01}'..='\u{FF60}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let latin1 = b"Ren\xe9 M\xfcller";
+        let shift_jis = encoding_rs::SHIFT_JIS.encode("山田太郎").0.into_owned();
+        assert_eq!(Charset::Auto.decode(latin1), "René Müller");
+        assert_eq!(Charset::Auto.transcoded_from(latin1), Some(Charset::Latin1));
+        assert_eq!(Charset::Auto.decode(&shift_jis), "山田太郎");
+        assert_eq!(Charset::Auto.transcoded_from(&shift_jis), Some(Charset::ShiftJis));
+        assert_eq!(Charset::Auto.transcoded_from("Zoë".as_bytes()), None);
+        assert_eq!(Charset::Latin1.transcoded_from(b"Canon"), None);
+
+        // Forced, UTF-8 bytes are read as Latin-1 all the same
+        assert_eq!(Charset::Latin1.decode("é".as_bytes()), "Ã©");
+        assert_eq!("SJIS".parse(), Ok(Charset::ShiftJis));
+        assert!("ebcdic".parse::<Charset>().is_err());
+    }
+}
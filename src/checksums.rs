@@ -0,0 +1,159 @@
+use crate::throttle::{Throttle, Throttled};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Layout of the checksum manifest written with --manifest-format
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    /// Input for `sha256sum -c`: digest, two spaces, path
+    Sha256sum,
+    /// A BagIt `manifest-sha256.txt`: digest, space, percent-encoded `data/...` path
+    /// relative to the manifest's directory
+    Bagit,
+}
+
+impl ManifestFormat {
+    /// File name used when --manifest-output is not given
+    pub fn default_path(self) -> &'static str {
+        match self {
+            ManifestFormat::Sha256sum => "SHA256SUMS",
+            ManifestFormat::Bagit => "manifest-sha256.txt",
+        }
+    }
+}
+
+/// SHA-256 digests of processed files, written out once every file is done
+#[derive(Debug)]
+pub struct ChecksumManifest {
+    format: ManifestFormat,
+    path: PathBuf,
+    throttle: Option<&'static Throttle>,
+    /// (hex digest, path relative to the manifest's directory)
+    entries: Vec<(String, PathBuf)>,
+}
+
+impl ChecksumManifest {
+    pub fn new(format: ManifestFormat, path: PathBuf, throttle: Option<&'static Throttle>) -> Self {
+        ChecksumManifest { format, path, throttle, entries: Vec::new() }
+    }
+
+    /// Hash a file and record it
+    pub fn add(&mut self, file: &Path) -> Result<()> {
+        let relative = match self.format {
+            ManifestFormat::Sha256sum => {
+                let base = self.path.parent().unwrap_or(Path::new(""));
+                // Paths stay as given unless they sit under the manifest's directory
+                let relative = file.strip_prefix(base).unwrap_or(file);
+                relative.components().filter(|c| *c != Component::CurDir).collect()
+            }
+            ManifestFormat::Bagit => self.bag_path(file)?,
+        };
+        let digest = sha256_file(file, self.throttle)?;
+        self.entries.push((digest, relative));
+        Ok(())
+    }
+
+    /// `file` relative to the bag, which is the manifest's directory. A bag's
+    /// manifest only lists its payload, so the file must be under `data/`.
+    fn bag_path(&self, file: &Path) -> Result<PathBuf> {
+        let absolute = |path: &Path| -> Result<PathBuf> {
+            let path = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+            let path = std::path::absolute(path).with_context(|| format!("Failed to resolve {}", path.display()))?;
+            Ok(path.components().filter(|c| *c != Component::CurDir).collect())
+        };
+        let bag = absolute(self.path.parent().unwrap_or(Path::new("")))?;
+        absolute(file)?
+            .strip_prefix(&bag)
+            .ok()
+            .filter(|relative| relative.starts_with("data"))
+            .map(Path::to_path_buf)
+            .ok_or_else(|| anyhow!("{} is not in the data directory of the bag at {}", file.display(), bag.display()))
+    }
+
+    pub fn render(&self) -> String {
+        self.entries.iter().map(|(digest, path)| match self.format {
+            ManifestFormat::Sha256sum => sha256sum_line(digest, path),
+            ManifestFormat::Bagit => format!("{} {}\n", digest, bagit_path(path)),
+        }).collect()
+    }
+
+    /// Write the manifest, or with `dry_run` only report where it would go.
+    /// Reports go to stderr, as stdout may be carrying table or JSON output.
+    pub fn write(&self, dry_run: bool) -> Result<()> {
+        if dry_run {
+            eprintln!("Would create: {}", self.path.display());
+            return Ok(());
+        }
+        fs::write(&self.path, self.render())
+            .with_context(|| format!("Failed to write checksum manifest {}", self.path.display()))?;
+        eprintln!("Wrote checksum manifest: {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// Lowercase hex SHA-256 of a file's contents
+pub fn sha256_file(path: &Path, throttle: Option<&Throttle>) -> Result<String> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open file {}", path.display()))?;
+    let mut file = Throttled::new(file, throttle);
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// A line as GNU sha256sum writes it, which escapes names containing a
+/// backslash or newline and flags the line with a leading backslash
+fn sha256sum_line(digest: &str, path: &Path) -> String {
+    let name = path.to_string_lossy();
+    if name.contains(['\\', '\n', '\r']) {
+        let escaped = name.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+        format!("\\{}  {}\n", digest, escaped)
+    } else {
+        format!("{}  {}\n", digest, name)
+    }
+}
+
+/// BagIt file path: `/`-separated, with `%`, CR and LF percent-encoded
+fn bagit_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_formats() {
+        let dir = std::env::temp_dir().join(format!("jme-checksums-{}", std::process::id()));
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let file = dir.join("data").join("100%.jpg");
+        fs::write(&file, "abc").unwrap();
+
+        let mut sums = ChecksumManifest::new(ManifestFormat::Sha256sum, dir.join("SHA256SUMS"), None);
+        sums.add(&file).unwrap();
+        let mut bag = ChecksumManifest::new(ManifestFormat::Bagit, dir.join("manifest-sha256.txt"), None);
+        bag.add(&file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sums.render(), format!("{}  data{}100%.jpg\n", digest, std::path::MAIN_SEPARATOR));
+        assert_eq!(bag.render(), format!("{} data/100%25.jpg\n", digest));
+        assert!(bag.add(&dir.join("outside.jpg")).is_err());
+    }
+
+    #[test]
+    fn test_sha256sum_escaping() {
+        assert_eq!(sha256sum_line("ab", Path::new("a\nb.jpg")), "\\ab  a\\nb.jpg\n");
+    }
+}
@@ -0,0 +1,90 @@
+use crate::pixels::{luma, Preview};
+use serde::{Deserialize, Serialize};
+
+/// Number of dominant colours reported
+const DOMINANT_COLORS: usize = 5;
+/// Number of luma histogram bins
+const HISTOGRAM_BINS: usize = 16;
+
+/// Colour features for search-by-colour, computed from decoded pixels
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorStats {
+    /// Mean Rec. 601 luma, from 0 (black) to 1 (white)
+    pub average_brightness: f64,
+    /// Most common colours, most frequent first
+    pub dominant_colors: Vec<DominantColor>,
+    /// Share of pixels in each of 16 equal luma bins, darkest first
+    pub histogram: Vec<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DominantColor {
+    /// `#rrggbb`
+    pub hex: String,
+    /// Share of pixels, from 0 to 1
+    pub fraction: f64,
+}
+
+/// Compute colour statistics. Colours are grouped by their top 4 bits per
+/// channel, and each group is reported as the mean of its pixels.
+pub fn analyze(preview: &Preview) -> ColorStats {
+    let mut buckets = vec![(0u64, [0u64; 3]); 1 << 12];
+    let mut histogram = [0u64; HISTOGRAM_BINS];
+    let mut luma_sum = 0.0;
+    let mut count = 0u64;
+
+    for pixel in preview.pixels() {
+        let [r, g, b] = pixel;
+        let bucket = &mut buckets[(r as usize >> 4) << 8 | (g as usize >> 4) << 4 | b as usize >> 4];
+        bucket.0 += 1;
+        for (sum, value) in bucket.1.iter_mut().zip(pixel) {
+            *sum += value as u64;
+        }
+        let y = luma(pixel);
+        histogram[((y as usize) * HISTOGRAM_BINS / 256).min(HISTOGRAM_BINS - 1)] += 1;
+        luma_sum += y;
+        count += 1;
+    }
+
+    let total = count.max(1) as f64;
+    buckets.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+    let dominant_colors = buckets.iter()
+        .take_while(|(n, _)| *n > 0)
+        .take(DOMINANT_COLORS)
+        .map(|(n, sums)| {
+            let [r, g, b] = sums.map(|sum| (sum / n) as u8);
+            DominantColor { hex: format!("#{:02x}{:02x}{:02x}", r, g, b), fraction: round(*n as f64 / total) }
+        })
+        .collect();
+
+    ColorStats {
+        average_brightness: round(luma_sum / total / 255.0),
+        dominant_colors,
+        histogram: histogram.iter().map(|&n| round(n as f64 / total)).collect(),
+    }
+}
+
+/// Round to 4 decimal places so output stays readable and stable
+fn round(value: f64) -> f64 {
+    (value * 10_000.0).round() / 10_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        // Three red pixels and one black one
+        let preview = Preview { width: 2, height: 2, rgb: vec![255, 0, 0, 255, 0, 0, 255, 0, 0, 0, 0, 0] };
+        let stats = analyze(&preview);
+        assert_eq!(stats.dominant_colors, [
+            DominantColor { hex: "#ff0000".to_string(), fraction: 0.75 },
+            DominantColor { hex: "#000000".to_string(), fraction: 0.25 },
+        ]);
+        assert_eq!(stats.histogram.len(), 16);
+        assert_eq!(stats.histogram[0], 0.25);
+        assert_eq!(stats.histogram[4], 0.75);
+        assert!((stats.average_brightness - 0.299 * 0.75).abs() < 1e-3);
+    }
+}
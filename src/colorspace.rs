@@ -0,0 +1,371 @@
+use crate::jpeg::{Segment, ICC_SIGNATURE};
+use exif::{Exif, In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+/// EXIF ColorSpace value for sRGB
+const EXIF_SRGB: u32 = 1;
+/// EXIF ColorSpace value for anything else, usually Adobe RGB
+const EXIF_UNCALIBRATED: u32 = 0xFFFF;
+/// Relative difference beyond which two resolutions disagree
+const RESOLUTION_TOLERANCE: f64 = 0.01;
+
+/// A color space as a file can declare it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpaceName {
+    Srgb,
+    AdobeRgb,
+    DisplayP3,
+    ProphotoRgb,
+    /// EXIF ColorSpace 0xFFFF with nothing saying which space is meant
+    Uncalibrated,
+    Other,
+}
+
+impl ColorSpaceName {
+    /// The space an ICC profile's description names, e.g. `sRGB IEC61966-2.1`
+    fn from_description(description: &str) -> Self {
+        let name = description.to_lowercase().replace([' ', '-', '_'], "");
+        if name.contains("srgb") {
+            ColorSpaceName::Srgb
+        } else if name.contains("adobergb") {
+            ColorSpaceName::AdobeRgb
+        } else if name.contains("p3") {
+            ColorSpaceName::DisplayP3
+        } else if name.contains("prophoto") || name.contains("romm") {
+            ColorSpaceName::ProphotoRgb
+        } else {
+            ColorSpaceName::Other
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ColorSpaceName::Srgb => "sRGB",
+            ColorSpaceName::AdobeRgb => "Adobe RGB",
+            ColorSpaceName::DisplayP3 => "Display P3",
+            ColorSpaceName::ProphotoRgb => "ProPhoto RGB",
+            ColorSpaceName::Uncalibrated => "uncalibrated",
+            ColorSpaceName::Other => "another color space",
+        }
+    }
+}
+
+/// Unit of a JFIF density
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DensityUnit {
+    /// The densities only give the pixel aspect ratio
+    None,
+    Dpi,
+    /// Dots per centimetre
+    Dpcm,
+}
+
+/// The JFIF APP0 header
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Jfif {
+    /// e.g. `1.02`
+    pub version: String,
+    pub density_unit: DensityUnit,
+    pub x_density: u16,
+    pub y_density: u16,
+}
+
+impl Jfif {
+    pub fn parse(segments: &[Segment]) -> Option<Self> {
+        let data = &segments.iter().find(|s| s.is_app(0, b"JFIF\0"))?.data;
+        let &[_, _, _, _, _, major, minor, unit, x1, x2, y1, y2, ..] = data.as_slice() else {
+            return None;
+        };
+        Some(Jfif {
+            version: format!("{}.{:02}", major, minor),
+            density_unit: match unit {
+                1 => DensityUnit::Dpi,
+                2 => DensityUnit::Dpcm,
+                _ => DensityUnit::None,
+            },
+            x_density: u16::from_be_bytes([x1, x2]),
+            y_density: u16::from_be_bytes([y1, y2]),
+        })
+    }
+
+    /// The densities in dots per inch, when they have a unit
+    fn dpi(&self) -> Option<(f64, f64)> {
+        let scale = match self.density_unit {
+            DensityUnit::Dpi => 1.0,
+            DensityUnit::Dpcm => 2.54,
+            DensityUnit::None => return None,
+        };
+        Some((self.x_density as f64 * scale, self.y_density as f64 * scale))
+    }
+}
+
+/// The EXIF tags that declare a color space or a print resolution
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExifColorTags {
+    pub color_space: Option<u32>,
+    /// InteroperabilityIndex, `R98` for sRGB or `R03` for Adobe RGB under DCF
+    pub interop_index: Option<String>,
+    /// XResolution and YResolution in dots per inch
+    pub resolution_dpi: Option<(f64, f64)>,
+}
+
+impl ExifColorTags {
+    pub fn from_exif(exif: &Exif) -> Self {
+        let uint = |tag| exif.get_field(tag, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+        let rational = |tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(values) => values.first().map(|r| r.to_f64()).filter(|n| n.is_finite() && *n > 0.0),
+            _ => None,
+        };
+        let scale = match uint(Tag::ResolutionUnit).unwrap_or(2) {
+            2 => Some(1.0),
+            3 => Some(2.54),
+            _ => None,
+        };
+        let interop_index = exif.get_field(Tag::InteroperabilityIndex, In::PRIMARY)
+            .and_then(|f| match &f.value {
+                Value::Ascii(parts) => parts.first().map(|p| String::from_utf8_lossy(p).trim().to_string()),
+                _ => None,
+            })
+            .filter(|index| !index.is_empty());
+        ExifColorTags {
+            color_space: uint(Tag::ColorSpace),
+            interop_index,
+            resolution_dpi: rational(Tag::XResolution).zip(rational(Tag::YResolution)).zip(scale).map(|((x, y), s)| (x * s, y * s)),
+        }
+    }
+
+    /// The space the EXIF tags declare, reading an uncalibrated ColorSpace with
+    /// interoperability index R03 as Adobe RGB, as DCF cameras write it
+    fn declared(&self) -> Option<ColorSpaceName> {
+        match (self.color_space?, self.interop_index.as_deref()) {
+            (EXIF_SRGB, _) => Some(ColorSpaceName::Srgb),
+            (EXIF_UNCALIBRATED, Some("R03")) => Some(ColorSpaceName::AdobeRgb),
+            (EXIF_UNCALIBRATED, _) => Some(ColorSpaceName::Uncalibrated),
+            _ => Some(ColorSpaceName::Other),
+        }
+    }
+}
+
+/// How a file tags its colors: the EXIF ColorSpace and interoperability index,
+/// the embedded ICC profile and the JFIF density, with every disagreement between
+/// them that would make a print lab or a viewer render or size it differently
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorSpace {
+    /// The space the file declares: its ICC profile's, or failing that its EXIF tags'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declared: Option<ColorSpaceName>,
+    /// `srgb`, `uncalibrated`, or the number of a reserved ColorSpace value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exif_color_space: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interop_index: Option<String>,
+    /// Description of the embedded ICC profile, e.g. `Adobe RGB (1998)`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icc_profile: Option<String>,
+    /// Gamma of the ICC profile's tone curves, when they are a plain power curve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icc_gamma: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jfif: Option<Jfif>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mismatches: Vec<String>,
+}
+
+impl ColorSpace {
+    /// Cross-check the EXIF tags with a JPEG's ICC profile and JFIF header.
+    /// `None` when the file tags none of them.
+    pub fn check(tags: &ExifColorTags, segments: &[Segment]) -> Option<Self> {
+        let profile = icc_profile(segments);
+        let icc_profile = profile.as_deref().and_then(icc_description);
+        let icc_space = icc_profile.as_deref().map(ColorSpaceName::from_description);
+        let jfif = Jfif::parse(segments);
+        if tags.color_space.is_none() && tags.interop_index.is_none() && profile.is_none() && jfif.is_none() {
+            return None;
+        }
+
+        let mut mismatches = Vec::new();
+        let exif_space = tags.declared();
+        match (exif_space, icc_space) {
+            (Some(exif), Some(icc)) if exif != icc && exif != ColorSpaceName::Uncalibrated => mismatches.push(format!(
+                "EXIF declares {} but the ICC profile is {}",
+                exif.label(), icc_profile.as_deref().unwrap_or_default(),
+            )),
+            (Some(ColorSpaceName::Uncalibrated), Some(ColorSpaceName::Srgb)) => mismatches.push(
+                "EXIF ColorSpace is uncalibrated but the ICC profile is sRGB".to_string(),
+            ),
+            (Some(ColorSpaceName::AdobeRgb), None) if profile.is_none() => mismatches.push(
+                "Adobe RGB is declared only by the EXIF interoperability index, without an ICC profile; most viewers will assume sRGB".to_string(),
+            ),
+            (Some(ColorSpaceName::Uncalibrated), None) if profile.is_none() => mismatches.push(
+                "EXIF ColorSpace is uncalibrated and there is no ICC profile; the intended color space is unknown".to_string(),
+            ),
+            _ => {}
+        }
+        match (tags.color_space, tags.interop_index.as_deref()) {
+            (Some(EXIF_SRGB), Some("R03")) => mismatches.push("EXIF interoperability index R03 (Adobe RGB) contradicts ColorSpace sRGB".to_string()),
+            (Some(EXIF_UNCALIBRATED), Some("R98")) => mismatches.push("EXIF interoperability index R98 (sRGB) contradicts ColorSpace uncalibrated".to_string()),
+            _ => {}
+        }
+        if let (Some((jx, jy)), Some((ex, ey))) = (jfif.as_ref().and_then(Jfif::dpi), tags.resolution_dpi) {
+            let differs = |a: f64, b: f64| (a - b).abs() > RESOLUTION_TOLERANCE * a.max(b);
+            if differs(jx, ex) || differs(jy, ey) {
+                mismatches.push(format!("JFIF density of {}x{} dpi differs from the EXIF resolution of {}x{} dpi", jx, jy, ex, ey));
+            }
+        }
+
+        Some(ColorSpace {
+            declared: icc_space.or(exif_space),
+            exif_color_space: tags.color_space.map(|value| match value {
+                EXIF_SRGB => "srgb".to_string(),
+                EXIF_UNCALIBRATED => "uncalibrated".to_string(),
+                other => other.to_string(),
+            }),
+            interop_index: tags.interop_index.clone(),
+            icc_profile,
+            icc_gamma: profile.as_deref().and_then(icc_gamma),
+            jfif,
+            mismatches,
+        })
+    }
+}
+
+/// The ICC profile of a JPEG, put back together from its APP2 chunks in sequence order
+pub fn icc_profile(segments: &[Segment]) -> Option<Vec<u8>> {
+    let mut chunks: Vec<(u8, &[u8])> = segments.iter()
+        .filter(|s| s.is_app(2, ICC_SIGNATURE))
+        .filter_map(|s| {
+            let rest = &s.data[ICC_SIGNATURE.len()..];
+            Some((*rest.first()?, rest.get(2..)?))
+        })
+        .collect();
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|&(sequence, _)| sequence);
+    Some(chunks.into_iter().flat_map(|(_, data)| data).copied().collect())
+}
+
+/// The data of a tag in an ICC profile's tag table
+fn icc_tag<'a>(profile: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let u32_at = |at: usize| profile.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let count = u32_at(128)?;
+    (0..count.min(256))
+        .map(|i| 132 + i * 12)
+        .find(|&entry| profile.get(entry..entry + 4) == Some(signature))
+        .and_then(|entry| {
+            let (offset, size) = (u32_at(entry + 4)?, u32_at(entry + 8)?);
+            profile.get(offset..offset.checked_add(size)?)
+        })
+}
+
+/// The profile description: a `desc` text in version 2 profiles, or the first
+/// `mluc` record in version 4
+fn icc_description(profile: &[u8]) -> Option<String> {
+    let tag = icc_tag(profile, b"desc")?;
+    let u32_at = |at: usize| tag.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let text = match tag.get(..4)? {
+        b"desc" => {
+            let len = u32_at(8)?;
+            String::from_utf8_lossy(tag.get(12..len.checked_add(12)?)?).trim_end_matches('\0').to_string()
+        }
+        b"mluc" => {
+            let (len, offset) = (u32_at(20)?, u32_at(24)?);
+            let units: Vec<u16> = tag.get(offset..offset.checked_add(len)?)?
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units).trim_end_matches('\0').to_string()
+        }
+        _ => return None,
+    };
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// The gamma of the red tone curve, when it is a single exponent: a `curv` with
+/// one entry or a `para` of function type 0. sRGB's curve, linear near black, is not.
+fn icc_gamma(profile: &[u8]) -> Option<f64> {
+    let curve = icc_tag(profile, b"rTRC")?;
+    let gamma = match curve.get(..4)? {
+        b"curv" => match curve.get(8..12)? {
+            [0, 0, 0, 0] => 1.0,
+            [0, 0, 0, 1] => u16::from_be_bytes([*curve.get(12)?, *curve.get(13)?]) as f64 / 256.0,
+            _ => return None,
+        },
+        b"para" if curve.get(8..10)? == [0, 0] => i32::from_be_bytes(curve.get(12..16)?.try_into().ok()?) as f64 / 65536.0,
+        _ => return None,
+    };
+    // Three decimals, as profiles store the exponent in 8 or 16 fractional bits
+    Some((gamma * 1000.0).round() / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 2 ICC profile with a `desc` tag and a plain gamma red tone curve,
+    /// split into `chunks` APP2 segments given out of order
+    fn icc_segments(description: &str, gamma: u16, chunks: usize) -> Vec<Segment> {
+        let mut desc = b"desc\0\0\0\0".to_vec();
+        desc.extend((description.len() as u32 + 1).to_be_bytes());
+        desc.extend(description.as_bytes());
+        desc.push(0);
+        let curv = [&b"curv\0\0\0\0\0\0\0\x01"[..], &gamma.to_be_bytes()].concat();
+        let mut profile = vec![0u8; 128];
+        profile.extend(2u32.to_be_bytes());
+        let desc_at = 128 + 4 + 2 * 12;
+        for (signature, at, data) in [(b"desc", desc_at, &desc), (b"rTRC", desc_at + desc.len(), &curv)] {
+            profile.extend(signature);
+            profile.extend((at as u32).to_be_bytes());
+            profile.extend((data.len() as u32).to_be_bytes());
+        }
+        profile.extend(&desc);
+        profile.extend(&curv);
+
+        let size = profile.len().div_ceil(chunks);
+        let mut segments: Vec<Segment> = profile.chunks(size).enumerate()
+            .map(|(i, chunk)| Segment { marker: 0xE2, data: [ICC_SIGNATURE, &[i as u8 + 1, chunks as u8], chunk].concat(), offset: None })
+            .collect();
+        segments.reverse();
+        segments
+    }
+
+    fn jfif(unit: u8, density: u16) -> Segment {
+        let data = [&b"JFIF\0\x01\x02"[..], &[unit], &density.to_be_bytes(), &density.to_be_bytes(), &[0, 0]].concat();
+        Segment { marker: 0xE0, data, offset: None }
+    }
+
+    #[test]
+    fn test_color_space() {
+        let adobe = icc_segments("Adobe RGB (1998)", 563, 3);
+        assert_eq!(icc_profile(&adobe).map(|p| icc_description(&p)), Some(Some("Adobe RGB (1998)".to_string())));
+
+        // A camera set to Adobe RGB, as DCF describes it
+        let dcf = ExifColorTags { color_space: Some(0xFFFF), interop_index: Some("R03".to_string()), resolution_dpi: Some((300.0, 300.0)) };
+        let mut segments = adobe;
+        segments.push(jfif(1, 300));
+        let checked = ColorSpace::check(&dcf, &segments).unwrap();
+        assert_eq!(checked.declared, Some(ColorSpaceName::AdobeRgb));
+        assert_eq!(checked.exif_color_space.as_deref(), Some("uncalibrated"));
+        assert_eq!(checked.icc_gamma, Some(2.199));
+        assert_eq!(checked.jfif.as_ref().map(|j| (j.version.as_str(), j.density_unit)), Some(("1.02", DensityUnit::Dpi)));
+        assert_eq!(checked.mismatches, Vec::<String>::new());
+
+        // sRGB in EXIF but an Adobe RGB profile, and 72 dpi in JFIF (28 dots per cm)
+        let srgb = ExifColorTags { color_space: Some(1), ..dcf.clone() };
+        let mut segments = icc_segments("Adobe RGB (1998)", 563, 1);
+        segments.push(jfif(2, 28));
+        let checked = ColorSpace::check(&srgb, &segments).unwrap();
+        assert_eq!(checked.declared, Some(ColorSpaceName::AdobeRgb));
+        assert_eq!(checked.mismatches.len(), 3, "{:?}", checked.mismatches);
+        assert!(checked.mismatches[0].starts_with("EXIF declares sRGB but the ICC profile is Adobe RGB"));
+
+        // Adobe RGB in EXIF alone is invisible to most viewers
+        let checked = ColorSpace::check(&ExifColorTags { resolution_dpi: None, ..dcf }, &[]).unwrap();
+        assert_eq!(checked.declared, Some(ColorSpaceName::AdobeRgb));
+        assert!(checked.mismatches[0].contains("without an ICC profile"));
+
+        assert_eq!(ColorSpace::check(&ExifColorTags::default(), &[]), None);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::detect::ImageFormat;
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// How a value is printed by exiftool, which scripts parsing `exiftool -json` expect
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Conversion {
+    /// Copied unchanged
+    Plain,
+    /// `2.3 MB`
+    FileSize,
+    /// `2024:05:10 14:03:22+00:00`
+    FileDate,
+    /// `2024:05:10 14:03:22`
+    ExifDate,
+    /// `-rw-r--r--` from an octal mode
+    Permissions,
+    /// `Horizontal (normal)` from an Orientation number
+    Orientation,
+    /// First letter capitalised, as in `Auto`
+    Capitalized,
+    /// `Daylight` from `daylight`
+    LightSource,
+    /// `35.0 mm`
+    Millimetres,
+    /// `5 m`
+    Metres,
+    /// `10.88 m`
+    MetresPrecise,
+    /// `0.029 mm`
+    CircleOfConfusion,
+    /// `+120.10`, as DJI writes its XMP values
+    Signed,
+}
+
+/// Our field paths (as JSON pointers) and the exiftool tag reporting the same value
+const TAGS: &[(&str, &str, Conversion)] = &[
+    ("/filename", "FileName", Conversion::Plain),
+    ("/size", "FileSize", Conversion::FileSize),
+    ("/modified_time", "FileModifyDate", Conversion::FileDate),
+    ("/created_time", "FileCreateDate", Conversion::FileDate),
+    ("/mode", "FilePermissions", Conversion::Permissions),
+    ("/width", "ImageWidth", Conversion::Plain),
+    ("/height", "ImageHeight", Conversion::Plain),
+    ("/orientation", "Orientation", Conversion::Orientation),
+    ("/capture_time", "DateTimeOriginal", Conversion::ExifDate),
+    ("/camera_model", "Model", Conversion::Plain),
+    ("/camera_serial", "SerialNumber", Conversion::Plain),
+    ("/camera_firmware", "FirmwareVersion", Conversion::Plain),
+    ("/image_unique_id", "ImageUniqueID", Conversion::Plain),
+    ("/sequence_number", "ImageNumber", Conversion::Plain),
+    ("/shutter_count", "ShutterCount", Conversion::Plain),
+    ("/description/image_description", "ImageDescription", Conversion::Plain),
+    ("/description/user_comment", "UserComment", Conversion::Plain),
+    ("/white_balance/mode", "WhiteBalance", Conversion::Capitalized),
+    ("/white_balance/light_source", "LightSource", Conversion::LightSource),
+    ("/white_balance/color_temperature", "ColorTemperature", Conversion::Plain),
+    ("/focus/focal_length", "FocalLength", Conversion::Millimetres),
+    ("/focus/aperture", "FNumber", Conversion::Plain),
+    ("/focus/subject_distance", "SubjectDistance", Conversion::Metres),
+    ("/focus/circle_of_confusion", "CircleOfConfusion", Conversion::CircleOfConfusion),
+    ("/focus/hyperfocal_distance", "HyperfocalDistance", Conversion::MetresPrecise),
+    ("/enrichment/crop_factor", "ScaleFactor35efl", Conversion::Plain),
+    ("/drone/relative_altitude", "RelativeAltitude", Conversion::Signed),
+    ("/drone/absolute_altitude", "AbsoluteAltitude", Conversion::Signed),
+    ("/drone/gimbal_pitch", "GimbalPitchDegree", Conversion::Signed),
+    ("/drone/gimbal_yaw", "GimbalYawDegree", Conversion::Signed),
+    ("/drone/gimbal_roll", "GimbalRollDegree", Conversion::Signed),
+    ("/drone/flight_pitch", "FlightPitchDegree", Conversion::Signed),
+    ("/drone/flight_yaw", "FlightYawDegree", Conversion::Signed),
+    ("/drone/flight_roll", "FlightRollDegree", Conversion::Signed),
+    ("/drone/flight_speed_x", "FlightXSpeed", Conversion::Signed),
+    ("/drone/flight_speed_y", "FlightYSpeed", Conversion::Signed),
+    ("/drone/flight_speed_z", "FlightZSpeed", Conversion::Signed),
+];
+
+/// exiftool's Flash descriptions by tag value
+const FLASH: &[(u32, &str)] = &[
+    (0x00, "No Flash"),
+    (0x01, "Fired"),
+    (0x05, "Fired, Return not detected"),
+    (0x07, "Fired, Return detected"),
+    (0x08, "On, Did not fire"),
+    (0x09, "On, Fired"),
+    (0x0D, "On, Return not detected"),
+    (0x0F, "On, Return detected"),
+    (0x10, "Off, Did not fire"),
+    (0x14, "Off, Did not fire, Return not detected"),
+    (0x18, "Auto, Did not fire"),
+    (0x19, "Auto, Fired"),
+    (0x1D, "Auto, Fired, Return not detected"),
+    (0x1F, "Auto, Fired, Return detected"),
+    (0x20, "No flash function"),
+    (0x30, "Off, No flash function"),
+    (0x41, "Fired, Red-eye reduction"),
+    (0x45, "Fired, Red-eye reduction, Return not detected"),
+    (0x47, "Fired, Red-eye reduction, Return detected"),
+    (0x49, "On, Red-eye reduction"),
+    (0x4D, "On, Red-eye reduction, Return not detected"),
+    (0x4F, "On, Red-eye reduction, Return detected"),
+    (0x50, "Off, Red-eye reduction"),
+    (0x58, "Auto, Did not fire, Red-eye reduction"),
+    (0x59, "Auto, Fired, Red-eye reduction"),
+    (0x5D, "Auto, Fired, Red-eye reduction, Return not detected"),
+    (0x5F, "Auto, Fired, Red-eye reduction, Return detected"),
+];
+
+/// Rewrite one serialized metadata record (with RFC 3339 timestamps) as the
+/// object `exiftool -json` prints for `source_file`. Fields exiftool has no
+/// equivalent for are left out.
+pub fn exiftool_object(metadata: &Value, source_file: &str) -> Value {
+    let mut out = Map::new();
+    out.insert("SourceFile".into(), source_file.into());
+    for &(pointer, tag, conversion) in TAGS {
+        if let Some(value) = metadata.pointer(pointer).filter(|v| !v.is_null()) {
+            out.insert(tag.into(), convert(value, conversion).unwrap_or_else(|| value.clone()));
+        }
+        // exiftool groups the file type right after the file tags
+        if tag == "FilePermissions" {
+            let format = metadata.get("format").and_then(|f| ImageFormat::deserialize(f).ok());
+            if let Some((file_type, extension, mime_type)) = format.and_then(file_type) {
+                out.insert("FileType".into(), file_type.into());
+                out.insert("FileTypeExtension".into(), extension.into());
+                out.insert("MIMEType".into(), mime_type.into());
+            }
+        }
+    }
+    if let Some(flash) = metadata.get("flash").and_then(flash_description) {
+        out.insert("Flash".into(), flash.into());
+    }
+    let (width, height) = (metadata["width"].as_u64(), metadata["height"].as_u64());
+    if let (Some(width), Some(height)) = (width, height) {
+        out.insert("ImageSize".into(), format!("{}x{}", width, height).into());
+        out.insert("Megapixels".into(), (((width * height) as f64 / 100_000.0).round() / 10.0).into());
+    }
+    if let Some(dof) = metadata.pointer("/focus/depth_of_field") {
+        let near = dof["near"].as_f64().unwrap_or(0.0);
+        let description = match (dof["far"].as_f64(), dof["total"].as_f64()) {
+            (Some(far), Some(total)) => format!("{:.2} m ({:.2} - {:.2} m)", total, near, far),
+            _ => format!("inf ({:.2} m - inf)", near),
+        };
+        out.insert("DOF".into(), description.into());
+    }
+    let focal_length = metadata.pointer("/focus/focal_length").and_then(Value::as_f64);
+    let focal_length_35mm = metadata.pointer("/enrichment/focal_length_35mm").and_then(Value::as_f64);
+    if let (Some(focal_length), Some(equivalent)) = (focal_length, focal_length_35mm) {
+        let description = format!("{:.1} mm (35 mm equivalent: {:.1} mm)", focal_length, equivalent);
+        out.insert("FocalLength35efl".into(), description.into());
+    }
+    Value::Object(out)
+}
+
+fn convert(value: &Value, conversion: Conversion) -> Option<Value> {
+    let text = match conversion {
+        Conversion::Plain => return None,
+        Conversion::FileSize => file_size(value.as_u64()?),
+        Conversion::FileDate => DateTime::parse_from_rfc3339(value.as_str()?).ok()?
+            .format("%Y:%m:%d %H:%M:%S%:z").to_string(),
+        Conversion::ExifDate => DateTime::parse_from_rfc3339(value.as_str()?).ok()?
+            .format("%Y:%m:%d %H:%M:%S").to_string(),
+        Conversion::Permissions => permissions(u32::from_str_radix(value.as_str()?, 8).ok()?),
+        Conversion::Orientation => orientation(value.as_u64()?)?.to_string(),
+        Conversion::Capitalized => {
+            let text = value.as_str()?;
+            let mut chars = text.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+        }
+        Conversion::LightSource => light_source(value.as_str()?),
+        Conversion::Millimetres => format!("{:.1} mm", value.as_f64()?),
+        Conversion::Metres => format!("{} m", value.as_f64()?),
+        Conversion::MetresPrecise => format!("{:.2} m", value.as_f64()?),
+        Conversion::CircleOfConfusion => format!("{:.3} mm", value.as_f64()?),
+        Conversion::Signed => format!("{:+.2}", value.as_f64()?),
+    };
+    Some(text.into())
+}
+
+/// exiftool's binary-prefixed file size
+fn file_size(bytes: u64) -> String {
+    let (kib, mib) = (bytes as f64 / 1024.0, bytes as f64 / 1_048_576.0);
+    match bytes {
+        0..=2047 => format!("{} bytes", bytes),
+        2048..=10_239 => format!("{:.1} kB", kib),
+        10_240..=2_097_151 => format!("{:.0} kB", kib),
+        2_097_152..=10_485_759 => format!("{:.1} MB", mib),
+        _ => format!("{:.0} MB", mib),
+    }
+}
+
+/// `ls -l` style permissions, as exiftool prints FilePermissions
+fn permissions(mode: u32) -> String {
+    let bits = (0..9).rev().map(|i| {
+        let set = mode & (1 << i) != 0;
+        match (set, i % 3) {
+            (false, _) => '-',
+            (true, 2) => 'r',
+            (true, 1) => 'w',
+            (true, _) => 'x',
+        }
+    });
+    std::iter::once('-').chain(bits).collect()
+}
+
+fn orientation(value: u64) -> Option<&'static str> {
+    Some(match value {
+        1 => "Horizontal (normal)",
+        2 => "Mirror horizontal",
+        3 => "Rotate 180",
+        4 => "Mirror vertical",
+        5 => "Mirror horizontal and rotate 270 CW",
+        6 => "Rotate 90 CW",
+        7 => "Mirror horizontal and rotate 90 CW",
+        8 => "Rotate 270 CW",
+        _ => return None,
+    })
+}
+
+/// exiftool's LightSource names, from ours
+fn light_source(name: &str) -> String {
+    match name {
+        "tungsten" => "Tungsten (Incandescent)".to_string(),
+        "iso_studio_tungsten" => "ISO Studio Tungsten".to_string(),
+        "d50" | "d55" | "d65" | "d75" => name.to_uppercase(),
+        "standard_light_a" | "standard_light_b" | "standard_light_c" => {
+            format!("Standard Light {}", name[name.len() - 1..].to_uppercase())
+        }
+        _ => name.split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// Reassemble the Flash tag bits from our structured form and describe them
+fn flash_description(flash: &Value) -> Option<String> {
+    let flag = |name: &str| flash[name].as_bool();
+    let return_bits = match flash["return"].as_str()? {
+        "no_detection" => 0,
+        "reserved" => 1,
+        "not_detected" => 2,
+        _ => 3,
+    };
+    let mode_bits = match flash["mode"].as_str()? {
+        "unknown" => 0,
+        "compulsory_firing" => 1,
+        "compulsory_suppression" => 2,
+        _ => 3,
+    };
+    let bits = u32::from(flag("fired")?)
+        | return_bits << 1
+        | mode_bits << 3
+        | u32::from(!flag("function_present")?) << 5
+        | u32::from(flag("red_eye_reduction")?) << 6;
+    Some(FLASH.iter()
+        .find(|&&(value, _)| value == bits)
+        .map(|&(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("Unknown (0x{:x})", bits)))
+}
+
+/// exiftool's FileType, FileTypeExtension and MIMEType for a detected format
+fn file_type(format: ImageFormat) -> Option<(&'static str, &'static str, &'static str)> {
+    Some(match format {
+        ImageFormat::Jfif | ImageFormat::ExifJpeg | ImageFormat::Jpeg => ("JPEG", "jpg", "image/jpeg"),
+        ImageFormat::Jpeg2000 => ("JP2", "jp2", "image/jp2"),
+        ImageFormat::Tiff => ("TIFF", "tif", "image/tiff"),
+        ImageFormat::WebP => ("WEBP", "webp", "image/webp"),
+        ImageFormat::JpegXl => ("JXL", "jxl", "image/jxl"),
+        ImageFormat::Heic => ("HEIC", "heic", "image/heic"),
+        ImageFormat::Avif => ("AVIF", "avif", "image/avif"),
+        ImageFormat::Unknown => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_exiftool_object() {
+        let metadata = json!({
+            "filename": "IMG_0001.jpg",
+            "format": "exif_jpeg",
+            "size": 2444055,
+            "modified_time": "2024-05-10T14:03:22Z",
+            "mode": "0644",
+            "width": 6720,
+            "height": 4480,
+            "orientation": 6,
+            "capture_time": "2024-05-10T14:03:21.250Z",
+            "camera_model": "Canon EOS 5D Mark IV",
+            "flash": {"fired": false, "return": "no_detection", "mode": "compulsory_suppression",
+                      "function_present": true, "red_eye_reduction": false},
+            "white_balance": {"mode": "auto", "light_source": "tungsten"},
+            "focus": {"focal_length": 35.0, "aperture": 1.4, "depth_of_field": {"near": 3.432, "far": 9.206, "total": 5.774}},
+            "regions": [],
+        });
+        let out = exiftool_object(&metadata, "photos/IMG_0001.jpg");
+        let keys: Vec<&String> = out.as_object().unwrap().keys().take(5).collect();
+        assert_eq!(keys, ["SourceFile", "FileName", "FileSize", "FileModifyDate", "FilePermissions"]);
+        assert_eq!(out["FileSize"], "2.3 MB");
+        assert_eq!(out["FileModifyDate"], "2024:05:10 14:03:22+00:00");
+        assert_eq!(out["FilePermissions"], "-rw-r--r--");
+        assert_eq!(out["FileType"], "JPEG");
+        assert_eq!(out["MIMEType"], "image/jpeg");
+        assert_eq!(out["Orientation"], "Rotate 90 CW");
+        assert_eq!(out["DateTimeOriginal"], "2024:05:10 14:03:21");
+        assert_eq!(out["Flash"], "Off, Did not fire");
+        assert_eq!(out["WhiteBalance"], "Auto");
+        assert_eq!(out["LightSource"], "Tungsten (Incandescent)");
+        assert_eq!(out["FocalLength"], "35.0 mm");
+        assert_eq!(out["FNumber"], 1.4);
+        assert_eq!(out["ImageSize"], "6720x4480");
+        assert_eq!(out["Megapixels"], 30.1);
+        assert_eq!(out["DOF"], "5.77 m (3.43 - 9.21 m)");
+        assert!(out.get("regions").is_none());
+
+        let out = exiftool_object(&json!({"filename": "IMG_0002.heic", "format": "heic"}), "IMG_0002.heic");
+        assert_eq!(out["FileType"], "HEIC");
+        assert_eq!(out["FileTypeExtension"], "heic");
+        assert_eq!(out["MIMEType"], "image/heic");
+    }
+
+    #[test]
+    fn test_file_size() {
+        assert_eq!(file_size(512), "512 bytes");
+        assert_eq!(file_size(5000), "4.9 kB");
+        assert_eq!(file_size(500_000), "488 kB");
+        assert_eq!(file_size(50_000_000), "48 MB");
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tally of records checked against the `--require` fields
+#[derive(Debug, Default)]
+pub struct Compliance {
+    required: Vec<String>,
+    checked: usize,
+    failing: usize,
+    /// Records missing each required field
+    missing: BTreeMap<String, usize>,
+}
+
+impl Compliance {
+    pub fn new(required: Vec<String>) -> Self {
+        Compliance { required, ..Compliance::default() }
+    }
+
+    /// Check a flat record, returning the required fields it lacks
+    pub fn check(&mut self, record: &Value) -> Vec<String> {
+        let missing: Vec<String> = self.required.iter()
+            .filter(|field| !is_present(record, field))
+            .cloned()
+            .collect();
+        self.checked += 1;
+        if !missing.is_empty() {
+            self.failing += 1;
+        }
+        for field in &missing {
+            *self.missing.entry(field.clone()).or_default() += 1;
+        }
+        missing
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.failing > 0
+    }
+
+    /// How many records passed, and how often each field was missing
+    pub fn summary(&self) -> String {
+        let mut out = format!("Compliance: {} of {} files have all required fields\n", self.checked - self.failing, self.checked);
+        for field in &self.required {
+            let missing = self.missing.get(field).copied().unwrap_or(0);
+            let _ = writeln!(out, "  {}: missing in {}", field, missing);
+        }
+        out
+    }
+}
+
+/// The value at a dotted `path`, e.g. `gps.latitude`
+fn lookup<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(record, |value, key| value.get(key))
+}
+
+/// Whether the dotted `path` (e.g. `gps` or `description.user_comment`) holds
+/// a value other than null or an empty string, array or object
+fn is_present(record: &Value, path: &str) -> bool {
+    match lookup(record, path) {
+        None | Some(Value::Null) => false,
+        Some(Value::String(s)) => !s.trim().is_empty(),
+        Some(Value::Array(items)) => !items.is_empty(),
+        Some(Value::Object(fields)) => !fields.is_empty(),
+        Some(_) => true,
+    }
+}
+
+/// Rules `audit` checks each record against, by flat field name as for --require
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    required: Vec<String>,
+    forbidden: Vec<String>,
+    /// The only values each field may hold, when present
+    allowed_values: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    required: Vec<String>,
+    #[serde(default)]
+    forbidden: Vec<String>,
+    #[serde(default)]
+    allowed_values: BTreeMap<String, AllowedValues>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AllowedValues {
+    List(Vec<String>),
+    /// A file of one value per line, relative to the policy; blank lines and `#` comments are skipped
+    File { file: PathBuf },
+}
+
+/// Why a record breaks a policy, as a stable code for tooling
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationCode {
+    MissingRequired,
+    ForbiddenPresent,
+    ValueNotAllowed,
+}
+
+/// One way a record breaks a policy
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Violation {
+    pub code: ViolationCode,
+    pub field: String,
+    /// The offending value, for `value_not_allowed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let code = serde_json::to_value(self.code).ok().and_then(|code| code.as_str().map(str::to_string)).unwrap_or_default();
+        match &self.value {
+            Some(value) => write!(f, "{}: {} = {}", code, self.field, value),
+            None => write!(f, "{}: {}", code, self.field),
+        }
+    }
+}
+
+impl Policy {
+    /// Read a policy file such as:
+    ///
+    /// ```toml
+    /// required = ["copyright", "artist"]
+    /// forbidden = ["gps", "description.user_comment"]
+    ///
+    /// [allowed_values]
+    /// camera_model = ["Canon EOS 5D Mark IV", "X-T5"]
+    /// camera_serial = { file = "fleet.txt" }
+    /// ```
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Policy::parse(&toml, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Parse a policy, reading value lists named in it from `dir`
+    pub fn parse(toml: &str, dir: &Path) -> Result<Self> {
+        let file: PolicyFile = toml::from_str(toml)?;
+        let allowed_values = file.allowed_values.into_iter()
+            .map(|(field, allowed)| {
+                let values = match allowed {
+                    AllowedValues::List(values) => values,
+                    AllowedValues::File { file } => {
+                        let path = dir.join(file);
+                        fs::read_to_string(&path)
+                            .with_context(|| format!("In allowed_values.{}: failed to read {}", field, path.display()))?
+                            .lines()
+                            .map(str::trim)
+                            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                            .map(str::to_string)
+                            .collect()
+                    }
+                };
+                Ok((field, values))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Policy { required: file.required, forbidden: file.forbidden, allowed_values })
+    }
+
+    /// Every rule a flat record breaks, in the order the policy lists them
+    pub fn check(&self, record: &Value) -> Vec<Violation> {
+        let violation = |code, field: &String, value| Violation { code, field: field.clone(), value };
+        let missing = self.required.iter()
+            .filter(|field| !is_present(record, field))
+            .map(|field| violation(ViolationCode::MissingRequired, field, None));
+        let forbidden = self.forbidden.iter()
+            .filter(|field| is_present(record, field))
+            .map(|field| violation(ViolationCode::ForbiddenPresent, field, None));
+        let disallowed = self.allowed_values.iter()
+            .filter_map(|(field, allowed)| {
+                let value = match lookup(record, field)? {
+                    Value::Null => return None,
+                    Value::String(s) => s.trim().to_string(),
+                    other => other.to_string(),
+                };
+                (!allowed.contains(&value)).then(|| violation(ViolationCode::ValueNotAllowed, field, Some(value)))
+            });
+        missing.chain(forbidden).chain(disallowed).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check() {
+        let mut compliance = Compliance::new(vec!["capture_time".into(), "gps".into(), "description.user_comment".into()]);
+        let record = json!({"capture_time": "2024-05-10T14:15:00Z", "gps": {"latitude": 1.0, "longitude": 2.0}, "description": {"user_comment": "hi"}});
+        assert!(compliance.check(&record).is_empty());
+        let record = json!({"capture_time": "2024-05-10T14:15:00Z", "description": {"user_comment": " "}});
+        assert_eq!(compliance.check(&record), ["gps", "description.user_comment"]);
+        assert!(compliance.has_failures());
+        assert_eq!(compliance.summary(), "Compliance: 1 of 2 files have all required fields\n  capture_time: missing in 0\n  gps: missing in 1\n  description.user_comment: missing in 1\n");
+    }
+
+    #[test]
+    fn test_policy() {
+        let dir = std::env::temp_dir().join(format!("jme-policy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("fleet.txt"), "# studio bodies\n025021000535\n\n4501234\n").unwrap();
+        let toml = "required = [\"copyright\"]\nforbidden = [\"gps\"]\n[allowed_values]\ncamera_serial = { file = \"fleet.txt\" }\norientation = [\"1\"]\n";
+        let policy = Policy::parse(toml, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let record = json!({"copyright": "Studio", "camera_serial": "025021000535", "orientation": 1});
+        assert!(policy.check(&record).is_empty());
+        let record = json!({"gps": {"latitude": 1.0}, "camera_serial": "999", "orientation": 6});
+        let violations = policy.check(&record);
+        assert_eq!(violations.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "missing_required: copyright",
+            "forbidden_present: gps",
+            "value_not_allowed: camera_serial = 999",
+            "value_not_allowed: orientation = 6",
+        ]);
+        assert_eq!(serde_json::to_value(&violations[2]).unwrap(), json!({"code": "value_not_allowed", "field": "camera_serial", "value": "999"}));
+
+        assert!(Policy::parse("forbiden = []", &dir).is_err());
+        assert!(format!("{:#}", Policy::parse("[allowed_values]\nx = { file = \"missing.txt\" }", &dir).unwrap_err()).contains("allowed_values.x"));
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+
+/// Compression applied to sidecars and combined output with --gzip or --zstd
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Extension appended to compressed files, as in `IMG_0001.json.gz`
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::encode_all(bytes, 0)?),
+        }
+    }
+
+    /// A writer compressing into `writer`, ended with [`Output::finish`]
+    pub fn writer<W: Write + 'static>(self, writer: W) -> Result<Output> {
+        let writer: Box<dyn Write> = Box::new(writer);
+        Ok(Output(match self {
+            Compression::Gzip => Stream::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            Compression::Zstd => Stream::Zstd(zstd::Encoder::new(writer, 0)?),
+        }))
+    }
+}
+
+/// A stream written as is or through --gzip or --zstd compression. It must be
+/// ended with [`Output::finish`], which unlike dropping it reports a failure to
+/// write the end of the compressed stream.
+pub struct Output(Stream);
+
+enum Stream {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+}
+
+impl Output {
+    pub fn plain(writer: impl Write + 'static) -> Self {
+        Output(Stream::Plain(Box::new(writer)))
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let mut writer = match self.0 {
+            Stream::Plain(writer) => writer,
+            Stream::Gzip(encoder) => encoder.finish()?,
+            Stream::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(writer.flush()?)
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match &mut self.0 {
+            Stream::Plain(writer) => writer,
+            Stream::Gzip(encoder) => encoder,
+            Stream::Zstd(encoder) => encoder,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+/// `path` with the extension of `compression` appended, if any
+pub fn with_extension(path: &Path, compression: Option<Compression>) -> PathBuf {
+    let Some(compression) = compression else {
+        return path.to_path_buf();
+    };
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(compression.extension());
+    PathBuf::from(name)
+}
+
+/// Whether `path` names a JSON file, compressed or not
+pub fn is_json(path: &Path) -> bool {
+    let path = match Compression::of(path) {
+        Some(_) => path.with_extension(""),
+        None => path.to_path_buf(),
+    };
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Undo gzip or zstd compression, recognised by its magic bytes; other data is returned as is
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    if bytes.starts_with(GZIP_MAGIC) {
+        MultiGzDecoder::new(bytes.as_slice()).read_to_end(&mut out).context("Invalid gzip data")?;
+    } else if bytes.starts_with(ZSTD_MAGIC) {
+        out = zstd::decode_all(bytes.as_slice()).context("Invalid zstd data")?;
+    } else {
+        return Ok(bytes);
+    }
+    Ok(out)
+}
+
+/// Read a text file that may be compressed
+pub fn read_to_string(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let bytes = decompress(bytes).with_context(|| format!("Failed to decompress {}", path.display()))?;
+    String::from_utf8(bytes).with_context(|| format!("{} is not UTF-8 text", path.display()))
+}
+
+/// Whichever of `path`, `path.gz` and `path.zst` exists, or the most recently
+/// modified of them when a run with another compression left more than one
+pub fn find(path: &Path) -> Option<PathBuf> {
+    [None, Some(Compression::Gzip), Some(Compression::Zstd)].into_iter()
+        .map(|compression| with_extension(path, compression))
+        .filter_map(|candidate| Some((fs::metadata(&candidate).ok()?.modified().ok(), candidate)))
+        .reduce(|newest, candidate| if candidate.0 > newest.0 { candidate } else { newest })
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let json = br#"{"filename": "IMG_0001.jpg"}"#.repeat(20);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(&json).unwrap();
+            assert!(compressed.len() < json.len());
+            assert_eq!(decompress(compressed).unwrap(), json);
+        }
+        assert_eq!(decompress(json.clone()).unwrap(), json);
+
+        let path = with_extension(Path::new("shoot/IMG_0001.json"), Some(Compression::Zstd));
+        assert_eq!(path, Path::new("shoot/IMG_0001.json.zst"));
+        assert!(is_json(&path));
+        assert!(is_json(Path::new("IMG_0001.JSON")));
+        assert!(!is_json(Path::new("IMG_0001.xmp.gz")));
+    }
+
+    #[test]
+    fn test_output() {
+        let path = std::env::temp_dir().join(format!("jme-output-{}.json.zst", std::process::id()));
+        let mut output = Compression::Zstd.writer(fs::File::create(&path).unwrap()).unwrap();
+        output.write_all(b"{}\n").unwrap();
+        output.finish().unwrap();
+        let written = read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written.unwrap(), "{}\n");
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = std::env::temp_dir().join(format!("jme-find-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("IMG_0001.json");
+        assert_eq!(find(&path), None);
+        fs::write(dir.join("IMG_0001.json.zst"), "").unwrap();
+        assert_eq!(find(&path), Some(dir.join("IMG_0001.json.zst")));
+        // The newer of two sidecars wins, whatever its compression
+        let older = fs::File::create(&path).unwrap();
+        older.set_modified(std::time::SystemTime::UNIX_EPOCH).unwrap();
+        let found = find(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found, Some(dir.join("IMG_0001.json.zst")));
+    }
+}
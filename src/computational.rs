@@ -0,0 +1,246 @@
+use crate::jpeg::{self, Segment};
+use crate::xmp;
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+
+/// Google camera XMP namespace (`GCamera:`), which marks motion photos
+pub const GCAMERA_NS: &str = "http://ns.google.com/photos/1.0/camera/";
+/// Google container XMP namespace (`Container:`), listing files appended to the JPEG
+pub const CONTAINER_NS: &str = "http://ns.google.com/photos/1.0/container/";
+/// Fields of a `Container:Item`
+pub const ITEM_NS: &str = "http://ns.google.com/photos/1.0/container/item/";
+/// Google depth map XMP namespace (`GDepth:`)
+pub const GDEPTH_NS: &str = "http://ns.google.com/photos/1.0/depthmap/";
+/// Adobe/ISO gain map XMP namespace (`hdrgm:`), used by Ultra HDR
+pub const HDRGM_NS: &str = "http://ns.adobe.com/hdr-gain-map/1.0/";
+/// Apple auxiliary image XMP namespace (`apdi:`)
+pub const APDI_NS: &str = "http://ns.apple.com/pixeldatainfo/1.0/";
+
+/// Signature of a Multi-Picture Format APP2 segment
+pub const MPF_SIGNATURE: &[u8] = b"MPF\0";
+const MP_ENTRY_TAG: u16 = 0xB002;
+/// MP type codes of large thumbnails, which are previews rather than auxiliary images
+const MP_LARGE_THUMBNAILS: [u32; 2] = [0x010001, 0x010002];
+const MP_DISPARITY: u32 = 0x020002;
+
+/// What the camera app embedded besides the photo itself
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ComputationalMetadata {
+    /// An HDR gain map, Ultra HDR or Apple
+    pub has_gain_map: bool,
+    pub has_depth_map: bool,
+    /// An Apple portrait effects or segmentation matte
+    pub has_portrait_matte: bool,
+    /// A Google motion photo or legacy micro video
+    pub is_motion_photo: bool,
+    /// File offset where the motion photo's video starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_offset: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_length: Option<u64>,
+    /// Timestamp of the video frame matching the still, in microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presentation_timestamp_us: Option<i64>,
+    /// Types of the auxiliary images in the MPF index, e.g.
+    /// `urn:com:apple:photo:2018:aux:portraiteffectsmatte`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auxiliary_images: Vec<String>,
+}
+
+/// A secondary image from the MPF index
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MpEntry {
+    /// MP type code, e.g. 0x020002 for a disparity image
+    pub image_type: u32,
+    /// File offset of the image's SOI marker
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Secondary images that may be auxiliary images, skipping the first image and previews
+pub fn auxiliary_entries(segments: &[Segment]) -> Vec<MpEntry> {
+    let Some(index) = segments.iter().position(|s| s.is_app(2, MPF_SIGNATURE)) else {
+        return Vec::new();
+    };
+    // MPF offsets count from the TIFF header after the signature
+    let base = jpeg::segment_offset(segments, index) + 4 + MPF_SIGNATURE.len() as u64;
+    mp_entries(&segments[index].data[MPF_SIGNATURE.len()..])
+        .into_iter()
+        .filter(|(image_type, _, offset)| *offset != 0 && !MP_LARGE_THUMBNAILS.contains(image_type))
+        .map(|(image_type, size, offset)| MpEntry { image_type, offset: base + offset as u64, size: size as u64 })
+        .collect()
+}
+
+/// (type, size, offset) of each MP entry in an MPF TIFF block
+fn mp_entries(tiff: &[u8]) -> Vec<(u32, u32, u32)> {
+    let big_endian = match tiff.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return Vec::new(),
+    };
+    let u16_at = |at: usize| tiff.get(at..at + 2).map(|b| if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) });
+    let u32_at = |at: usize| tiff.get(at..at + 4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }
+    });
+    let Some(ifd) = u32_at(4).map(|o| o as usize) else {
+        return Vec::new();
+    };
+    let count = u16_at(ifd).unwrap_or(0) as usize;
+    let Some(entry) = (0..count).map(|i| ifd + 2 + i * 12).find(|&at| u16_at(at) == Some(MP_ENTRY_TAG)) else {
+        return Vec::new();
+    };
+    let (Some(len), Some(start)) = (u32_at(entry + 4), u32_at(entry + 8)) else {
+        return Vec::new();
+    };
+    (0..len as usize / 16)
+        .map(|i| start as usize + i * 16)
+        .map_while(|at| Some((u32_at(at)? & 0x00FF_FFFF, u32_at(at + 4)?, u32_at(at + 8)?)))
+        .collect()
+}
+
+/// Type of each auxiliary image found in `data`, which holds the file from `data_offset` on.
+/// Images outside `data` or without a known type are left out.
+pub fn auxiliary_types(entries: &[MpEntry], data: &[u8], data_offset: u64) -> Vec<String> {
+    entries.iter().filter_map(|entry| {
+        let start = usize::try_from(entry.offset.checked_sub(data_offset)?).ok()?;
+        let image = data.get(start..)?;
+        let image = &image[..image.len().min(entry.size as usize)];
+        let header = jpeg::read_header(&mut &image[..]).ok()?;
+        let packet = xmp::packet(&header.segments);
+        let doc = packet.as_deref().and_then(xmp::parse);
+        doc.as_ref()
+            .and_then(|doc| xmp::property(doc, APDI_NS, "AuxiliaryImageType"))
+            .or_else(|| doc.as_ref().and_then(|doc| xmp::property(doc, HDRGM_NS, "Version")).map(|_| "hdrgm".to_string()))
+            .or_else(|| (entry.image_type == MP_DISPARITY).then(|| "disparity".to_string()))
+    }).collect()
+}
+
+/// Flags from the primary XMP and the auxiliary image types, if the file has any of them
+pub fn from_image(doc: Option<&Document>, auxiliary_images: Vec<String>, size: u64) -> Option<ComputationalMetadata> {
+    let property = |namespace, name| doc.and_then(|doc| xmp::property(doc, namespace, name));
+    let items: Vec<_> = doc.map(container_items).unwrap_or_default();
+    let item = |semantic: &str| items.iter().find(|(s, _)| s == semantic);
+
+    let motion_item = item("MotionPhoto");
+    let is_motion_photo = motion_item.is_some()
+        || ["MotionPhoto", "MicroVideo"].into_iter().any(|name| property(GCAMERA_NS, name).as_deref() == Some("1"));
+    // The video is the last file in the container; legacy micro video gives its offset from the end
+    let video_length = motion_item
+        .and_then(|(_, length)| *length)
+        .or_else(|| property(GCAMERA_NS, "MicroVideoOffset").and_then(|v| v.parse().ok()));
+    let video_offset = video_length.filter(|_| is_motion_photo).and_then(|length| size.checked_sub(length));
+
+    let aux = |matches: fn(&str) -> bool| auxiliary_images.iter().any(|t| matches(&t.to_ascii_lowercase()));
+    let metadata = ComputationalMetadata {
+        has_gain_map: property(HDRGM_NS, "Version").is_some() || item("GainMap").is_some()
+            || aux(|t| t == "hdrgm" || t.ends_with(":hdrgainmap")),
+        has_depth_map: property(GDEPTH_NS, "Format").is_some() || item("Depth").is_some()
+            || aux(|t| t.contains("depth") || t.contains("disparity")),
+        has_portrait_matte: aux(|t| t.ends_with("matte")),
+        is_motion_photo,
+        video_offset,
+        video_length: video_length.filter(|_| is_motion_photo),
+        presentation_timestamp_us: property(GCAMERA_NS, "MotionPhotoPresentationTimestampUs")
+            .or_else(|| property(GCAMERA_NS, "MicroVideoPresentationTimestampUs"))
+            .and_then(|v| v.parse().ok())
+            .filter(|_| is_motion_photo),
+        auxiliary_images,
+    };
+    (metadata != ComputationalMetadata::default()).then_some(metadata)
+}
+
+/// (semantic, length) of each `Container:Directory` item
+fn container_items(doc: &Document) -> Vec<(String, Option<u64>)> {
+    let Some(directory) = doc.descendants().find(|node| node.has_tag_name((CONTAINER_NS, "Directory"))) else {
+        return Vec::new();
+    };
+    xmp::list_items(directory)
+        .filter_map(|li| xmp::child(li, CONTAINER_NS, "Item"))
+        .filter_map(|item| {
+            let length = xmp::field(item, ITEM_NS, "Length").and_then(|v| v.parse().ok());
+            Some((xmp::field(item, ITEM_NS, "Semantic")?, length))
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use super::*;
+    use crate::content::{extract_content, extract_from_bytes, ExtractOptions};
+    use crate::fixture::{generate, FixtureSpec};
+    use std::io::Cursor;
+
+    /// `jpeg` with `segments` (marker, payload) inserted after SOI
+    fn with_segments(jpeg: &[u8], segments: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut out = jpeg[..2].to_vec();
+        for (marker, data) in segments {
+            out.extend([0xFF, *marker]);
+            out.extend((data.len() as u16 + 2).to_be_bytes());
+            out.extend(data);
+        }
+        out.extend(&jpeg[2..]);
+        out
+    }
+
+    fn xmp_segment(body: &str) -> (u8, Vec<u8>) {
+        let packet = format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">{}</rdf:RDF></x:xmpmeta>"#,
+            body
+        );
+        (0xE1, [jpeg::XMP_SIGNATURE, packet.as_bytes()].concat())
+    }
+
+    /// Big-endian MPF block with the primary image and one secondary image `offset` bytes after the TIFF header
+    fn mpf_segment(primary_size: u32, secondary: (u32, u32, u32)) -> (u8, Vec<u8>) {
+        let mut data = MPF_SIGNATURE.to_vec();
+        data.extend(b"MM\0\x2a\0\0\0\x08");
+        data.extend(1u16.to_be_bytes());
+        data.extend(MP_ENTRY_TAG.to_be_bytes());
+        data.extend(7u16.to_be_bytes());
+        data.extend(32u32.to_be_bytes());
+        data.extend(26u32.to_be_bytes());
+        data.extend(0u32.to_be_bytes());
+        for (image_type, size, offset) in [(0x030000, primary_size, 0), secondary] {
+            data.extend(image_type.to_be_bytes());
+            data.extend(size.to_be_bytes());
+            data.extend(offset.to_be_bytes());
+            data.extend([0; 4]);
+        }
+        (0xE2, data)
+    }
+
+    #[test]
+    fn test_motion_photo_and_apple_matte() {
+        let plain = generate(&FixtureSpec::default()).unwrap();
+        let motion = xmp_segment(r#"<rdf:Description xmlns:GCamera="http://ns.google.com/photos/1.0/camera/"
+            xmlns:Container="http://ns.google.com/photos/1.0/container/" xmlns:Item="http://ns.google.com/photos/1.0/container/item/"
+            GCamera:MotionPhoto="1" GCamera:MotionPhotoPresentationTimestampUs="500000"><Container:Directory><rdf:Seq>
+            <rdf:li rdf:parseType="Resource"><Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary"/></rdf:li>
+            <rdf:li rdf:parseType="Resource"><Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="1000"/></rdf:li>
+            </rdf:Seq></Container:Directory></rdf:Description>"#);
+        let mut bytes = with_segments(&plain, &[motion]);
+        bytes.extend([0u8; 1000]);
+        let computational = extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap().computational.unwrap();
+        assert!(computational.is_motion_photo && !computational.has_gain_map);
+        assert_eq!(computational.video_offset, Some(bytes.len() as u64 - 1000));
+        assert_eq!(computational.presentation_timestamp_us, Some(500000));
+
+        let matte = with_segments(&plain, &[xmp_segment(
+            r#"<rdf:Description xmlns:apdi="http://ns.apple.com/pixeldatainfo/1.0/" apdi:AuxiliaryImageType="urn:com:apple:photo:2018:aux:portraiteffectsmatte"/>"#,
+        )]);
+        // The MPF segment's own length is fixed, so the secondary image's offset is known up front
+        let mpf_len = mpf_segment(0, (0, 0, 0)).1.len() as u64 + 4;
+        let primary_size = (plain.len() as u64 + mpf_len) as u32;
+        let tiff_start = 2 + 4 + MPF_SIGNATURE.len() as u32;
+        let mpf = mpf_segment(primary_size, (0, matte.len() as u32, primary_size - tiff_start));
+        let mut bytes = with_segments(&plain, &[mpf]);
+        bytes.extend(&matte);
+        let content = extract_content(&mut Cursor::new(&bytes), bytes.len() as u64, &ExtractOptions::default()).unwrap();
+        let computational = content.computational.unwrap();
+        assert!(computational.has_portrait_matte && !computational.is_motion_photo);
+        assert_eq!(computational.auxiliary_images, ["urn:com:apple:photo:2018:aux:portraiteffectsmatte"]);
+
+        assert_eq!(extract_from_bytes(&plain, &ExtractOptions::default()).unwrap().computational, None);
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{bail, Context, Result};
+use chrono::Duration;
+use jpeg_metadata_extractor::template::Template;
+use jpeg_metadata_extractor::timeshift;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Settings read from a `--config` TOML file
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Output fields computed from each record, as (name, template), under `derived`
+    pub derived: Vec<(String, Template)>,
+    /// How far to move the capture time of each camera, by serial number, to
+    /// correct a drifting clock
+    pub clock_offsets: BTreeMap<String, Duration>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    derived: BTreeMap<String, String>,
+    #[serde(default)]
+    clock_offsets: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Parse a config file such as:
+    ///
+    /// ```toml
+    /// [derived]
+    /// shoot_id = "{capture_time:%Y%m%d}-{camera_serial|hash8}"
+    ///
+    /// [clock_offsets]
+    /// 025021000535 = "-00:02:13"
+    /// ```
+    pub fn parse(toml: &str) -> Result<Self> {
+        let file: ConfigFile = toml::from_str(toml)?;
+        let derived = file.derived.into_iter()
+            .map(|(name, text)| {
+                let template = Template::parse(&text).with_context(|| format!("In derived.{}", name))?;
+                Ok((name, template))
+            })
+            .collect::<Result<_>>()?;
+        let clock_offsets = file.clock_offsets.into_iter()
+            .map(|(serial, text)| {
+                let offset = parse_clock_offset(&text).with_context(|| format!("In clock_offsets.{}", serial))?;
+                Ok((serial, offset))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Config { derived, clock_offsets })
+    }
+
+    /// The correction for a camera's clock, if one is configured for its serial number
+    pub fn clock_offset(&self, serial: &str) -> Option<Duration> {
+        self.clock_offsets.get(serial.trim()).copied()
+    }
+
+    /// Evaluate the derived fields against a flat record; fields whose template
+    /// refers to a missing value are left out
+    pub fn derive(&self, record: &serde_json::Value) -> BTreeMap<String, String> {
+        self.derived.iter()
+            .filter_map(|(name, template)| Some((name.clone(), template.render(record)?)))
+            .collect()
+    }
+}
+
+/// Parse a clock offset written as `[+-]HH:MM:SS`, or like `--offset` of `timeshift`, e.g. `-2m13s`
+fn parse_clock_offset(text: &str) -> Result<Duration> {
+    let (sign, clock) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if !clock.contains(':') {
+        return Ok(timeshift::parse_offset(text)?);
+    }
+    let parts = clock.split(':').map(|part| part.parse::<u32>().ok().filter(|_| part.len() == 2)).collect::<Option<Vec<_>>>();
+    let (hours, minutes, seconds) = match parts.as_deref() {
+        Some(&[hours, minutes, seconds]) if minutes < 60 && seconds < 60 => (hours, minutes, seconds),
+        _ => bail!("expected an offset such as -00:02:13, not '{}'", text),
+    };
+    Ok(Duration::seconds((hours * 3600 + minutes * 60 + seconds) as i64) * sign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_fields() {
+        let config = Config::parse("[derived]\nshoot = \"{capture_time:%Y-%m}/{camera_model|slug}\"\nlens = \"{lens_model}\"\n").unwrap();
+        let record = serde_json::json!({"capture_time": "2024-05-10T14:15:00Z", "camera_model": "X-T5"});
+        assert_eq!(config.derive(&record), BTreeMap::from([("shoot".to_string(), "2024-05/x-t5".to_string())]));
+
+        assert!(Config::parse("[derived]\nbad = \"{a|nope}\"\n").unwrap_err().to_string().contains("derived.bad"));
+        assert!(Config::parse("[derivd]\n").is_err());
+    }
+
+    #[test]
+    fn test_clock_offsets() {
+        let config = Config::parse("[clock_offsets]\n025021000535 = \"-00:02:13\"\nA7 = \"+1h\"\nB = \"01:00:05\"\n").unwrap();
+        assert_eq!(config.clock_offset("025021000535"), Some(-Duration::seconds(133)));
+        assert_eq!(config.clock_offset("A7"), Some(Duration::hours(1)));
+        assert_eq!(config.clock_offset("B"), Some(Duration::seconds(3605)));
+        assert_eq!(config.clock_offset("other"), None);
+
+        for bad in ["-2:13", "00:61:00", "soon"] {
+            let error = Config::parse(&format!("[clock_offsets]\nX = \"{}\"\n", bad)).unwrap_err();
+            assert!(format!("{:#}", error).contains("clock_offsets.X"), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,820 @@
+use crate::detect::ImageFormat;
+use crate::error::{ExtractError, Result};
+use crate::jpeg::{self, PayloadBreakdown, Segment, EXIF_SIGNATURE, ICC_SIGNATURE, XMP_SIGNATURE};
+use crate::raw::{self, Page, TiffData};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// JPEG XL codestream signature
+pub const JXL_CODESTREAM: &[u8] = &[0xFF, 0x0A];
+/// Signature box starting a JPEG XL container
+pub const JXL_CONTAINER: &[u8] = &[0x00, 0x00, 0x00, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A];
+
+/// Width to height ratios a JPEG XL size header can give instead of a width
+const JXL_RATIOS: [(u64, u64); 7] = [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)];
+
+/// Brands of HEIF files coded with AV1 rather than HEVC
+pub const AVIF_BRANDS: [&[u8]; 2] = [b"avif", b"avis"];
+/// Brands of HEIF files, including the generic `mif1` and `msf1` that AVIF files may also use
+pub const HEIF_BRANDS: [&[u8]; 8] = [b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1"];
+/// Content type of an XMP item
+const XMP_CONTENT_TYPE: &str = "application/rdf+xml";
+
+/// Metadata blocks and dimensions read from a WebP, JPEG XL, HEIC, AVIF or TIFF file
+#[derive(Debug, Default)]
+pub struct Container {
+    pub dimensions: Option<(u32, u32)>,
+    /// TIFF data of the EXIF block, from its byte order mark on
+    pub exif: Option<Vec<u8>>,
+    /// The XMP packet
+    pub xmp: Option<Vec<u8>>,
+    /// The ICC profile of the primary image, from a HEIF `colr` property
+    pub icc: Option<Vec<u8>>,
+    /// Bytes attributed to each kind of payload, counting chunk and box headers as `other`
+    pub payload_breakdown: PayloadBreakdown,
+    /// Every page of a TIFF file, the first being the one `dimensions` and `exif` describe
+    pub pages: Vec<Page>,
+    pub warnings: Vec<String>,
+}
+
+impl Container {
+    /// The EXIF and XMP blocks as the APP1 segments a JPEG holds them in, and the
+    /// ICC profile as a single APP2 chunk, so they are read by the same code and
+    /// reported with the same schema
+    pub fn segments(&self) -> Vec<Segment> {
+        let exif = self.exif.as_ref().map(|tiff| Segment { marker: 0xE1, data: [EXIF_SIGNATURE, tiff].concat(), offset: None });
+        let xmp = self.xmp.as_ref().map(|packet| Segment { marker: 0xE1, data: [XMP_SIGNATURE, packet].concat(), offset: None });
+        let icc = self.icc.as_ref().map(|profile| Segment { marker: 0xE2, data: [ICC_SIGNATURE, &[1, 1], profile].concat(), offset: None });
+        exif.into_iter().chain(xmp).chain(icc).collect()
+    }
+}
+
+/// Bytes of zeros a TIFF's EXIF block may hold where image data lies between its
+/// IFDs and values; past this the fields are copied into a block of their own
+const MAX_EXIF_GAP: usize = 1 << 20;
+/// Bytes read from the start of a JPEG XL codestream, enough for its size header
+const CODESTREAM_HEAD: u64 = 32;
+/// Bytes read from the start of a WebP frame, enough for its dimensions
+const FRAME_HEAD: u64 = 10;
+
+/// A file read piece by piece at the offsets its boxes, chunks or IFDs give, so
+/// that the metadata is read and the image data is not. Every byte read counts
+/// against the metadata size limit.
+struct Source<'a, R> {
+    reader: &'a mut R,
+    /// Position of the start of the file in the reader
+    base: u64,
+    len: u64,
+    max_size: Option<u64>,
+    read: u64,
+}
+
+impl<'a, R: Read + Seek> Source<'a, R> {
+    /// The file from the reader's position to its end
+    fn new(reader: &'a mut R, max_size: Option<u64>) -> Result<Self> {
+        let base = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?.saturating_sub(base);
+        Ok(Source { reader, base, len, max_size, read: 0 })
+    }
+
+    /// `len` bytes at `offset`, or fewer where the file ends first
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let len = len.min(self.len.saturating_sub(offset));
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        self.read = self.read.saturating_add(len);
+        if let Some(max) = self.max_size.filter(|&max| self.read > max) {
+            return Err(jpeg::LimitExceeded::MetadataSize(max).into());
+        }
+        self.reader.seek(SeekFrom::Start(self.base + offset))?;
+        let mut bytes = Vec::new();
+        self.reader.by_ref().take(len).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// Read a WebP, JPEG XL, HEIC, AVIF or TIFF file from the reader's position on,
+/// reading no more than `max_size` bytes of it
+pub fn read<R: Read + Seek>(format: ImageFormat, reader: &mut R, max_size: Option<u64>) -> Result<Container> {
+    let mut source = Source::new(reader, max_size)?;
+    match format {
+        ImageFormat::WebP => read_webp(&mut source),
+        ImageFormat::JpegXl => read_jxl(&mut source),
+        ImageFormat::Heic | ImageFormat::Avif => read_heif(&mut source),
+        ImageFormat::Tiff => read_tiff(&mut source),
+        _ => Err(ExtractError::Unsupported(format!("image format {}", format))),
+    }
+}
+
+/// Read a TIFF file, or a TIFF-based RAW file, and list all the pages in its
+/// chain of IFDs. Only the IFDs and the values they point to are read. Where
+/// they lie close together, as in a RAW file, they are the EXIF block in place;
+/// otherwise, and for a BigTIFF, they are copied into one. The fields reported
+/// are those of the first page, as with a JPEG, and `pages` describes the rest.
+fn read_tiff<R: Read + Seek>(source: &mut Source<R>) -> Result<Container> {
+    if !raw::is_tiff(&source.read_at(0, 4)?) {
+        return Err(ExtractError::NotAnImage("not a TIFF file".to_string()));
+    }
+    let data = raw::Sparse::read(source.len, |offset, len| source.read_at(offset, len as u64))?;
+    let (pages, image_data) = raw::pages(&data);
+    let mut warnings = Vec::new();
+    if pages.len() == raw::MAX_PAGES {
+        warnings.push(format!("Only the first {} pages are listed", raw::MAX_PAGES));
+    }
+    let in_place = data.get(0..4).filter(|header| !raw::is_bigtiff(header)).and_then(|_| data.dense(MAX_EXIF_GAP));
+    let exif = in_place.or_else(|| raw::classic_exif(&data));
+    if exif.is_none() {
+        warnings.push("Could not read the EXIF fields of this TIFF file".to_string());
+    }
+    let xmp = raw::xmp_packet(&data).map(<[u8]>::to_vec);
+    let xmp_len = xmp.as_ref().map_or(0, |packet| packet.len() as u64);
+    let payload_breakdown = PayloadBreakdown {
+        // IFDs and their values, which in a TIFF file are all EXIF
+        exif: source.len.saturating_sub(image_data + xmp_len),
+        xmp: xmp_len,
+        image_data,
+        ..Default::default()
+    };
+    Ok(Container {
+        dimensions: pages.first().and_then(|page| page.width.zip(page.height)),
+        exif,
+        xmp,
+        icc: None,
+        payload_breakdown,
+        pages,
+        warnings,
+    })
+}
+
+/// Read the chunks of a WebP file: `EXIF` and `XMP ` for metadata, and `VP8X`,
+/// `VP8 ` or `VP8L` for the dimensions
+fn read_webp<R: Read + Seek>(source: &mut Source<R>) -> Result<Container> {
+    let header = source.read_at(0, 12)?;
+    if header.len() < 12 || &header[..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err(ExtractError::NotAnImage("not a WebP file".to_string()));
+    }
+    let mut container = Container { payload_breakdown: PayloadBreakdown { other: 12, ..Default::default() }, ..Default::default() };
+    let mut pos = 12;
+    while pos + 8 <= source.len {
+        let chunk = source.read_at(pos, 8)?;
+        let fourcc = &chunk[..4];
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        let start = pos + 8;
+        let end = start.saturating_add(size).min(source.len);
+        if end - start < size {
+            container.warnings.push(format!("{} chunk is cut short", String::from_utf8_lossy(fourcc).trim_end()));
+        }
+        // Chunks are padded to an even length
+        let next = end.saturating_add(size & 1).min(source.len);
+        let total = next - pos;
+        match fourcc {
+            b"VP8X" => {
+                let payload = source.read_at(start, FRAME_HEAD.min(end - start))?;
+                if payload.len() >= 10 {
+                    let u24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]) + 1;
+                    container.dimensions = Some((u24(&payload[4..7]), u24(&payload[7..10])));
+                }
+                container.payload_breakdown.other += total;
+            }
+            b"VP8 " | b"VP8L" | b"ALPH" | b"ANMF" => {
+                if container.dimensions.is_none() && matches!(fourcc, b"VP8 " | b"VP8L") {
+                    let payload = source.read_at(start, FRAME_HEAD.min(end - start))?;
+                    container.dimensions = webp_frame_dimensions(fourcc, &payload);
+                }
+                container.payload_breakdown.image_data += total;
+            }
+            b"EXIF" => {
+                if container.exif.is_none() {
+                    let payload = source.read_at(start, end - start)?;
+                    // Some writers keep the JPEG APP1 signature
+                    let tiff = payload.strip_prefix(EXIF_SIGNATURE).unwrap_or(&payload);
+                    container.exif = Some(tiff.to_vec());
+                }
+                container.payload_breakdown.exif += total;
+            }
+            b"XMP " => {
+                if container.xmp.is_none() {
+                    container.xmp = Some(source.read_at(start, end - start)?);
+                }
+                container.payload_breakdown.xmp += total;
+            }
+            b"ICCP" => container.payload_breakdown.icc += total,
+            _ => container.payload_breakdown.other += total,
+        }
+        pos = next;
+    }
+    Ok(container)
+}
+
+/// Dimensions from the frame header of a simple lossy or lossless WebP
+fn webp_frame_dimensions(fourcc: &[u8], payload: &[u8]) -> Option<(u32, u32)> {
+    match fourcc {
+        b"VP8 " if payload.get(3..6)? == [0x9D, 0x01, 0x2A] => {
+            let size = |at: usize| u16::from_le_bytes([payload[at], payload[at + 1]]) as u32 & 0x3FFF;
+            payload.get(6..10).map(|_| (size(6), size(8)))
+        }
+        b"VP8L" if payload.first() == Some(&0x2F) => {
+            let bits = u32::from_le_bytes(payload.get(1..5)?.try_into().ok()?);
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        _ => None,
+    }
+}
+
+/// Read a JPEG XL file: a bare codestream, which holds no EXIF or XMP, or a
+/// container whose `Exif` and `xml ` boxes do
+fn read_jxl<R: Read + Seek>(source: &mut Source<R>) -> Result<Container> {
+    let head = source.read_at(0, CODESTREAM_HEAD)?;
+    if head.starts_with(JXL_CODESTREAM) {
+        return Ok(Container {
+            dimensions: jxl_dimensions(&head),
+            payload_breakdown: PayloadBreakdown { image_data: source.len, ..Default::default() },
+            ..Default::default()
+        });
+    }
+    if !head.starts_with(JXL_CONTAINER) {
+        return Err(ExtractError::NotAnImage("not a JPEG XL file".to_string()));
+    }
+    let mut container = Container::default();
+    let len = source.len;
+    for bmff_box in box_headers(len, |pos| source.read_at(pos, 16), &mut container.warnings)? {
+        let total = bmff_box.total;
+        match &bmff_box.box_type {
+            b"jxlc" | b"jxlp" => {
+                if container.dimensions.is_none() {
+                    // Partial codestream boxes start with a sequence number
+                    let skip = if &bmff_box.box_type == b"jxlp" { 4 } else { 0 };
+                    let head = source.read_at(bmff_box.start, (skip + CODESTREAM_HEAD).min(bmff_box.len))?;
+                    let codestream = head.get(skip as usize..).unwrap_or_default();
+                    if codestream.starts_with(JXL_CODESTREAM) {
+                        container.dimensions = jxl_dimensions(codestream);
+                    }
+                }
+                container.payload_breakdown.image_data += total;
+            }
+            b"Exif" => {
+                if container.exif.is_none() {
+                    match exif_tiff(&source.read_at(bmff_box.start, bmff_box.len)?) {
+                        Some(tiff) => container.exif = Some(tiff.to_vec()),
+                        None => container.warnings.push("Ignored an Exif box with a bad TIFF header offset".to_string()),
+                    }
+                }
+                container.payload_breakdown.exif += total;
+            }
+            b"xml " => {
+                if container.xmp.is_none() {
+                    container.xmp = Some(source.read_at(bmff_box.start, bmff_box.len)?);
+                }
+                container.payload_breakdown.xmp += total;
+            }
+            b"brob" => {
+                let inner = source.read_at(bmff_box.start, 4.min(bmff_box.len))?;
+                container.warnings.push(format!("Skipped a Brotli-compressed {} box", String::from_utf8_lossy(&inner).trim_end()));
+                container.payload_breakdown.other += total;
+            }
+            _ => container.payload_breakdown.other += total,
+        }
+    }
+    Ok(container)
+}
+
+/// TIFF data of an ISO-BMFF Exif box or item, which starts with a four-byte
+/// offset to the TIFF header
+fn exif_tiff(payload: &[u8]) -> Option<&[u8]> {
+    let offset = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?) as usize;
+    payload.get(4usize.checked_add(offset)?..)
+}
+
+/// Read a HEIF file, as HEIC and AVIF files are: the `meta` box lists items,
+/// of which the primary one is the image and an `Exif` item and a `mime` item
+/// of type `application/rdf+xml` hold the metadata, and locates their data.
+/// Only the `meta` box and the metadata items are read.
+/// Metadata items are counted in the payload breakdown rather than the box holding them.
+fn read_heif<R: Read + Seek>(source: &mut Source<R>) -> Result<Container> {
+    if source.read_at(4, 4)? != b"ftyp" {
+        return Err(ExtractError::NotAnImage("not a HEIF file".to_string()));
+    }
+    let mut container = Container::default();
+    let mut meta = None;
+    let len = source.len;
+    for bmff_box in box_headers(len, |pos| source.read_at(pos, 16), &mut container.warnings)? {
+        match &bmff_box.box_type {
+            b"mdat" => container.payload_breakdown.image_data += bmff_box.total,
+            b"meta" => {
+                if meta.is_none() {
+                    meta = Some(source.read_at(bmff_box.start, bmff_box.len)?);
+                }
+                container.payload_breakdown.other += bmff_box.total;
+            }
+            _ => container.payload_breakdown.other += bmff_box.total,
+        }
+    }
+    let Some(meta) = meta else {
+        container.warnings.push("No meta box found".to_string());
+        return Ok(container);
+    };
+    let items = HeifItems::parse(meta.get(4..).unwrap_or_default(), &mut container.warnings);
+
+    container.dimensions = items.primary.and_then(|id| items.dimensions(id));
+    container.icc = items.icc().map(<[u8]>::to_vec);
+    let breakdown = &mut container.payload_breakdown;
+    breakdown.icc = items.icc_len;
+    breakdown.other -= items.icc_len.min(breakdown.other);
+    let exif = items.find(|item| &item.item_type == b"Exif");
+    let xmp = items.find(|item| &item.item_type == b"mime" && item.content_type.as_deref() == Some(XMP_CONTENT_TYPE));
+    for (id, is_exif) in exif.map(|id| (id, true)).into_iter().chain(xmp.map(|id| (id, false))) {
+        let Some((bytes, in_file)) = items.data(id, source)? else {
+            container.warnings.push(format!("Ignored item {} whose data lies outside the file", id));
+            continue;
+        };
+        // Moved out of the mdat or idat box that holds it
+        let len = bytes.len() as u64;
+        let breakdown = &mut container.payload_breakdown;
+        let holder = if in_file { &mut breakdown.image_data } else { &mut breakdown.other };
+        *holder -= len.min(*holder);
+        if is_exif {
+            breakdown.exif += len;
+            match exif_tiff(&bytes) {
+                Some(tiff) => container.exif = Some(tiff.to_vec()),
+                None => container.warnings.push("Ignored an Exif item with a bad TIFF header offset".to_string()),
+            }
+        } else {
+            breakdown.xmp += len;
+            container.xmp = Some(bytes);
+        }
+    }
+    Ok(container)
+}
+
+/// An entry of a HEIF item information box
+struct HeifItem {
+    id: u32,
+    item_type: [u8; 4],
+    /// MIME type of `mime` items
+    content_type: Option<String>,
+}
+
+/// Where an item's data is: in the file, or in the `meta` box's `idat` box
+struct ItemLocation {
+    in_idat: bool,
+    /// Offsets and lengths; a length of 0 runs to the end
+    extents: Vec<(u64, u64)>,
+}
+
+/// The item boxes of a HEIF `meta` box
+#[derive(Default)]
+struct HeifItems<'a> {
+    primary: Option<u32>,
+    items: Vec<HeifItem>,
+    locations: BTreeMap<u32, ItemLocation>,
+    idat: &'a [u8],
+    /// Item properties, numbered from 1 in the order of the `ipco` box
+    properties: Vec<BmffBox<'a>>,
+    /// Property numbers associated with each item
+    associations: BTreeMap<u32, Vec<usize>>,
+    /// Bytes of `colr` properties holding an ICC profile
+    icc_len: u64,
+}
+
+impl<'a> HeifItems<'a> {
+    fn parse(meta: &'a [u8], warnings: &mut Vec<String>) -> Self {
+        let mut items = HeifItems::default();
+        for bmff_box in read_boxes(meta, warnings) {
+            let payload = bmff_box.payload;
+            let parsed = match &bmff_box.box_type {
+                b"pitm" => parse_pitm(payload).map(|id| items.primary = Some(id)),
+                b"iinf" => parse_iinf(payload, warnings).map(|entries| items.items = entries),
+                b"iloc" => parse_iloc(payload).map(|locations| items.locations = locations),
+                b"idat" => {
+                    items.idat = payload;
+                    Some(())
+                }
+                b"iprp" => {
+                    for child in read_boxes(payload, warnings) {
+                        match &child.box_type {
+                            b"ipco" => items.properties = read_boxes(child.payload, warnings),
+                            b"ipma" => items.associations.extend(parse_ipma(child.payload).unwrap_or_default()),
+                            _ => {}
+                        }
+                    }
+                    Some(())
+                }
+                _ => Some(()),
+            };
+            if parsed.is_none() {
+                let name = String::from_utf8_lossy(&bmff_box.box_type).into_owned();
+                warnings.push(format!("Ignored a malformed {} box", name));
+            }
+        }
+        items.icc_len = items.properties.iter()
+            .filter(|p| &p.box_type == b"colr" && matches!(p.payload.get(..4), Some(b"prof" | b"rICC")))
+            .map(|p| p.total)
+            .sum();
+        items
+    }
+
+    fn find(&self, predicate: impl Fn(&HeifItem) -> bool) -> Option<u32> {
+        self.items.iter().find(|item| predicate(item)).map(|item| item.id)
+    }
+
+    /// Width and height from the item's `ispe` property
+    fn dimensions(&self, id: u32) -> Option<(u32, u32)> {
+        let ispe = self.associations.get(&id)?.iter()
+            .filter_map(|&index| self.properties.get(index.checked_sub(1)?))
+            .find(|p| &p.box_type == b"ispe")?;
+        let mut reader = BeReader { data: ispe.payload, pos: 4 };
+        Some((reader.uint(4)? as u32, reader.uint(4)? as u32))
+    }
+
+    /// The primary item's ICC profile, from a `colr` property of type `prof` or
+    /// `rICC`; any such property when the file names no primary item
+    fn icc(&self) -> Option<&[u8]> {
+        let is_icc = |p: &&BmffBox| &p.box_type == b"colr" && matches!(p.payload.get(..4), Some(b"prof" | b"rICC"));
+        let colr = match self.primary {
+            Some(id) => self.associations.get(&id)?.iter()
+                .filter_map(|&index| self.properties.get(index.checked_sub(1)?))
+                .find(is_icc)?,
+            None => self.properties.iter().find(is_icc)?,
+        };
+        colr.payload.get(4..)
+    }
+
+    /// The item's data, and whether it lies in the file rather than the `idat` box;
+    /// `None` if any of it lies outside
+    fn data<R: Read + Seek>(&self, id: u32, source: &mut Source<R>) -> Result<Option<(Vec<u8>, bool)>> {
+        let Some(location) = self.locations.get(&id) else {
+            return Ok(None);
+        };
+        let mut bytes = Vec::new();
+        for &(offset, length) in &location.extents {
+            let within = if location.in_idat { self.idat.len() as u64 } else { source.len };
+            let length = match length {
+                0 => within.saturating_sub(offset),
+                length => length,
+            };
+            if offset.checked_add(length).is_none_or(|end| end > within) {
+                return Ok(None);
+            }
+            if location.in_idat {
+                bytes.extend_from_slice(&self.idat[offset as usize..(offset + length) as usize]);
+            } else {
+                bytes.extend(source.read_at(offset, length)?);
+            }
+        }
+        Ok(Some((bytes, !location.in_idat)))
+    }
+}
+
+/// Reads big-endian fields of ISO-BMFF boxes
+struct BeReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BeReader<'_> {
+    /// An unsigned integer of `bytes` bytes; 0 bytes read as 0, as `iloc` fields may be
+    fn uint(&mut self, bytes: usize) -> Option<u64> {
+        let field = self.data.get(self.pos..self.pos.checked_add(bytes)?)?;
+        self.pos += bytes;
+        Some(field.iter().fold(0, |value, &b| value << 8 | b as u64))
+    }
+
+    /// Version and flags of a full box
+    fn full_box(&mut self) -> Option<(u8, u32)> {
+        Some((self.uint(1)? as u8, self.uint(3)? as u32))
+    }
+
+    fn four_cc(&mut self) -> Option<[u8; 4]> {
+        let field = self.data.get(self.pos..self.pos + 4)?.try_into().ok()?;
+        self.pos += 4;
+        Some(field)
+    }
+
+    fn c_string(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        self.pos += (len + 1).min(rest.len());
+        Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+}
+
+fn parse_pitm(payload: &[u8]) -> Option<u32> {
+    let mut reader = BeReader { data: payload, pos: 0 };
+    let (version, _) = reader.full_box()?;
+    Some(reader.uint(if version == 0 { 2 } else { 4 })? as u32)
+}
+
+fn parse_iinf(payload: &[u8], warnings: &mut Vec<String>) -> Option<Vec<HeifItem>> {
+    let mut reader = BeReader { data: payload, pos: 0 };
+    let (version, _) = reader.full_box()?;
+    reader.uint(if version == 0 { 2 } else { 4 })?;
+    let entries = read_boxes(&payload[reader.pos..], warnings).into_iter()
+        .filter(|b| &b.box_type == b"infe")
+        .filter_map(|infe| {
+            let mut reader = BeReader { data: infe.payload, pos: 0 };
+            // Versions 0 and 1 predate item types and are not used by HEIF
+            let (version, _) = reader.full_box().filter(|&(version, _)| version >= 2)?;
+            let id = reader.uint(if version == 2 { 2 } else { 4 })? as u32;
+            reader.uint(2)?;
+            let item_type = reader.four_cc()?;
+            let content_type = (&item_type == b"mime").then(|| reader.c_string()).flatten();
+            Some(HeifItem { id, item_type, content_type })
+        })
+        .collect();
+    Some(entries)
+}
+
+fn parse_iloc(payload: &[u8]) -> Option<BTreeMap<u32, ItemLocation>> {
+    let mut reader = BeReader { data: payload, pos: 0 };
+    let (version, _) = reader.full_box()?;
+    let sizes = reader.uint(1)?;
+    let (offset_size, length_size) = ((sizes >> 4) as usize, (sizes & 0x0F) as usize);
+    let sizes = reader.uint(1)?;
+    let base_offset_size = (sizes >> 4) as usize;
+    let index_size = if version >= 1 { (sizes & 0x0F) as usize } else { 0 };
+    let wide = if version >= 2 { 4 } else { 2 };
+    let count = reader.uint(wide)?;
+    let mut locations = BTreeMap::new();
+    for _ in 0..count {
+        let id = reader.uint(wide)? as u32;
+        let method = if version >= 1 { reader.uint(2)? & 0x0F } else { 0 };
+        reader.uint(2)?;
+        let base = reader.uint(base_offset_size)?;
+        let extent_count = reader.uint(2)?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            reader.uint(index_size)?;
+            let offset = reader.uint(offset_size)?;
+            extents.push((base.checked_add(offset)?, reader.uint(length_size)?));
+        }
+        // Method 2 refers to other items, which metadata never does
+        if method < 2 {
+            locations.insert(id, ItemLocation { in_idat: method == 1, extents });
+        }
+    }
+    Some(locations)
+}
+
+fn parse_ipma(payload: &[u8]) -> Option<BTreeMap<u32, Vec<usize>>> {
+    let mut reader = BeReader { data: payload, pos: 0 };
+    let (version, flags) = reader.full_box()?;
+    let count = reader.uint(4)?;
+    let mut associations = BTreeMap::new();
+    for _ in 0..count {
+        let id = reader.uint(if version == 0 { 2 } else { 4 })? as u32;
+        let properties = (0..reader.uint(1)?)
+            .map(|_| match flags & 1 {
+                1 => reader.uint(2).map(|index| index & 0x7FFF),
+                _ => reader.uint(1).map(|index| index & 0x7F),
+            })
+            .map(|index| index.map(|index| index as usize))
+            .collect::<Option<Vec<_>>>()?;
+        associations.insert(id, properties);
+    }
+    Some(associations)
+}
+
+/// An ISO-BMFF box, as JPEG XL, HEIC and AVIF files are built of
+struct BmffBox<'a> {
+    box_type: [u8; 4],
+    payload: &'a [u8],
+    /// Size including the header
+    total: u64,
+}
+
+/// Where an ISO-BMFF box lies in a file, for reading only the boxes needed
+struct BoxHeader {
+    box_type: [u8; 4],
+    /// Offset and length of the payload
+    start: u64,
+    len: u64,
+    /// Size including the header
+    total: u64,
+}
+
+/// The boxes laid out one after another in `data`. A box running past the end
+/// is cut short and a malformed size ends the list, each with a warning.
+fn read_boxes<'a>(data: &'a [u8], warnings: &mut Vec<String>) -> Vec<BmffBox<'a>> {
+    let header_at = |pos: u64| Ok(data[pos as usize..].iter().take(16).copied().collect());
+    let headers = box_headers(data.len() as u64, header_at, warnings).unwrap_or_default();
+    headers.into_iter()
+        .map(|h| BmffBox { box_type: h.box_type, payload: &data[h.start as usize..(h.start + h.len) as usize], total: h.total })
+        .collect()
+}
+
+/// Like [`read_boxes`], for `len` bytes whose headers are read through
+/// `header_at(pos)`, giving up to the 16 bytes of a header with a large size
+fn box_headers(len: u64, mut header_at: impl FnMut(u64) -> Result<Vec<u8>>, warnings: &mut Vec<String>) -> Result<Vec<BoxHeader>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= len {
+        let head = header_at(pos)?;
+        let size = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as u64;
+        let box_type: [u8; 4] = head[4..8].try_into().unwrap();
+        let name = String::from_utf8_lossy(&box_type).trim_end().to_string();
+        let (header, size) = match size {
+            0 => (8, len - pos),
+            1 => match head.get(8..16) {
+                Some(large) => (16, u64::from_be_bytes(large.try_into().unwrap())),
+                None => break,
+            },
+            size => (8, size),
+        };
+        if size < header {
+            warnings.push(format!("Stopped at a {}-byte {} box, smaller than its header", size, name));
+            break;
+        }
+        let end = pos.checked_add(size).filter(|&end| end <= len).unwrap_or_else(|| {
+            warnings.push(format!("{} box is cut short", name));
+            len
+        });
+        boxes.push(BoxHeader { box_type, start: pos + header, len: end - pos - header, total: end - pos });
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+/// Reads the bits of a JPEG XL codestream, least significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = self.data.get(self.pos / 8)?;
+            value |= (((byte >> (self.pos % 8)) & 1) as u32) << i;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    /// A dimension coded as a two-bit selector of the number of bits that follow
+    fn dimension(&mut self) -> Option<u32> {
+        let bits = [9, 13, 18, 30][self.read(2)? as usize];
+        Some(self.read(bits)? + 1)
+    }
+}
+
+/// Dimensions from the size header that follows the codestream signature
+fn jxl_dimensions(codestream: &[u8]) -> Option<(u32, u32)> {
+    let mut bits = Bits { data: codestream.get(JXL_CODESTREAM.len()..)?, pos: 0 };
+    let small = bits.read(1)? == 1;
+    let height = if small { (bits.read(5)? + 1) * 8 } else { bits.dimension()? };
+    let width = match bits.read(3)? {
+        0 if small => (bits.read(5)? + 1) * 8,
+        0 => bits.dimension()?,
+        ratio => {
+            let (num, den) = JXL_RATIOS[ratio as usize - 1];
+            u32::try_from(height as u64 * num / den).ok()?
+        }
+    };
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorspace::ColorSpaceName;
+    use crate::content::{extract_from_bytes, ExtractOptions};
+    use std::io::Cursor;
+
+    /// TIFF data and XMP packet of a test image
+    fn metadata() -> (Vec<u8>, Vec<u8>) {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let segments = jpeg::read_segments(&mut bytes.as_slice()).unwrap();
+        let exif = segments.iter().find(|s| s.is_app(1, EXIF_SIGNATURE)).unwrap();
+        let xmp = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:subject><rdf:Bag><rdf:li>harbour</rdf:li></rdf:Bag></dc:subject></rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        (exif.data[EXIF_SIGNATURE.len()..].to_vec(), xmp.to_vec())
+    }
+
+    #[test]
+    fn test_webp() {
+        let (tiff, xmp) = metadata();
+        let chunk = |fourcc: &[u8], payload: &[u8]| {
+            let mut chunk = [fourcc, &(payload.len() as u32).to_le_bytes(), payload].concat();
+            if payload.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+        // VP8X with the EXIF and XMP flags, on a 640x480 canvas
+        let vp8x = [0x0C, 0, 0, 0, 0x7F, 0x02, 0x00, 0xDF, 0x01, 0x00];
+        let vp8l = [0x2F, 0x7F, 0xC2, 0x77, 0x00, 0x00];
+        let body = [chunk(b"VP8X", &vp8x), chunk(b"VP8L", &vp8l), chunk(b"EXIF", &tiff), chunk(b"XMP ", &xmp)].concat();
+        let webp = [b"RIFF".as_slice(), &(body.len() as u32 + 4).to_le_bytes(), b"WEBP", &body].concat();
+
+        let content = extract_from_bytes(&webp, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.format, ImageFormat::WebP);
+        assert_eq!((content.width, content.height), (Some(640), Some(480)));
+        assert_eq!(content.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert_eq!(content.keywords, ["harbour"]);
+        assert_eq!(content.payload_breakdown.exif, 8 + tiff.len() as u64 + (tiff.len() % 2) as u64);
+        assert_eq!(webp_frame_dimensions(b"VP8L", &vp8l), Some((640, 480)));
+    }
+
+    #[test]
+    fn test_jxl() {
+        let (tiff, xmp) = metadata();
+        let boxed = |box_type: &[u8], payload: &[u8]| [&(payload.len() as u32 + 8).to_be_bytes(), box_type, payload].concat();
+        // A small 64x64 image: small flag, height / 8 - 1 = 7, ratio 1:1
+        let codestream = [0xFF, 0x0A, 0x4F, 0x00];
+        let jxl = [
+            JXL_CONTAINER.to_vec(),
+            boxed(b"ftyp", b"jxl \0\0\0\0jxl "),
+            boxed(b"Exif", &[&[0, 0, 0, 0], tiff.as_slice()].concat()),
+            boxed(b"xml ", &xmp),
+            boxed(b"jxlc", &codestream),
+        ].concat();
+
+        let content = extract_from_bytes(&jxl, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.format, ImageFormat::JpegXl);
+        assert_eq!((content.width, content.height), (Some(64), Some(64)));
+        assert_eq!(content.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert_eq!(content.keywords, ["harbour"]);
+
+        let bare = read(ImageFormat::JpegXl, &mut Cursor::new(&codestream), None).unwrap();
+        assert_eq!((bare.dimensions, bare.exif), (Some((64, 64)), None));
+        // Not small: height 750 with a 13-bit field, then the 4:3 ratio
+        let mut header = 0u32;
+        let mut at = 0;
+        for (value, bits) in [(0, 1), (1, 2), (749, 13), (3, 3)] {
+            header |= value << at;
+            at += bits;
+        }
+        let codestream = [JXL_CODESTREAM, &header.to_le_bytes()].concat();
+        assert_eq!(jxl_dimensions(&codestream), Some((1000, 750)));
+    }
+
+    #[test]
+    fn test_avif() {
+        let (tiff, xmp) = metadata();
+        let boxed = |box_type: &[u8], payload: &[u8]| [&(payload.len() as u32 + 8).to_be_bytes(), box_type, payload].concat();
+        let full = |box_type: &[u8], version: u8, payload: &[u8]| boxed(box_type, &[&[version, 0, 0, 0], payload].concat());
+        let infe = |id: u16, item_type: &[u8], extra: &[u8]| full(b"infe", 2, &[&id.to_be_bytes(), &[0, 0][..], item_type, extra].concat());
+
+        let av01 = vec![0x12; 100];
+        let exif = [&[0, 0, 0, 0], tiff.as_slice()].concat();
+        // An ICC profile whose one tag is a version 2 `desc` text
+        let description = b"Display P3\0";
+        let desc = [b"desc".as_slice(), &[0; 4], &(description.len() as u32).to_be_bytes(), description].concat();
+        let icc = [&[0; 128][..], &1u32.to_be_bytes(), b"desc", &144u32.to_be_bytes(), &(desc.len() as u32).to_be_bytes(), &desc].concat();
+        let ftyp = boxed(b"ftyp", b"avif\0\0\0\0mif1miaf");
+        // Version 0 iloc with 4-byte offsets and lengths, filled in once the mdat position is known
+        let meta = |mdat_start: u32| {
+            let mut iloc = vec![0x44, 0x00, 0x00, 0x03];
+            let mut at = mdat_start;
+            for (id, len) in [(1u16, av01.len()), (2, exif.len()), (3, xmp.len())] {
+                iloc.extend([&id.to_be_bytes()[..], &[0, 0, 0, 1], &at.to_be_bytes(), &(len as u32).to_be_bytes()].concat());
+                at += len as u32;
+            }
+            let iinf = [&[0, 3][..], &infe(1, b"av01", b""), &infe(2, b"Exif", b""), &infe(3, b"mime", b"application/rdf+xml\0")].concat();
+            let ispe = full(b"ispe", 0, &[1920u32.to_be_bytes(), 1080u32.to_be_bytes()].concat());
+            let colr = boxed(b"colr", &[b"prof".as_slice(), &icc].concat());
+            let ipma = full(b"ipma", 0, &[&1u32.to_be_bytes()[..], &1u16.to_be_bytes(), &[2, 0x81, 0x82]].concat());
+            let iprp = boxed(b"iprp", &[boxed(b"ipco", &[ispe, colr].concat()), ipma].concat());
+            full(b"meta", 0, &[full(b"pitm", 0, &1u16.to_be_bytes()), full(b"iinf", 0, &iinf), full(b"iloc", 0, &iloc), iprp].concat())
+        };
+        let mdat_start = (ftyp.len() + meta(0).len() + 8) as u32;
+        let avif = [ftyp, meta(mdat_start), boxed(b"mdat", &[av01.as_slice(), &exif, &xmp].concat())].concat();
+
+        let content = extract_from_bytes(&avif, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.format, ImageFormat::Avif);
+        assert_eq!((content.width, content.height), (Some(1920), Some(1080)));
+        assert_eq!(content.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert_eq!(content.keywords, ["harbour"]);
+        let color_space = content.color_space.as_ref().unwrap();
+        assert_eq!(color_space.icc_profile.as_deref(), Some("Display P3"));
+        assert_eq!(color_space.declared, Some(ColorSpaceName::DisplayP3));
+        let breakdown = &content.payload_breakdown;
+        assert_eq!((breakdown.exif, breakdown.xmp, breakdown.image_data), (exif.len() as u64, xmp.len() as u64, 108));
+
+        // An item located past the end of the file is reported rather than read
+        let cut = read(ImageFormat::Avif, &mut Cursor::new(&avif[..avif.len() - xmp.len()]), None).unwrap();
+        assert!(cut.xmp.is_none());
+        assert!(cut.warnings.iter().any(|w| w.starts_with("Ignored item 3")), "{:?}", cut.warnings);
+    }
+
+    #[test]
+    fn test_limits() {
+        let (tiff, _) = metadata();
+        // A megabyte of strip data after the metadata, which is never read
+        let strips = vec![0x55; 1 << 20];
+        let file = [tiff.as_slice(), &strips].concat();
+        let options = ExtractOptions { max_metadata_size: Some(tiff.len() as u64 * 2), ..Default::default() };
+        let content = extract_from_bytes(&file, &options).unwrap();
+        assert_eq!(content.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+
+        // An EXIF chunk larger than the limit is refused, wherever it lies
+        let chunk = |fourcc: &[u8], payload: &[u8]| [fourcc, &(payload.len() as u32).to_le_bytes(), payload].concat();
+        let body = [chunk(b"VP8L", &strips), chunk(b"EXIF", &tiff)].concat();
+        let webp = [b"RIFF".as_slice(), &(body.len() as u32 + 4).to_le_bytes(), b"WEBP", &body].concat();
+        let options = ExtractOptions { max_metadata_size: Some(tiff.len() as u64 / 2), ..Default::default() };
+        let error = extract_from_bytes(&webp, &options).unwrap_err();
+        assert!(matches!(error, ExtractError::LimitExceeded(jpeg::LimitExceeded::MetadataSize(_))));
+        assert!(extract_from_bytes(&webp, &ExtractOptions::default()).unwrap().camera_model.is_some());
+    }
+}
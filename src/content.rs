@@ -0,0 +1,732 @@
+use crate::audio::{self, AudioNote};
+use crate::cameras::{CameraSpec, Enrichment};
+use crate::charset::Charset;
+use crate::colors::{self, ColorStats};
+use crate::colorspace::ColorSpace;
+use crate::computational::{self, ComputationalMetadata};
+use crate::containers;
+use crate::derivatives;
+use crate::detect::{self, ImageFormat};
+use crate::encoding::{self, Encoding};
+use crate::drone::{self, DroneMetadata};
+use crate::error::{ExtractError, Result};
+use crate::exif_metadata::{exif_from_segments, Description, ExifMetadata, GpsPosition, TimeSource};
+use crate::focus::Focus;
+use crate::jpeg::{self, PayloadBreakdown, SegmentOffsets};
+use crate::keywords;
+use crate::lighting::{self, Flash, WhiteBalance};
+use crate::makernote;
+use crate::os_metadata::OsMetadata;
+use crate::panorama::{self, PanoramaMetadata};
+use crate::pixels;
+use crate::provenance::Source;
+use crate::quality::{self, QualityMetrics};
+use crate::raw::Page;
+use crate::regions::{self, Region, RegionSource};
+use crate::thermal::{self, ThermalMetadata};
+use crate::xmp;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// Settings that change what content extraction reports
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExtractOptions {
+    /// Additional EXIF tag IDs to report under `exif_extra`, including unknown tags
+    pub tags: Vec<u16>,
+    /// Report string fields as kamadak-exif display values (quoted) instead of clean strings
+    #[serde(default)]
+    pub raw_values: bool,
+    /// Decode the image and report colour statistics
+    #[serde(default)]
+    pub analyze_colors: bool,
+    /// Decode the image and report sharpness and clipping
+    #[serde(default)]
+    pub quality_metrics: bool,
+    /// Decode the image and report a perceptual hash, for finding resized and re-encoded copies
+    #[serde(default)]
+    pub perceptual_hash: bool,
+    /// Minimum side length to decode at for pixel analysis; 0 decodes at full resolution
+    #[serde(default)]
+    pub analysis_size: u16,
+    /// Camera database entries taking precedence over the built-in ones
+    #[serde(default)]
+    pub cameras: BTreeMap<String, CameraSpec>,
+    /// Record where each field came from under `provenance`
+    #[serde(default)]
+    pub provenance: bool,
+    /// Refuse JPEG files whose header runs past this many bytes, and other
+    /// formats whose metadata takes more than this many bytes to read
+    #[serde(default)]
+    pub max_metadata_size: Option<u64>,
+    /// Report the rating, title, comments, authors and tags set in Windows Explorer
+    #[serde(default)]
+    pub windows_properties: bool,
+    /// Character set of EXIF text that is not UTF-8
+    #[serde(default)]
+    pub input_charset: Charset,
+    /// Report only the last term of hierarchical keywords, see [`keywords::from_xmp`]
+    #[serde(default)]
+    pub leaf_keywords: bool,
+    /// Report the byte ranges of the metadata segments under `segment_offsets`
+    #[serde(default)]
+    pub segment_offsets: bool,
+}
+
+impl ExtractOptions {
+    /// Whether any requested analysis needs the decoded image
+    pub fn decodes_pixels(&self) -> bool {
+        self.analyze_colors || self.quality_metrics || self.perceptual_hash
+    }
+}
+
+/// Metadata derived purely from an image's bytes, independent of where it is stored
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentMetadata {
+    pub format: ImageFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Width as displayed, which is the stored height when `orientation` turns the image on its side
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_time: Option<DateTime<Utc>>,
+    /// The most reliable timestamp the image holds, see [`TimeSource`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_time_source: Option<TimeSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_serial: Option<String>,
+    /// From the maker note, or from XMP `aux:Firmware` where an editor has moved it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_firmware: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_unique_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence_number: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutter_count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flash: Option<Flash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub white_balance: Option<WhiteBalance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus: Option<Focus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrichment: Option<Enrichment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gps: Option<GpsPosition>,
+    /// Single terms from the XMP `dc:subject` bag, with hierarchical keywords split up
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// `|`-separated keyword paths from `lr:hierarchicalSubject` and `dc:subject`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hierarchical_keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub exif_extra: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Description::is_empty")]
+    pub description: Description,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copyright: Option<String>,
+    /// Properties set in Windows Explorer, with [`ExtractOptions::windows_properties`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows_properties: Option<OsMetadata>,
+    /// Text fields decoded from a legacy character set rather than UTF-8
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub transcoded_fields: BTreeMap<String, Charset>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drone: Option<DroneMetadata>,
+    /// Radiometric settings of FLIR thermal images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thermal: Option<ThermalMetadata>,
+    /// Photo sphere and other panorama properties from GPano XMP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panorama: Option<PanoramaMetadata>,
+    /// Gain maps, depth and matte images, and motion photo video
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub computational: Option<ComputationalMetadata>,
+    /// A voice memo embedded in APPn segments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioNote>,
+    /// Named face and other regions from MWG or Microsoft People XMP
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<Region>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colors: Option<ColorStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityMetrics>,
+    /// Difference hash of the decoded image as 16 hex digits, see [`derivatives::perceptual_hash`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perceptual_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Encoding>,
+    /// Color space tagging from EXIF, the ICC profile and JFIF, with any disagreements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_space: Option<ColorSpace>,
+    /// Every page of a multi-page TIFF, in file order; the other fields describe the first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<Page>,
+    pub payload_breakdown: PayloadBreakdown,
+    /// Byte ranges of the EXIF, XMP and ICC segments, the thumbnail and the scan
+    /// data of a JPEG, when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_offsets: Option<SegmentOffsets>,
+    /// Where each field came from, with --provenance
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provenance: BTreeMap<String, Source>,
+    /// Out-of-spec data that was skipped or worked around while reading
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Extract content metadata from a JPEG stream of `size` bytes.
+///
+/// The stream is read exactly once and, for a single-scan image without pixel
+/// analysis, never past the start of scan marker: everything else reported here
+/// lives in the header, so the entropy-coded image data is neither read nor
+/// decoded. Progressive images are read to the end to count their scans, and
+/// images whose MPF index lists auxiliary images are read through the last of them.
+/// WebP, JPEG XL, HEIC, AVIF and TIFF files are read by seeking from box to box or
+/// IFD to IFD, reading the metadata and skipping the image data.
+pub fn extract_content<R: Read + Seek>(reader: &mut R, size: u64, options: &ExtractOptions) -> Result<ContentMetadata> {
+    let mut header = Vec::new();
+    reader.by_ref().take(detect::SNIFF_LEN).read_to_end(&mut header)?;
+    let format = detect::detect_bytes(&header);
+    match format {
+        ImageFormat::Unknown => return Err(ExtractError::NotAnImage("not a recognised image format".to_string())),
+        format if !format.is_supported() => return Err(ExtractError::Unsupported(format!("image format {}", format))),
+        ImageFormat::WebP | ImageFormat::JpegXl | ImageFormat::Heic | ImageFormat::Avif | ImageFormat::Tiff => {
+            reader.seek(SeekFrom::Current(-(header.len() as i64)))?;
+            let container = containers::read(format, reader, options.max_metadata_size)?;
+            return extract_container(format, container, size, options);
+        }
+        _ => {}
+    }
+
+    if options.decodes_pixels() {
+        let mut bytes = header;
+        reader.read_to_end(&mut bytes)?;
+        let jpeg::Header { segments, warnings } = jpeg::read_header_within(&mut Cursor::new(&bytes), options.max_metadata_size)?;
+        let mut exif = exif_from_segments(&segments, options)?;
+        exif.warnings.splice(0..0, warnings);
+        let preview = pixels::decode_preview(&bytes, options.analysis_size)?;
+        let auxiliary = computational::auxiliary_types(&computational::auxiliary_entries(&segments), &bytes, 0);
+        let mut content = build_content(format, &segments, exif, auxiliary, size, options);
+        if let Some(encoding) = content.encoding.as_mut().filter(|e| e.has_more_scans(&segments)) {
+            let scan_data = bytes.get(jpeg::header_len(&segments) as usize..).unwrap_or_default();
+            encoding.scans += encoding::count_remaining_scans(scan_data)?;
+        }
+        content.colors = options.analyze_colors.then(|| colors::analyze(&preview));
+        content.quality = options.quality_metrics.then(|| quality::analyze(&preview));
+        content.perceptual_hash = options.perceptual_hash.then(|| format!("{:016x}", derivatives::perceptual_hash(&preview)));
+        if options.provenance {
+            // All are computed from the decoded scan data
+            let scan = jpeg_source(&segments, |marker| marker == jpeg::SOS);
+            let analyses = [
+                ("colors", content.colors.is_some()),
+                ("quality", content.quality.is_some()),
+                ("perceptual_hash", content.perceptual_hash.is_some()),
+            ];
+            for (field, present) in analyses {
+                if let Some(scan) = scan.clone().filter(|_| present) {
+                    content.provenance.insert(field.to_string(), Source::derived([scan]));
+                }
+            }
+        }
+        return Ok(content);
+    }
+
+    let mut stream = Cursor::new(header).chain(reader);
+    let jpeg::Header { segments, warnings } = jpeg::read_header_within(&mut stream, options.max_metadata_size)?;
+    let mut exif = exif_from_segments(&segments, options)?;
+    exif.warnings.splice(0..0, warnings);
+
+    let mut entries = computational::auxiliary_entries(&segments);
+    let consumed = jpeg::header_len(&segments);
+    let mut images = Vec::new();
+    if let Some(end) = entries.iter().map(|e| e.offset + e.size).max() {
+        if options.max_metadata_size.is_some_and(|max| end > max) {
+            exif.warnings.push(format!("Auxiliary images end at offset {}, past the metadata size limit; not identified", end));
+            entries.clear();
+        } else {
+            stream.by_ref().take(end.saturating_sub(consumed)).read_to_end(&mut images)?;
+        }
+    }
+    let auxiliary = computational::auxiliary_types(&entries, &images, consumed);
+    let mut content = build_content(format, &segments, exif, auxiliary, size, options);
+    if let Some(encoding) = content.encoding.as_mut().filter(|e| e.has_more_scans(&segments)) {
+        encoding.scans += encoding::count_remaining_scans(Cursor::new(images).chain(stream))?;
+    }
+    Ok(content)
+}
+
+/// Extract content metadata from a WebP, JPEG XL, HEIC, AVIF or TIFF file through the
+/// same code as a JPEG's. Provenance and pixel analysis are not available for
+/// these formats, as both refer to JPEG segments and scan data.
+fn extract_container(format: ImageFormat, container: containers::Container, size: u64, options: &ExtractOptions) -> Result<ContentMetadata> {
+    let segments = container.segments();
+    let options = ExtractOptions { provenance: false, ..options.clone() };
+    let mut exif = exif_from_segments(&segments, &options)?;
+    exif.warnings.splice(0..0, container.warnings);
+    if options.decodes_pixels() {
+        exif.warnings.push(format!("Pixel analysis is not available for {}", format));
+    }
+    let mut content = build_content(format, &segments, exif, Vec::new(), size, &options);
+    (content.width, content.height) = container.dimensions.unzip();
+    (content.display_width, content.display_height) = display_dimensions(container.dimensions, content.orientation).unzip();
+    content.payload_breakdown = container.payload_breakdown;
+    // A single page adds nothing to the fields already reported
+    if container.pages.len() > 1 {
+        content.pages = container.pages;
+    }
+    Ok(content)
+}
+
+/// Extract content metadata from an in-memory image, without touching the filesystem.
+///
+/// Without pixel analysis only the header region up to the start of scan is
+/// ever read, so this is cheap on memory-mapped files of any size.
+pub fn extract_from_bytes(bytes: &[u8], options: &ExtractOptions) -> Result<ContentMetadata> {
+    extract_content(&mut Cursor::new(bytes), bytes.len() as u64, options)
+}
+
+/// Extract content metadata from a stream that cannot seek, such as a pipe or a
+/// socket, reading a JPEG no further than its start of scan segment. The rest of
+/// the stream is left unread, so a transfer can be inspected while in flight;
+/// wrap the reader to keep the consumed bytes if they must be passed on.
+///
+/// The file size is not known, so the header length stands in for it: the
+/// payload breakdown reports no image data, progressive scans past the first are
+/// not counted and MPF auxiliary images are not identified. Pixel analysis is
+/// skipped with a warning. WebP, JPEG XL, HEIC, AVIF and TIFF files may keep their
+/// metadata anywhere, so they are buffered whole, up to `max_metadata_size`.
+pub fn extract_from_stream<R: Read>(reader: &mut R, options: &ExtractOptions) -> Result<ContentMetadata> {
+    let mut header = Vec::new();
+    reader.by_ref().take(detect::SNIFF_LEN).read_to_end(&mut header)?;
+    let format = detect::detect_bytes(&header);
+    match format {
+        ImageFormat::Unknown => return Err(ExtractError::NotAnImage("not a recognised image format".to_string())),
+        format if !format.is_supported() => return Err(ExtractError::Unsupported(format!("image format {}", format))),
+        ImageFormat::WebP | ImageFormat::JpegXl | ImageFormat::Heic | ImageFormat::Avif | ImageFormat::Tiff => {
+            let mut bytes = header;
+            match options.max_metadata_size {
+                Some(max) => {
+                    reader.take((max + 1).saturating_sub(bytes.len() as u64)).read_to_end(&mut bytes)?;
+                    if bytes.len() as u64 > max {
+                        return Err(jpeg::LimitExceeded::MetadataSize(max).into());
+                    }
+                }
+                None => {
+                    reader.read_to_end(&mut bytes)?;
+                }
+            }
+            let container = containers::read(format, &mut Cursor::new(&bytes), options.max_metadata_size)?;
+            return extract_container(format, container, bytes.len() as u64, options);
+        }
+        _ => {}
+    }
+
+    let jpeg::Header { segments, warnings } = jpeg::read_header_within(&mut Cursor::new(header).chain(reader), options.max_metadata_size)?;
+    let mut exif = exif_from_segments(&segments, options)?;
+    exif.warnings.splice(0..0, warnings);
+    if options.decodes_pixels() {
+        exif.warnings.push("Pixel analysis is not available when reading a stream".to_string());
+    }
+    Ok(build_content(format, &segments, exif, Vec::new(), jpeg::header_len(&segments), options))
+}
+
+fn build_content(
+    format: ImageFormat,
+    segments: &[jpeg::Segment],
+    exif: ExifMetadata,
+    auxiliary: Vec<String>,
+    size: u64,
+    options: &ExtractOptions,
+) -> ContentMetadata {
+    let dimensions = jpeg::dimensions(segments);
+    let xmp_packet = xmp::packet(segments);
+    let xmp_doc = xmp_packet.as_deref().and_then(xmp::parse);
+    let drone = xmp_doc.as_ref().and_then(drone::from_xmp);
+    let thermal = thermal::from_segments(segments);
+    let panorama = xmp_doc.as_ref().and_then(panorama::from_xmp);
+    let has_auxiliary = !auxiliary.is_empty();
+    let computational = computational::from_image(xmp_doc.as_ref(), auxiliary, size);
+    let audio = audio::from_segments(segments);
+    let regions = xmp_doc.as_ref().map(regions::from_xmp).unwrap_or_default();
+    let keywords = xmp_doc.as_ref().map(|doc| keywords::from_xmp(doc, options.leaf_keywords)).unwrap_or_default();
+    let has_hierarchy = xmp_doc.as_ref().is_some_and(|doc| doc.descendants().any(|n| n.has_tag_name((keywords::LR_NS, "hierarchicalSubject"))));
+    // Stock agencies often only fill in the XMP
+    let xmp_artist = xmp_doc.as_ref().and_then(|doc| xmp::first_item(doc, xmp::DC_NS, "creator")).filter(|_| exif.artist.is_none());
+    let xmp_copyright = xmp_doc.as_ref().and_then(|doc| xmp::first_item(doc, xmp::DC_NS, "rights")).filter(|_| exif.copyright.is_none());
+    let xmp_firmware = xmp_doc.as_ref()
+        .and_then(|doc| xmp::property(doc, xmp::AUX_NS, "Firmware"))
+        .and_then(|firmware| makernote::firmware_version(&firmware))
+        .filter(|_| exif.camera_firmware.is_none());
+    let color_temperature = xmp_doc.as_ref()
+        .and_then(|doc| xmp::property(doc, lighting::CRS_NS, "Temperature"))
+        .and_then(|t| t.parse().ok());
+    let white_balance = match (exif.white_balance, color_temperature) {
+        (Some(wb), color_temperature) => Some(WhiteBalance { color_temperature, ..wb }),
+        (None, Some(t)) => Some(WhiteBalance { color_temperature: Some(t), ..WhiteBalance::default() }),
+        (None, None) => None,
+    };
+    let mut provenance = exif.provenance;
+    if options.provenance {
+        provenance.insert("format".to_string(), Source::Jpeg { segment: "SOI".to_string(), offset: 0 });
+        let sof = jpeg_source(segments, jpeg::is_sof);
+        if let Some(sof) = sof.clone().filter(|_| dimensions.is_some()) {
+            provenance.insert("width".to_string(), sof.clone());
+            provenance.insert("height".to_string(), sof.clone());
+            let from = [Some(sof), provenance.get("orientation").cloned()];
+            provenance.insert("display_width".to_string(), Source::derived(from.clone().into_iter().flatten()));
+            provenance.insert("display_height".to_string(), Source::derived(from.into_iter().flatten()));
+        }
+        let xmp_fields = [
+            ("keywords", !keywords.flat.is_empty(), "dc:subject"),
+            ("hierarchical_keywords", !keywords.hierarchical.is_empty(), if has_hierarchy { "lr:hierarchicalSubject" } else { "dc:subject" }),
+            ("artist", xmp_artist.is_some(), "dc:creator"),
+            ("copyright", xmp_copyright.is_some(), "dc:rights"),
+            ("camera_firmware", xmp_firmware.is_some(), "aux:Firmware"),
+            ("drone", drone.is_some(), "drone-dji"),
+            ("panorama", panorama.is_some(), "GPano"),
+            ("regions", regions.iter().any(|r| r.source == RegionSource::Mwg), "mwg-rs:Regions"),
+            ("regions", regions.iter().any(|r| r.source == RegionSource::Microsoft), "MP:RegionInfo"),
+        ];
+        for (field, present, property) in xmp_fields {
+            if present {
+                // Regions from both schemas are merged, so report both
+                let source = match provenance.remove(field) {
+                    Some(earlier) => Source::derived([earlier, Source::xmp(property)]),
+                    None => Source::xmp(property),
+                };
+                provenance.insert(field.to_string(), source);
+            }
+        }
+        if color_temperature.is_some() {
+            let exif_source = provenance.remove("white_balance");
+            let from = exif_source.into_iter().chain([Source::xmp("crs:Temperature")]);
+            provenance.insert("white_balance".to_string(), Source::derived(from));
+        }
+        if let Some(sof) = sof {
+            provenance.insert("encoding".to_string(), sof);
+        }
+        if let Some(index) = segments.iter().position(thermal::is_flir_segment).filter(|_| thermal.is_some()) {
+            let offset = jpeg::segment_offset(segments, index);
+            provenance.insert("thermal".to_string(), Source::Jpeg { segment: "APP1".to_string(), offset });
+        }
+        if computational.is_some() {
+            let xmp = xmp_doc.as_ref().map(|_| Source::xmp("GCamera"));
+            let mpf = jpeg_source(segments, |marker| marker == 0xE2).filter(|_| has_auxiliary);
+            provenance.insert("computational".to_string(), Source::derived(xmp.into_iter().chain(mpf)));
+        }
+        if let Some((index, _)) = audio::wav_segment(segments).filter(|_| audio.is_some()) {
+            let offset = jpeg::segment_offset(segments, index);
+            provenance.insert("audio".to_string(), Source::Jpeg { segment: jpeg::marker_name(segments[index].marker), offset });
+        }
+        provenance.insert("payload_breakdown".to_string(), Source::derived([]));
+    }
+    let payload_breakdown = PayloadBreakdown::from_segments(
+        segments,
+        size,
+        exif.thumbnail_length.unwrap_or(0).into(),
+    );
+    let segment_offsets = options.segment_offsets
+        .then(|| SegmentOffsets::from_segments(segments, size, exif.thumbnail_offset.zip(exif.thumbnail_length)))
+        .filter(|offsets| *offsets != SegmentOffsets::default());
+    if options.provenance && segment_offsets.is_some() {
+        provenance.insert("segment_offsets".to_string(), Source::derived([]));
+    }
+
+    ContentMetadata {
+        format,
+        width: dimensions.map(|(w, _)| w),
+        height: dimensions.map(|(_, h)| h),
+        display_width: display_dimensions(dimensions, exif.orientation).map(|(w, _)| w),
+        display_height: display_dimensions(dimensions, exif.orientation).map(|(_, h)| h),
+        orientation: exif.orientation,
+        capture_time: exif.capture_time,
+        best_time: exif.best_time.map(|(time, _)| time),
+        best_time_source: exif.best_time.map(|(_, source)| source),
+        camera_model: exif.camera_model,
+        camera_serial: exif.camera_serial,
+        camera_firmware: exif.camera_firmware.or(xmp_firmware),
+        image_unique_id: exif.image_unique_id,
+        sequence_number: exif.sequence_number,
+        shutter_count: exif.shutter_count,
+        flash: exif.flash,
+        white_balance,
+        focus: exif.focus,
+        enrichment: exif.enrichment,
+        gps: exif.gps,
+        keywords: keywords.flat,
+        hierarchical_keywords: keywords.hierarchical,
+        exif_extra: exif.extra,
+        description: exif.description,
+        artist: exif.artist.or(xmp_artist),
+        copyright: exif.copyright.or(xmp_copyright),
+        windows_properties: exif.windows_properties,
+        transcoded_fields: exif.transcoded_fields,
+        drone,
+        thermal,
+        panorama,
+        computational,
+        audio,
+        regions,
+        colors: None,
+        quality: None,
+        perceptual_hash: None,
+        encoding: Encoding::from_segments(segments),
+        color_space: ColorSpace::check(&exif.color_tags, segments),
+        pages: Vec::new(),
+        payload_breakdown,
+        segment_offsets,
+        provenance,
+        warnings: exif.warnings,
+    }
+}
+
+/// Width and height as displayed: EXIF orientations 5 to 8 rotate the image by
+/// 90 or 270 degrees, swapping its stored dimensions
+pub fn display_dimensions(dimensions: Option<(u32, u32)>, orientation: Option<u32>) -> Option<(u32, u32)> {
+    let (width, height) = dimensions?;
+    match orientation {
+        Some(5..=8) => Some((height, width)),
+        _ => Some((width, height)),
+    }
+}
+
+/// The first segment whose marker matches, as a [`Source::Jpeg`]
+fn jpeg_source(segments: &[jpeg::Segment], matches: impl Fn(u8) -> bool) -> Option<Source> {
+    let index = segments.iter().position(|s| matches(s.marker))?;
+    Some(Source::Jpeg { segment: jpeg::marker_name(segments[index].marker), offset: jpeg::segment_offset(segments, index) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_from_bytes() {
+        let bytes = std::fs::read("images/JAM19896.jpg").unwrap();
+        let content = extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.format, ImageFormat::Jfif);
+        assert_eq!((content.width, content.height), (Some(5040), Some(3360)));
+        assert!(content.capture_time.is_some());
+        assert_eq!(content.payload_breakdown.image_data + content.payload_breakdown.other
+            + content.payload_breakdown.exif + content.payload_breakdown.xmp
+            + content.payload_breakdown.icc + content.payload_breakdown.thumbnail, bytes.len() as u64);
+
+        assert!(matches!(extract_from_bytes(b"", &ExtractOptions::default()), Err(ExtractError::NotAnImage(_))));
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = std::fs::read("images/JAM19896.jpg").unwrap();
+        let options = ExtractOptions { max_metadata_size: Some(1024), ..Default::default() };
+        let error = extract_from_bytes(&bytes, &options).unwrap_err();
+        assert!(matches!(error, ExtractError::LimitExceeded(jpeg::LimitExceeded::MetadataSize(1024))));
+
+        // Endless empty comments never reach the start of scan
+        let mut crafted = vec![0xFF, jpeg::SOI];
+        crafted.extend([0xFF, 0xFE, 0x00, 0x02].repeat(jpeg::MAX_SEGMENTS + 1));
+        let error = extract_from_bytes(&crafted, &ExtractOptions::default()).unwrap_err();
+        assert!(matches!(error, ExtractError::LimitExceeded(jpeg::LimitExceeded::Segments(jpeg::MAX_SEGMENTS))));
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_analyze_colors() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        assert!(extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap().colors.is_none());
+
+        let options = ExtractOptions { analyze_colors: true, analysis_size: 64, ..Default::default() };
+        let colors = extract_from_bytes(&bytes, &options).unwrap().colors.unwrap();
+        assert!((0.0..=1.0).contains(&colors.average_brightness));
+        assert!(!colors.dominant_colors.is_empty());
+        assert!((colors.histogram.iter().sum::<f64>() - 1.0).abs() < 0.01);
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_quality_metrics() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let options = ExtractOptions { quality_metrics: true, analysis_size: 64, ..Default::default() };
+        let content = extract_from_bytes(&bytes, &options).unwrap();
+        assert!(content.colors.is_none());
+        let quality = content.quality.unwrap();
+        assert!(quality.sharpness > 0.0);
+        assert!((0.0..=100.0).contains(&quality.clipped_highlights_pct));
+    }
+
+    #[test]
+    fn test_display_dimensions() {
+        assert_eq!(display_dimensions(Some((6000, 4000)), Some(6)), Some((4000, 6000)));
+        assert_eq!(display_dimensions(Some((6000, 4000)), Some(3)), Some((6000, 4000)));
+        assert_eq!(display_dimensions(Some((6000, 4000)), None), Some((6000, 4000)));
+        assert_eq!(display_dimensions(None, Some(8)), None);
+
+        let content = extract_from_bytes(&std::fs::read("images/JAM19896.jpg").unwrap(), &ExtractOptions::default()).unwrap();
+        assert_eq!((content.display_width, content.display_height), (Some(5040), Some(3360)));
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_segment_offsets() {
+        let original = std::fs::read("images/JAM26284.jpg").unwrap();
+        let bytes = crate::thumbnail::replace(&original, &crate::thumbnail::generate(&original).unwrap()).unwrap();
+        assert!(extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap().segment_offsets.is_none());
+
+        let options = ExtractOptions { segment_offsets: true, ..Default::default() };
+        let offsets = extract_from_bytes(&bytes, &options).unwrap().segment_offsets.unwrap();
+        let at = |range: &jpeg::ByteRange| &bytes[range.start as usize..(range.start + range.length) as usize];
+        assert!(at(&offsets.exif[0]).starts_with(b"\xFF\xE1") && at(&offsets.exif[0])[4..].starts_with(b"Exif\0\0"));
+        assert!(at(&offsets.xmp[0]).starts_with(b"\xFF\xE1"));
+        assert!(offsets.icc.iter().all(|range| at(range)[4..].starts_with(jpeg::ICC_SIGNATURE)));
+        let thumbnail = at(offsets.thumbnail.as_ref().unwrap());
+        assert!(thumbnail.starts_with(&[0xFF, 0xD8]) && thumbnail.ends_with(&[0xFF, 0xD9]));
+        let image_data = offsets.image_data.unwrap();
+        assert_eq!(image_data.start + image_data.length, bytes.len() as u64);
+    }
+
+    #[test]
+    fn test_extract_content_reads_only_header() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let full = extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap();
+
+        // A stream that ends right after the start of scan segment must yield the same result
+        let header_len = jpeg::header_len(&jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap());
+        let mut header_only = Cursor::new(&bytes[..header_len as usize]);
+        let content = extract_content(&mut header_only, bytes.len() as u64, &ExtractOptions::default()).unwrap();
+        assert_eq!(header_only.position(), header_len);
+        assert_eq!(content.payload_breakdown, full.payload_breakdown);
+        assert_eq!(content.capture_time, full.capture_time);
+    }
+
+    #[test]
+    fn test_extract_from_stream() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let full = extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap();
+
+        // A slice reads without seeking, and what is left of it shows how far the stream was read
+        let mut stream = &bytes[..];
+        let options = ExtractOptions { perceptual_hash: true, ..Default::default() };
+        let content = extract_from_stream(&mut stream, &options).unwrap();
+        let header_len = jpeg::header_len(&jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap());
+        assert_eq!(stream.len() as u64, bytes.len() as u64 - header_len);
+        assert_eq!(content.capture_time, full.capture_time);
+        assert_eq!((content.width, content.height), (full.width, full.height));
+        assert_eq!(content.payload_breakdown.image_data, 0);
+        assert_eq!(content.perceptual_hash, None);
+        assert!(content.warnings.iter().any(|w| w.contains("Pixel analysis")));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        assert!(extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap().provenance.is_empty());
+
+        let options = ExtractOptions { provenance: true, ..Default::default() };
+        let provenance = extract_from_bytes(&bytes, &options).unwrap().provenance;
+        let Some(Source::Exif { tag, ifd, offset: Some(offset), .. }) = provenance.get("camera_model") else {
+            panic!("camera_model has no EXIF source: {:?}", provenance.get("camera_model"));
+        };
+        assert_eq!((tag.as_str(), ifd.as_str()), ("Model", "IFD0"));
+        assert_eq!(&bytes[*offset as usize..][..5], b"Canon");
+        let Some(Source::Jpeg { segment, offset }) = provenance.get("width") else { panic!() };
+        assert!(segment.starts_with("SOF"));
+        assert_eq!(bytes[*offset as usize..][..2], [0xFF, 0xC0 + segment[3..].parse::<u8>().unwrap()]);
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_recovers_out_of_spec_exif() {
+        let spec = crate::fixture::FixtureSpec { orientation: Some(6), model: Some("SM-G900F".to_string()), ..Default::default() };
+        let bytes = crate::fixture::generate(&spec).unwrap();
+        let segment = |data: &[u8]| [&[0xFF, 0xE1][..], &((data.len() + 2) as u16).to_be_bytes(), data].concat();
+
+        // An unreadable EXIF segment, then junk before the real one, whose signature is damaged
+        let mut damaged = bytes[..2].to_vec();
+        damaged.extend(segment(b"Exif\0\0MM\0*\0\0\xFF\xFF"));
+        damaged.extend([0u8; 3]);
+        damaged.extend(bytes[2..].windows(6).position(|w| w == b"Exif\0\0").map(|i| {
+            let mut rest = bytes[2..].to_vec();
+            rest[i..i + 6].copy_from_slice(b"EXIF\0\xFF");
+            rest
+        }).unwrap());
+        let content = extract_from_bytes(&damaged, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.orientation, Some(6));
+        assert_eq!(content.camera_model.as_deref(), Some("SM-G900F"));
+        assert_eq!(content.warnings.len(), 3, "{:?}", content.warnings);
+        assert!(content.warnings[0].starts_with("Skipped 3 bytes"));
+
+        // A header cut short after the EXIF segment keeps what was read
+        let segments = jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap();
+        let app1 = segments.iter().position(|s| s.marker == 0xE1).unwrap();
+        let app1_end = (jpeg::segment_offset(&segments, app1) + segments[app1].total_len()) as usize;
+        let truncated = extract_from_bytes(&bytes[..app1_end + 10], &ExtractOptions::default()).unwrap();
+        assert_eq!(truncated.orientation, Some(6));
+        assert!(truncated.warnings[0].starts_with("Stopped reading the JPEG header"));
+
+        let plain = crate::fixture::generate(&Default::default()).unwrap();
+        assert_eq!(extract_from_bytes(&plain, &ExtractOptions::default()).unwrap().warnings, ["No EXIF data found"]);
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_recovers_oversized_segments() {
+        use exif::{experimental::Writer, Field, In, Tag, Value};
+        let bytes = crate::fixture::generate(&Default::default()).unwrap();
+        let app1 = |length: usize, data: &[u8]| [&[0xFF, 0xE1][..], &(length as u16).to_be_bytes(), data].concat();
+
+        // EXIF split over three APP1 segments by a long description, with the Exif IFD in the last
+        let description = Field { tag: Tag::ImageDescription, ifd_num: In::PRIMARY, value: Value::Ascii(vec![vec![b'x'; 140_000]]) };
+        let taken = Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"2024:05:06 07:08:09".to_vec()]) };
+        let mut writer = Writer::new();
+        writer.push_field(&description);
+        writer.push_field(&taken);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let payload = [jpeg::EXIF_SIGNATURE, &tiff.into_inner()].concat();
+        let mut split = bytes[..2].to_vec();
+        split.extend(app1(65535, &payload[..65533]));
+        for chunk in payload[65533..].chunks(65527) {
+            split.extend(app1(chunk.len() + 8, &[jpeg::EXIF_SIGNATURE, chunk].concat()));
+        }
+        split.extend(&bytes[2..]);
+        let content = extract_from_bytes(&split, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.capture_time.unwrap().to_rfc3339(), "2024-05-06T07:08:09+00:00");
+        assert_eq!(content.description.image_description.map(|d| d.len()), Some(140_000));
+        assert!(content.warnings.contains(&"EXIF data continues over 2 more APP1 segments".to_string()), "{:?}", content.warnings);
+
+        // XMP of 70,000 bytes whose length field wrapped past 65535, as stitching software writes it
+        let padding = " ".repeat(70_000);
+        let packet = format!(concat!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">{}"#,
+            r#"<rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:subject><rdf:Bag><rdf:li>stitched</rdf:li></rdf:Bag>"#,
+            r#"</dc:subject></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        ), padding);
+        let data = [jpeg::XMP_SIGNATURE, packet.as_bytes()].concat();
+        let mut wrapped = bytes[..2].to_vec();
+        wrapped.extend(app1((data.len() + 2) % 0x10000, &data));
+        wrapped.extend(&bytes[2..]);
+        let segments = jpeg::read_header(&mut Cursor::new(&wrapped)).unwrap().segments;
+        assert_eq!(segments[0].data.len(), data.len());
+        assert_eq!(jpeg::header_len(&segments), jpeg::header_len(&jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap()) + data.len() as u64 + 4);
+        let content = extract_from_bytes(&wrapped, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.keywords, ["stitched"]);
+        assert_eq!((content.width, content.height), (Some(16), Some(16)));
+        assert!(content.warnings[0].starts_with("APP1 segment at offset 2 is"), "{:?}", content.warnings);
+    }
+}
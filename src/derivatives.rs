@@ -0,0 +1,184 @@
+use crate::pixels::{luma, Preview};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+/// Width and height of the grid [`perceptual_hash`] compares; one column more
+/// than bits per row, as each bit compares neighbouring cells
+const HASH_COLUMNS: u32 = 9;
+const HASH_ROWS: u32 = 8;
+
+/// Difference hash of an image: shrink it to a 9x8 grid of average luma and set
+/// one bit per cell brighter than its right-hand neighbour. Resizing and
+/// re-encoding barely change it, so copies of one image are a few bits apart.
+pub fn perceptual_hash(preview: &Preview) -> u64 {
+    let mut sums = [[0f64; HASH_COLUMNS as usize]; HASH_ROWS as usize];
+    let mut counts = [[0u32; HASH_COLUMNS as usize]; HASH_ROWS as usize];
+    let (width, height) = (preview.width.max(1), preview.height.max(1));
+    for (i, pixel) in preview.pixels().enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let (column, row) = ((x * HASH_COLUMNS / width) as usize, (y * HASH_ROWS / height) as usize);
+        sums[row][column] += luma(pixel);
+        counts[row][column] += 1;
+    }
+
+    let mut hash = 0;
+    for (sums, counts) in sums.iter().zip(&counts) {
+        let cells: Vec<f64> = sums.iter().zip(counts).map(|(&sum, &n)| sum / n.max(1) as f64).collect();
+        for pair in cells.windows(2) {
+            hash = hash << 1 | (pair[0] > pair[1]) as u64;
+        }
+    }
+    hash
+}
+
+/// Parse a hash as reported, 16 hex digits
+pub fn parse_hash(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok()
+}
+
+/// What derivative linkage needs to know about one image
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Image<'a> {
+    /// EXIF ImageUniqueID
+    pub unique_id: Option<&'a str>,
+    pub capture_time: Option<DateTime<Utc>>,
+    pub perceptual_hash: Option<u64>,
+    /// Width times height
+    pub pixels: Option<u64>,
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Find copies of the same image: those sharing an ImageUniqueID, and those
+/// whose perceptual hashes are at most `max_distance` bits apart and whose
+/// capture times, where both have one, are within a second (re-encoding often
+/// drops the fractional seconds). Of each set of copies the one with the most
+/// pixels, then the largest file, is taken as the original.
+///
+/// Returns for each image the index of its original, `None` for originals and
+/// images without copies.
+pub fn link(images: &[Image], max_distance: u32) -> Vec<Option<usize>> {
+    let mut sets = DisjointSets::new(images.len());
+
+    let mut by_id: HashMap<&str, usize> = HashMap::new();
+    for (i, image) in images.iter().enumerate() {
+        if let Some(id) = image.unique_id {
+            sets.union(*by_id.entry(id).or_insert(i), i);
+        }
+    }
+
+    // Only images close in capture time, or both without one, can match by hash
+    let mut timed: Vec<(DateTime<Utc>, usize)> = images.iter().enumerate()
+        .filter_map(|(i, image)| Some((image.capture_time?, i)))
+        .collect();
+    timed.sort();
+    for (n, &(time, i)) in timed.iter().enumerate() {
+        for &(other_time, j) in &timed[n + 1..] {
+            if other_time - time >= Duration::seconds(1) {
+                break;
+            }
+            if similar(&images[i], &images[j], max_distance) {
+                sets.union(i, j);
+            }
+        }
+    }
+    let untimed: Vec<usize> = (0..images.len()).filter(|&i| images[i].capture_time.is_none()).collect();
+    for (n, &i) in untimed.iter().enumerate() {
+        for &j in &untimed[n + 1..] {
+            if similar(&images[i], &images[j], max_distance) {
+                sets.union(i, j);
+            }
+        }
+    }
+
+    let mut originals: HashMap<usize, usize> = HashMap::new();
+    for i in 0..images.len() {
+        let original = originals.entry(sets.find(i)).or_insert(i);
+        let rank = |image: &Image| (image.pixels.unwrap_or(0), image.size);
+        if rank(&images[i]) > rank(&images[*original]) {
+            *original = i;
+        }
+    }
+    (0..images.len())
+        .map(|i| Some(originals[&sets.find(i)]).filter(|&original| original != i))
+        .collect()
+}
+
+fn similar(a: &Image, b: &Image, max_distance: u32) -> bool {
+    match (a.perceptual_hash, b.perceptual_hash) {
+        (Some(a), Some(b)) => (a ^ b).count_ones() <= max_distance,
+        _ => false,
+    }
+}
+
+/// Union-find over image indices
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        DisjointSets { parents: (0..len).collect() }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "decode")]
+    use crate::pixels::decode_preview;
+    use chrono::TimeZone;
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_perceptual_hash() {
+        let bytes = std::fs::read("images/JAM19896.jpg").unwrap();
+        let small = perceptual_hash(&decode_preview(&bytes, 64).unwrap());
+        let large = perceptual_hash(&decode_preview(&bytes, 512).unwrap());
+        assert!((small ^ large).count_ones() <= 4, "{:016x} {:016x}", small, large);
+
+        let other = perceptual_hash(&decode_preview(&std::fs::read("images/JAM26284.jpg").unwrap(), 64).unwrap());
+        assert!((small ^ other).count_ones() > 10);
+        assert_eq!(parse_hash(&format!("{:016x}", small)), Some(small));
+    }
+
+    #[test]
+    fn test_link() {
+        let time = |secs: i64| Some(Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap());
+        let image = |hash: u64, pixels: u64| Image {
+            capture_time: time(0),
+            perceptual_hash: Some(hash),
+            pixels: Some(pixels),
+            size: pixels / 4,
+            ..Default::default()
+        };
+        let images = [
+            image(0xff00, 1_000_000),
+            // Resized, a bit off by re-encoding
+            image(0xff01, 4_000_000),
+            image(0xff03, 250_000),
+            // Same picture a minute later, e.g. the next frame on a tripod
+            Image { capture_time: time(60), ..image(0xff00, 4_000_000) },
+            // Another picture at the same time
+            image(0x00ff, 4_000_000),
+            // Same ID but stripped of its capture time and heavily edited
+            Image { unique_id: Some("abc"), capture_time: None, perceptual_hash: None, ..image(0, 100) },
+            Image { unique_id: Some("abc"), ..image(0x1234, 800) },
+        ];
+        assert_eq!(link(&images, 4), [Some(1), None, Some(1), None, None, Some(6), None]);
+        assert_eq!(link(&images, 0)[..3], [None, None, None]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::containers::{AVIF_BRANDS, HEIF_BRANDS, JXL_CODESTREAM, JXL_CONTAINER};
+use crate::error::Result;
+use crate::raw;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Number of leading bytes inspected when sniffing a file's format, enough
+/// for the first compatible brands of an ISO-BMFF file
+pub const SNIFF_LEN: u64 = 32;
+
+/// JP2 container signature box
+const JP2_SIGNATURE: &[u8] = &[0x00, 0x00, 0x00, 0x0C, b'j', b'P', b' ', b' ', 0x0D, 0x0A, 0x87, 0x0A];
+/// Raw JPEG 2000 codestream: SOC followed by SIZ
+const J2K_SIGNATURE: &[u8] = &[0xFF, 0x4F, 0xFF, 0x51];
+
+/// Image format determined from file content rather than extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    /// JPEG with a JFIF APP0 header
+    Jfif,
+    /// JPEG starting with an EXIF APP1 segment and no JFIF header
+    ExifJpeg,
+    /// Any other JPEG stream (e.g. Adobe APP14 or bare tables)
+    Jpeg,
+    /// JPEG 2000, either a JP2 container or a raw codestream
+    Jpeg2000,
+    /// TIFF, including TIFF-based RAW formats such as CR2, NEF, ARW and DNG
+    Tiff,
+    #[serde(rename = "webp")]
+    WebP,
+    JpegXl,
+    /// HEIF with HEVC-coded images
+    Heic,
+    /// HEIF with AV1-coded images
+    Avif,
+    Unknown,
+}
+
+impl ImageFormat {
+    /// The formats metadata can be extracted from, see [`ImageFormat::is_supported`]
+    pub const SUPPORTED: [ImageFormat; 8] = [
+        ImageFormat::Jfif, ImageFormat::ExifJpeg, ImageFormat::Jpeg, ImageFormat::Tiff,
+        ImageFormat::WebP, ImageFormat::JpegXl, ImageFormat::Heic, ImageFormat::Avif,
+    ];
+
+    /// Whether the format is a baseline/progressive JPEG we can extract from
+    pub fn is_jpeg(self) -> bool {
+        matches!(self, ImageFormat::Jfif | ImageFormat::ExifJpeg | ImageFormat::Jpeg)
+    }
+
+    /// Whether metadata can be extracted: JPEGs, and containers whose EXIF and
+    /// XMP blocks are read as a JPEG's would be
+    pub fn is_supported(self) -> bool {
+        Self::SUPPORTED.contains(&self)
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Jfif => "JPEG (JFIF)",
+            ImageFormat::ExifJpeg => "JPEG (EXIF)",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::Jpeg2000 => "JPEG 2000",
+            ImageFormat::Tiff => "TIFF or TIFF-based RAW",
+            ImageFormat::WebP => "WebP",
+            ImageFormat::JpegXl => "JPEG XL",
+            ImageFormat::Heic => "HEIC",
+            ImageFormat::Avif => "AVIF",
+            ImageFormat::Unknown => "unknown format",
+        })
+    }
+}
+
+/// Identify the format of a buffer from its leading bytes.
+///
+/// Short or empty buffers are reported as unknown rather than treated as errors.
+pub fn detect_bytes(header: &[u8]) -> ImageFormat {
+    if header.starts_with(JP2_SIGNATURE) || header.starts_with(J2K_SIGNATURE) {
+        return ImageFormat::Jpeg2000;
+    }
+    if raw::is_tiff(header) {
+        return ImageFormat::Tiff;
+    }
+    if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        return ImageFormat::WebP;
+    }
+    if header.starts_with(JXL_CONTAINER) || header.starts_with(JXL_CODESTREAM) {
+        return ImageFormat::JpegXl;
+    }
+    if let Some(format) = heif_format(header) {
+        return format;
+    }
+    if !header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return ImageFormat::Unknown;
+    }
+    match header.get(3) {
+        Some(0xE0) if header.get(6..10) == Some(b"JFIF") => ImageFormat::Jfif,
+        Some(0xE1) if header.get(6..10) == Some(b"Exif") => ImageFormat::ExifJpeg,
+        _ => ImageFormat::Jpeg,
+    }
+}
+
+/// HEIC or AVIF from the brands of an ISO-BMFF `ftyp` box. Files with only the
+/// generic `mif1` or `msf1` brand as their major brand are AVIF if a compatible
+/// brand says so.
+fn heif_format(header: &[u8]) -> Option<ImageFormat> {
+    if header.get(4..8) != Some(b"ftyp") {
+        return None;
+    }
+    let major = header.get(8..12)?;
+    let size = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+    let mut compatible = header.get(16..size.min(header.len()))?.chunks_exact(4);
+    if AVIF_BRANDS.contains(&major) || compatible.any(|brand| AVIF_BRANDS.contains(&brand)) {
+        Some(ImageFormat::Avif)
+    } else {
+        HEIF_BRANDS.contains(&major).then_some(ImageFormat::Heic)
+    }
+}
+
+/// Identify the format of a file by sniffing its content
+pub fn detect_format(path: &Path) -> Result<ImageFormat> {
+    let file = File::open(path)?;
+    let mut header = Vec::with_capacity(SNIFF_LEN as usize);
+    file.take(SNIFF_LEN).read_to_end(&mut header)?;
+    Ok(detect_bytes(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_bytes() {
+        assert_eq!(detect_bytes(b""), ImageFormat::Unknown);
+        assert_eq!(detect_bytes(&[0xFF]), ImageFormat::Unknown);
+        assert_eq!(detect_bytes(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10, b'E', b'x', b'i', b'f']), ImageFormat::ExifJpeg);
+        assert_eq!(detect_bytes(&[0xFF, 0xD8, 0xFF, 0xEE, 0x00, 0x0E]), ImageFormat::Jpeg);
+        assert_eq!(detect_bytes(JP2_SIGNATURE), ImageFormat::Jpeg2000);
+        assert_eq!(detect_bytes(&[0xFF, 0x4F, 0xFF, 0x51, 0x00]), ImageFormat::Jpeg2000);
+        assert_eq!(detect_bytes(b"II*\0\x08\0\0\0"), ImageFormat::Tiff);
+        assert_eq!(detect_bytes(b"RIFF\x24\0\0\0WEBPVP8 "), ImageFormat::WebP);
+        assert_eq!(detect_bytes(JXL_CONTAINER), ImageFormat::JpegXl);
+        assert_eq!(detect_bytes(&[0xFF, 0x0A, 0x4F, 0x00]), ImageFormat::JpegXl);
+        assert_eq!(detect_bytes(b"\0\0\0\x18ftypavif\0\0\0\0mif1miaf"), ImageFormat::Avif);
+        assert_eq!(detect_bytes(b"\0\0\0\x18ftypmif1\0\0\0\0mif1avif"), ImageFormat::Avif);
+        assert_eq!(detect_bytes(b"\0\0\0\x18ftypheic\0\0\0\0mif1heic"), ImageFormat::Heic);
+        assert_eq!(detect_bytes(b"\0\0\0\x14ftypisom\0\0\0\0mp41"), ImageFormat::Unknown);
+    }
+}
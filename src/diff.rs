@@ -0,0 +1,56 @@
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// A single metadata field that differs between two sources
+#[derive(Debug, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.left, &self.right) {
+            (Some(l), Some(r)) => write!(f, "~ {}: {} -> {}", self.field, l, r),
+            (Some(l), None) => write!(f, "- {}: {}", self.field, l),
+            (None, Some(r)) => write!(f, "+ {}: {}", self.field, r),
+            (None, None) => write!(f, "  {}", self.field),
+        }
+    }
+}
+
+/// Compare two metadata objects field by field, in sorted field order.
+///
+/// Nested objects are compared recursively and reported with dotted paths.
+pub fn diff_metadata(left: &Value, right: &Value) -> Vec<FieldDiff> {
+    let mut diffs = Vec::new();
+    diff_values("", left, right, &mut diffs);
+    diffs
+}
+
+fn diff_values(prefix: &str, left: &Value, right: &Value, diffs: &mut Vec<FieldDiff>) {
+    match (left, right) {
+        (Value::Object(l), Value::Object(r)) => diff_objects(prefix, l, r, diffs),
+        _ if left != right => diffs.push(FieldDiff {
+            field: prefix.to_string(),
+            left: Some(left.clone()),
+            right: Some(right.clone()),
+        }),
+        _ => {}
+    }
+}
+
+fn diff_objects(prefix: &str, left: &Map<String, Value>, right: &Map<String, Value>, diffs: &mut Vec<FieldDiff>) {
+    let mut keys: Vec<&String> = left.keys().chain(right.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    for key in keys {
+        let field = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match (left.get(key), right.get(key)) {
+            (Some(l), Some(r)) => diff_values(&field, l, r, diffs),
+            (l, r) => diffs.push(FieldDiff { field, left: l.cloned(), right: r.cloned() }),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::xmp;
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+
+/// DJI's XMP namespace (`drone-dji:`)
+pub const DJI_NS: &str = "http://www.dji.com/drone-dji/1.0/";
+
+/// Flight and gimbal state recorded by drone cameras. Angles are in degrees,
+/// altitudes in metres and speeds in metres per second.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DroneMetadata {
+    pub vendor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_altitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absolute_altitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gimbal_pitch: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gimbal_yaw: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gimbal_roll: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_pitch: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_yaw: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_roll: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_speed_x: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_speed_y: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_speed_z: Option<f64>,
+}
+
+/// A drone vendor's XMP namespace and the property names it uses for each field
+struct Vendor {
+    name: &'static str,
+    namespace: &'static str,
+    /// Property names in `DroneMetadata` field order, from relative_altitude to flight_speed_z
+    properties: [&'static str; 11],
+}
+
+const VENDORS: &[Vendor] = &[Vendor {
+    name: "DJI",
+    namespace: DJI_NS,
+    properties: [
+        "RelativeAltitude",
+        "AbsoluteAltitude",
+        "GimbalPitchDegree",
+        "GimbalYawDegree",
+        "GimbalRollDegree",
+        "FlightPitchDegree",
+        "FlightYawDegree",
+        "FlightRollDegree",
+        "FlightXSpeed",
+        "FlightYSpeed",
+        "FlightZSpeed",
+    ],
+}];
+
+/// Drone metadata from the first vendor namespace present in the XMP
+pub fn from_xmp(doc: &Document) -> Option<DroneMetadata> {
+    VENDORS.iter().find_map(|vendor| {
+        let values = vendor.properties.map(|name| {
+            xmp::property(doc, vendor.namespace, name).and_then(|v| v.parse::<f64>().ok())
+        });
+        if values.iter().all(Option::is_none) {
+            return None;
+        }
+        let [relative_altitude, absolute_altitude, gimbal_pitch, gimbal_yaw, gimbal_roll,
+             flight_pitch, flight_yaw, flight_roll, flight_speed_x, flight_speed_y, flight_speed_z] = values;
+        Some(DroneMetadata {
+            vendor: vendor.name.to_string(),
+            relative_altitude,
+            absolute_altitude,
+            gimbal_pitch,
+            gimbal_yaw,
+            gimbal_roll,
+            flight_pitch,
+            flight_yaw,
+            flight_roll,
+            flight_speed_x,
+            flight_speed_y,
+            flight_speed_z,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dji_xmp() {
+        let packet = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="DJI Meta Data" xmlns:drone-dji="http://www.dji.com/drone-dji/1.0/"
+  drone-dji:AbsoluteAltitude="+512.43" drone-dji:RelativeAltitude="+120.10"
+  drone-dji:GimbalPitchDegree="-90.00" drone-dji:GimbalYawDegree="+35.20" drone-dji:GimbalRollDegree="+0.00"
+  drone-dji:FlightXSpeed="+1.2" drone-dji:FlightYSpeed="-0.4" drone-dji:FlightZSpeed="+0.0"/>
+</rdf:RDF></x:xmpmeta>"#;
+        let doc = xmp::parse(packet).unwrap();
+        let drone = from_xmp(&doc).unwrap();
+        assert_eq!(drone.vendor, "DJI");
+        assert_eq!(drone.relative_altitude, Some(120.1));
+        assert_eq!(drone.gimbal_pitch, Some(-90.0));
+        assert_eq!(drone.flight_speed_y, Some(-0.4));
+        assert_eq!(drone.flight_yaw, None);
+    }
+
+    #[test]
+    fn test_no_drone_xmp() {
+        let packet = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="0"/></rdf:RDF></x:xmpmeta>"#;
+        assert_eq!(from_xmp(&xmp::parse(packet).unwrap()), None);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::error::Result;
+use crate::jpeg::{self, Segment, EOI, SOS};
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, Read};
+
+/// Quantization table marker
+const DQT: u8 = 0xDB;
+
+/// IJG standard luminance quantization table (Annex K), which quality 50 uses unscaled
+const STD_LUMINANCE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61,
+    12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77,
+    24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// JPEG coding process, from the start of frame marker
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodingProcess {
+    Baseline,
+    ExtendedSequential,
+    Progressive,
+    Lossless,
+}
+
+/// How the image data is encoded
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Encoding {
+    pub process: CodingProcess,
+    /// Arithmetic rather than Huffman entropy coding
+    pub arithmetic: bool,
+    /// Number of start of scan segments
+    pub scans: u32,
+    /// IJG-equivalent quality (1-100) estimated from the luminance quantization table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_quality: Option<u8>,
+}
+
+impl Encoding {
+    /// Encoding as far as the header shows it. `scans` counts only the first scan;
+    /// see [`Encoding::has_more_scans`].
+    pub fn from_segments(segments: &[Segment]) -> Option<Self> {
+        let frame = segments.iter().find(|s| jpeg::is_sof(s.marker))?;
+        let process = match frame.marker & 0x03 {
+            0 => CodingProcess::Baseline,
+            1 => CodingProcess::ExtendedSequential,
+            2 => CodingProcess::Progressive,
+            _ => CodingProcess::Lossless,
+        };
+        Some(Encoding {
+            process,
+            arithmetic: frame.marker >= 0xC8,
+            scans: segments.iter().filter(|s| s.marker == SOS).count() as u32,
+            estimated_quality: estimate_quality(segments),
+        })
+    }
+
+    /// Whether scans beyond the first follow: always for progressive images,
+    /// and for sequential ones whose first scan does not cover every component
+    pub fn has_more_scans(&self, segments: &[Segment]) -> bool {
+        if self.process == CodingProcess::Progressive {
+            return true;
+        }
+        let frame_components = segments.iter()
+            .find(|s| jpeg::is_sof(s.marker))
+            .and_then(|s| s.data.get(5).copied());
+        let scan_components = segments.iter()
+            .find(|s| s.marker == SOS)
+            .and_then(|s| s.data.first().copied());
+        matches!((frame_components, scan_components), (Some(f), Some(s)) if s < f)
+    }
+}
+
+/// Count the start of scan markers in the rest of a stream positioned just
+/// after the first scan header. Stuffed bytes and restart markers inside
+/// entropy-coded data are skipped, as are the payloads of marker segments
+/// between scans.
+pub fn count_remaining_scans<R: Read>(reader: R) -> Result<u32> {
+    let mut bytes = BufReader::new(reader).bytes();
+    let mut scans = 0;
+    while let Some(byte) = bytes.next() {
+        if byte? != 0xFF {
+            continue;
+        }
+        let mut marker = 0xFF;
+        while marker == 0xFF {
+            match bytes.next() {
+                Some(byte) => marker = byte?,
+                None => return Ok(scans),
+            }
+        }
+        match marker {
+            0x00 | 0xD0..=0xD7 => continue,
+            EOI => break,
+            _ => {}
+        }
+        if marker == SOS {
+            scans += 1;
+        }
+        let (Some(high), Some(low)) = (bytes.next(), bytes.next()) else {
+            break;
+        };
+        let len = u16::from_be_bytes([high?, low?]) as usize;
+        for _ in 2..len {
+            if bytes.next().transpose()?.is_none() {
+                return Ok(scans);
+            }
+        }
+    }
+    Ok(scans)
+}
+
+/// Estimate quality as the IJG quality setting whose scaled standard table is
+/// closest to the image's luminance table. Exact for libjpeg-style encoders;
+/// for others it gives the nearest equivalent.
+fn estimate_quality(segments: &[Segment]) -> Option<u8> {
+    let table = segments.iter()
+        .filter(|s| s.marker == DQT)
+        .find_map(|s| luminance_table(&s.data))?;
+    let mut sorted_table = table.clone();
+    sorted_table.sort_unstable();
+    (1..=100u8).min_by_key(|&quality| {
+        // Compare sorted values so the zigzag order of the stored table does not matter
+        let mut scaled = ijg_table(quality);
+        scaled.sort_unstable();
+        scaled.iter().zip(&sorted_table).map(|(&a, &b)| a.abs_diff(b) as u32).sum::<u32>()
+    })
+}
+
+/// The standard luminance table as libjpeg scales it for `quality` (with baseline clamping)
+fn ijg_table(quality: u8) -> [u16; 64] {
+    let scale = if quality < 50 { 5000 / quality as u32 } else { 200 - 2 * quality as u32 };
+    STD_LUMINANCE.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// Table 0 from a DQT segment, which may hold several 8- or 16-bit tables
+fn luminance_table(mut data: &[u8]) -> Option<Vec<u16>> {
+    while let Some((&info, rest)) = data.split_first() {
+        let wide = info >> 4 != 0;
+        let len = if wide { 128 } else { 64 };
+        let values = rest.get(..len)?;
+        if info & 0x0F == 0 {
+            return Some(if wide {
+                values.chunks_exact(2).map(|v| u16::from_be_bytes([v[0], v[1]])).collect()
+            } else {
+                values.iter().map(|&v| v as u16).collect()
+            });
+        }
+        data = &rest[len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dqt(scale: impl Fn(u16) -> u16) -> Segment {
+        let mut data = vec![0x00];
+        data.extend(STD_LUMINANCE.iter().map(|&q| scale(q).clamp(1, 255) as u8));
+        Segment { marker: DQT, data, offset: None }
+    }
+
+    #[test]
+    fn test_estimate_quality() {
+        assert_eq!(estimate_quality(&[dqt(|q| q)]), Some(50));
+        assert_eq!(estimate_quality(&[dqt(|_| 1)]), Some(100));
+        // IJG quality 90 scales the standard table to 20%
+        assert_eq!(estimate_quality(&[dqt(|q| (q * 20 + 50) / 100)]), Some(90));
+        assert_eq!(estimate_quality(&[dqt(|q| q * 2)]), Some(25));
+        assert_eq!(estimate_quality(&[]), None);
+    }
+
+    #[test]
+    fn test_count_remaining_scans() {
+        // Entropy data with a stuffed byte and a restart marker, a DHT between
+        // scans, then a second scan and EOI
+        let stream = [
+            0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56,
+            0xFF, 0xC4, 0x00, 0x04, 0xFF, 0xDA,
+            0xFF, 0xDA, 0x00, 0x03, 0x01, 0x78,
+            0xFF, 0xD9,
+        ];
+        assert_eq!(count_remaining_scans(&stream[..]).unwrap(), 1);
+    }
+}
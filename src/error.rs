@@ -0,0 +1,128 @@
+use crate::jpeg::LimitExceeded;
+use std::fmt;
+
+/// Why the library failed, for callers that handle some failures differently
+/// from others instead of matching on messages
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ExtractError {
+    Io(std::io::Error),
+    /// The data is not an image at all, e.g. lacks a JPEG start of image marker
+    NotAnImage(String),
+    /// The image has no EXIF data where some was required
+    NoExif,
+    /// Malformed data in `tag`: an EXIF tag, a JPEG segment, or the name of the
+    /// document or value being parsed. `source` is the parser's own error, if any.
+    ParseError { tag: String, reason: String, source: Option<Box<dyn std::error::Error + Send + Sync>> },
+    /// Valid data this library does not handle, e.g. an image format other than JPEG
+    Unsupported(String),
+    /// A header too large to read, as crafted files may be
+    LimitExceeded(LimitExceeded),
+    /// Writing an image or EXIF block failed
+    Encode(String),
+    /// A custom [`crate::extractor::Extractor`] failed or could not be registered.
+    /// `source` is the extractor's own [`ExtractError`] when it failed.
+    Extractor { name: String, source: Box<dyn std::error::Error + Send + Sync> },
+}
+
+pub type Result<T, E = ExtractError> = std::result::Result<T, E>;
+
+impl ExtractError {
+    pub fn parse(tag: impl Into<String>, reason: impl fmt::Display) -> Self {
+        ExtractError::ParseError { tag: tag.into(), reason: reason.to_string(), source: None }
+    }
+
+    /// A parse error caused by another library's `error`, kept as its source
+    pub fn parse_from(tag: impl Into<String>, error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        ExtractError::ParseError { tag: tag.into(), reason: error.to_string(), source: Some(Box::new(error)) }
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExtractError::Io(e) => write!(f, "{}", e),
+            ExtractError::NotAnImage(reason) => write!(f, "Not an image: {}", reason),
+            ExtractError::NoExif => write!(f, "No EXIF data"),
+            ExtractError::ParseError { tag, reason, .. } => write!(f, "Invalid {}: {}", tag, reason),
+            ExtractError::Unsupported(what) => write!(f, "Unsupported {}", what),
+            ExtractError::LimitExceeded(limit) => write!(f, "Limit exceeded: {}", limit),
+            ExtractError::Encode(reason) => write!(f, "Failed to encode {}", reason),
+            ExtractError::Extractor { name, source } => write!(f, "Extractor '{}': {}", name, source),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::Io(e) => Some(e),
+            ExtractError::ParseError { source: Some(source), .. } | ExtractError::Extractor { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ExtractError {
+    fn from(e: std::io::Error) -> Self {
+        ExtractError::Io(e)
+    }
+}
+
+impl From<LimitExceeded> for ExtractError {
+    fn from(limit: LimitExceeded) -> Self {
+        ExtractError::LimitExceeded(limit)
+    }
+}
+
+impl From<exif::Error> for ExtractError {
+    fn from(e: exif::Error) -> Self {
+        match e {
+            exif::Error::Io(e) => ExtractError::Io(e),
+            exif::Error::NotFound(_) => ExtractError::NoExif,
+            exif::Error::NotSupported(what) => ExtractError::Unsupported(what.to_string()),
+            other => ExtractError::parse_from("EXIF", other),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ExtractError {
+    fn from(e: serde_json::Error) -> Self {
+        match e.classify() {
+            serde_json::error::Category::Io => ExtractError::Io(e.into()),
+            _ => ExtractError::parse_from("JSON", e),
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+impl From<jpeg_decoder::Error> for ExtractError {
+    fn from(e: jpeg_decoder::Error) -> Self {
+        match e {
+            jpeg_decoder::Error::Io(e) => ExtractError::Io(e),
+            jpeg_decoder::Error::Unsupported(feature) => ExtractError::Unsupported(format!("JPEG feature {:?}", feature)),
+            other => ExtractError::parse_from("JPEG image data", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exif_errors() {
+        assert!(matches!(ExtractError::from(exif::Error::NotFound("Exif")), ExtractError::NoExif));
+        let error = ExtractError::from(exif::Error::InvalidFormat("Truncated IFD"));
+        assert_eq!(error.to_string(), "Invalid EXIF: Truncated IFD");
+        assert!(matches!(error, ExtractError::ParseError { ref tag, .. } if tag == "EXIF"));
+        let source = std::error::Error::source(&error).unwrap();
+        assert!(matches!(source.downcast_ref(), Some(exif::Error::InvalidFormat("Truncated IFD"))));
+
+        let io = ExtractError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert!(std::error::Error::source(&io).unwrap().downcast_ref::<std::io::Error>().is_some());
+        let json = ExtractError::from(serde_json::from_str::<serde_json::Value>("{").unwrap_err());
+        assert!(std::error::Error::source(&json).unwrap().is::<serde_json::Error>());
+        assert!(std::error::Error::source(&ExtractError::NoExif).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+/// What event clustering needs to know about one photo
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Photo {
+    pub capture_time: Option<DateTime<Utc>>,
+    /// Latitude and longitude in decimal degrees
+    pub position: Option<(f64, f64)>,
+}
+
+/// Summary of one event
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Event {
+    pub id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub photos: usize,
+    /// Mean position of the photos that have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+/// Mean radius of the Earth in kilometres
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance in kilometres between two positions in decimal degrees
+pub fn distance_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.1 - a.1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+/// Group photos into events: runs in capture-time order where each photo was
+/// taken at most `max_gap` after the previous one and, with `max_distance_km`,
+/// at most that far from the previous photo of the run that has a position.
+///
+/// Returns an event id per photo, `None` for photos without a capture time,
+/// and a summary per event. Ids are `event-1`, `event-2`, ... in time order.
+pub fn cluster(photos: &[Photo], max_gap: Duration, max_distance_km: Option<f64>) -> (Vec<Option<String>>, Vec<Event>) {
+    let mut order: Vec<(DateTime<Utc>, usize)> = photos.iter().enumerate()
+        .filter_map(|(i, p)| Some((p.capture_time?, i)))
+        .collect();
+    order.sort();
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut last_time = None;
+    let mut last_position = None;
+    for (time, i) in order {
+        let position = photos[i].position;
+        let too_late = last_time.is_some_and(|last| time - last > max_gap);
+        let too_far = match (max_distance_km, last_position, position) {
+            (Some(max), Some(last), Some(here)) => distance_km(last, here) > max,
+            _ => false,
+        };
+        if groups.is_empty() || too_late || too_far {
+            groups.push(Vec::new());
+            last_position = None;
+        }
+        groups.last_mut().unwrap().push(i);
+        last_time = Some(time);
+        last_position = position.or(last_position);
+    }
+
+    let mut ids = vec![None; photos.len()];
+    let mut events = Vec::new();
+    for (n, group) in groups.iter().enumerate() {
+        let id = format!("event-{}", n + 1);
+        for &i in group {
+            ids[i] = Some(id.clone());
+        }
+        let positions: Vec<(f64, f64)> = group.iter().filter_map(|&i| photos[i].position).collect();
+        let mean = |part: fn(&(f64, f64)) -> f64| {
+            (!positions.is_empty()).then(|| positions.iter().map(part).sum::<f64>() / positions.len() as f64)
+        };
+        events.push(Event {
+            id,
+            start: photos[group[0]].capture_time.unwrap(),
+            end: photos[group[group.len() - 1]].capture_time.unwrap(),
+            photos: group.len(),
+            latitude: mean(|p| p.0),
+            longitude: mean(|p| p.1),
+        });
+    }
+    (ids, events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn photo(minutes: i64, position: Option<(f64, f64)>) -> Photo {
+        Photo {
+            capture_time: Some(Utc.timestamp_opt(1_700_000_000 + minutes * 60, 0).unwrap()),
+            position,
+        }
+    }
+
+    #[test]
+    fn test_distance_km() {
+        let paris = (48.8566, 2.3522);
+        let london = (51.5074, -0.1278);
+        assert!((distance_km(paris, london) - 343.5).abs() < 1.0);
+        assert_eq!(distance_km(paris, paris), 0.0);
+    }
+
+    #[test]
+    fn test_cluster() {
+        let photos = [
+            photo(100, None),
+            photo(0, Some((48.0, 2.0))),
+            photo(30, None),
+            // 3 hours after the previous photo
+            photo(380, Some((48.0, 2.0))),
+            // Half an hour later, but about 111 km north
+            photo(410, Some((49.0, 2.0))),
+            Photo { capture_time: None, position: None },
+        ];
+        let (ids, events) = cluster(&photos, Duration::hours(2), None);
+        let id = |n: &str| Some(n.to_string());
+        assert_eq!(ids, [id("event-1"), id("event-1"), id("event-1"), id("event-2"), id("event-2"), None]);
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].start, events[0].end, events[0].photos), (photos[1].capture_time.unwrap(), photos[0].capture_time.unwrap(), 3));
+        assert_eq!((events[1].latitude, events[1].longitude), (Some(48.5), Some(2.0)));
+
+        let (ids, events) = cluster(&photos, Duration::hours(2), Some(50.0));
+        assert_eq!(ids[3..5], [id("event-2"), id("event-3")]);
+        assert_eq!(events[2].latitude, Some(49.0));
+    }
+}
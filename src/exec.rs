@@ -0,0 +1,320 @@
+use anyhow::{bail, Context, Result};
+use jpeg_metadata_extractor::template::Template;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::manifest::Job;
+use crate::sink::Sink;
+use crate::{metadata_value, sidecar_path, writes_json_sidecar, Args, ImageMetadata};
+
+/// Names tried for a record's temporary file before giving up
+const TEMP_ATTEMPTS: usize = 16;
+
+/// The number a command was started as, and how it exited
+type Exit = (usize, std::io::Result<ExitStatus>);
+
+/// An --exec command line, split into words before any placeholder is filled in,
+/// so field values with spaces or quotes reach the program as single arguments
+/// and are never seen by a shell
+#[derive(Clone, Debug)]
+pub struct ExecCommand {
+    words: Vec<Template>,
+    /// Whether each word starts with a `-` of its own, as the options the command
+    /// line gives do, rather than one a field value brings in
+    options: Vec<bool>,
+    /// Whether `{json_path}` is used, so records without a sidecar need a file written
+    uses_json_path: bool,
+}
+
+impl ExecCommand {
+    /// Parse a command such as `upload --key {camera_serial} {json_path} {file}`.
+    /// Words are split on whitespace outside quotes and placeholders; each is a
+    /// template like those of `--config`, with `{file}` and `{json_path}` added.
+    pub fn parse(text: &str) -> Result<Self> {
+        let words = split_words(text)?;
+        if words.is_empty() {
+            bail!("empty command");
+        }
+        let options = words.iter().map(|word| word.starts_with('-')).collect();
+        let words: Vec<Template> = words.iter()
+            .map(|word| Template::parse(word).with_context(|| format!("In {:?}", word)))
+            .collect::<Result<_>>()?;
+        let uses_json_path = words.iter().any(|word| word.fields().iter().any(|field| field == "json_path"));
+        Ok(ExecCommand { words, options, uses_json_path })
+    }
+
+    /// The program and its arguments for a flat record, or `None` if a field the
+    /// command uses is missing and has no `default`
+    fn render(&self, record: &serde_json::Value) -> Option<Vec<String>> {
+        self.words.iter().map(|word| word.render(record)).collect()
+    }
+
+    /// Whether a field value made an argument start with `-`, which the program
+    /// would take for an option
+    fn injects_option(&self, words: &[String]) -> bool {
+        words.iter().zip(&self.options).skip(1).any(|(word, &option)| word.starts_with('-') && !option)
+    }
+}
+
+/// Split a command line on whitespace, keeping `{...}` placeholders whole and
+/// removing the single or double quotes around quoted words
+fn split_words(text: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('{', _) if chars.peek() == Some(&'{') => {
+                chars.next();
+                word.get_or_insert_with(String::new).push_str("{{");
+            }
+            ('{', _) => {
+                let word = word.get_or_insert_with(String::new);
+                word.push(c);
+                loop {
+                    match chars.next() {
+                        Some(c) => word.push(c),
+                        None => bail!("unclosed '{{' in {:?}", text),
+                    }
+                    if word.ends_with('}') {
+                        break;
+                    }
+                }
+            }
+            ('\'' | '"', None) => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (c, Some(open)) if c == open => quote = None,
+            (c, None) if c.is_whitespace() => words.extend(word.take()),
+            (c, _) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("unclosed quote in {:?}", text);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Runs the --exec command for each record once its outputs are written, with at
+/// most `limit` commands running at once; further records wait for one to exit.
+/// Failures are reported and do not stop the run.
+pub struct ExecSink<'a> {
+    command: ExecCommand,
+    args: &'a Args,
+    limit: usize,
+    /// Commands still running, by the number they were started as
+    running: HashMap<usize, Running>,
+    started: usize,
+    /// Exit statuses sent by the thread waiting on each command
+    exits: (Sender<Exit>, Receiver<Exit>),
+}
+
+/// A command started for one record
+struct Running {
+    path: PathBuf,
+    /// The record's JSON, written for a record without a sidecar and removed on exit
+    temp: Option<PathBuf>,
+}
+
+impl<'a> ExecSink<'a> {
+    pub fn new(command: ExecCommand, args: &'a Args) -> Self {
+        let limit = args.exec_jobs.map(usize::from)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+        ExecSink { command, args, limit, running: HashMap::new(), started: 0, exits: mpsc::channel() }
+    }
+
+    /// Report and forget the commands that have exited
+    fn reap(&mut self) {
+        while let Ok((id, status)) = self.exits.1.try_recv() {
+            self.exited(id, status);
+        }
+    }
+
+    /// Block until a running command exits, and report it
+    fn wait(&mut self) {
+        // The sink holds a sender, so this waits rather than failing
+        if let Ok((id, status)) = self.exits.1.recv() {
+            self.exited(id, status);
+        }
+    }
+
+    fn exited(&mut self, id: usize, status: std::io::Result<ExitStatus>) {
+        if let Some(running) = self.running.remove(&id) {
+            finished(&running, status);
+        }
+    }
+}
+
+impl Sink for ExecSink<'_> {
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()> {
+        let mut record = serde_json::to_value(&metadata)?;
+        let mut temp = None;
+        let json_path = if !self.command.uses_json_path {
+            PathBuf::new()
+        } else if writes_json_sidecar(job, self.args) {
+            sidecar_path(job, self.args)
+        } else if self.args.dry_run {
+            PathBuf::from("<record>.json")
+        } else {
+            temp.insert(write_temp(&serde_json::to_vec_pretty(&metadata_value(job, &metadata, self.args)?)?)?).clone()
+        };
+        if let Some(object) = record.as_object_mut() {
+            object.insert("file".to_string(), argument_path(&job.path).into());
+            object.insert("json_path".to_string(), argument_path(&json_path).into());
+        }
+        let Some(words) = self.command.render(&record) else {
+            eprintln!("Skipped --exec (a field it uses is missing): {}", job.path.display());
+            remove(temp.as_deref());
+            return Ok(());
+        };
+        if self.command.injects_option(&words) {
+            eprintln!("Skipped --exec (a field value starts with '-'): {}", job.path.display());
+            remove(temp.as_deref());
+            return Ok(());
+        }
+        if self.args.dry_run {
+            println!("Would run: {}", words.join(" "));
+            return Ok(());
+        }
+
+        self.reap();
+        while self.running.len() >= self.limit {
+            self.wait();
+        }
+        let spawned = std::process::Command::new(&words[0])
+            .args(&words[1..])
+            .stdin(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                let (id, exits) = (self.started, self.exits.0.clone());
+                self.started += 1;
+                std::thread::spawn(move || {
+                    let _ = exits.send((id, child.wait()));
+                });
+                self.running.insert(id, Running { path: job.path.clone(), temp });
+            }
+            Err(e) => {
+                eprintln!("Error running --exec for {}: {}: {}", job.path.display(), words[0], e);
+                remove(temp.as_deref());
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        while !self.running.is_empty() {
+            self.wait();
+        }
+        Ok(())
+    }
+}
+
+/// Report a command that failed, and remove its record's temporary file
+fn finished(running: &Running, status: std::io::Result<ExitStatus>) {
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Error running --exec for {}: {}", running.path.display(), status),
+        Err(e) => eprintln!("Error running --exec for {}: {}", running.path.display(), e),
+    }
+    remove(running.temp.as_deref());
+}
+
+fn remove(temp: Option<&Path>) {
+    if let Some(temp) = temp {
+        let _ = std::fs::remove_file(temp);
+    }
+}
+
+/// A path as a command argument, with `./` before a relative path starting with
+/// `-` so it is not taken for an option
+fn argument_path(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    if path.is_relative() && text.starts_with('-') { format!("./{}", text) } else { text.into_owned() }
+}
+
+/// Write a record's JSON to a new file in the temporary directory. The file is
+/// created afresh, never through an existing file or link another user left
+/// there, and only its owner can read it.
+fn write_temp(contents: &[u8]) -> Result<PathBuf> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    for _ in 0..TEMP_ATTEMPTS {
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("jme-exec-{}-{}.json", std::process::id(), count));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = match options.open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        };
+        if let Err(e) = file.write_all(contents) {
+            remove(Some(&path));
+            return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+        }
+        return Ok(path);
+    }
+    bail!("Failed to create a temporary file for --exec in {}", std::env::temp_dir().display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_split_words() {
+        let words = split_words(r#"upload --tag "two words" {format(capture_time, "%Y %m")|default(x y)} 'it''s' {{a}}"#).unwrap();
+        assert_eq!(words, ["upload", "--tag", "two words", r#"{format(capture_time, "%Y %m")|default(x y)}"#, "its", "{{a}}"]);
+        assert_eq!(split_words(r#""" x"#).unwrap(), ["", "x"]);
+        assert!(split_words("echo {file").is_err() && split_words("echo 'x").is_err());
+        assert!(ExecCommand::parse("  ").is_err() && ExecCommand::parse("echo {file|nope}").is_err());
+    }
+
+    #[test]
+    fn test_exec_command() {
+        assert!(ExecCommand::parse("upload {json_path|default(x)}").unwrap().uses_json_path);
+        assert!(!ExecCommand::parse("echo json_path {{json_path}} {file}").unwrap().uses_json_path);
+
+        let command = ExecCommand::parse("tag -k {keywords.0} --title={title}").unwrap();
+        let words = |keyword: &str, title: &str| vec!["tag".to_string(), "-k".to_string(), keyword.to_string(), format!("--title={}", title)];
+        assert!(!command.injects_option(&words("harbour", "-1 and more")));
+        assert!(command.injects_option(&words("--delete", "x")));
+        assert_eq!(argument_path(Path::new("-rf.jpg")), "./-rf.jpg");
+        assert_eq!(argument_path(Path::new("images/-rf.jpg")), "images/-rf.jpg");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_sink() {
+        let dir = std::env::temp_dir().join(format!("jme-exec-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out.txt");
+        let command = format!(r#"sh -c 'cat "$1" > "$2.json"; echo "$3" > "$2"' sh {{json_path}} {} "{{camera_model}} {{file}}""#, out.display());
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--format", "jsonl", "--exec", &command, "images"]);
+        let registry = crate::ExtractorRegistry::new();
+        let job = Job::new(PathBuf::from("images/JAM26284.jpg"));
+        let metadata = crate::extract_metadata(&job.path, &args, &registry).unwrap();
+        let mut sink = ExecSink::new(args.exec.clone().unwrap(), &args);
+        sink.write(&job, metadata).unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "Canon EOS 5D Mark IV images/JAM26284.jpg\n");
+        let record: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join("out.txt.json")).unwrap()).unwrap();
+        assert_eq!(record["file"]["filename"], "JAM26284.jpg");
+        // The record's temporary file is gone once the command exits
+        assert!(!std::fs::read_dir(std::env::temp_dir()).unwrap()
+            .any(|e| e.unwrap().file_name().to_string_lossy().starts_with(&format!("jme-exec-{}-", std::process::id()))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
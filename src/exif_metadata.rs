@@ -0,0 +1,935 @@
+use crate::cameras::{self, Enrichment};
+use crate::charset::Charset;
+use crate::colorspace::ExifColorTags;
+use crate::content::ExtractOptions;
+use crate::error::Result;
+use crate::focus::{self, Focus};
+use crate::jpeg::{self, Segment};
+use crate::lighting::{self, Flash, WhiteBalance};
+use crate::makernote;
+use crate::os_metadata::{self, OsMetadata};
+use crate::provenance::{ExifLocations, Source};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use exif::{Context, Exif, Field, In, Reader, Tag, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// TIFF/EP ImageNumber, which kamadak-exif has no constant for
+const IMAGE_NUMBER: u16 = 0x9211;
+/// Date/time tags with the tags holding their fractional seconds
+const SUBSEC_TAGS: [(Tag, Tag); 3] = [
+    (Tag::DateTimeOriginal, Tag::SubSecTimeOriginal),
+    (Tag::DateTimeDigitized, Tag::SubSecTimeDigitized),
+    (Tag::DateTime, Tag::SubSecTime),
+];
+/// ASCII tags reported as text, with their output field names
+const TEXT_FIELDS: [(&str, Tag); 6] = [
+    ("camera_model", Tag::Model),
+    ("camera_serial", Tag::BodySerialNumber),
+    ("image_unique_id", Tag::ImageUniqueID),
+    ("description.image_description", Tag::ImageDescription),
+    ("artist", Tag::Artist),
+    ("copyright", Tag::Copyright),
+];
+
+/// Where a record's `best_time` came from. The variants are in order of
+/// precedence: the first timestamp a file has is the one reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSource {
+    /// DateTimeOriginal, when the shutter fired
+    ExifOriginal,
+    /// DateTimeDigitized, when the image was stored, or scanned for film
+    ExifDigitized,
+    /// GPSDateStamp and GPSTimeStamp, in UTC from the satellites
+    Gps,
+    /// Parsed from the file name, by the command line's --date-from-filename
+    Filename,
+    /// The file's modification time, which every file has
+    FilesystemModified,
+}
+
+/// Fields read from a JPEG's EXIF segment
+#[derive(Debug, Default)]
+pub struct ExifMetadata {
+    pub orientation: Option<u32>,
+    pub capture_time: Option<DateTime<Utc>>,
+    /// The first of DateTimeOriginal, DateTimeDigitized and the GPS time; unlike
+    /// `capture_time` it never falls back to DateTime, which editors rewrite
+    pub best_time: Option<(DateTime<Utc>, TimeSource)>,
+    pub camera_model: Option<String>,
+    pub camera_serial: Option<String>,
+    /// Firmware version from a Canon maker note or the Software tag, see
+    /// [`makernote::firmware`] and [`makernote::software_firmware`]
+    pub camera_firmware: Option<String>,
+    /// ImageUniqueID, which editors are meant to keep when they save a copy
+    pub image_unique_id: Option<String>,
+    /// TIFF/EP ImageNumber, which some cameras use to number frames in a sequence
+    pub sequence_number: Option<u32>,
+    /// Shutter actuations from the maker note, or failing that ImageNumber
+    pub shutter_count: Option<u32>,
+    pub flash: Option<Flash>,
+    pub white_balance: Option<WhiteBalance>,
+    /// ColorSpace, InteroperabilityIndex and resolution, checked by [`crate::colorspace::ColorSpace::check`]
+    pub color_tags: ExifColorTags,
+    pub focus: Option<Focus>,
+    /// Camera database values for the model
+    pub enrichment: Option<Enrichment>,
+    pub gps: Option<GpsPosition>,
+    pub thumbnail_length: Option<u32>,
+    /// Offset of the thumbnail from the start of the TIFF header
+    pub thumbnail_offset: Option<u32>,
+    /// Tags requested by numeric ID, keyed by "0xNNNN"
+    pub extra: BTreeMap<String, String>,
+    /// Typed values of the tags in `extra`, under the same keys
+    pub values: BTreeMap<String, ExifValue>,
+    pub description: Description,
+    pub artist: Option<String>,
+    /// Copyright notice; a separate editor's notice is joined after the photographer's
+    pub copyright: Option<String>,
+    /// Properties set in Windows Explorer, when [`ExtractOptions::windows_properties`] is set
+    pub windows_properties: Option<OsMetadata>,
+    /// Text fields that were not UTF-8, with the character set they were decoded from
+    pub transcoded_fields: BTreeMap<String, Charset>,
+    /// Where each field above came from, keyed by output field name; only
+    /// filled in when [`ExtractOptions::provenance`] is set
+    pub provenance: BTreeMap<String, Source>,
+    /// Problems worked around while reading, such as a missing EXIF segment or damaged IFDs
+    pub warnings: Vec<String>,
+}
+
+/// An EXIF value with its TIFF type, for arithmetic without parsing display text
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum ExifValue {
+    Byte(Vec<u8>),
+    /// ASCII strings, decoded and trimmed of trailing NULs
+    Ascii(Vec<String>),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<Rational>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<SRational>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    /// A type this crate does not understand, with its TIFF type code and count
+    Unknown { type_code: u16, count: u32 },
+}
+
+/// Unsigned rational, e.g. an exposure time of 1/250
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rational {
+    pub num: u32,
+    pub denom: u32,
+}
+
+/// Signed rational, e.g. an exposure bias of -1/3
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SRational {
+    pub num: i32,
+    pub denom: i32,
+}
+
+impl Rational {
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.denom as f64
+    }
+}
+
+impl SRational {
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.denom as f64
+    }
+}
+
+impl From<&Value> for ExifValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Byte(v) => ExifValue::Byte(v.clone()),
+            Value::Ascii(v) => ExifValue::Ascii(v.iter().map(|s| decode_text(s, Charset::Auto)).collect()),
+            Value::Short(v) => ExifValue::Short(v.clone()),
+            Value::Long(v) => ExifValue::Long(v.clone()),
+            Value::Rational(v) => ExifValue::Rational(
+                v.iter().map(|r| Rational { num: r.num, denom: r.denom }).collect()),
+            Value::SByte(v) => ExifValue::SByte(v.clone()),
+            Value::Undefined(v, _) => ExifValue::Undefined(v.clone()),
+            Value::SShort(v) => ExifValue::SShort(v.clone()),
+            Value::SLong(v) => ExifValue::SLong(v.clone()),
+            Value::SRational(v) => ExifValue::SRational(
+                v.iter().map(|r| SRational { num: r.num, denom: r.denom }).collect()),
+            Value::Float(v) => ExifValue::Float(v.clone()),
+            Value::Double(v) => ExifValue::Double(v.clone()),
+            Value::Unknown(type_code, count, _) => ExifValue::Unknown { type_code: *type_code, count: *count },
+        }
+    }
+}
+
+/// Free-text descriptive fields, decoded to UTF-8
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Description {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_comment: Option<String>,
+    /// Character code declared by the UserComment prefix: ascii, jis, unicode or undefined
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_comment_encoding: Option<String>,
+}
+
+impl Description {
+    pub fn is_empty(&self) -> bool {
+        self.image_description.is_none() && self.user_comment.is_none()
+    }
+}
+
+/// Position from the GPS IFD, in decimal degrees (negative south and west),
+/// with the receiver's movement when it was recorded
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level, negative below it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    /// Geodetic datum of the position as recorded in GPSMapDatum, e.g. `WGS-84`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datum: Option<String>,
+    /// Ground speed in metres per second, from GPSSpeed in km/h, mph or knots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    /// Direction of movement, from GPSTrack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<Bearing>,
+    /// Direction the camera pointed, from GPSImgDirection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_direction: Option<Bearing>,
+    /// Dilution of precision of the fix, lower being more precise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dop: Option<f64>,
+}
+
+/// A direction in degrees clockwise from north
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bearing {
+    pub degrees: f64,
+    pub north: North,
+}
+
+/// Which north a [`Bearing`] is measured from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum North {
+    True,
+    Magnetic,
+}
+
+/// Extract EXIF metadata from a JPEG stream, reading no further than the start of scan
+pub fn read_exif_metadata<R: Read>(reader: &mut R, options: &ExtractOptions) -> Result<ExifMetadata> {
+    let header = jpeg::read_header(reader)?;
+    let mut metadata = exif_from_segments(&header.segments, options)?;
+    metadata.warnings.splice(0..0, header.warnings);
+    Ok(metadata)
+}
+
+/// Where the TIFF data of an APP1 segment starts: after the `Exif\0\0` signature,
+/// or failing that the first TIFF header in the first 64 bytes, as some phones
+/// write a damaged signature or junk before it
+fn tiff_start(segment: &Segment) -> Option<usize> {
+    if segment.marker != 0xE1 || segment.is_app(1, jpeg::XMP_SIGNATURE) || segment.is_app(1, jpeg::XMP_EXTENSION_SIGNATURE) {
+        return None;
+    }
+    let is_tiff_header = |bytes: &[u8]| bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*");
+    let standard = jpeg::EXIF_SIGNATURE.len();
+    if segment.data.starts_with(jpeg::EXIF_SIGNATURE) && is_tiff_header(&segment.data[standard..]) {
+        return Some(standard);
+    }
+    let window = &segment.data[..segment.data.len().min(64)];
+    (0..window.len()).find(|&i| is_tiff_header(&segment.data[i..]))
+}
+
+/// The APP1 segments holding the rest of an EXIF block too large for the one at
+/// `index`: those directly after it that start with the Exif signature but no TIFF
+/// header. Some cameras and editors split EXIF with a large maker note this way.
+fn continuation_segments(segments: &[Segment], index: usize) -> &[Segment] {
+    if segments[index].data.len() + 2 < u16::MAX as usize {
+        return &[];
+    }
+    let rest = &segments[index + 1..];
+    let count = rest.iter()
+        .take_while(|s| s.is_app(1, jpeg::EXIF_SIGNATURE) && tiff_start(s) != Some(jpeg::EXIF_SIGNATURE.len()))
+        .count();
+    &rest[..count]
+}
+
+/// Extract EXIF metadata from already-parsed header segments.
+///
+/// Every APP1 segment holding TIFF data is tried in order and the first that
+/// parses is used, keeping whatever fields survive damaged IFDs. A file without
+/// readable EXIF gives empty metadata; each problem is noted in `warnings`.
+pub fn exif_from_segments(segments: &[Segment], options: &ExtractOptions) -> Result<ExifMetadata> {
+    let mut warnings = Vec::new();
+    let mut found = None;
+    for (index, start) in segments.iter().enumerate().filter_map(|(i, s)| Some((i, tiff_start(s)?))) {
+        let mut tiff = segments[index].data[start..].to_vec();
+        let continued = continuation_segments(segments, index);
+        if !continued.is_empty() {
+            warnings.push(format!("EXIF data continues over {} more APP1 segments", continued.len()));
+            tiff.extend(continued.iter().flat_map(|s| &s.data[jpeg::EXIF_SIGNATURE.len()..]));
+        }
+        let mut reader = Reader::new();
+        reader.continue_on_error(true);
+        let result = reader.read_raw(tiff.clone()).or_else(|e| e.distill_partial_result(|errors| {
+            warnings.extend(errors.iter().map(|e| format!("Skipped unreadable EXIF data: {}", e)));
+        }));
+        match result {
+            Ok(exif) if exif.fields().next().is_some() => {
+                if start != jpeg::EXIF_SIGNATURE.len() {
+                    warnings.push(format!("EXIF data starts at byte {} of its APP1 segment instead of after an Exif signature", start));
+                }
+                let first_len = segments[index].data.len() - start;
+                found = Some((index, start, first_len, tiff, exif));
+                break;
+            }
+            Ok(_) => warnings.push("Ignored an EXIF segment with no readable fields".to_string()),
+            Err(e) => warnings.push(format!("Ignored unreadable EXIF segment: {}", e)),
+        }
+    }
+    let Some((index, start, first_len, tiff, exif)) = found else {
+        if warnings.is_empty() {
+            warnings.push("No EXIF data found".to_string());
+        }
+        return Ok(ExifMetadata { warnings, ..ExifMetadata::default() });
+    };
+    // The TIFF header follows the marker, length and signature
+    let tiff_offset = jpeg::segment_offset(segments, index) + 4 + start as u64;
+    // Offsets past the first segment would not account for the headers of the rest
+    let locations = options.provenance.then(|| ExifLocations::parse(&tiff[..first_len], tiff_offset));
+
+    let orientation = exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0));
+
+    let capture_time = [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| exif_datetime(&exif, tag))
+        .or_else(|| gps_datetime(&exif));
+    let best_time = [(Tag::DateTimeOriginal, TimeSource::ExifOriginal), (Tag::DateTimeDigitized, TimeSource::ExifDigitized)]
+        .into_iter()
+        .find_map(|(tag, source)| Some((exif_datetime(&exif, tag)?, source)))
+        .or_else(|| Some((gps_datetime(&exif)?, TimeSource::Gps)));
+
+    let string_field = |tag: Tag| exif.get_field(tag, In::PRIMARY)
+        .map(|field| string_value(field, &exif, options.raw_values, options.input_charset));
+    let camera_model = string_field(Tag::Model);
+    let camera_serial = string_field(Tag::BodySerialNumber);
+    let camera_firmware = makernote::firmware(&exif).or_else(|| software_firmware(&exif));
+    let image_unique_id = string_field(Tag::ImageUniqueID).filter(|id| !id.is_empty());
+    let sequence_number = [Context::Exif, Context::Tiff].into_iter()
+        .find_map(|context| exif.get_field(Tag(context, IMAGE_NUMBER), In::PRIMARY))
+        .and_then(|field| field.value.get_uint(0));
+
+    let shutter_count = makernote::shutter_count(&exif).or(sequence_number);
+    let flash = lighting::flash(&exif);
+    let white_balance = lighting::white_balance(&exif);
+    let color_tags = ExifColorTags::from_exif(&exif);
+    let camera = camera_model.as_deref().and_then(|model| cameras::lookup(model, &options.cameras));
+    let focus = focus::from_exif(&exif, camera.as_ref());
+    let enrichment = camera.map(|spec| Enrichment::new(&spec, focus::focal_length(&exif)));
+    let gps = gps_position(&exif);
+
+    let thumbnail_length = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)
+        .and_then(|field| field.value.get_uint(0));
+    let thumbnail_offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)
+        .and_then(|field| field.value.get_uint(0));
+
+    let requested: Vec<(String, &Field)> = options.tags.iter()
+        .filter_map(|&id| {
+            let field = exif.fields().find(|f| f.ifd_num == In::PRIMARY && f.tag.number() == id)?;
+            Some((format!("0x{:04X}", id), field))
+        })
+        .collect();
+    let extra = requested.iter()
+        .map(|(key, field)| (key.clone(), extra_value(field, &exif, options.raw_values, options.input_charset)))
+        .collect();
+    let values = requested.iter()
+        .map(|(key, field)| (key.clone(), ExifValue::from(&field.value)))
+        .collect();
+
+    let image_description = exif.get_field(Tag::ImageDescription, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            Value::Ascii(values) => values.first().map(|v| decode_text(v, options.input_charset)),
+            _ => None,
+        })
+        .filter(|text| !text.is_empty());
+
+    let ascii_field = |tag: Tag| exif.get_field(tag, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            Value::Ascii(values) => Some(values.iter().map(|v| decode_text(v, options.input_charset)).filter(|v| !v.is_empty()).collect::<Vec<_>>().join("; ")),
+            _ => None,
+        })
+        .filter(|text| !text.is_empty());
+    let artist = ascii_field(Tag::Artist);
+    let copyright = ascii_field(Tag::Copyright);
+
+    let (user_comment, user_comment_encoding) = exif.get_field(Tag::UserComment, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            Value::Undefined(bytes, _) => decode_user_comment(bytes, exif.little_endian(), options.input_charset),
+            _ => None,
+        })
+        .filter(|(text, _)| !text.is_empty())
+        .map(|(text, encoding)| (Some(text), Some(encoding.to_string())))
+        .unwrap_or_default();
+
+    let transcoded_fields = transcoded_fields(&exif, options);
+    let windows_properties = options.windows_properties.then(|| os_metadata::windows_properties(&exif)).flatten();
+
+    warnings.extend(field_warnings(&exif));
+
+    let mut metadata = ExifMetadata {
+        orientation,
+        capture_time,
+        best_time,
+        camera_model,
+        camera_serial,
+        camera_firmware,
+        image_unique_id,
+        sequence_number,
+        shutter_count,
+        flash,
+        white_balance,
+        color_tags,
+        focus,
+        enrichment,
+        gps,
+        thumbnail_length,
+        thumbnail_offset,
+        extra,
+        values,
+        description: Description { image_description, user_comment, user_comment_encoding },
+        artist,
+        copyright,
+        windows_properties,
+        transcoded_fields,
+        provenance: BTreeMap::new(),
+        warnings,
+    };
+    if let Some(locations) = locations {
+        metadata.provenance = exif_provenance(&exif, &locations, &metadata, &options.tags);
+    }
+    Ok(metadata)
+}
+
+/// Sources of the fields of `metadata` that are present
+fn exif_provenance(exif: &Exif, locations: &ExifLocations, metadata: &ExifMetadata, tags: &[u16]) -> BTreeMap<String, Source> {
+    let present = |tags: &[Tag]| -> Vec<Source> {
+        tags.iter()
+            .filter(|&&tag| exif.get_field(tag, In::PRIMARY).is_some())
+            .map(|&tag| locations.source(tag, In::PRIMARY))
+            .collect()
+    };
+    // One tag is reported as itself, several as a value derived from them all
+    let read_from = |tags: &[Tag]| {
+        let mut sources = present(tags);
+        match sources.len() {
+            0 => None,
+            1 => sources.pop(),
+            _ => Some(Source::derived(sources)),
+        }
+    };
+    let image_number = [Tag(Context::Exif, IMAGE_NUMBER), Tag(Context::Tiff, IMAGE_NUMBER)];
+    let capture_tags: Vec<Tag> = match SUBSEC_TAGS.iter().find(|&&(tag, _)| exif_datetime(exif, tag).is_some()) {
+        Some(&(tag, subsec)) => vec![tag, subsec],
+        None => vec![Tag::GPSDateStamp, Tag::GPSTimeStamp],
+    };
+    let shutter_count_tags = if makernote::shutter_count(exif).is_some() { vec![Tag::MakerNote] } else { image_number.to_vec() };
+
+    let mut provenance = BTreeMap::new();
+    let mut add = |field: &str, present: bool, source: Option<Source>| {
+        if let Some(source) = source.filter(|_| present) {
+            provenance.insert(field.to_string(), source);
+        }
+    };
+    add("orientation", metadata.orientation.is_some(), read_from(&[Tag::Orientation]));
+    add("capture_time", metadata.capture_time.is_some(), read_from(&capture_tags));
+    let best_time_tags = match metadata.best_time.map(|(_, source)| source) {
+        Some(TimeSource::ExifOriginal) => vec![Tag::DateTimeOriginal, Tag::SubSecTimeOriginal],
+        Some(TimeSource::ExifDigitized) => vec![Tag::DateTimeDigitized, Tag::SubSecTimeDigitized],
+        _ => vec![Tag::GPSDateStamp, Tag::GPSTimeStamp],
+    };
+    add("best_time", metadata.best_time.is_some(), read_from(&best_time_tags));
+    add("camera_model", metadata.camera_model.is_some(), read_from(&[Tag::Model]));
+    add("camera_serial", metadata.camera_serial.is_some(), read_from(&[Tag::BodySerialNumber]));
+    let firmware_tag = if makernote::firmware(exif).is_some() { Tag::MakerNote } else { Tag::Software };
+    add("camera_firmware", metadata.camera_firmware.is_some(), read_from(&[firmware_tag]));
+    add("image_unique_id", metadata.image_unique_id.is_some(), read_from(&[Tag::ImageUniqueID]));
+    add("sequence_number", metadata.sequence_number.is_some(), read_from(&image_number[..1]).or_else(|| read_from(&image_number[1..])));
+    add("shutter_count", metadata.shutter_count.is_some(), read_from(&shutter_count_tags));
+    add("flash", metadata.flash.is_some(), read_from(&[Tag::Flash]));
+    add("white_balance", metadata.white_balance.is_some(), read_from(&[Tag::WhiteBalance, Tag::LightSource]));
+    let focus_tags = [Tag::FocalLength, Tag::FNumber, Tag::SubjectDistance, Tag::FocalPlaneXResolution, Tag::FocalPlaneYResolution];
+    add("focus", metadata.focus.is_some(), Some(Source::derived(present(&focus_tags))));
+    add("enrichment", metadata.enrichment.is_some(), Some(Source::derived(present(&[Tag::Model, Tag::FocalLength]))));
+    let gps_tags = [
+        Tag::GPSLatitudeRef, Tag::GPSLatitude, Tag::GPSLongitudeRef, Tag::GPSLongitude, Tag::GPSAltitudeRef, Tag::GPSAltitude, Tag::GPSMapDatum,
+        Tag::GPSSpeedRef, Tag::GPSSpeed, Tag::GPSTrackRef, Tag::GPSTrack, Tag::GPSImgDirectionRef, Tag::GPSImgDirection, Tag::GPSDOP,
+    ];
+    add("gps", metadata.gps.is_some(), Some(Source::derived(present(&gps_tags))));
+    add("description.image_description", metadata.description.image_description.is_some(), read_from(&[Tag::ImageDescription]));
+    add("description.user_comment", metadata.description.user_comment.is_some(), read_from(&[Tag::UserComment]));
+    add("artist", metadata.artist.is_some(), read_from(&[Tag::Artist]));
+    add("copyright", metadata.copyright.is_some(), read_from(&[Tag::Copyright]));
+    let windows_tags = os_metadata::WINDOWS_TAGS.map(|id| Tag(Context::Tiff, id));
+    add("windows_properties", metadata.windows_properties.is_some(), Some(Source::derived(present(&windows_tags))));
+    for &id in tags {
+        let key = format!("0x{:04X}", id);
+        let field = exif.fields().find(|f| f.ifd_num == In::PRIMARY && f.tag.number() == id);
+        add(&format!("exif_extra.{}", key), metadata.extra.contains_key(&key), field.map(|f| locations.source(f.tag, In::PRIMARY)));
+    }
+    provenance
+}
+
+/// Which reported text fields needed transcoding to UTF-8, and from what
+fn transcoded_fields(exif: &Exif, options: &ExtractOptions) -> BTreeMap<String, Charset> {
+    let transcoded = |field: &Field| match &field.value {
+        Value::Ascii(values) => values.iter().find_map(|v| options.input_charset.transcoded_from(v)),
+        // Text after the character code, unless that names JIS or Unicode
+        Value::Undefined(bytes, _) if field.tag == Tag::UserComment => bytes.split_at_checked(8)
+            .filter(|(code, _)| !matches!(*code, b"JIS\0\0\0\0\0" | b"UNICODE\0"))
+            .and_then(|(_, text)| options.input_charset.transcoded_from(text)),
+        _ => None,
+    };
+    let fields = TEXT_FIELDS.into_iter()
+        .chain([("description.user_comment", Tag::UserComment)])
+        .map(|(name, tag)| (name.to_string(), tag))
+        .chain(options.tags.iter().map(|&id| (format!("exif_extra.0x{:04X}", id), Tag(Context::Exif, id))));
+    fields
+        .filter_map(|(name, tag)| {
+            let field = exif.fields().find(|f| f.ifd_num == In::PRIMARY && f.tag.number() == tag.number())?;
+            Some((name, transcoded(field)?))
+        })
+        .collect()
+}
+
+/// Decode nominally-ASCII text to UTF-8 from `charset`, trimming trailing NULs and whitespace
+fn decode_text(bytes: &[u8], charset: Charset) -> String {
+    charset.decode(bytes).trim_end_matches(|c: char| c == '\0' || c.is_whitespace()).to_string()
+}
+
+/// Decode UserComment, whose first 8 bytes name the character code of the rest
+fn decode_user_comment(bytes: &[u8], little_endian: bool, charset: Charset) -> Option<(String, &'static str)> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let (code, text) = bytes.split_at(8);
+    let decoded = match code {
+        b"ASCII\0\0\0" => (decode_text(text, charset), "ascii"),
+        b"JIS\0\0\0\0\0" => {
+            // JIS X 0208 code points are EUC-JP with the high bit of each byte cleared
+            let euc: Vec<u8> = text.iter().map(|b| if *b >= 0x21 { b | 0x80 } else { *b }).collect();
+            let (text, _, _) = encoding_rs::EUC_JP.decode(&euc);
+            (text.trim_end_matches(['\0', ' ']).to_string(), "jis")
+        }
+        b"UNICODE\0" => {
+            // UCS-2 in the byte order of the TIFF header, though some writers use
+            // little-endian regardless; honour a byte order mark when present
+            let encoding = match text {
+                [0xFF, 0xFE, ..] => encoding_rs::UTF_16LE,
+                [0xFE, 0xFF, ..] => encoding_rs::UTF_16BE,
+                _ if little_endian => encoding_rs::UTF_16LE,
+                _ => encoding_rs::UTF_16BE,
+            };
+            let (text, _, _) = encoding.decode(text);
+            (text.trim_end_matches(|c: char| c == '\0' || c.is_whitespace()).to_string(), "unicode")
+        }
+        _ => (decode_text(text, charset), "undefined"),
+    };
+    Some(decoded)
+}
+
+/// Format a tag requested by ID. Tags kamadak-exif knows are shown as display
+/// values; byte-typed values of unknown (e.g. vendor) tags are hex-encoded.
+fn extra_value(field: &Field, exif: &Exif, raw_values: bool, charset: Charset) -> String {
+    let known = field.tag.description().is_some();
+    match &field.value {
+        Value::Undefined(bytes, _) | Value::Byte(bytes) if !known => to_hex(bytes),
+        Value::SByte(bytes) if !known => to_hex(&bytes.iter().map(|&b| b as u8).collect::<Vec<_>>()),
+        _ => string_value(field, exif, raw_values, charset),
+    }
+}
+
+/// A field as a clean string: ASCII values are decoded and trimmed of trailing
+/// NULs and whitespace, everything else uses its display form. With
+/// `raw_values`, the display form is used as-is (ASCII values stay quoted).
+fn string_value(field: &Field, exif: &Exif, raw_values: bool, charset: Charset) -> String {
+    match &field.value {
+        Value::Ascii(values) if !raw_values => {
+            values.iter().map(|v| decode_text(v, charset)).collect::<Vec<_>>().join(", ")
+        }
+        _ => field.display_value().with_unit(exif).to_string(),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse an EXIF ASCII date/time field ("YYYY:MM:DD HH:MM:SS")
+fn exif_datetime(exif: &Exif, tag: Tag) -> Option<DateTime<Utc>> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let ascii = match &field.value {
+        Value::Ascii(values) => values.first()?,
+        _ => return None,
+    };
+    let mut dt = exif::DateTime::from_ascii(ascii).ok()?;
+    let subsec = SUBSEC_TAGS.iter()
+        .find(|&&(t, _)| t == tag)
+        .and_then(|&(_, subsec_tag)| exif.get_field(subsec_tag, In::PRIMARY));
+    if let Some(Value::Ascii(values)) = subsec.map(|field| &field.value) {
+        // A malformed fraction is ignored rather than losing the whole timestamp
+        if let Some(digits) = values.first().and_then(|v| v.split(|&b| b == 0).next()) {
+            let _ = dt.parse_subsec(digits);
+        }
+    }
+    let date = NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?;
+    let naive = date.and_hms_nano_opt(dt.hour.into(), dt.minute.into(), dt.second.into(), dt.nanosecond.unwrap_or(0))?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// Fields that are present but were left out of the metadata, or reported as
+/// they are despite being out of range
+fn field_warnings(exif: &Exif) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let present = |tag| exif.get_field(tag, In::PRIMARY).is_some();
+    let shown = |tag| exif.get_field(tag, In::PRIMARY).map_or(String::new(), |f| f.display_value().to_string());
+
+    for tag in [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime] {
+        if present(tag) && exif_datetime(exif, tag).is_none() {
+            warnings.push(format!("{} present but unparseable: {}", tag, shown(tag)));
+        }
+    }
+    if (present(Tag::GPSDateStamp) || present(Tag::GPSTimeStamp)) && gps_datetime(exif).is_none() {
+        warnings.push("GPSDateStamp and GPSTimeStamp present but unusable".to_string());
+    }
+    if let Some(orientation) = exif.get_field(Tag::Orientation, In::PRIMARY).and_then(|f| f.value.get_uint(0)) {
+        if !(1..=8).contains(&orientation) {
+            warnings.push(format!("Orientation {} is not 1 to 8", orientation));
+        }
+    }
+
+    if COORDINATES.iter().any(|&(tag, _, _)| present(tag)) {
+        for (tag, ref_tag, negative) in COORDINATES {
+            if !present(tag) {
+                warnings.push(format!("GPS position without {}", tag));
+            } else if !present(ref_tag) {
+                warnings.push(format!("{} without {}", tag, ref_tag));
+            } else if gps_coordinate(exif, tag, ref_tag, negative).is_none() {
+                warnings.push(format!("{} present but unparseable: {}", tag, shown(tag)));
+            }
+        }
+    }
+    warnings
+}
+
+/// Combine GPSDateStamp and GPSTimeStamp, which are always recorded in UTC
+fn gps_datetime(exif: &Exif) -> Option<DateTime<Utc>> {
+    let date = match &exif.get_field(Tag::GPSDateStamp, In::PRIMARY)?.value {
+        Value::Ascii(values) => std::str::from_utf8(values.first()?).ok()?.to_string(),
+        _ => return None,
+    };
+    let date = NaiveDate::parse_from_str(date.trim(), "%Y:%m:%d").ok()?;
+
+    let time = match &exif.get_field(Tag::GPSTimeStamp, In::PRIMARY)?.value {
+        Value::Rational(values) if values.len() >= 3 => {
+            (values[0].to_f64(), values[1].to_f64(), values[2].to_f64())
+        }
+        _ => return None,
+    };
+    let naive = date.and_hms_opt(time.0 as u32, time.1 as u32, time.2 as u32)?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// Coordinate tags with their reference tags and the reference letter that makes them negative
+const COORDINATES: [(Tag, Tag, u8); 2] = [
+    (Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+    (Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+];
+
+/// Signed decimal degrees from a coordinate tag and its reference tag
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative: u8) -> Option<f64> {
+    let degrees = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) if v.len() >= 3 && v[0].denom != 0 && v[1].denom != 0 => {
+            // Some writers leave the seconds as 0/0 when minutes carry the fraction
+            let seconds = if v[2].denom == 0 { 0.0 } else { v[2].to_f64() };
+            v[0].to_f64() + v[1].to_f64() / 60.0 + seconds / 3600.0
+        }
+        _ => return None,
+    };
+    let sign = match &exif.get_field(ref_tag, In::PRIMARY)?.value {
+        Value::Ascii(values) if values.first()?.first() == Some(&negative) => -1.0,
+        _ => 1.0,
+    };
+    Some((sign * degrees * 1e7).round() / 1e7)
+}
+
+/// GPSLatitude and GPSLongitude with their reference tags, and GPSAltitude if present
+fn gps_position(exif: &Exif) -> Option<GpsPosition> {
+    let [(lat, lat_ref, south), (lon, lon_ref, west)] = COORDINATES;
+    let latitude = gps_coordinate(exif, lat, lat_ref, south)?;
+    let longitude = gps_coordinate(exif, lon, lon_ref, west)?;
+    let altitude = gps_rational(exif, Tag::GPSAltitude);
+    // 1 is below sea level; some writers store the byte as the text "1"
+    let below_sea_level = match exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(values)) => values.first().is_some_and(|v| v.trim_ascii() == b"1"),
+        Some(value) => value.get_uint(0) == Some(1),
+        None => exif.get_field(Tag::GPSAltitude, In::PRIMARY)
+            .is_some_and(|f| matches!(&f.value, Value::SRational(v) if v.first().is_some_and(|r| r.num < 0))),
+    };
+    let altitude = altitude.map(|a| if below_sea_level { -a } else { a });
+    // Metres per second in one unit of each GPSSpeedRef, km/h being the default
+    let speed = gps_rational(exif, Tag::GPSSpeed).map(|speed| match gps_ref(exif, Tag::GPSSpeedRef) {
+        Some(b'M') => speed * 0.44704,
+        Some(b'N') => speed * 1852.0 / 3600.0,
+        _ => speed / 3.6,
+    });
+    Some(GpsPosition {
+        latitude,
+        longitude,
+        altitude,
+        datum: gps_text(exif, Tag::GPSMapDatum),
+        speed: speed.map(|s| (s * 1000.0).round() / 1000.0),
+        track: gps_bearing(exif, Tag::GPSTrack, Tag::GPSTrackRef),
+        image_direction: gps_bearing(exif, Tag::GPSImgDirection, Tag::GPSImgDirectionRef),
+        dop: gps_rational(exif, Tag::GPSDOP),
+    })
+}
+
+/// The camera's firmware version as written in the Software tag, if it is one
+fn software_firmware(exif: &Exif) -> Option<String> {
+    match &exif.get_field(Tag::Software, In::PRIMARY)?.value {
+        Value::Ascii(values) => makernote::software_firmware(&String::from_utf8_lossy(values.first()?)),
+        _ => None,
+    }
+}
+
+/// The first value of a RATIONAL GPS tag
+fn gps_rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) => v.first().filter(|r| r.denom != 0).map(|r| r.to_f64()),
+        // Out of spec, but some writers sign the value itself (e.g. GPSAltitude)
+        Value::SRational(v) => v.first().filter(|r| r.denom != 0).map(|r| r.to_f64().abs()),
+        _ => None,
+    }
+}
+
+/// A GPS text tag, trimmed of the padding some writers leave
+fn gps_text(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => {
+            let text = String::from_utf8_lossy(values.first()?).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+            Some(text).filter(|t| !t.is_empty())
+        }
+        _ => None,
+    }
+}
+
+/// The letter of a GPS reference tag, such as `K` for GPSSpeedRef in km/h
+fn gps_ref(exif: &Exif, tag: Tag) -> Option<u8> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first()?.first().copied(),
+        _ => None,
+    }
+}
+
+/// A direction tag with its reference tag, `T` for true north and `M` for
+/// magnetic; true north when the reference is missing, as the standard defaults to
+fn gps_bearing(exif: &Exif, tag: Tag, ref_tag: Tag) -> Option<Bearing> {
+    let degrees = gps_rational(exif, tag).filter(|d| (0.0..=360.0).contains(d))?;
+    let north = match gps_ref(exif, ref_tag) {
+        Some(b'M') => North::Magnetic,
+        _ => North::True,
+    };
+    Some(Bearing { degrees: (degrees * 100.0).round() / 100.0 % 360.0, north })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_extra_tags_by_id() {
+        let mut reader = BufReader::new(File::open("images/JAM26284.jpg").unwrap());
+        // Model, and a tag id that is not present in the file
+        let options = ExtractOptions { tags: vec![0x0110, 0xBEEF], ..Default::default() };
+        let exif = read_exif_metadata(&mut reader, &options).unwrap();
+        assert_eq!(exif.extra.len(), 1);
+        assert_eq!(exif.extra["0x0110"], "Canon EOS 5D Mark IV");
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let mut reader = BufReader::new(File::open("images/JAM26284.jpg").unwrap());
+        // ExposureTime, Model
+        let options = ExtractOptions { tags: vec![0x829A, 0x0110], ..Default::default() };
+        let exif = read_exif_metadata(&mut reader, &options).unwrap();
+        match &exif.values["0x829A"] {
+            ExifValue::Rational(v) => assert!(v[0].denom > 0 && v[0].to_f64() > 0.0),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(exif.values["0x0110"], ExifValue::Ascii(vec!["Canon EOS 5D Mark IV".to_string()]));
+    }
+
+    #[test]
+    fn test_raw_values() {
+        let open = || BufReader::new(File::open("images/JAM26284.jpg").unwrap());
+        let clean = read_exif_metadata(&mut open(), &ExtractOptions::default()).unwrap();
+        assert_eq!(clean.camera_serial.as_deref(), Some("025021000535"));
+
+        let options = ExtractOptions { raw_values: true, ..Default::default() };
+        let raw = read_exif_metadata(&mut open(), &options).unwrap();
+        assert_eq!(raw.camera_serial.as_deref(), Some("\"025021000535\""));
+    }
+
+    #[test]
+    fn test_gps_position() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let point = crate::gpx::TrackPoint {
+            time: Utc::now(),
+            latitude: -33.8568,
+            longitude: 151.2153,
+            elevation: Some(-4.5),
+        };
+        let tagged = crate::exif_write::rewrite(&bytes, &crate::gpx::exif_fields(&point), None).unwrap();
+        let exif = read_exif_metadata(&mut tagged.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(exif.gps, Some(GpsPosition {
+            latitude: -33.8568,
+            longitude: 151.2153,
+            altitude: Some(-4.5),
+            datum: Some("WGS-84".to_string()),
+            ..Default::default()
+        }));
+
+        let untagged = read_exif_metadata(&mut bytes.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(untagged.gps, None);
+
+        // A dive photo whose writer stored the altitude reference as text and padded the datum
+        let field = |tag, value| Field { tag, ifd_num: In::PRIMARY, value };
+        let mut fields: Vec<Field> = crate::gpx::exif_fields(&crate::gpx::TrackPoint { elevation: None, ..point }).into_iter()
+            .filter(|f| f.tag != Tag::GPSMapDatum)
+            .collect();
+        fields.extend([
+            field(Tag::GPSAltitudeRef, Value::Ascii(vec![b"1".to_vec()])),
+            field(Tag::GPSAltitude, Value::Rational(vec![exif::Rational { num: 183, denom: 10 }])),
+            field(Tag::GPSMapDatum, Value::Ascii(vec![b"TOKYO   ".to_vec()])),
+        ]);
+        let dive = crate::exif_write::rewrite(&bytes, &fields, None).unwrap();
+        let gps = read_exif_metadata(&mut dive.as_slice(), &ExtractOptions::default()).unwrap().gps.unwrap();
+        assert_eq!((gps.altitude, gps.datum.as_deref()), (Some(-18.3), Some("TOKYO")));
+    }
+
+    #[test]
+    fn test_gps_movement() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let point = crate::gpx::TrackPoint { time: Utc::now(), latitude: 1.0, longitude: 2.0, elevation: None };
+        let field = |tag, value| Field { tag, ifd_num: In::PRIMARY, value };
+        let rational = |num, denom| Value::Rational(vec![exif::Rational { num, denom }]);
+        let ascii = |text: &str| Value::Ascii(vec![text.as_bytes().to_vec()]);
+        let mut fields = crate::gpx::exif_fields(&point);
+        fields.extend([
+            field(Tag::GPSSpeedRef, ascii("N")),
+            field(Tag::GPSSpeed, rational(20, 1)),
+            field(Tag::GPSTrackRef, ascii("M")),
+            field(Tag::GPSTrack, rational(9050, 100)),
+            field(Tag::GPSImgDirection, rational(180, 1)),
+            field(Tag::GPSDOP, rational(12, 10)),
+        ]);
+        let tagged = crate::exif_write::rewrite(&bytes, &fields, None).unwrap();
+        let gps = read_exif_metadata(&mut tagged.as_slice(), &ExtractOptions::default()).unwrap().gps.unwrap();
+        assert_eq!(gps.speed, Some(10.289));
+        assert_eq!(gps.track, Some(Bearing { degrees: 90.5, north: North::Magnetic }));
+        // Without GPSImgDirectionRef the direction is taken as true
+        assert_eq!(gps.image_direction, Some(Bearing { degrees: 180.0, north: North::True }));
+        assert_eq!(gps.dop, Some(1.2));
+        let json = serde_json::to_value(gps).unwrap();
+        assert_eq!(json["track"], serde_json::json!({"degrees": 90.5, "north": "magnetic"}));
+
+        // km/h when GPSSpeedRef is missing
+        fields.retain(|f| f.tag != Tag::GPSSpeedRef);
+        let tagged = crate::exif_write::rewrite_with(&bytes, &fields, None, |f| f.tag != Tag::GPSSpeedRef).unwrap();
+        let gps = read_exif_metadata(&mut tagged.as_slice(), &ExtractOptions::default()).unwrap().gps.unwrap();
+        assert_eq!(gps.speed, Some(5.556));
+    }
+
+    #[test]
+    fn test_field_warnings() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let point = crate::gpx::TrackPoint { time: Utc::now(), latitude: 1.0, longitude: 2.0, elevation: None };
+        let mut fields: Vec<Field> = crate::gpx::exif_fields(&point).into_iter()
+            .filter(|f| f.tag != Tag::GPSLatitudeRef)
+            .collect();
+        fields.push(Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"yesterday".to_vec()]) });
+        let damaged = crate::exif_write::rewrite_with(&bytes, &fields, None, |f| f.tag != Tag::GPSLatitudeRef).unwrap();
+        let exif = read_exif_metadata(&mut damaged.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(exif.gps, None);
+        assert!(exif.warnings.contains(&"DateTimeOriginal present but unparseable: \"yesterday\"".to_string()), "{:?}", exif.warnings);
+        assert!(exif.warnings.contains(&"GPSLatitude without GPSLatitudeRef".to_string()), "{:?}", exif.warnings);
+
+        let clean = read_exif_metadata(&mut bytes.as_slice(), &ExtractOptions::default()).unwrap();
+        assert!(clean.warnings.is_empty(), "{:?}", clean.warnings);
+    }
+
+    #[test]
+    fn test_transcoded_fields() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let shift_jis = encoding_rs::SHIFT_JIS.encode("山田太郎").0.into_owned();
+        let fields = [
+            Field { tag: Tag::Artist, ifd_num: In::PRIMARY, value: Value::Ascii(vec![shift_jis]) },
+            Field { tag: Tag::Copyright, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"\xa9 Ren\xe9".to_vec()]) },
+        ];
+        let tagged = crate::exif_write::rewrite(&bytes, &fields, None).unwrap();
+        let exif = read_exif_metadata(&mut tagged.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(exif.artist.as_deref(), Some("山田太郎"));
+        assert_eq!(exif.copyright.as_deref(), Some("© René"));
+        let expected = BTreeMap::from([("artist".to_string(), Charset::ShiftJis), ("copyright".to_string(), Charset::Latin1)]);
+        assert_eq!(exif.transcoded_fields, expected);
+
+        let options = ExtractOptions { input_charset: Charset::Latin1, ..ExtractOptions::default() };
+        let forced = read_exif_metadata(&mut tagged.as_slice(), &options).unwrap();
+        assert_eq!(forced.transcoded_fields["artist"], Charset::Latin1);
+        let clean = read_exif_metadata(&mut bytes.as_slice(), &ExtractOptions::default()).unwrap();
+        assert!(clean.transcoded_fields.is_empty());
+    }
+
+    #[test]
+    fn test_decode_user_comment() {
+        let ascii = b"ASCII\0\0\0Flight 12 alt=120m\0\0  ";
+        assert_eq!(decode_user_comment(ascii, false, Charset::Auto), Some(("Flight 12 alt=120m".to_string(), "ascii")));
+
+        let mut unicode = b"UNICODE\0".to_vec();
+        unicode.extend("héllo".encode_utf16().flat_map(|u| u.to_le_bytes()));
+        assert_eq!(decode_user_comment(&unicode, true, Charset::Auto), Some(("héllo".to_string(), "unicode")));
+
+        // "日本" in JIS X 0208
+        let jis = b"JIS\0\0\0\0\0\x46\x7C\x4B\x5C";
+        assert_eq!(decode_user_comment(jis, false, Charset::Auto), Some(("日本".to_string(), "jis")));
+
+        let blank = [0u8; 16];
+        assert_eq!(decode_user_comment(&blank, false, Charset::Auto), Some((String::new(), "undefined")));
+        assert_eq!(decode_user_comment(b"short", false, Charset::Auto), None);
+    }
+
+    #[test]
+    fn test_extra_value_hex_for_unknown_tags() {
+        let field = Field {
+            tag: Tag(exif::Context::Exif, 0xC0DE),
+            ifd_num: In::PRIMARY,
+            value: Value::Undefined(vec![0x01, 0xAB, 0xFF], 0),
+        };
+        let exif = Reader::new().read_raw(b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0".to_vec()).unwrap();
+        assert_eq!(extra_value(&field, &exif, false, Charset::Auto), "01abff");
+    }
+}
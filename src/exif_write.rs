@@ -0,0 +1,98 @@
+use crate::error::{ExtractError, Result};
+use crate::jpeg::{self, EXIF_SIGNATURE, SOI};
+use crate::thumbnail;
+use exif::experimental::Writer;
+use exif::{Field, In, Rational, Reader, Tag, Value};
+use std::io::Cursor;
+
+/// Rewrite a JPEG's EXIF segment, creating it if the file has none.
+///
+/// `fields` are added, replacing any existing field with the same tag in the
+/// same IFD. `thumbnail` replaces the embedded thumbnail; when it is `None` the
+/// current one is kept. Everything outside the EXIF segment is copied verbatim,
+/// but maker notes that use absolute offsets may be invalidated by the relocation.
+pub fn rewrite(bytes: &[u8], fields: &[Field], thumbnail: Option<&[u8]>) -> Result<Vec<u8>> {
+    rewrite_with(bytes, fields, thumbnail, |_| true)
+}
+
+/// Like [`rewrite`], additionally dropping existing fields for which `keep` returns false
+pub fn rewrite_with(bytes: &[u8], fields: &[Field], thumbnail: Option<&[u8]>, keep: impl Fn(&Field) -> bool) -> Result<Vec<u8>> {
+    let segments = jpeg::read_segments(&mut Cursor::new(bytes))?;
+    let existing = segments.iter()
+        .find(|s| s.is_app(1, EXIF_SIGNATURE))
+        .map(|s| Reader::new().read_raw(s.data[EXIF_SIGNATURE.len()..].to_vec()))
+        .transpose()?;
+    let current_thumbnail = match thumbnail {
+        Some(_) => None,
+        None => thumbnail::embedded(&segments)?,
+    };
+    let thumbnail = thumbnail.or(current_thumbnail.as_deref());
+
+    let resolution = |ifd_num| [
+        Field { tag: Tag::XResolution, ifd_num, value: Value::Rational(vec![Rational { num: 72, denom: 1 }]) },
+        Field { tag: Tag::YResolution, ifd_num, value: Value::Rational(vec![Rational { num: 72, denom: 1 }]) },
+        Field { tag: Tag::ResolutionUnit, ifd_num, value: Value::Short(vec![2]) },
+    ];
+    let compression = Field { tag: Tag::Compression, ifd_num: In::THUMBNAIL, value: Value::Short(vec![6]) };
+    let primary_defaults = resolution(In::PRIMARY);
+    let thumbnail_defaults = resolution(In::THUMBNAIL);
+
+    let kept: Vec<&Field> = existing.iter()
+        .flat_map(|exif| exif.fields())
+        .filter(|f| f.ifd_num == In::PRIMARY || f.ifd_num == In::THUMBNAIL)
+        .filter(|f| keep(f) && !fields.iter().any(|new| new.tag == f.tag && new.ifd_num == f.ifd_num))
+        .collect();
+    let all: Vec<&Field> = kept.into_iter().chain(fields).collect();
+    let has = |tag, ifd_num| all.iter().any(|f| f.tag == tag && f.ifd_num == ifd_num);
+
+    let mut writer = Writer::new();
+    for field in &all {
+        writer.push_field(field);
+    }
+    if !all.iter().any(|f| f.ifd_num == In::PRIMARY) {
+        primary_defaults.iter().for_each(|f| writer.push_field(f));
+    }
+    if let Some(thumbnail) = thumbnail {
+        if !has(Tag::Compression, In::THUMBNAIL) {
+            writer.push_field(&compression);
+        }
+        for field in thumbnail_defaults.iter().filter(|f| !has(f.tag, In::THUMBNAIL)) {
+            writer.push_field(field);
+        }
+        writer.set_jpeg(thumbnail, In::THUMBNAIL);
+    }
+
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, existing.as_ref().is_some_and(|exif| exif.little_endian()))
+        .map_err(|e| ExtractError::Encode(format!("EXIF data: {}", e)))?;
+    let mut app1 = EXIF_SIGNATURE.to_vec();
+    app1.extend(tiff.into_inner());
+    if app1.len() + 2 > u16::MAX as usize {
+        return Err(ExtractError::Encode("EXIF data: larger than the 64 KiB segment limit".to_string()));
+    }
+
+    let mut out = vec![0xFF, SOI];
+    let mut written = false;
+    let push_segment = |out: &mut Vec<u8>, marker: u8, data: &[u8]| {
+        out.extend([0xFF, marker]);
+        out.extend(((data.len() + 2) as u16).to_be_bytes());
+        out.extend(data);
+    };
+    for segment in &segments {
+        if segment.is_app(1, EXIF_SIGNATURE) {
+            if !written {
+                push_segment(&mut out, segment.marker, &app1);
+                written = true;
+            }
+            continue;
+        }
+        // A new EXIF segment goes after any JFIF APP0 segments
+        if !written && segment.marker != 0xE0 {
+            push_segment(&mut out, 0xE1, &app1);
+            written = true;
+        }
+        push_segment(&mut out, segment.marker, &segment.data);
+    }
+    out.extend(&bytes[jpeg::header_len(&segments) as usize..]);
+    Ok(out)
+}
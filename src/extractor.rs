@@ -0,0 +1,126 @@
+use crate::detect::ImageFormat;
+use crate::error::{ExtractError, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// A seekable byte source handed to extractors
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A source of metadata beyond the built-in filesystem and EXIF fields.
+///
+/// Each extractor owns one namespace in the output, named by [`Extractor::name`].
+pub trait Extractor {
+    /// Namespace the extracted value is stored under; must be unique within a registry
+    fn name(&self) -> &str;
+
+    /// Whether this extractor understands the given format. Defaults to any JPEG.
+    fn supports(&self, format: ImageFormat) -> bool {
+        format.is_jpeg()
+    }
+
+    /// Extract metadata from the start of `reader`. `Ok(None)` means there was nothing to report.
+    fn extract(&self, reader: &mut dyn ReadSeek, format: ImageFormat) -> Result<Option<Value>>;
+}
+
+/// An ordered collection of extractors with unique names.
+///
+/// Extractors run in registration order and each sees the reader rewound to the start.
+#[derive(Default)]
+pub struct ExtractorRegistry {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl ExtractorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an extractor, failing if its name is empty or already registered
+    pub fn register(&mut self, extractor: Box<dyn Extractor>) -> Result<()> {
+        let name = extractor.name();
+        let invalid = |reason: &str| ExtractError::Extractor { name: name.to_string(), source: reason.into() };
+        if name.is_empty() {
+            return Err(invalid("names must not be empty"));
+        }
+        if self.contains(name) {
+            return Err(invalid("already registered"));
+        }
+        self.extractors.push(extractor);
+        Ok(())
+    }
+
+    /// Add an extractor, replacing any existing one of the same name in its original position
+    pub fn register_or_replace(&mut self, extractor: Box<dyn Extractor>) {
+        match self.extractors.iter().position(|e| e.name() == extractor.name()) {
+            Some(index) => self.extractors[index] = extractor,
+            None => self.extractors.push(extractor),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.extractors.iter().any(|e| e.name() == name)
+    }
+
+    /// Names of registered extractors in load order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.extractors.iter().map(|e| e.name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extractors.is_empty()
+    }
+
+    /// Run every extractor that supports `format`, keyed by extractor name
+    pub fn run(&self, reader: &mut dyn ReadSeek, format: ImageFormat) -> Result<BTreeMap<String, Value>> {
+        let mut output = BTreeMap::new();
+        for extractor in self.extractors.iter().filter(|e| e.supports(format)) {
+            reader.seek(SeekFrom::Start(0))?;
+            let value = extractor.extract(reader, format)
+                .map_err(|e| ExtractError::Extractor { name: extractor.name().to_string(), source: Box::new(e) })?;
+            if let Some(value) = value {
+                output.insert(extractor.name().to_string(), value);
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    struct FirstByte(&'static str);
+
+    impl Extractor for FirstByte {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn extract(&self, reader: &mut dyn ReadSeek, _format: ImageFormat) -> Result<Option<Value>> {
+            let mut byte = [0u8; 1];
+            reader.read_exact(&mut byte)?;
+            Ok(Some(Value::from(byte[0])))
+        }
+    }
+
+    #[test]
+    fn test_registry_conflicts_and_order() {
+        let mut registry = ExtractorRegistry::new();
+        registry.register(Box::new(FirstByte("b"))).unwrap();
+        registry.register(Box::new(FirstByte("a"))).unwrap();
+        let error = registry.register(Box::new(FirstByte("a"))).unwrap_err();
+        assert_eq!(error.to_string(), "Extractor 'a': already registered");
+        registry.register_or_replace(Box::new(FirstByte("b")));
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["b", "a"]);
+
+        let mut reader = Cursor::new(vec![0xFF, 0xD8]);
+        let output = registry.run(&mut reader, ImageFormat::Jpeg).unwrap();
+        assert_eq!(output["a"], Value::from(0xFF));
+        assert_eq!(output["b"], Value::from(0xFF));
+        assert!(registry.run(&mut reader, ImageFormat::Unknown).unwrap().is_empty());
+    }
+}
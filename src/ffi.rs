@@ -0,0 +1,79 @@
+//! C ABI for linking the extractor into non-Rust programs.
+//!
+//! Results are returned as NUL-terminated JSON strings allocated by this
+//! library; callers must release them with [`free_metadata_json`].
+
+use std::ffi::{c_char, c_int, CString};
+use std::ptr;
+
+/// Extraction succeeded and `*out` holds the metadata JSON
+pub const JME_OK: c_int = 0;
+/// A required pointer argument was null
+pub const JME_ERR_NULL_ARGUMENT: c_int = 1;
+/// Extraction failed and `*out` holds `{"error": "..."}`
+pub const JME_ERR_EXTRACTION: c_int = 2;
+
+/// Extract metadata from `len` bytes at `data` and store a JSON string in `*out`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+/// The string written to `*out` must be freed with [`free_metadata_json`].
+#[no_mangle]
+pub unsafe extern "C" fn extract_metadata_json(data: *const u8, len: usize, out: *mut *mut c_char) -> c_int {
+    if data.is_null() || out.is_null() {
+        return JME_ERR_NULL_ARGUMENT;
+    }
+    *out = ptr::null_mut();
+
+    let bytes = std::slice::from_raw_parts(data, len);
+    let (status, json) = match crate::content::extract_from_bytes(bytes, &Default::default()) {
+        Ok(metadata) => match serde_json::to_string(&metadata) {
+            Ok(json) => (JME_OK, json),
+            Err(e) => (JME_ERR_EXTRACTION, error_json(&e.to_string())),
+        },
+        Err(e) => (JME_ERR_EXTRACTION, error_json(&e.to_string())),
+    };
+
+    // serde_json escapes control characters, so the output never contains NUL
+    *out = CString::new(json).expect("JSON contains no NUL bytes").into_raw();
+    status
+}
+
+/// Release a string returned by [`extract_metadata_json`]. Null is ignored.
+///
+/// # Safety
+///
+/// `json` must be null or a pointer previously returned through `out` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn free_metadata_json(json: *mut c_char) {
+    if !json.is_null() {
+        drop(CString::from_raw(json));
+    }
+}
+
+fn error_json(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_extract_metadata_json() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let mut out = ptr::null_mut();
+        let status = unsafe { extract_metadata_json(bytes.as_ptr(), bytes.len(), &mut out) };
+        assert_eq!(status, JME_OK);
+        let json = unsafe { CStr::from_ptr(out) }.to_str().unwrap().to_owned();
+        unsafe { free_metadata_json(out) };
+        assert!(json.contains("\"width\":5040"));
+
+        let status = unsafe { extract_metadata_json(b"nope".as_ptr(), 4, &mut out) };
+        assert_eq!(status, JME_ERR_EXTRACTION);
+        unsafe { free_metadata_json(out) };
+        assert_eq!(unsafe { extract_metadata_json(ptr::null(), 0, &mut out) }, JME_ERR_NULL_ARGUMENT);
+    }
+}
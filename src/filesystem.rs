@@ -0,0 +1,283 @@
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct FilesystemMetadata {
+    /// Absolute path with symlinks and `..` resolved
+    pub canonical_path: Option<PathBuf>,
+    /// UUID of the filesystem holding the file, where the platform exposes one
+    pub volume_id: Option<String>,
+    pub size: u64,
+    /// Birth time, if the platform and filesystem record one
+    pub created_time: Option<DateTime<Utc>>,
+    pub modified_time: DateTime<Utc>,
+    pub is_symlink: bool,
+    pub link_target: Option<PathBuf>,
+    pub inode: Option<u64>,
+    pub device: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Permission bits in octal, e.g. "0644"
+    pub mode: Option<String>,
+    pub readonly: bool,
+    /// Extended attributes, hex-encoded when the value is not valid UTF-8
+    pub xattrs: BTreeMap<String, String>,
+}
+
+/// Where a reported timestamp actually came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// The filesystem's recorded birth time
+    BirthTime,
+    /// Substituted from the modification time because no birth time exists
+    ModifiedTime,
+}
+
+/// Provenance of each filesystem timestamp in the output
+#[derive(Clone, Debug, Serialize)]
+pub struct TimestampSources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_time: Option<TimestampSource>,
+}
+
+/// Extract filesystem metadata from a file
+pub fn extract_filesystem_metadata(path: &Path) -> Result<FilesystemMetadata> {
+    let metadata = fs::metadata(path)?;
+
+    // Birth time is unsupported on many Linux filesystems, so its absence is not an error
+    let created_time = metadata.created().ok();
+    let modified_time = metadata.modified()?;
+
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    let link_target = if is_symlink { fs::read_link(path).ok() } else { None };
+    let (device, inode) = match file_identity(&metadata) {
+        Some((device, inode)) => (Some(device), Some(inode)),
+        None => (None, None),
+    };
+    let (uid, gid, mode) = match ownership(&metadata) {
+        Some((uid, gid, mode)) => (Some(uid), Some(gid), Some(format!("{:04o}", mode))),
+        None => (None, None, None),
+    };
+
+    Ok(FilesystemMetadata {
+        canonical_path: fs::canonicalize(path).ok(),
+        volume_id: volume_id(&metadata),
+        size: metadata.len(),
+        created_time: created_time.map(DateTime::from),
+        modified_time: DateTime::from(modified_time),
+        is_symlink,
+        link_target,
+        inode,
+        device,
+        uid,
+        gid,
+        mode,
+        readonly: metadata.permissions().readonly(),
+        xattrs: read_xattrs(path),
+    })
+}
+
+/// Device and inode numbers identifying the underlying file, shared by hardlinks
+#[cfg(unix)]
+pub fn file_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_identity(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// The UUID of the filesystem a file is on, which stays the same wherever a
+/// removable drive is mounted. Read from the `/dev/disk/by-uuid` links, so only
+/// on Linux and only for block devices; network and virtual filesystems have none.
+#[cfg(target_os = "linux")]
+pub fn volume_id(metadata: &fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::LazyLock;
+    static VOLUMES: LazyLock<BTreeMap<u64, String>> = LazyLock::new(|| {
+        fs::read_dir("/dev/disk/by-uuid").into_iter().flatten().flatten()
+            .filter_map(|link| Some((fs::metadata(link.path()).ok()?.rdev(), link.file_name().into_string().ok()?)))
+            .collect()
+    });
+    VOLUMES.get(&metadata.dev()).cloned()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn volume_id(_metadata: &fs::Metadata) -> Option<String> {
+    None
+}
+
+/// Owning user and group IDs and the permission bits of the file
+#[cfg(unix)]
+fn ownership(metadata: &fs::Metadata) -> Option<(u32, u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.uid(), metadata.gid(), metadata.mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn ownership(_metadata: &fs::Metadata) -> Option<(u32, u32, u32)> {
+    None
+}
+
+/// Whether an extended attribute is user-assigned rather than system-managed.
+///
+/// Linux confines user attributes to the `user.` namespace; macOS has no
+/// namespaces, so everything except quarantine bookkeeping is kept (Finder
+/// tags live in `com.apple.metadata:_kMDItemUserTags`).
+#[cfg(unix)]
+fn is_user_xattr(name: &str) -> bool {
+    if cfg!(target_os = "macos") {
+        name != "com.apple.quarantine"
+    } else {
+        name.starts_with("user.")
+    }
+}
+
+/// Read user extended attributes. Filesystems without xattr support yield none.
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> BTreeMap<String, String> {
+    let Ok(names) = xattr::list(path) else {
+        return BTreeMap::new();
+    };
+    names
+        .filter_map(|name| {
+            let name = name.into_string().ok()?;
+            if !is_user_xattr(&name) {
+                return None;
+            }
+            let value = xattr::get(path, &name).ok()??;
+            let value = match String::from_utf8(value) {
+                Ok(text) => text,
+                Err(e) => e.as_bytes().iter().map(|b| format!("{:02x}", b)).collect(),
+            };
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &Path) -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+/// A file name or path as text. Bytes that are not UTF-8, as in names from old
+/// Latin-1 systems, are escaped as `\xNN` so that distinct names stay distinct.
+#[cfg(unix)]
+pub fn path_text(path: &OsStr) -> String {
+    use std::fmt::Write;
+    use std::os::unix::ffi::OsStrExt;
+    let mut text = String::new();
+    for chunk in path.as_bytes().utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(text, "\\x{:02X}", byte);
+        }
+    }
+    text
+}
+
+/// A file name or path as text, with unpaired surrogates replaced
+#[cfg(not(unix))]
+pub fn path_text(path: &OsStr) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Serialize an optional path with [`path_text`], for `#[serde(serialize_with)]`
+pub fn serialize_path<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_str(&path_text(path.as_os_str())),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// `path` in the `\\?\` form Windows needs past the legacy 260-character limit
+/// when it is that long; other paths, and all paths elsewhere, are unchanged
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    // Directories are limited to 248 characters, leaving room for an 8.3 file name
+    const LEGACY_LIMIT: usize = 248;
+    let text = path.as_os_str().to_string_lossy();
+    if text.len() < LEGACY_LIMIT || text.starts_with(r"\\?\") {
+        return Cow::Borrowed(path);
+    }
+    // The prefix turns off normalization, so resolve `..` and `/` first
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    let prefixed = match absolute.as_os_str().to_string_lossy().strip_prefix(r"\\") {
+        Some(unc) => std::ffi::OsString::from(format!(r"\\?\UNC\{}", unc)),
+        None => {
+            let mut prefixed = std::ffi::OsString::from(r"\\?\");
+            prefixed.push(absolute.as_os_str());
+            prefixed
+        }
+    };
+    Cow::Owned(PathBuf::from(prefixed))
+}
+
+/// Other platforms have no legacy path length limit to work around
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ownership_and_mode() {
+        let meta = extract_filesystem_metadata(Path::new("images/JAM26284.jpg")).unwrap();
+        assert!(meta.uid.is_some() && meta.gid.is_some());
+        let mode = meta.mode.unwrap();
+        assert_eq!(mode.len(), 4);
+        assert!(u32::from_str_radix(&mode, 8).is_ok());
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        let meta = extract_filesystem_metadata(Path::new("images/../images/JAM26284.jpg")).unwrap();
+        let canonical = meta.canonical_path.unwrap();
+        assert!(canonical.is_absolute());
+        assert!(canonical.ends_with("images/JAM26284.jpg") && !canonical.to_string_lossy().contains(".."));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_text() {
+        use std::os::unix::ffi::OsStrExt;
+        assert_eq!(path_text(OsStr::from_bytes(b"caf\xE9.jpg")), "caf\\xE9.jpg");
+        assert_eq!(path_text(OsStr::new("café.jpg")), "café.jpg");
+        let value = serde_json::to_value(Link { target: Some(PathBuf::from(OsStr::from_bytes(b"\xFF/a.jpg"))) }).unwrap();
+        assert_eq!(value["target"], "\\xFF/a.jpg");
+    }
+
+    #[derive(Serialize)]
+    struct Link {
+        #[serde(serialize_with = "serialize_path")]
+        target: Option<PathBuf>,
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_user_xattr() {
+        if cfg!(target_os = "macos") {
+            assert!(is_user_xattr("com.apple.metadata:_kMDItemUserTags"));
+        } else {
+            assert!(is_user_xattr("user.xdg.tags"));
+            assert!(!is_user_xattr("security.selinux"));
+        }
+    }
+}
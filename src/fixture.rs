@@ -0,0 +1,191 @@
+use crate::error::{ExtractError, Result};
+use crate::exif_write;
+use crate::gpx;
+use chrono::{DateTime, NaiveDateTime};
+use exif::{Field, In, Tag, Value};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Nikon type 3 maker note header, then a big-endian TIFF header whose IFD starts at 8
+const NIKON_NOTE_HEADER: &[u8] = b"Nikon\0\x02\x10\0\0MM\0\x2a\0\0\0\x08";
+const NIKON_SHUTTER_COUNT: u16 = 0x00A7;
+
+/// A synthetic JPEG to generate: a small patterned image with only the EXIF
+/// fields given here, so test corpora can be rebuilt byte for byte without real photos
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixtureSpec {
+    pub width: u16,
+    pub height: u16,
+    /// Varies the pixel pattern, so fixtures with the same EXIF can still differ
+    pub seed: u64,
+    pub orientation: Option<u16>,
+    /// Written to DateTime, DateTimeOriginal and DateTimeDigitized
+    pub capture_time: Option<NaiveDateTime>,
+    /// Zone for the capture time, e.g. +02:00
+    pub offset_time: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub gps: Option<FixtureGps>,
+    /// Written as a Nikon maker note, which also sets the make when none is given
+    pub shutter_count: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureGps {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level
+    pub altitude: Option<f64>,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        FixtureSpec {
+            width: 16,
+            height: 16,
+            seed: 0,
+            orientation: None,
+            capture_time: None,
+            offset_time: None,
+            make: None,
+            model: None,
+            serial: None,
+            gps: None,
+            shutter_count: None,
+        }
+    }
+}
+
+/// Parse a corpus of named fixtures, one table each:
+///
+/// ```toml
+/// [rotated]
+/// orientation = 6
+/// capture_time = "2024-05-10T14:15:00"
+/// gps = { latitude = 48.8584, longitude = 2.2945 }
+/// ```
+pub fn parse_corpus(toml: &str) -> Result<BTreeMap<String, FixtureSpec>> {
+    toml::from_str(toml).map_err(|e| ExtractError::parse_from("fixture corpus", e))
+}
+
+/// Encode the fixture. The output depends only on `spec`.
+pub fn generate(spec: &FixtureSpec) -> Result<Vec<u8>> {
+    if spec.width == 0 || spec.height == 0 {
+        return Err(ExtractError::parse("fixture", "dimensions must be at least 1x1"));
+    }
+    let jpeg = crate::pixels::encode(&pixels(spec), spec.width, spec.height, 90, "fixture image")?;
+    let fields = exif_fields(spec)?;
+    if fields.is_empty() {
+        return Ok(jpeg);
+    }
+    exif_write::rewrite(&jpeg, &fields, None)
+}
+
+/// A diagonal gradient with seeded noise
+fn pixels(spec: &FixtureSpec) -> Vec<u8> {
+    // xorshift64; zero is its one fixed point
+    let mut state = spec.seed ^ 0x9E37_79B9_7F4A_7C15;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 56) as u8
+    };
+    let (width, height) = (spec.width as usize, spec.height as usize);
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let r = (x * 255 / (width - 1).max(1)) as u8;
+            let g = (y * 255 / (height - 1).max(1)) as u8;
+            rgb.extend([r, g, next() / 2 + 64]);
+        }
+    }
+    rgb
+}
+
+fn exif_fields(spec: &FixtureSpec) -> Result<Vec<Field>> {
+    let field = |tag, value| Field { tag, ifd_num: In::PRIMARY, value };
+    let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+    let mut fields = Vec::new();
+    let make = spec.make.as_deref().or(spec.shutter_count.map(|_| "NIKON CORPORATION"));
+    if let Some(make) = make {
+        fields.push(field(Tag::Make, ascii(make)));
+    }
+    if let Some(model) = &spec.model {
+        fields.push(field(Tag::Model, ascii(model)));
+    }
+    if let Some(serial) = &spec.serial {
+        fields.push(field(Tag::BodySerialNumber, ascii(serial)));
+    }
+    if let Some(orientation) = spec.orientation {
+        if !(1..=8).contains(&orientation) {
+            return Err(ExtractError::parse("Orientation", format!("must be 1 to 8, not {}", orientation)));
+        }
+        fields.push(field(Tag::Orientation, Value::Short(vec![orientation])));
+    }
+    if let Some(time) = spec.capture_time {
+        let text = time.format("%Y:%m:%d %H:%M:%S").to_string();
+        for tag in [Tag::DateTime, Tag::DateTimeOriginal, Tag::DateTimeDigitized] {
+            fields.push(field(tag, ascii(&text)));
+        }
+    }
+    if let Some(offset) = &spec.offset_time {
+        if DateTime::parse_from_str(&format!("2000-01-01T00:00:00{}", offset), "%Y-%m-%dT%H:%M:%S%:z").is_err() {
+            return Err(ExtractError::parse("OffsetTime", format!("'{}' is not a zone like +02:00", offset)));
+        }
+        for tag in [Tag::OffsetTime, Tag::OffsetTimeOriginal, Tag::OffsetTimeDigitized] {
+            fields.push(field(tag, ascii(offset)));
+        }
+    }
+    if let Some(gps) = spec.gps {
+        fields.extend(gpx::position_fields(gps.latitude, gps.longitude, gps.altitude));
+    }
+    if let Some(count) = spec.shutter_count {
+        let mut note = NIKON_NOTE_HEADER.to_vec();
+        note.extend(1u16.to_be_bytes());
+        note.extend(NIKON_SHUTTER_COUNT.to_be_bytes());
+        note.extend(4u16.to_be_bytes());
+        note.extend(1u32.to_be_bytes());
+        note.extend(count.to_be_bytes());
+        note.extend(0u32.to_be_bytes());
+        fields.push(field(Tag::MakerNote, Value::Undefined(note, 0)));
+    }
+    Ok(fields)
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use super::*;
+    use crate::content::{extract_from_bytes, ExtractOptions};
+
+    #[test]
+    fn test_generate() {
+        let corpus = parse_corpus(r#"
+            [nikon]
+            width = 24
+            orientation = 6
+            capture_time = "2024-05-10T14:15:00"
+            offset_time = "+02:00"
+            model = "NIKON D850"
+            serial = "6001234"
+            gps = { latitude = 48.8584, longitude = -2.2945, altitude = 35.0 }
+            shutter_count = 12345
+        "#).unwrap();
+        let spec = &corpus["nikon"];
+        let bytes = generate(spec).unwrap();
+        assert_eq!(bytes, generate(spec).unwrap());
+        assert_ne!(bytes, generate(&FixtureSpec { seed: 1, ..spec.clone() }).unwrap());
+
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(exif.get_field(Tag::Orientation, In::PRIMARY).unwrap().value.get_uint(0), Some(6));
+        assert_eq!(crate::makernote::shutter_count(&exif), Some(12345));
+        let content = extract_from_bytes(&bytes, &ExtractOptions::default()).unwrap();
+        assert_eq!((content.width, content.height), (Some(24), Some(16)));
+
+        assert!(generate(&FixtureSpec { orientation: Some(9), ..FixtureSpec::default() }).is_err());
+        assert!(parse_corpus("[a]\ncolour = 1\n").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::cameras::{CameraSpec, Sensor};
+use exif::{Exif, In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+/// Circle of confusion as a fraction of the sensor diagonal (the d/1500 convention)
+const COC_DIVISOR: f64 = 1500.0;
+
+/// Focus distance and derived depth of field. Distances are in metres,
+/// lengths at the sensor in millimetres.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Focus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject_distance: Option<f64>,
+    /// True when SubjectDistance records focus at infinity
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub at_infinity: bool,
+    pub focal_length: f64,
+    pub aperture: f64,
+    /// Only known when the sensor size is, from the camera database or the focal plane resolution tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circle_of_confusion: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hyperfocal_distance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth_of_field: Option<DepthOfField>,
+}
+
+/// Range of acceptable sharpness around the subject
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DepthOfField {
+    pub near: f64,
+    /// Absent, like `total`, when sharpness extends to infinity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub far: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+}
+
+/// Focus block from FocalLength, FNumber and SubjectDistance. Requires focal
+/// length and aperture; depth of field also needs a subject distance and sensor size.
+pub fn from_exif(exif: &Exif, camera: Option<&CameraSpec>) -> Option<Focus> {
+    let focal_length = focal_length(exif)?;
+    let aperture = rational(exif, Tag::FNumber).filter(|&n| n > 0.0)?;
+    let distance = exif.get_field(Tag::SubjectDistance, In::PRIMARY).and_then(|f| match &f.value {
+        Value::Rational(v) => v.first().copied(),
+        _ => None,
+    });
+    // 0 means unknown and 0xFFFFFFFF infinity
+    let at_infinity = distance.is_some_and(|d| d.num == u32::MAX);
+    let subject_distance = distance
+        .filter(|d| d.num != 0 && d.num != u32::MAX && d.denom != 0)
+        .map(|d| d.to_f64());
+
+    let sensor = camera.map(CameraSpec::sensor).or_else(|| focal_plane_sensor(exif));
+    let coc = sensor.map(|s| s.diagonal() / COC_DIVISOR);
+    let hyperfocal = coc.map(|c| focal_length * focal_length / (aperture * c) + focal_length);
+    let depth_of_field = hyperfocal.and_then(|h| {
+        let f = focal_length;
+        let (near, far) = if at_infinity {
+            (h - f, None)
+        } else {
+            let s = subject_distance? * 1000.0;
+            let near = s * (h - f) / (h + s - 2.0 * f);
+            (near, (s < h).then(|| s * (h - f) / (h - s)))
+        };
+        Some(DepthOfField {
+            near: metres(near),
+            far: far.map(metres),
+            total: far.map(|far| metres(far - near)),
+        })
+    });
+
+    Some(Focus {
+        subject_distance,
+        at_infinity,
+        focal_length,
+        aperture,
+        circle_of_confusion: coc.map(|c| (c * 1000.0).round() / 1000.0),
+        hyperfocal_distance: hyperfocal.map(metres),
+        depth_of_field,
+    })
+}
+
+/// FocalLength in millimetres
+pub(crate) fn focal_length(exif: &Exif) -> Option<f64> {
+    rational(exif, Tag::FocalLength).filter(|&f| f > 0.0)
+}
+
+/// Sensor size from the focal plane resolution and the image dimensions
+fn focal_plane_sensor(exif: &Exif) -> Option<Sensor> {
+    let unit_mm = match exif.get_field(Tag::FocalPlaneResolutionUnit, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        .unwrap_or(2)
+    {
+        2 => 25.4,
+        3 => 10.0,
+        _ => return None,
+    };
+    let side = |resolution, pixels| {
+        let resolution = rational(exif, resolution).filter(|&r| r > 0.0)?;
+        let pixels = exif.get_field(pixels, In::PRIMARY)?.value.get_uint(0)?;
+        Some(pixels as f64 / resolution * unit_mm)
+    };
+    Some(Sensor {
+        width: side(Tag::FocalPlaneXResolution, Tag::PixelXDimension)?,
+        height: side(Tag::FocalPlaneYResolution, Tag::PixelYDimension)?,
+    })
+}
+
+fn rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) => v.first().filter(|r| r.denom != 0).map(|r| r.to_f64()),
+        _ => None,
+    }
+}
+
+/// Millimetres to metres, to the nearest millimetre
+fn metres(mm: f64) -> f64 {
+    mm.round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cameras;
+    use exif::experimental::Writer;
+    use exif::{Field, Rational};
+    use std::io::Cursor;
+
+    fn exif_with(fields: &[Field]) -> Exif {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut buf = Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        exif::Reader::new().read_raw(buf.into_inner()).unwrap()
+    }
+
+    fn rational_field(tag: Tag, num: u32, denom: u32) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Rational(vec![Rational { num, denom }]) }
+    }
+
+    #[test]
+    fn test_depth_of_field() {
+        let exif = exif_with(&[
+            rational_field(Tag::FocalLength, 50, 1),
+            rational_field(Tag::FNumber, 8, 1),
+            rational_field(Tag::SubjectDistance, 5, 1),
+        ]);
+        let camera = cameras::lookup("Canon EOS 5D Mark IV", &Default::default());
+        let focus = from_exif(&exif, camera.as_ref()).unwrap();
+        assert_eq!(focus.subject_distance, Some(5.0));
+        assert_eq!(focus.circle_of_confusion, Some(0.029));
+        // 50mm at f/8 on full frame: hyperfocal about 10.9 m, sharp from about 3.4 m to 9.2 m
+        assert_eq!(focus.hyperfocal_distance, Some(10.884));
+        let dof = focus.depth_of_field.unwrap();
+        assert_eq!((dof.near, dof.far), (3.432, Some(9.206)));
+
+        // Beyond the hyperfocal distance sharpness extends to infinity
+        let far = exif_with(&[
+            rational_field(Tag::FocalLength, 50, 1),
+            rational_field(Tag::FNumber, 8, 1),
+            rational_field(Tag::SubjectDistance, 20, 1),
+        ]);
+        let dof = from_exif(&far, camera.as_ref()).unwrap().depth_of_field.unwrap();
+        assert_eq!((dof.far, dof.total), (None, None));
+
+        // Without a known sensor only the inputs are reported
+        let unknown = from_exif(&exif, None).unwrap();
+        assert_eq!((unknown.hyperfocal_distance, unknown.depth_of_field), (None, None));
+    }
+}
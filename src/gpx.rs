@@ -0,0 +1,170 @@
+use crate::error::{ExtractError, Result};
+use chrono::{DateTime, Duration, Timelike, Utc};
+use exif::{Field, In, Rational, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+/// A timestamped position from a GPX track
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackPoint {
+    pub time: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<f64>,
+}
+
+/// Track points from every `<trk>` in a GPX file, in time order
+#[derive(Debug, Default)]
+pub struct Track {
+    points: Vec<TrackPoint>,
+}
+
+impl Track {
+    /// Parse GPX 1.0 or 1.1; points without a valid time or position are skipped
+    pub fn parse(xml: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(xml).map_err(|e| ExtractError::parse_from("GPX file", e))?;
+        let mut points: Vec<TrackPoint> = doc.descendants()
+            .filter(|node| node.tag_name().name() == "trkpt")
+            .filter_map(|node| Some(TrackPoint {
+                time: DateTime::parse_from_rfc3339(child_text(node, "time")?).ok()?.with_timezone(&Utc),
+                latitude: node.attribute("lat")?.parse().ok()?,
+                longitude: node.attribute("lon")?.parse().ok()?,
+                elevation: child_text(node, "ele").and_then(|e| e.parse().ok()),
+            }))
+            .collect();
+        points.sort_by_key(|p| p.time);
+        Ok(Track { points })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Position at `time`, interpolated linearly between the surrounding points.
+    /// Returns `None` outside the track or when the surrounding points are more
+    /// than `max_gap` apart, since the route between them is unknown.
+    pub fn locate(&self, time: DateTime<Utc>, max_gap: Duration) -> Option<TrackPoint> {
+        let after = self.points.partition_point(|p| p.time < time);
+        let next = self.points.get(after)?;
+        if next.time == time {
+            return Some(*next);
+        }
+        let prev = self.points.get(after.checked_sub(1)?)?;
+        let gap = next.time - prev.time;
+        if gap > max_gap {
+            return None;
+        }
+        let t = (time - prev.time).num_milliseconds() as f64 / gap.num_milliseconds() as f64;
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Some(TrackPoint {
+            time,
+            latitude: lerp(prev.latitude, next.latitude),
+            longitude: lerp(prev.longitude, next.longitude),
+            elevation: prev.elevation.zip(next.elevation).map(|(a, b)| lerp(a, b)),
+        })
+    }
+}
+
+/// Trimmed text of the first child element named `name`, in any namespace
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|c| c.tag_name().name() == name).and_then(|c| c.text()).map(str::trim)
+}
+
+/// GPS tags that describe the fix a position came from, and no longer apply
+/// once the position is replaced
+pub const FIX_TAGS: [Tag; 4] = [Tag::GPSAltitudeRef, Tag::GPSAltitude, Tag::GPSTimeStamp, Tag::GPSDateStamp];
+
+/// EXIF GPS fields recording `point`, including its time
+pub fn exif_fields(point: &TrackPoint) -> Vec<Field> {
+    let mut fields = position_fields(point.latitude, point.longitude, point.elevation);
+    let time = point.time;
+    let rational = |num, denom| Rational { num, denom };
+    fields.push(Field {
+        tag: Tag::GPSTimeStamp,
+        ifd_num: In::PRIMARY,
+        value: Value::Rational(vec![
+            rational(time.hour(), 1),
+            rational(time.minute(), 1),
+            rational(time.second() * 1000 + time.timestamp_subsec_millis().min(999), 1000),
+        ]),
+    });
+    fields.push(Field {
+        tag: Tag::GPSDateStamp,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![time.format("%Y:%m:%d").to_string().into_bytes()]),
+    });
+    fields
+}
+
+/// EXIF GPS fields recording a position, with the altitude if it is known. GPX
+/// positions are always on the WGS 84 datum, which is recorded with them.
+pub fn position_fields(latitude: f64, longitude: f64, elevation: Option<f64>) -> Vec<Field> {
+    let field = |tag, value| Field { tag, ifd_num: In::PRIMARY, value };
+    let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+    let mut fields = vec![
+        field(Tag::GPSVersionID, Value::Byte(vec![2, 3, 0, 0])),
+        field(Tag::GPSLatitudeRef, ascii(if latitude < 0.0 { "S" } else { "N" })),
+        field(Tag::GPSLatitude, dms(latitude)),
+        field(Tag::GPSLongitudeRef, ascii(if longitude < 0.0 { "W" } else { "E" })),
+        field(Tag::GPSLongitude, dms(longitude)),
+        field(Tag::GPSMapDatum, ascii("WGS-84")),
+    ];
+    if let Some(elevation) = elevation {
+        fields.push(field(Tag::GPSAltitudeRef, Value::Byte(vec![u8::from(elevation < 0.0)])));
+        fields.push(field(Tag::GPSAltitude, Value::Rational(vec![Rational {
+            num: (elevation.abs() * 100.0).round() as u32,
+            denom: 100,
+        }])));
+    }
+    fields
+}
+
+/// Degrees, minutes and seconds (to 1/1000 s) as three rationals
+fn dms(coordinate: f64) -> Value {
+    let millis = (coordinate.abs() * 3_600_000.0).round() as u64;
+    let degrees = millis / 3_600_000;
+    let minutes = millis / 60_000 % 60;
+    let seconds = millis % 60_000;
+    Value::Rational(vec![
+        Rational { num: degrees as u32, denom: 1 },
+        Rational { num: minutes as u32, denom: 1 },
+        Rational { num: seconds as u32, denom: 1000 },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const GPX: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1"><trk><trkseg>
+  <trkpt lat="51.5000" lon="-0.1000"><ele>10</ele><time>2024-05-10T10:00:00Z</time></trkpt>
+  <trkpt lat="51.5100" lon="-0.1200"><ele>20</ele><time>2024-05-10T10:01:00Z</time></trkpt>
+  <trkpt lat="51.6000" lon="-0.2000"><time>2024-05-10T12:00:00Z</time></trkpt>
+</trkseg></trk></gpx>"#;
+
+    #[test]
+    fn test_locate() {
+        let track = Track::parse(GPX).unwrap();
+        let at = |h, m, s| Utc.with_ymd_and_hms(2024, 5, 10, h, m, s).unwrap();
+        let gap = Duration::minutes(5);
+
+        let point = track.locate(at(10, 0, 30), gap).unwrap();
+        assert!((point.latitude - 51.505).abs() < 1e-9);
+        assert!((point.longitude + 0.11).abs() < 1e-9);
+        assert_eq!(point.elevation, Some(15.0));
+
+        assert_eq!(track.locate(at(10, 1, 0), gap).unwrap().latitude, 51.51);
+        assert_eq!(track.locate(at(11, 0, 0), gap), None);
+        assert_eq!(track.locate(at(9, 0, 0), gap), None);
+    }
+
+    #[test]
+    fn test_dms() {
+        let Value::Rational(parts) = dms(-0.1205) else { panic!("expected rationals") };
+        let parts: Vec<(u32, u32)> = parts.iter().map(|r| (r.num, r.denom)).collect();
+        assert_eq!(parts, [(0, 1), (7, 1), (13800, 1000)]);
+    }
+}
@@ -0,0 +1,386 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use glob::{MatchOptions, Pattern};
+use jpeg_metadata_extractor::filesystem;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Patterns used for directory inputs when no `--include` is given
+const DEFAULT_INCLUDES: &[&str] = &["*.jpg", "*.jpeg", "*.jpe", "*.jfif", "*.webp", "*.jxl", "*.heic", "*.heif", "*.avif", "*.tif", "*.tiff"];
+/// Patterns added to the defaults when RAW files are read through their previews
+const RAW_INCLUDES: &[&str] = &["*.cr2", "*.nef", "*.arw", "*.dng"];
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// File listing paths to skip below its directory, in gitignore syntax
+pub const IGNORE_FILE: &str = ".jmeignore";
+
+/// As git matches ignore patterns: case-sensitive, with `*` not crossing directories
+const IGNORE_MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Filename, size and modification time filters applied to expanded inputs.
+/// All of them are checked from directory entries and `stat` alone, without opening files.
+#[derive(Debug, Default)]
+pub struct Filters {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    /// Directories whose name matches are skipped with everything below them
+    exclude_dirs: Vec<Pattern>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Only files modified at or after this time
+    pub modified_after: Option<DateTime<Utc>>,
+    /// Only files modified before this time
+    pub modified_before: Option<DateTime<Utc>>,
+    /// Also match RAW file extensions when no include patterns are set
+    pub include_raw: bool,
+}
+
+impl Filters {
+    pub fn new(include: &[String], exclude: &[String], exclude_dirs: &[String]) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Pattern>> {
+            patterns.iter()
+                .map(|p| Pattern::new(p).with_context(|| format!("Invalid glob pattern '{}'", p)))
+                .collect()
+        };
+        Ok(Filters {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            exclude_dirs: compile(exclude_dirs)?,
+            ..Default::default()
+        })
+    }
+
+    /// Whether any directory along `path`, or `path` itself when it is a
+    /// directory, matches an `exclude_dirs` pattern. Callers pass the path below
+    /// the scan root, so the directories the scan starts in are not judged.
+    fn in_excluded_dir(&self, path: &Path, is_dir: bool) -> bool {
+        if self.exclude_dirs.is_empty() {
+            return false;
+        }
+        let dirs = if is_dir { Some(path) } else { path.parent() };
+        dirs.into_iter()
+            .flat_map(Path::components)
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(filesystem::path_text(name)),
+                _ => None,
+            })
+            .any(|name| self.exclude_dirs.iter().any(|p| p.matches_with(&name, MATCH_OPTIONS)))
+    }
+
+    /// Whether a file's size and modification time are within the configured ranges.
+    /// Files that cannot be stat'ed are accepted so the error surfaces later.
+    fn accepts_metadata(&self, path: &Path) -> bool {
+        if self.min_size.is_none() && self.max_size.is_none()
+            && self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        let Ok(metadata) = fs::metadata(path) else {
+            return true;
+        };
+        self.accepts_stat(metadata.len(), metadata.modified().ok().map(Into::into))
+    }
+
+    fn accepts_stat(&self, size: u64, modified: Option<DateTime<Utc>>) -> bool {
+        if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        modified.is_none_or(|modified| in_range(modified, self.modified_after, self.modified_before))
+    }
+
+    /// Whether a file inside an archive should be processed, judged like a file
+    /// found by expanding a directory
+    pub fn accepts_member(&self, name: &str, size: u64, modified: Option<DateTime<Utc>>) -> bool {
+        self.accepts_name(Path::new(name), true) && !self.in_excluded_dir(Path::new(name), false) && self.accepts_stat(size, modified)
+    }
+
+    /// Whether a file found below `root` should be processed. Files found by
+    /// expanding a directory fall back to the default JPEG extensions when no
+    /// include patterns are set.
+    fn accepts(&self, path: &Path, root: &Path, from_directory: bool) -> bool {
+        let below = normalized(path);
+        let below = below.strip_prefix(normalized(root)).unwrap_or(&below);
+        self.accepts_name(path, from_directory) && !self.in_excluded_dir(below, false) && self.accepts_metadata(path)
+    }
+
+    /// The include and exclude checks of [`Filters::accepts`]
+    fn accepts_name(&self, path: &Path, from_directory: bool) -> bool {
+        let Some(name) = path.file_name().map(filesystem::path_text) else {
+            return !from_directory;
+        };
+        let name = name.as_str();
+        let matches = |p: &Pattern| p.matches_with(name, MATCH_OPTIONS)
+            || p.matches_path_with(path, MATCH_OPTIONS);
+
+        let included = if !self.include.is_empty() {
+            self.include.iter().any(matches)
+        } else if from_directory {
+            let raw = if self.include_raw { RAW_INCLUDES } else { &[] };
+            DEFAULT_INCLUDES.iter().chain(raw).any(|p| Pattern::new(p).unwrap().matches_with(name, MATCH_OPTIONS))
+        } else {
+            true
+        };
+        included && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Whether `time` is at or after `after` and strictly before `before`
+pub fn in_range(time: DateTime<Utc>, after: Option<DateTime<Utc>>, before: Option<DateTime<Utc>>) -> bool {
+    after.is_none_or(|after| time >= after) && before.is_none_or(|before| time < before)
+}
+
+/// Whether the argument contains glob metacharacters
+fn is_pattern(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+/// The directory a glob pattern walks from: its components before the first with metacharacters
+fn pattern_root(pattern: &Path) -> PathBuf {
+    let root: PathBuf = pattern.components()
+        .take_while(|c| !c.as_os_str().to_str().is_some_and(is_pattern))
+        .collect();
+    if root.as_os_str().is_empty() { PathBuf::from(".") } else { root }
+}
+
+/// A path without `.` components, so that `./a.jpg` lies below `.` as `a.jpg` does
+fn normalized(path: &Path) -> PathBuf {
+    path.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+/// One line of an ignore file
+#[derive(Debug)]
+struct IgnoreRule {
+    pattern: Pattern,
+    /// `!pattern`, which re-includes what an earlier rule ignored
+    negated: bool,
+    /// `pattern/`, which only matches directories
+    dir_only: bool,
+    /// Patterns with a `/` other than at the end match the path from the ignore
+    /// file's directory; others match a name at any depth
+    anchored: bool,
+}
+
+/// The rules of one directory's ignore file, none if it has none
+#[derive(Debug, Default)]
+struct IgnoreFile {
+    dir: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreFile {
+    fn parse(dir: &Path, text: &str) -> Self {
+        let rules = text.lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let pattern = Pattern::new(line.strip_prefix('/').unwrap_or(line)).ok()?;
+                Some(IgnoreRule { pattern, negated, dir_only, anchored })
+            })
+            .collect();
+        IgnoreFile { dir: dir.to_path_buf(), rules }
+    }
+
+    /// Whether the last rule matching `path` ignores it, or `None` when no rule matches
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let relative = relative.components().map(|c| filesystem::path_text(c.as_os_str())).collect::<Vec<_>>().join("/");
+        let name = relative.rsplit('/').next().unwrap_or_default();
+        self.rules.iter().rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| match rule.anchored {
+                true => rule.pattern.matches_with(&relative, IGNORE_MATCH_OPTIONS),
+                false => rule.pattern.matches_with(name, IGNORE_MATCH_OPTIONS),
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// `.jmeignore` files read during one expansion, each read once
+#[derive(Default)]
+struct IgnoreFiles {
+    loaded: HashMap<PathBuf, Rc<IgnoreFile>>,
+}
+
+impl IgnoreFiles {
+    fn load(&mut self, dir: &Path) -> Rc<IgnoreFile> {
+        self.loaded.entry(dir.to_path_buf())
+            .or_insert_with(|| Rc::new(match fs::read_to_string(dir.join(IGNORE_FILE)) {
+                Ok(text) => IgnoreFile::parse(dir, &text),
+                Err(_) => IgnoreFile::default(),
+            }))
+            .clone()
+    }
+
+    /// Whether `path`, found by walking `root`, is ignored by the ignore files of
+    /// `root` or a directory between them. As with git, a file in an ignored
+    /// directory stays ignored, and deeper ignore files take precedence.
+    fn ignores(&mut self, root: &Path, path: &Path) -> bool {
+        let (root, path) = (normalized(root), normalized(path));
+        let Ok(relative) = path.strip_prefix(&root) else {
+            return false;
+        };
+        let components: Vec<Component> = relative.components().collect();
+        let mut current = root.clone();
+        let mut files = vec![self.load(&root)];
+        for (i, component) in components.iter().enumerate() {
+            current.push(component);
+            let is_dir = i + 1 < components.len();
+            if files.iter().rev().find_map(|file| file.matches(&current, is_dir)).unwrap_or(false) {
+                return true;
+            }
+            if is_dir {
+                files.push(self.load(&current));
+            }
+        }
+        false
+    }
+}
+
+/// Expand command line inputs into the list of files to process.
+///
+/// Existing paths are used as-is, directories are replaced by the files they
+/// contain, and arguments containing glob metacharacters are expanded here so
+/// that shells which do not expand globs (e.g. cmd.exe) behave the same.
+/// Inputs that match nothing are passed through unchanged. On Windows, paths
+/// too long for the legacy limit get the `\\?\` prefix.
+///
+/// Files found in a directory or by a glob pattern are skipped when a
+/// [`IGNORE_FILE`] in that directory, or between the pattern's fixed prefix and
+/// the file, ignores them.
+pub fn expand_inputs(inputs: &[PathBuf], filters: &Filters) -> Result<Vec<PathBuf>> {
+    Ok(expand_inputs_with_roots(inputs, filters)?.into_iter().map(|(file, _)| file).collect())
+}
+
+/// [`expand_inputs`], with the scan root each file was found under: the
+/// directory given, the fixed prefix of the glob pattern, or for a file given
+/// directly its parent directory
+pub fn expand_inputs_with_roots(inputs: &[PathBuf], filters: &Filters) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut ignore_files = IgnoreFiles::default();
+    for input in inputs {
+        let input = &filesystem::long_path(input).into_owned();
+        if input.is_dir() {
+            if input.file_name().is_some_and(|name| filters.in_excluded_dir(Path::new(name), true)) {
+                continue;
+            }
+            let mut entries: Vec<PathBuf> = fs::read_dir(input)
+                .with_context(|| format!("Failed to read directory {}", input.display()))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_file() && filters.accepts(path, input, true) && !ignore_files.ignores(input, path))
+                .collect();
+            entries.sort();
+            files.extend(entries.into_iter().map(|path| (path, input.clone())));
+        } else if !input.exists() && input.to_str().is_some_and(is_pattern) {
+            let pattern = input.to_str().unwrap();
+            let root = pattern_root(input);
+            let matched: Vec<PathBuf> = glob::glob_with(pattern, MATCH_OPTIONS)
+                .with_context(|| format!("Invalid glob pattern '{}'", pattern))?
+                .filter_map(|entry| entry.ok())
+                .filter(|path| path.is_file() && filters.accepts(path, &root, false) && !ignore_files.ignores(&root, path))
+                .collect();
+            // Matches of a pattern without a directory part are relative to "." as written
+            let root = if root == Path::new(".") && !pattern.starts_with("./") { PathBuf::new() } else { root };
+            if matched.is_empty() {
+                files.push((input.clone(), root.clone()));
+            }
+            files.extend(matched.into_iter().map(|path| (path, root.clone())));
+        } else if filters.accepts(input, input.parent().unwrap_or(Path::new("")), false) {
+            files.push((input.clone(), input.parent().map(Path::to_path_buf).unwrap_or_default()));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_glob_pattern() {
+        let filters = Filters::default();
+        let files = expand_inputs(&[PathBuf::from("images/*.JPG")], &filters).unwrap();
+        assert_eq!(files, [PathBuf::from("images/JAM19896.jpg"), PathBuf::from("images/JAM26284.jpg")]);
+    }
+
+    #[test]
+    fn test_expand_inputs_with_roots() {
+        let inputs = [PathBuf::from("images"), PathBuf::from("images/JAM2*.jpg"), PathBuf::from("Cargo.toml")];
+        let files = expand_inputs_with_roots(&inputs, &Filters::new(&["*.jpg".to_string(), "*.toml".to_string()], &[], &[]).unwrap()).unwrap();
+        let relative: Vec<_> = files.iter().map(|(file, root)| file.strip_prefix(root).unwrap().to_path_buf()).collect();
+        assert_eq!(relative, [PathBuf::from("JAM19896.jpg"), PathBuf::from("JAM26284.jpg"), PathBuf::from("JAM26284.jpg"), PathBuf::from("Cargo.toml")]);
+    }
+
+    #[test]
+    fn test_expand_directory_with_filters() {
+        let filters = Filters::default();
+        let files = expand_inputs(&[PathBuf::from("images")], &filters).unwrap();
+        assert_eq!(files.len(), 2);
+
+        let filters = Filters::new(&["*".to_string()], &["JAM1*".to_string()], &[]).unwrap();
+        let files = expand_inputs(&[PathBuf::from("images")], &filters).unwrap();
+        assert_eq!(files, [PathBuf::from("images/JAM26284.jpg"), PathBuf::from("images/non-jpeg.png")]);
+    }
+
+    #[test]
+    fn test_size_and_modified_filters() {
+        let sizes: Vec<u64> = ["images/JAM19896.jpg", "images/JAM26284.jpg"].iter()
+            .map(|p| fs::metadata(p).unwrap().len())
+            .collect();
+        let filters = Filters { min_size: Some(sizes[0].max(sizes[1])), ..Default::default() };
+        let files = expand_inputs(&[PathBuf::from("images")], &filters).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let filters = Filters { modified_after: Some(Utc::now() + chrono::Duration::days(1)), ..Default::default() };
+        assert!(expand_inputs(&[PathBuf::from("images")], &filters).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ignore_files_and_excluded_dirs() {
+        let dir = std::env::temp_dir().join(format!("jme-ignore-{}", std::process::id()));
+        for sub in ["@eaDir", "export", "keep/.thumbnails", "keep/raw"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in ["a.jpg", "b.jpg", "@eaDir/a.jpg", "export/c.jpg", "keep/d.jpg", "keep/e.jpg", "keep/.thumbnails/d.jpg", "keep/raw/f.jpg"] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        fs::write(dir.join(IGNORE_FILE), "# exports are copies\n/export/\nb.jpg\n").unwrap();
+        fs::write(dir.join("keep").join(IGNORE_FILE), "*.jpg\n!d.jpg\nraw/\n").unwrap();
+
+        let filters = Filters::new(&[], &[], &["@eaDir".to_string(), ".thumb*".to_string()]).unwrap();
+        let pattern = format!("{}/**/*.jpg", dir.display());
+        let found = expand_inputs(&[PathBuf::from(pattern), dir.join("@eaDir"), dir.clone()], &filters).unwrap();
+        let relative: Vec<String> = found.iter().map(|p| p.strip_prefix(&dir).unwrap().display().to_string()).collect();
+        assert_eq!(relative, ["a.jpg", "keep/d.jpg", "a.jpg"]);
+
+        // Only the directories below the scan root are judged
+        let found = expand_inputs(&[dir.join("keep/.thumbnails/*.jpg")], &filters).unwrap();
+        assert_eq!(found, [dir.join("keep/.thumbnails/d.jpg")]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // A pattern without a directory part walks from ".", where its matches have no "./"
+        let mut ignore_files = IgnoreFiles::default();
+        ignore_files.loaded.insert(PathBuf::new(), Rc::new(IgnoreFile::parse(Path::new(""), "b.jpg\n")));
+        assert!(ignore_files.ignores(Path::new("."), Path::new("b.jpg")));
+        assert!(ignore_files.ignores(&pattern_root(Path::new("**/*.jpg")), Path::new("sub/b.jpg")));
+        assert!(!ignore_files.ignores(Path::new("."), Path::new("./a.jpg")));
+    }
+}
@@ -0,0 +1,430 @@
+use crate::error::{ExtractError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Start of image marker
+pub const SOI: u8 = 0xD8;
+/// Start of scan marker; entropy-coded image data follows
+pub const SOS: u8 = 0xDA;
+/// End of image marker
+pub const EOI: u8 = 0xD9;
+
+/// A marker segment from the JPEG header
+#[derive(Debug)]
+pub struct Segment {
+    pub marker: u8,
+    /// Payload following the two-byte length field
+    pub data: Vec<u8>,
+    /// File offset of the marker, for segments read from a file
+    pub offset: Option<u64>,
+}
+
+impl Segment {
+    /// Total bytes this segment occupies in the file, including marker and length
+    pub fn total_len(&self) -> u64 {
+        self.data.len() as u64 + 4
+    }
+
+    /// Whether this is an APPn segment whose payload starts with `signature`
+    pub fn is_app(&self, n: u8, signature: &[u8]) -> bool {
+        self.marker == 0xE0 + n && self.data.starts_with(signature)
+    }
+}
+
+/// Signature of an EXIF APP1 segment
+pub const EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+/// Signature of a standard XMP APP1 segment
+pub const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Signature of an extended XMP APP1 segment
+pub const XMP_EXTENSION_SIGNATURE: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+/// Signature of an ICC profile APP2 segment
+pub const ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+
+/// Most marker segments read before the start of scan; real files have a few dozen
+pub const MAX_SEGMENTS: usize = 4096;
+/// Most times an APPn segment's length field may have wrapped past 64 KiB, as
+/// stitching software writing several megabytes of XMP into one segment does
+const MAX_LENGTH_WRAPS: usize = 64;
+
+/// A header too large to read, as crafted files may be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Segments(usize),
+    /// The header would extend past this many bytes
+    MetadataSize(u64),
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Segments(max) => write!(f, "more than {} segments before the start of scan", max),
+            LimitExceeded::MetadataSize(max) => write!(f, "metadata larger than {} bytes", max),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Bytes on disk attributed to each kind of payload in a JPEG file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PayloadBreakdown {
+    /// EXIF segments, excluding the embedded thumbnail
+    pub exif: u64,
+    pub xmp: u64,
+    pub icc: u64,
+    /// Embedded EXIF thumbnail image
+    pub thumbnail: u64,
+    /// Entropy-coded scan data, from start of scan to the end of the file
+    pub image_data: u64,
+    /// SOI, tables, frame header and any other segments
+    pub other: u64,
+}
+
+impl PayloadBreakdown {
+    /// Attribute every header segment and the remaining scan data of a file
+    pub fn from_segments(segments: &[Segment], file_size: u64, thumbnail_len: u64) -> Self {
+        let mut breakdown = PayloadBreakdown { other: 2, ..Default::default() };
+        for segment in segments {
+            let len = segment.total_len();
+            if segment.is_app(1, EXIF_SIGNATURE) {
+                breakdown.exif += len;
+            } else if segment.is_app(1, XMP_SIGNATURE) || segment.is_app(1, XMP_EXTENSION_SIGNATURE) {
+                breakdown.xmp += len;
+            } else if segment.is_app(2, ICC_SIGNATURE) {
+                breakdown.icc += len;
+            } else {
+                breakdown.other += len;
+            }
+        }
+
+        breakdown.thumbnail = thumbnail_len.min(breakdown.exif);
+        breakdown.exif -= breakdown.thumbnail;
+        breakdown.image_data = file_size.saturating_sub(header_len(segments));
+        breakdown
+    }
+}
+
+/// A run of bytes in a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub start: u64,
+    pub length: u64,
+}
+
+/// Where the metadata lies in a JPEG file, so tools can read or strip it
+/// without parsing the file again. Segment ranges run from the marker through
+/// the end of the payload.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentOffsets {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exif: Vec<ByteRange>,
+    /// Standard and extended XMP segments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xmp: Vec<ByteRange>,
+    /// The chunks of the ICC profile, in file order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub icc: Vec<ByteRange>,
+    /// The embedded EXIF thumbnail, inside the first EXIF segment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<ByteRange>,
+    /// Entropy-coded scan data, from the end of the start of scan segment to the end of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_data: Option<ByteRange>,
+}
+
+impl SegmentOffsets {
+    /// Locate the metadata segments read from a file of `file_size` bytes, and the
+    /// thumbnail at `thumbnail` (offset from the TIFF header, length) in the first
+    /// EXIF segment. Segments not read from a file, as for other containers, are skipped.
+    pub fn from_segments(segments: &[Segment], file_size: u64, thumbnail: Option<(u32, u32)>) -> Self {
+        let mut offsets = SegmentOffsets::default();
+        for segment in segments {
+            let Some(start) = segment.offset else {
+                continue;
+            };
+            let range = ByteRange { start, length: segment.total_len() };
+            if segment.is_app(1, EXIF_SIGNATURE) {
+                offsets.exif.push(range);
+            } else if segment.is_app(1, XMP_SIGNATURE) || segment.is_app(1, XMP_EXTENSION_SIGNATURE) {
+                offsets.xmp.push(range);
+            } else if segment.is_app(2, ICC_SIGNATURE) {
+                offsets.icc.push(range);
+            } else if segment.marker == SOS {
+                let start = range.start + range.length;
+                offsets.image_data = Some(ByteRange { start, length: file_size.saturating_sub(start) });
+            }
+        }
+        // The TIFF header follows the marker, length field and Exif\0\0 signature
+        let tiff_start = offsets.exif.first().map(|exif| exif.start + 4 + EXIF_SIGNATURE.len() as u64);
+        offsets.thumbnail = tiff_start.zip(thumbnail)
+            .map(|(tiff_start, (offset, length))| ByteRange { start: tiff_start + offset as u64, length: length as u64 })
+            .filter(|range| offsets.exif.first().is_some_and(|exif| range.start + range.length <= exif.start + exif.length));
+        offsets
+    }
+}
+
+/// Read all marker segments up to and including the start of scan
+pub fn read_segments<R: Read>(reader: &mut R) -> Result<Vec<Segment>> {
+    read_marker_segments(reader, None, None)
+}
+
+/// Header segments read leniently, with a note for everything out of spec
+#[derive(Debug, Default)]
+pub struct Header {
+    pub segments: Vec<Segment>,
+    pub warnings: Vec<String>,
+}
+
+/// Like [`read_segments`], but skip junk between segments and stop at a truncated
+/// or malformed segment instead of failing, keeping the segments before it. Offsets
+/// computed from the segments, as by [`segment_offset`], do not count skipped junk.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<Header> {
+    read_header_within(reader, None)
+}
+
+/// Like [`read_header`], but fail with [`LimitExceeded`] rather than read a
+/// header extending past `max_size` bytes from the start of the file
+pub fn read_header_within<R: Read>(reader: &mut R, max_size: Option<u64>) -> Result<Header> {
+    let mut warnings = Vec::new();
+    let segments = read_marker_segments(reader, Some(&mut warnings), max_size)?;
+    Ok(Header { segments, warnings })
+}
+
+fn read_marker_segments<R: Read>(reader: &mut R, mut warnings: Option<&mut Vec<String>>, max_size: Option<u64>) -> Result<Vec<Segment>> {
+    let mut header = [0u8; 2];
+    match read_exact(reader, &mut header) {
+        Err(ExtractError::ParseError { .. }) => {}
+        result => result?,
+    }
+    if header != [0xFF, SOI] {
+        return Err(ExtractError::NotAnImage("missing JPEG start of image marker".to_string()));
+    }
+
+    let mut segments = Vec::new();
+    let mut offset = 2u64;
+    let reader = &mut Pushback { pending: Vec::new(), inner: reader };
+    loop {
+        if segments.len() == MAX_SEGMENTS {
+            return Err(LimitExceeded::Segments(MAX_SEGMENTS).into());
+        }
+        match read_segment(reader, &mut offset, warnings.as_deref_mut(), max_size) {
+            Ok(Some(mut segment)) => {
+                if let Some(warnings) = warnings.as_deref_mut().filter(|_| (0xE0..=0xEF).contains(&segment.marker)) {
+                    let declared = segment.data.len() + 2;
+                    if read_wrapped_length(reader, &mut segment, &mut offset, max_size)? {
+                        warnings.push(format!(
+                            "{} segment at offset {} is {} bytes long but its length field says {}; read it whole",
+                            marker_name(segment.marker), segment.offset.unwrap_or_default(), segment.data.len() + 2, declared,
+                        ));
+                    }
+                }
+                let marker = segment.marker;
+                segments.push(segment);
+                if marker == SOS {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e @ ExtractError::LimitExceeded(_)) => return Err(e),
+            Err(e) => match warnings.as_deref_mut() {
+                // Nothing is recovered from a file whose first segment is unreadable
+                Some(warnings) if !segments.is_empty() => {
+                    warnings.push(format!("Stopped reading the JPEG header at offset {}: {:#}", offset, e));
+                    break;
+                }
+                _ => return Err(e),
+            },
+        }
+    }
+    Ok(segments)
+}
+
+/// Read the next segment with a length field, or `None` at the end of image.
+/// With `warnings`, bytes that are not a marker are skipped instead of rejected.
+fn read_segment<R: Read>(
+    reader: &mut R,
+    offset: &mut u64,
+    mut warnings: Option<&mut Vec<String>>,
+    max_size: Option<u64>,
+) -> Result<Option<Segment>> {
+    loop {
+        let mut byte = [0u8; 1];
+        read_exact(reader, &mut byte)?;
+        if byte[0] != 0xFF {
+            let Some(warnings) = warnings.as_deref_mut() else {
+                return Err(ExtractError::parse("JPEG header", format!("expected a marker at offset {}", offset)));
+            };
+            let mut skipped = 1;
+            while byte[0] != 0xFF {
+                read_exact(reader, &mut byte)?;
+                skipped += 1;
+            }
+            skipped -= 1;
+            warnings.push(format!("Skipped {} bytes that are not a JPEG marker at offset {}", skipped, offset));
+            *offset += skipped;
+        }
+        // Markers may be preceded by any number of 0xFF fill bytes
+        let mut marker = 0xFF;
+        let mut fill = 0u64;
+        while marker == 0xFF {
+            read_exact(reader, &mut byte)?;
+            marker = byte[0];
+            fill += 1;
+        }
+        let marker_offset = *offset + fill - 1;
+        *offset += fill + 1;
+
+        // Standalone markers carry no length field
+        if marker == EOI {
+            return Ok(None);
+        }
+        if (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+            continue;
+        }
+
+        let mut len = [0u8; 2];
+        read_exact(reader, &mut len)?;
+        let len = u16::from_be_bytes(len) as usize;
+        if len < 2 {
+            return Err(ExtractError::parse(marker_name(marker), format!("segment length {} at offset {}", len, marker_offset)));
+        }
+        if let Some(max) = max_size.filter(|&max| *offset + len as u64 > max) {
+            return Err(LimitExceeded::MetadataSize(max).into());
+        }
+        let mut data = vec![0u8; len - 2];
+        reader.read_exact(&mut data).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ExtractError::parse(marker_name(marker), format!("segment at offset {} is truncated", marker_offset)),
+            _ => ExtractError::Io(e),
+        })?;
+        *offset += len as u64;
+        return Ok(Some(Segment { marker, data, offset: Some(marker_offset) }));
+    }
+}
+
+/// A reader that can be handed back bytes it has already given out
+struct Pushback<R> {
+    /// Bytes to give out again, last first
+    pending: Vec<u8>,
+    inner: R,
+}
+
+impl<R> Pushback<R> {
+    fn unread(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes.iter().rev());
+    }
+}
+
+impl<R: Read> Read for Pushback<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.inner.read(buf);
+        }
+        let n = buf.len().min(self.pending.len());
+        for byte in &mut buf[..n] {
+            *byte = self.pending.pop().unwrap_or_default();
+        }
+        Ok(n)
+    }
+}
+
+/// Whether a marker may follow a header segment: a frame, table, scan, APPn,
+/// comment or end of image marker, but not a restart marker or stuffed zero
+fn is_header_marker(marker: u8) -> bool {
+    matches!(marker, 0xC0..=0xCF | 0xD9..=0xDF | 0xE0..=0xEF | 0xFE)
+}
+
+/// When `segment` is not followed by a marker, look for one whole multiples of
+/// 64 KiB further on, as written by software that stores an APPn segment's length
+/// modulo 65536. Returns whether the segment was extended to that marker; if none
+/// is found every byte read is handed back, to be skipped as junk.
+fn read_wrapped_length<R: Read>(reader: &mut Pushback<R>, segment: &mut Segment, offset: &mut u64, max_size: Option<u64>) -> Result<bool> {
+    let mut extra = Vec::new();
+    loop {
+        let mut next = Vec::with_capacity(2);
+        reader.by_ref().take(2).read_to_end(&mut next)?;
+        reader.unread(&next);
+        if let [0xFF, marker] = next[..] {
+            if is_header_marker(marker) || extra.is_empty() {
+                break;
+            }
+        } else if extra.is_empty() && next.first() == Some(&0xFF) {
+            break;
+        }
+        let within_limit = max_size.is_none_or(|max| *offset + extra.len() as u64 + 0x10000 <= max);
+        if extra.len() == MAX_LENGTH_WRAPS * 0x10000 || !within_limit {
+            reader.unread(&extra);
+            return Ok(false);
+        }
+        let start = extra.len();
+        reader.by_ref().take(0x10000).read_to_end(&mut extra)?;
+        if extra.len() - start < 0x10000 {
+            reader.unread(&extra);
+            return Ok(false);
+        }
+    }
+    if extra.is_empty() {
+        return Ok(false);
+    }
+    *offset += extra.len() as u64;
+    segment.data.extend(extra);
+    Ok(true)
+}
+
+/// Read header bytes, treating the end of the data as a truncated header
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => ExtractError::parse("JPEG header", "unexpected end of data"),
+        _ => ExtractError::Io(e),
+    })
+}
+
+/// Whether the marker is a start of frame (SOF0-SOF15, excluding DHT, JPG and DAC)
+pub fn is_sof(marker: u8) -> bool {
+    (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC)
+}
+
+/// Bytes from the start of the file through the end of the start of scan segment
+pub fn header_len(segments: &[Segment]) -> u64 {
+    2 + segments.iter().map(Segment::total_len).sum::<u64>()
+}
+
+/// Append a segment's marker, length and payload to `out`
+pub fn push_segment(out: &mut Vec<u8>, segment: &Segment) -> Result<()> {
+    let len = u16::try_from(segment.data.len() + 2)
+        .map_err(|_| ExtractError::Encode(format!("{} segment: larger than the 64 KiB limit", marker_name(segment.marker))))?;
+    out.extend([0xFF, segment.marker]);
+    out.extend(len.to_be_bytes());
+    out.extend(&segment.data);
+    Ok(())
+}
+
+/// File offset of the marker of `segments[index]`, assuming no fill bytes between segments
+pub fn segment_offset(segments: &[Segment], index: usize) -> u64 {
+    2 + segments[..index].iter().map(Segment::total_len).sum::<u64>()
+}
+
+/// Conventional name of a marker, e.g. `APP1`, `SOF2` or `DQT`
+pub fn marker_name(marker: u8) -> String {
+    match marker {
+        0xC4 => "DHT".to_string(),
+        0xCC => "DAC".to_string(),
+        0xC0..=0xCF => format!("SOF{}", marker - 0xC0),
+        0xDA => "SOS".to_string(),
+        0xDB => "DQT".to_string(),
+        0xDD => "DRI".to_string(),
+        0xE0..=0xEF => format!("APP{}", marker - 0xE0),
+        0xFE => "COM".to_string(),
+        other => format!("0x{:02X}", other),
+    }
+}
+
+/// Image width and height from the start of frame segment
+pub fn dimensions(segments: &[Segment]) -> Option<(u32, u32)> {
+    let sof = segments.iter().find(|s| is_sof(s.marker))?;
+    if sof.data.len() < 5 {
+        return None;
+    }
+    let height = u16::from_be_bytes([sof.data[1], sof.data[2]]) as u32;
+    let width = u16::from_be_bytes([sof.data[3], sof.data[4]]) as u32;
+    Some((width, height))
+}
@@ -0,0 +1,100 @@
+use crate::xmp;
+use roxmltree::Document;
+
+/// Lightroom namespace, which holds the keyword hierarchy
+pub const LR_NS: &str = "http://ns.adobe.com/lightroom/1.0/";
+
+/// Separator between the levels of a hierarchical keyword, as Lightroom writes them
+const SEPARATOR: char = '|';
+
+/// Keywords in the two forms search indexes want
+#[derive(Debug, Default, PartialEq)]
+pub struct Keywords {
+    /// Single terms, e.g. `Animals`, `Birds` and `Owl` for `Animals|Birds|Owl`
+    pub flat: Vec<String>,
+    /// Full paths and their ancestors, e.g. `Animals`, `Animals|Birds` and `Animals|Birds|Owl`
+    pub hierarchical: Vec<String>,
+}
+
+/// Keywords from `dc:subject` and Lightroom's `lr:hierarchicalSubject`. Either
+/// may hold `|`-separated paths, which are split into their terms. With
+/// `leaf_only` the flat keywords are the last term of each path and the
+/// hierarchy lists only the paths as written, without their ancestors.
+pub fn from_xmp(doc: &Document, leaf_only: bool) -> Keywords {
+    let hierarchical_subject = doc.descendants()
+        .find(|node| node.has_tag_name((LR_NS, "hierarchicalSubject")))
+        .map(|subject| {
+            xmp::list_items(subject)
+                .filter_map(|item| item.text())
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    expand(xmp::keywords(doc), hierarchical_subject, leaf_only)
+}
+
+/// Expand `subject` entries, plain or hierarchical, and `hierarchy` paths; see [`from_xmp`]
+pub fn expand(subject: Vec<String>, hierarchy: Vec<String>, leaf_only: bool) -> Keywords {
+    let (paths, plain): (Vec<String>, Vec<String>) = subject.into_iter().partition(|s| s.contains(SEPARATOR));
+    let paths: Vec<Vec<&str>> = hierarchy.iter().chain(&paths)
+        .map(|path| path.split(SEPARATOR).map(str::trim).filter(|term| !term.is_empty()).collect())
+        .filter(|terms: &Vec<&str>| !terms.is_empty())
+        .collect();
+
+    let mut keywords = Keywords::default();
+    // Lists with ancestors written out, as ours are, hold paths that are not leaves
+    let is_branch = |terms: &[&str]| paths.iter().any(|other| other.len() > terms.len() && other.starts_with(terms));
+    for terms in paths.iter().filter(|terms| !(leaf_only && is_branch(terms))) {
+        let levels = if leaf_only { terms.len() - 1..terms.len() } else { 0..terms.len() };
+        for level in levels {
+            push_unique(&mut keywords.flat, terms[level]);
+            push_unique(&mut keywords.hierarchical, &terms[..=level].join("|"));
+        }
+    }
+    // Lightroom also lists each path's ancestors in dc:subject, which are not leaves
+    let branches: Vec<&str> = paths.iter().flat_map(|terms| terms[..terms.len() - 1].iter().copied()).collect();
+    for term in plain {
+        if !(leaf_only && branches.contains(&term.as_str())) {
+            push_unique(&mut keywords.flat, &term);
+        }
+    }
+    keywords
+}
+
+fn push_unique(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|v| v == value) {
+        list.push(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_xmp() {
+        let packet = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:lr="http://ns.adobe.com/lightroom/1.0/">
+<dc:subject><rdf:Bag><rdf:li>Animals</rdf:li><rdf:li>Birds</rdf:li><rdf:li>Owl</rdf:li><rdf:li>night</rdf:li></rdf:Bag></dc:subject>
+<lr:hierarchicalSubject><rdf:Bag><rdf:li>Animals|Birds|Owl</rdf:li><rdf:li>Animals|Birds|Heron</rdf:li></rdf:Bag></lr:hierarchicalSubject>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let doc = xmp::parse(packet).unwrap();
+        let keywords = from_xmp(&doc, false);
+        assert_eq!(keywords.flat, ["Animals", "Birds", "Owl", "Heron", "night"]);
+        assert_eq!(keywords.hierarchical, ["Animals", "Animals|Birds", "Animals|Birds|Owl", "Animals|Birds|Heron"]);
+
+        let leaves = from_xmp(&doc, true);
+        assert_eq!(leaves.flat, ["Owl", "Heron", "night"]);
+        assert_eq!(leaves.hierarchical, ["Animals|Birds|Owl", "Animals|Birds|Heron"]);
+    }
+
+    #[test]
+    fn test_expand_subject_paths() {
+        let subject = vec!["Places | France|Paris".to_string(), "night".to_string()];
+        let keywords = expand(subject, Vec::new(), false);
+        assert_eq!(keywords.flat, ["Places", "France", "Paris", "night"]);
+        assert_eq!(keywords.hierarchical, ["Places", "Places|France", "Places|France|Paris"]);
+        assert_eq!(expand(vec!["plain".to_string()], Vec::new(), true), Keywords { flat: vec!["plain".to_string()], hierarchical: Vec::new() });
+    }
+}
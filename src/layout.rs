@@ -0,0 +1,129 @@
+use serde_json::{Map, Value};
+
+/// Sections of nested output, in order, with the record fields each holds.
+/// Fields not listed, such as `extensions` and `provenance`, stay at the top level.
+const SECTIONS: &[(&str, &[&str])] = &[
+    ("file", &[
+        "filename", "archive", "canonical_path", "relative_path", "volume_id", "size", "created_time", "modified_time", "timestamp_source", "is_symlink",
+        "link_target", "inode", "device", "uid", "gid", "mode", "readonly", "xattrs",
+    ]),
+    ("image", &["format", "width", "height", "display_width", "display_height", "encoding", "color_space", "pages", "payload_breakdown", "segment_offsets", "computational"]),
+    ("exif", &[
+        "orientation", "capture_time", "capture_time_raw", "best_time", "best_time_source", "camera_model", "camera_serial", "camera_firmware", "camera_id", "image_unique_id", "sequence_number", "shutter_count",
+        "flash", "white_balance", "focus", "enrichment", "exif_extra", "description", "artist", "copyright",
+        "transcoded_fields",
+    ]),
+    ("gps", &["gps"]),
+    ("xmp", &["keywords", "hierarchical_keywords", "regions", "drone"]),
+    ("thermal", &["thermal"]),
+    ("panorama", &["panorama"]),
+    ("audio", &["audio"]),
+    ("os", &["spotlight", "windows_properties"]),
+    ("analysis", &[
+        "burst_group_id", "event_id", "original_of", "derivative_of", "colors", "quality", "perceptual_hash",
+        "metadata_fingerprint",
+    ]),
+];
+
+/// Where a flat field goes: its section, and its key there or `None` when the
+/// field's own object becomes the section
+fn place(field: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let (section, fields) = SECTIONS.iter().find(|(_, fields)| fields.contains(&field))?;
+    let key = match field {
+        "gps" | "thermal" | "panorama" | "audio" => None,
+        "exif_extra" => Some("extra"),
+        _ => fields.iter().find(|&&f| f == field).copied(),
+    };
+    Some((section, key))
+}
+
+/// The flat fields a section holds, for field selections naming a whole section
+pub fn section_fields(name: &str) -> Option<&'static [&'static str]> {
+    SECTIONS.iter().find(|(section, _)| *section == name).map(|(_, fields)| *fields)
+}
+
+/// Every section's name and flat fields, in output order
+pub fn sections() -> &'static [(&'static str, &'static [&'static str])] {
+    SECTIONS
+}
+
+/// Whether a top-level key of nested output is a section
+pub fn is_section(key: &str) -> bool {
+    SECTIONS.iter().any(|(section, _)| *section == key)
+}
+
+/// The nested path of a dotted flat field path, e.g. `exif_extra.0x0110` becomes `exif.extra.0x0110`
+pub fn nested_path(path: &str) -> String {
+    let (field, rest) = path.split_once('.').map_or((path, None), |(f, r)| (f, Some(r)));
+    let Some((section, key)) = place(field) else {
+        return path.to_string();
+    };
+    [Some(section), key, rest].into_iter().flatten().collect::<Vec<_>>().join(".")
+}
+
+/// Regroup a flat record into sections, e.g. `{"size": 1, "camera_model": "X"}`
+/// becomes `{"file": {"size": 1}, "exif": {"camera_model": "X"}}`
+pub fn nest(value: Value) -> Value {
+    let Value::Object(flat) = value else {
+        return value;
+    };
+    let mut sections: Vec<(&str, Map<String, Value>)> = SECTIONS.iter().map(|(name, _)| (*name, Map::new())).collect();
+    let mut rest = Map::new();
+    for (field, value) in flat {
+        match place(&field) {
+            Some((section, key)) => {
+                let map = &mut sections.iter_mut().find(|(name, _)| *name == section).unwrap().1;
+                match (key, value) {
+                    (Some(key), value) => {
+                        map.insert(key.to_string(), value);
+                    }
+                    (None, Value::Object(fields)) => map.extend(fields),
+                    (None, other) => {
+                        map.insert(field, other);
+                    }
+                }
+            }
+            None if field == "provenance" => {
+                let renamed = match value {
+                    Value::Object(sources) => sources.into_iter().map(|(k, v)| (nested_path(&k), v)).collect(),
+                    other => other,
+                };
+                rest.insert(field, renamed);
+            }
+            None => {
+                rest.insert(field, value);
+            }
+        }
+    }
+    let mut nested: Map<String, Value> = sections.into_iter()
+        .filter(|(_, map)| !map.is_empty())
+        .map(|(name, map)| (name.to_string(), Value::Object(map)))
+        .collect();
+    nested.extend(rest);
+    Value::Object(nested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nest() {
+        let flat = json!({
+            "filename": "a.jpg", "format": "jfif", "size": 10, "camera_model": "X-T5",
+            "gps": {"latitude": 48.1, "longitude": 2.5}, "exif_extra": {"0x0110": "X-T5"},
+            "keywords": ["cat"], "extensions": {"lens": {}}, "provenance": {"exif_extra.0x0110": {"source": "exif"}, "size": {"source": "filesystem"}},
+        });
+        let nested = nest(flat);
+        let keys: Vec<&String> = nested.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["file", "image", "exif", "gps", "xmp", "extensions", "provenance"]);
+        assert_eq!(nested["file"], json!({"filename": "a.jpg", "size": 10}));
+        assert_eq!(nested["exif"]["extra"]["0x0110"], "X-T5");
+        assert_eq!(nested["gps"]["latitude"], 48.1);
+        assert!(nested["provenance"]["exif.extra.0x0110"].is_object() && nested["provenance"]["file.size"].is_object());
+        assert_eq!(nested_path("description.user_comment"), "exif.description.user_comment");
+        assert_eq!(nested_path("gps"), "gps");
+        assert!(section_fields("xmp").unwrap().contains(&"keywords"));
+    }
+}
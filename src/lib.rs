@@ -0,0 +1,67 @@
+//! Metadata extraction for JPEG images.
+//!
+//! The `jpeg-metadata-extractor` binary is a thin command-line wrapper around
+//! these modules; they can also be used directly, e.g. to register custom
+//! [`extractor::Extractor`]s.
+//!
+//! [`content`] and the modules it uses never touch the filesystem, so they
+//! also build for `wasm32-unknown-unknown`; see the `wasm` module.
+//!
+//! Fallible functions return [`error::ExtractError`], whose variants separate
+//! unreadable input, malformed metadata and unsupported formats.
+
+pub mod anonymize;
+pub mod audio;
+pub mod burst;
+pub mod cameras;
+pub mod charset;
+pub mod colors;
+pub mod colorspace;
+pub mod compat;
+pub mod computational;
+pub mod containers;
+pub mod content;
+pub mod derivatives;
+pub mod detect;
+pub mod diff;
+pub mod drone;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod exif_metadata;
+pub mod exif_write;
+pub mod extractor;
+pub mod filesystem;
+pub mod fixture;
+pub mod focus;
+pub mod gpx;
+pub mod jpeg;
+pub mod keywords;
+pub mod lighting;
+pub mod locale;
+pub mod makernote;
+pub mod os_metadata;
+pub mod panorama;
+pub mod pixels;
+pub mod privacy;
+pub mod provenance;
+pub mod quality;
+pub mod raw;
+pub mod regions;
+pub mod stats;
+pub mod template;
+pub mod thermal;
+pub mod thumbnail;
+pub mod timeshift;
+pub mod transplant;
+pub mod xmp;
+pub mod xmp_write;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
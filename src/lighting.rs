@@ -0,0 +1,148 @@
+use exif::{Exif, In, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Adobe Camera Raw settings namespace, which records the white balance temperature
+pub const CRS_NS: &str = "http://ns.adobe.com/camera-raw-settings/1.0/";
+
+/// Strobe return light detection, bits 1-2 of the Flash tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashReturn {
+    NoDetection,
+    Reserved,
+    NotDetected,
+    Detected,
+}
+
+/// Flash firing mode, bits 3-4 of the Flash tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashMode {
+    Unknown,
+    CompulsoryFiring,
+    CompulsorySuppression,
+    Auto,
+}
+
+/// The Flash tag decoded into its bit fields
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Flash {
+    pub fired: bool,
+    #[serde(rename = "return")]
+    pub return_light: FlashReturn,
+    pub mode: FlashMode,
+    /// False when the camera has no flash function
+    pub function_present: bool,
+    pub red_eye_reduction: bool,
+}
+
+impl Flash {
+    pub fn from_bits(bits: u32) -> Self {
+        Flash {
+            fired: bits & 0x01 != 0,
+            return_light: match (bits >> 1) & 0x03 {
+                0 => FlashReturn::NoDetection,
+                1 => FlashReturn::Reserved,
+                2 => FlashReturn::NotDetected,
+                _ => FlashReturn::Detected,
+            },
+            mode: match (bits >> 3) & 0x03 {
+                0 => FlashMode::Unknown,
+                1 => FlashMode::CompulsoryFiring,
+                2 => FlashMode::CompulsorySuppression,
+                _ => FlashMode::Auto,
+            },
+            function_present: bits & 0x20 == 0,
+            red_eye_reduction: bits & 0x40 != 0,
+        }
+    }
+}
+
+/// White balance setting and the light it was set for
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WhiteBalance {
+    /// `auto` or `manual`, from the WhiteBalance tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// LightSource tag, e.g. `daylight` or `d65`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_source: Option<String>,
+    /// Colour temperature in kelvin, from Camera Raw settings in the XMP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_temperature: Option<u32>,
+}
+
+/// Decoded Flash tag, if present
+pub fn flash(exif: &Exif) -> Option<Flash> {
+    let bits = exif.get_field(Tag::Flash, In::PRIMARY)?.value.get_uint(0)?;
+    Some(Flash::from_bits(bits))
+}
+
+/// WhiteBalance and LightSource tags, if either is present
+pub fn white_balance(exif: &Exif) -> Option<WhiteBalance> {
+    let uint = |tag| exif.get_field(tag, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+    let mode = uint(Tag::WhiteBalance).map(|v| match v {
+        0 => "auto".to_string(),
+        1 => "manual".to_string(),
+        other => format!("unknown ({})", other),
+    });
+    let light_source = uint(Tag::LightSource).map(light_source_name);
+    if mode.is_none() && light_source.is_none() {
+        return None;
+    }
+    Some(WhiteBalance { mode, light_source, color_temperature: None })
+}
+
+/// Snake-case name of a LightSource value, as listed in EXIF 2.32
+fn light_source_name(value: u32) -> String {
+    let name = match value {
+        0 => "unknown",
+        1 => "daylight",
+        2 => "fluorescent",
+        3 => "tungsten",
+        4 => "flash",
+        9 => "fine_weather",
+        10 => "cloudy",
+        11 => "shade",
+        12 => "daylight_fluorescent",
+        13 => "day_white_fluorescent",
+        14 => "cool_white_fluorescent",
+        15 => "white_fluorescent",
+        16 => "warm_white_fluorescent",
+        17 => "standard_light_a",
+        18 => "standard_light_b",
+        19 => "standard_light_c",
+        20 => "d55",
+        21 => "d65",
+        22 => "d75",
+        23 => "d50",
+        24 => "iso_studio_tungsten",
+        255 => "other",
+        other => return format!("unknown ({})", other),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flash_bits() {
+        // Fired, return detected, auto, red-eye reduction
+        let flash = Flash::from_bits(0x5F);
+        assert!(flash.fired && flash.red_eye_reduction && flash.function_present);
+        assert_eq!((flash.return_light, flash.mode), (FlashReturn::Detected, FlashMode::Auto));
+
+        let none = Flash::from_bits(0x20);
+        assert!(!none.fired && !none.function_present);
+        assert_eq!(Flash::from_bits(0x10).mode, FlashMode::CompulsorySuppression);
+        assert_eq!(serde_json::to_value(&none).unwrap()["return"], "no_detection");
+    }
+
+    #[test]
+    fn test_light_source_name() {
+        assert_eq!(light_source_name(21), "d65");
+        assert_eq!(light_source_name(7), "unknown (7)");
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::{Datelike, NaiveDateTime};
+
+/// Conventions for numbers and dates in human-readable output such as the
+/// table and the stats report. JSON and other machine output never uses them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Locale {
+    /// Language tag, e.g. `de` or `en-GB`
+    pub tag: &'static str,
+    decimal: char,
+    /// Between groups of three digits; empty for no grouping
+    group: &'static str,
+    months: [&'static str; 12],
+    /// Date layout with `{d}`, `{month}` and `{y}` placeholders; empty for ISO 8601
+    date: &'static str,
+}
+
+const ENGLISH_MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+/// ISO 8601 dates and ungrouped numbers, as output has always been
+const C: Locale = Locale { tag: "C", decimal: '.', group: "", months: ENGLISH_MONTHS, date: "" };
+
+const LOCALES: &[Locale] = &[
+    Locale { tag: "en", decimal: '.', group: ",", months: ENGLISH_MONTHS, date: "{month} {d}, {y}" },
+    Locale { tag: "en-GB", decimal: '.', group: ",", months: ENGLISH_MONTHS, date: "{d} {month} {y}" },
+    Locale {
+        tag: "de", decimal: ',', group: ".", date: "{d}. {month} {y}",
+        months: ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"],
+    },
+    Locale {
+        tag: "fr", decimal: ',', group: "\u{202F}", date: "{d} {month} {y}",
+        months: ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"],
+    },
+    Locale {
+        tag: "es", decimal: ',', group: ".", date: "{d} de {month} de {y}",
+        months: ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre"],
+    },
+    Locale {
+        tag: "it", decimal: ',', group: ".", date: "{d} {month} {y}",
+        months: ["gennaio", "febbraio", "marzo", "aprile", "maggio", "giugno", "luglio", "agosto", "settembre", "ottobre", "novembre", "dicembre"],
+    },
+    Locale {
+        tag: "nl", decimal: ',', group: ".", date: "{d} {month} {y}",
+        months: ["januari", "februari", "maart", "april", "mei", "juni", "juli", "augustus", "september", "oktober", "november", "december"],
+    },
+    Locale {
+        tag: "pt", decimal: ',', group: ".", date: "{d} de {month} de {y}",
+        months: ["janeiro", "fevereiro", "março", "abril", "maio", "junho", "julho", "agosto", "setembro", "outubro", "novembro", "dezembro"],
+    },
+    Locale {
+        tag: "sv", decimal: ',', group: "\u{00A0}", date: "{d} {month} {y}",
+        months: ["januari", "februari", "mars", "april", "maj", "juni", "juli", "augusti", "september", "oktober", "november", "december"],
+    },
+    Locale {
+        tag: "pl", decimal: ',', group: "\u{00A0}", date: "{d} {month} {y}",
+        // Genitive, as used after a day number
+        months: ["stycznia", "lutego", "marca", "kwietnia", "maja", "czerwca", "lipca", "sierpnia", "września", "października", "listopada", "grudnia"],
+    },
+];
+
+impl Default for Locale {
+    fn default() -> Self {
+        C
+    }
+}
+
+impl Locale {
+    /// Look up a locale by tag, accepting forms like `de`, `de-AT` and
+    /// `de_DE.UTF-8`; a region without its own entry falls back to the language
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+        if name.eq_ignore_ascii_case("C") || name.eq_ignore_ascii_case("POSIX") {
+            return Some(C);
+        }
+        let language = name.split('-').next().unwrap_or_default();
+        LOCALES.iter().find(|l| l.tag.eq_ignore_ascii_case(&name))
+            .or_else(|| LOCALES.iter().find(|l| l.tag.eq_ignore_ascii_case(language)))
+            .copied()
+    }
+
+    /// Tags accepted by [`Locale::parse`], for error messages
+    pub fn supported() -> impl Iterator<Item = &'static str> {
+        std::iter::once(C.tag).chain(LOCALES.iter().map(|l| l.tag))
+    }
+
+    /// An integer with digit grouping, e.g. `1.234.567` in German
+    pub fn integer(&self, n: u64) -> String {
+        let digits = n.to_string();
+        let mut out = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push_str(self.group);
+            }
+            out.push(digit);
+        }
+        out
+    }
+
+    /// A number with `places` decimals and the locale's decimal separator
+    pub fn decimal(&self, x: f64, places: usize) -> String {
+        format!("{:.*}", places, x).replace('.', &self.decimal.to_string())
+    }
+
+    /// A date and time, e.g. `10. Mai 2024 14:15:00`; `seconds` false drops them
+    pub fn datetime(&self, time: NaiveDateTime, seconds: bool) -> String {
+        let clock = time.format(if seconds { "%H:%M:%S" } else { "%H:%M" });
+        if self.date.is_empty() {
+            return format!("{} {}", time.format("%Y-%m-%d"), clock);
+        }
+        let date = self.date
+            .replace("{d}", &time.day().to_string())
+            .replace("{month}", self.months[time.month0() as usize])
+            .replace("{y}", &time.year().to_string());
+        format!("{} {}", date, clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_locales() {
+        let time = NaiveDate::from_ymd_opt(2024, 5, 10).unwrap().and_hms_opt(14, 15, 0).unwrap();
+        let de = Locale::parse("de_DE.UTF-8").unwrap();
+        assert_eq!(de.tag, "de");
+        assert_eq!(de.integer(1_234_567), "1.234.567");
+        assert_eq!(de.decimal(2.8, 1), "2,8");
+        assert_eq!(de.datetime(time, true), "10. Mai 2024 14:15:00");
+        assert_eq!(Locale::parse("en-gb").unwrap().datetime(time, false), "10 May 2024 14:15");
+        assert_eq!(Locale::parse("en-US").unwrap().integer(999), "999");
+
+        let c = Locale::default();
+        assert_eq!((c.integer(1_234_567), c.datetime(time, true)), ("1234567".to_string(), "2024-05-10 14:15:00".to_string()));
+        assert_eq!(Locale::parse("xx"), None);
+    }
+}
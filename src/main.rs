@@ -1,169 +1,2905 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, TimeZone, Utc};
-use clap::Parser;
-use exif::{Reader, Tag, In};
-use serde::Serialize;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufReader, Read};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
 
-#[derive(Debug)]
-struct FilesystemMetadata {
-    size: u64,
-    created_time: DateTime<Utc>,
-    modified_time: DateTime<Utc>,
+mod archives;
+mod cache;
+mod capabilities;
+mod cas;
+mod catalog;
+mod checksums;
+mod compliance;
+mod compression;
+mod config;
+mod exec;
+mod inputs;
+mod layout;
+mod manifest;
+mod plan;
+#[cfg(feature = "queue")]
+mod publish;
+mod registry;
+mod report;
+mod schema;
+mod shutdown;
+mod signing;
+mod sink;
+mod state;
+mod throttle;
+mod timestamps;
+#[cfg(feature = "tui")]
+mod tui;
+
+use checksums::{ChecksumManifest, ManifestFormat};
+use compression::Compression;
+use compliance::{Compliance, Policy};
+use config::Config;
+use exec::ExecCommand;
+use manifest::Job;
+use plan::{Action, Change, Plan};
+use report::{FileTimer, Report, Stage};
+use signing::SigningKey;
+use state::RunState;
+use throttle::{Throttle, Throttled};
+use sink::{ExiftoolSink, Fanout, GroupingSink, JsonLinesSink, SidecarSink, Sink, TableSink};
+use timestamps::{TimeFormat, Zone};
+
+use jpeg_metadata_extractor::anonymize::{Anonymizer, Category};
+use jpeg_metadata_extractor::audio::{self, AudioNote, AudioSource};
+use jpeg_metadata_extractor::cameras::{self, CameraSpec, Enrichment};
+use jpeg_metadata_extractor::charset::Charset;
+use jpeg_metadata_extractor::colors::ColorStats;
+use jpeg_metadata_extractor::colorspace::ColorSpace;
+use jpeg_metadata_extractor::computational::ComputationalMetadata;
+use jpeg_metadata_extractor::content::{ContentMetadata, ExtractOptions};
+use jpeg_metadata_extractor::drone::DroneMetadata;
+use jpeg_metadata_extractor::panorama::PanoramaMetadata;
+use jpeg_metadata_extractor::thermal::{self, ThermalMetadata};
+use jpeg_metadata_extractor::encoding::Encoding;
+use jpeg_metadata_extractor::error::ExtractError;
+use jpeg_metadata_extractor::events::Event;
+use jpeg_metadata_extractor::exif_metadata::{Description, GpsPosition, TimeSource};
+use jpeg_metadata_extractor::extractor::ExtractorRegistry;
+use jpeg_metadata_extractor::filesystem::{self, extract_filesystem_metadata, file_identity};
+use jpeg_metadata_extractor::focus::Focus;
+use jpeg_metadata_extractor::gpx::{self, Track};
+use jpeg_metadata_extractor::lighting::{Flash, WhiteBalance};
+use jpeg_metadata_extractor::locale::Locale;
+use jpeg_metadata_extractor::os_metadata::{self, OsMetadata};
+use jpeg_metadata_extractor::privacy::{self, PrivacyZone, Redaction};
+use jpeg_metadata_extractor::provenance::Source;
+use jpeg_metadata_extractor::quality::QualityMetrics;
+use jpeg_metadata_extractor::regions::Region;
+use jpeg_metadata_extractor::stats::{Shot, Stats};
+use jpeg_metadata_extractor::template::Condition;
+use jpeg_metadata_extractor::thumbnail::{self, ThumbnailState};
+use jpeg_metadata_extractor::transplant::{self, SegmentKind};
+use jpeg_metadata_extractor::exif_metadata::read_exif_metadata;
+use jpeg_metadata_extractor::{content, detect, diff, exif_write, fixture, jpeg, raw, timeshift, xmp_write};
+
+/// How extracted metadata is reported
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    /// Write a pretty-printed .json sidecar next to each image
+    Json,
+    /// Print an aligned summary table to stdout without writing files
+    Table,
+    /// Stream one JSON object per line to stdout as each file completes
+    Jsonl,
+    /// Print a JSON array to stdout with the tag names and value formats of `exiftool -json`
+    Exiftool,
+    /// Write an .xmp sidecar next to each image, for Lightroom, Darktable and other XMP readers
+    Xmp,
 }
 
-#[derive(Debug)]
-struct ExifMetadata {
-    orientation: Option<u32>,
-    capture_time: Option<DateTime<Utc>>,
-    camera_model: Option<String>,
-    camera_serial: Option<String>,
+/// Where sidecars are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OutputLayout {
+    /// Next to each image, named after it
+    #[default]
+    Sidecar,
+    /// Under --store-dir at ab/cd/<sha256>.json, named by the SHA-256 of the
+    /// file's own contents, with index.json mapping input paths to hashes
+    Cas,
+}
+
+/// Ordering of combined output such as the table
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortBy {
+    /// The order files were given on the command line or in the manifest
+    Input,
+    Name,
+    Size,
+    /// Oldest first; files without a capture time come last
+    CaptureTime,
+}
+
+/// What to report as created_time when the filesystem has no birth time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CreatedFallback {
+    /// Use the modification time instead
+    Mtime,
+    /// Leave created_time out of the output
+    Omit,
 }
 
+/// Which timestamp `--after`/`--before` compare against
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DateField {
+    /// Filesystem modification time, checked before files are opened
+    Modified,
+    /// EXIF capture time, falling back to the modification time when absent
+    Capture,
+}
+
+/// What to do when a sidecar already exists
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OverwritePolicy {
+    Overwrite,
+    NoClobber,
+    Backup,
+}
+
+/// Subcommands that run instead of the default extraction
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show which metadata fields differ between two images, or an image and a .json sidecar
+    Diff {
+        left: PathBuf,
+        right: PathBuf,
+    },
+    /// Regenerate the embedded EXIF thumbnail where it is missing or no longer
+    /// matches the image. Files are rewritten in place; maker notes that use
+    /// absolute offsets may not survive.
+    FixThumbnail {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Regenerate even when the current thumbnail looks correct
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        write: WriteOptions,
+    },
+    /// Copy metadata segments from one image to another, e.g. to restore EXIF that
+    /// an editor dropped on export. The segments are copied verbatim, replacing the
+    /// target's own; fields describing the image, such as its dimensions, still
+    /// describe the source. The target keeps its own orientation.
+    CopyMeta {
+        /// Image to copy the metadata from
+        #[arg(long, value_name = "FILE")]
+        from: PathBuf,
+        /// Image to copy the metadata into; it is rewritten in place
+        #[arg(long, value_name = "FILE")]
+        to: PathBuf,
+        /// Metadata to copy (comma-separated: exif, icc, xmp)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',', default_value = "exif,icc,xmp")]
+        fields: Vec<SegmentKind>,
+        #[command(flatten)]
+        write: WriteOptions,
+    },
+    /// Correct capture times (DateTimeOriginal/Digitized and their sub-second tags)
+    /// for a camera whose clock was wrong
+    Timeshift {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Amount to add, e.g. -1h30m, +2d or 1500ms
+        #[arg(long, allow_hyphen_values = true, value_parser = parse_shift)]
+        offset: chrono::Duration,
+        /// Also set OffsetTimeOriginal/Digitized to this zone, e.g. +02:00
+        #[arg(long, value_name = "+HH:MM", allow_hyphen_values = true, value_parser = parse_exif_offset)]
+        set_timezone: Option<String>,
+        #[command(flatten)]
+        write: WriteOptions,
+    },
+    /// Summarise a batch: per-camera counts, histograms of focal length, aperture
+    /// and ISO, and shots per hour
+    Stats {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Print JSON instead of a console report
+        #[arg(long)]
+        json: bool,
+    },
+    /// Set GPS coordinates from a GPX track by matching capture times
+    Geotag {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// GPS track recorded alongside the photos
+        #[arg(long, value_name = "FILE")]
+        gpx: PathBuf,
+        /// How far the camera clock is ahead of UTC, as +HH:MM[:SS] or seconds;
+        /// e.g. +02:00 for a camera set to CEST
+        #[arg(long, value_name = "OFFSET", default_value = "0", allow_hyphen_values = true, value_parser = parse_clock_offset)]
+        clock_offset: chrono::Duration,
+        /// Leave photos untagged when the nearest track points are further apart than this
+        #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+        max_gap: i64,
+        #[command(flatten)]
+        write: WriteOptions,
+    },
+    /// Re-extract metadata and compare it with each image's existing .json sidecar,
+    /// reporting images changed since their sidecar was written and fields that no
+    /// longer match. Exits with status 1 if anything drifted or a sidecar is missing.
+    Validate {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Check each image's metadata against a policy of required and forbidden fields
+    /// and allowed values, listing violations by code (missing_required,
+    /// forbidden_present, value_not_allowed). Nothing is written. Exits with status 1
+    /// if any file breaks the policy or cannot be read.
+    Audit {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// TOML policy, e.g. `required = ["copyright"]`, `forbidden = ["gps"]` and an
+        /// `[allowed_values]` table such as `camera_serial = { file = "fleet.txt" }`
+        #[arg(long, value_name = "FILE", value_parser = parse_policy)]
+        policy: Policy,
+        /// Print a JSON line per file with its violations instead of a report
+        #[arg(long)]
+        json: bool,
+    },
+    /// Browse extracted metadata in a terminal UI, with filtering and sorting by any
+    /// field. Nothing is written, not even the extraction cache.
+    #[cfg(feature = "tui")]
+    Tui {
+        /// JPEG image files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Write synthetic JPEGs with chosen EXIF fields for reproducible test corpora.
+    /// SPEC is a TOML file with one table per fixture, written as <name>.jpg; see
+    /// the library's `fixture` module for the keys.
+    GenFixture {
+        spec: PathBuf,
+        /// Directory to write the fixtures to
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        output_dir: PathBuf,
+    },
+    /// Load existing .json sidecars into a --db catalog without re-reading the
+    /// images, keyed by the image path each sidecar was written for
+    ImportSidecars {
+        /// Sidecar files, directories or glob patterns
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Catalog to upsert into, e.g. postgres://user@host/photos or sqlite://photos.db
+        #[arg(long, value_name = "URL", value_parser = catalog::parse_url)]
+        db: String,
+    },
+    /// Print a shell completion script, e.g. `completions bash > /etc/bash_completion.d/jpeg-metadata-extractor`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Make the changes saved with --plan by fix-thumbnail, copy-meta, timeshift or
+    /// geotag. Files modified since the plan was made are left alone.
+    Apply {
+        /// Plan file written by --plan
+        plan: PathBuf,
+        #[command(flatten)]
+        confirmation: Confirmation,
+    },
+    /// Check the signatures of JSON sidecars or JSON Lines output written with --sign.
+    /// Exits with status 1 if any record is unsigned or fails to verify.
+    VerifySignature {
+        /// Signed .json sidecars or JSON Lines files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// The key file the reports were signed with
+        #[arg(long, value_name = "FILE", value_parser = parse_signing_key)]
+        key: SigningKey,
+    },
+}
+
+/// How the subcommands that rewrite images confirm their changes. Each lists the
+/// first changes and asks before modifying anything.
+#[derive(clap::Args, Debug)]
+struct WriteOptions {
+    /// Only list the changes, without asking or modifying any file
+    #[arg(long)]
+    dry_run: bool,
+    /// Save the changes to this file, to make later with `apply`, instead of making them
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    plan: Option<PathBuf>,
+    #[command(flatten)]
+    confirmation: Confirmation,
+}
+
+#[derive(clap::Args, Debug)]
+struct Confirmation {
+    /// Modify files without asking; required when not running in a terminal
+    #[arg(long)]
+    yes: bool,
+    /// How many changes to list before asking
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    preview: usize,
+}
+
+/// Cargo features and whether this binary was built with them, for `--version` and `--capabilities`
+const FEATURES: [(&str, bool); 7] = [
+    ("decode", cfg!(feature = "decode")),
+    ("tui", cfg!(feature = "tui")),
+    ("sqlite", cfg!(feature = "sqlite")),
+    ("postgres", cfg!(feature = "postgres")),
+    ("queue", cfg!(feature = "queue")),
+    ("ffi", cfg!(feature = "ffi")),
+    ("python", cfg!(feature = "python")),
+];
+
+/// `--version` with the features compiled in, e.g. `+decode +tui -sqlite`
+static LONG_VERSION: LazyLock<String> = LazyLock::new(|| {
+    let features: Vec<String> = FEATURES.iter()
+        .map(|(name, enabled)| format!("{}{}", if *enabled { '+' } else { '-' }, name))
+        .collect();
+    format!("{}\nfeatures: {}", env!("CARGO_PKG_VERSION"), features.join(" "))
+});
+
 /// Command line arguments
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, long_version = LONG_VERSION.as_str(), about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(after_help = "Options listed with [env: JME_...] may instead be set in that environment \
+    variable, e.g. for a container; the command line takes precedence. Flags take \
+    true or false.")]
 struct Args {
-    // JPEG image files to process
-    #[arg(required = true)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // JPEG image files, directories or glob patterns to process
+    #[arg(required_unless_present_any = ["manifest", "clear_cache", "generate_man", "capabilities"])]
     files: Vec<PathBuf>,
+
+    /// JSON or CSV file listing inputs with per-file output, fields and format overrides
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Only process files whose name matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files whose name matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Skip directories whose name matches this glob, and everything below them,
+    /// e.g. @eaDir or .thumbnails (repeatable). Paths listed in a `.jmeignore`
+    /// file, in gitignore syntax, are skipped as well.
+    #[arg(long, value_name = "GLOB")]
+    exclude_dir: Vec<String>,
+
+    /// strftime-style pattern used to read the capture time from the filename
+    /// when the EXIF data has none (e.g. '%Y%m%d_%H%M%S')
+    #[arg(long, value_name = "PATTERN")]
+    date_from_filename: Option<String>,
+
+    /// Output format
+    #[arg(long, env = "JME_FORMAT", value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// Also write a SHA-256 checksum manifest of the processed files, for fixity
+    /// checks and archival packaging
+    #[arg(long, env = "JME_MANIFEST_FORMAT", value_enum, value_name = "FORMAT")]
+    manifest_format: Option<ManifestFormat>,
+
+    /// Checksum manifest path [default: SHA256SUMS or manifest-sha256.txt]
+    #[arg(long, value_name = "FILE", requires = "manifest_format")]
+    manifest_output: Option<PathBuf>,
+
+    /// Also report this EXIF tag, by numeric ID such as 0x9286 (repeatable)
+    #[arg(long = "tag", value_name = "ID", value_parser = parse_tag_id)]
+    tags: Vec<u16>,
+
+    /// Emit string fields exactly as the EXIF library displays them, including quotes
+    #[arg(long)]
+    raw_values: bool,
+
+    /// Character set of EXIF text that is not UTF-8: auto, utf-8, latin-1 or
+    /// shift-jis. auto takes valid UTF-8 as it is and otherwise tells Shift_JIS
+    /// from Latin-1. Fields that needed converting are listed in transcoded_fields.
+    #[arg(long, env = "JME_INPUT_CHARSET", value_name = "CHARSET", default_value = "auto")]
+    input_charset: Charset,
+
+    /// Report only the last term of hierarchical keywords such as Animals|Birds|Owl:
+    /// Owl under keywords, and only the full path under hierarchical_keywords
+    #[arg(long)]
+    leaf_keywords: bool,
+
+    /// How timestamps are written
+    #[arg(long, env = "JME_TIME_FORMAT", value_enum, default_value_t = TimeFormat::Rfc3339)]
+    time_format: TimeFormat,
+
+    /// Time zone for timestamps: local, utc or an offset like +HH:MM
+    #[arg(long, env = "JME_TIMEZONE", value_name = "ZONE", default_value = "utc")]
+    timezone: Zone,
+
+    /// Decode each image and report average brightness, dominant colours and a luma histogram
+    #[arg(long)]
+    analyze_colors: bool,
+
+    /// Decode each image and report a Laplacian-variance sharpness score and the
+    /// percentages of clipped highlights and shadows under `quality`
+    #[arg(long)]
+    quality_metrics: bool,
+
+    /// Decode at reduced scale for analysis, to at least this many pixels per side
+    /// (0 decodes at full resolution)
+    #[arg(long, value_name = "PIXELS", default_value_t = 256)]
+    analysis_size: u16,
+
+    /// TOML config file; its `[derived]` table defines extra output fields from
+    /// templates such as `shoot_id = "{capture_time:%Y%m%d}-{camera_serial|hash8}"` or
+    /// `age = "{age_days(capture_time)}"`, and its `[clock_offsets]` table moves the capture time of cameras by serial
+    /// number, e.g. `025021000535 = "-00:02:13"`, keeping the original as capture_time_raw
+    #[arg(long, env = "JME_CONFIG", value_name = "FILE", value_parser = parse_config)]
+    config: Option<Config>,
+
+    /// TOML file of camera models with sensor size and megapixels, used before the
+    /// built-in database for `enrichment` and depth of field
+    #[arg(long, env = "JME_CAMERA_DB", value_name = "FILE", value_parser = parse_camera_db)]
+    camera_db: Option<BTreeMap<String, CameraSpec>>,
+
+    /// Fields every record must have (comma-separated flat field names, with dots
+    /// for nested ones, e.g. capture_time,gps,copyright). Files missing any are
+    /// reported, a compliance summary follows the run and the exit status is 1
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+    require: Vec<String>,
+
+    /// Replace identifying fields with salted hashes (comma-separated: camera_serial, gps, body).
+    /// camera_serial also covers the owner and artist names. GPS data is removed rather than hashed.
+    #[arg(long, value_name = "CATEGORIES", value_delimiter = ',')]
+    anonymize: Vec<Category>,
+
+    /// Secret salt for --anonymize, so pseudonyms stay stable across runs; without
+    /// one a random salt is used and pseudonyms only match within a single run
+    #[arg(long, env = "JME_ANONYMIZE_SALT", hide_env_values = true, value_name = "SALT", requires = "anonymize")]
+    anonymize_salt: Option<String>,
+
+    /// With --anonymize, also rewrite the image files: identifying EXIF fields are
+    /// hashed, and the maker note and the matching XMP properties removed. The
+    /// files are listed once every record is written, and modified on confirmation.
+    #[arg(long, requires = "anonymize")]
+    anonymize_files: bool,
+
+    /// Hide the GPS position of photos taken within RADIUS_KM of LAT,LON, e.g. home;
+    /// repeat for several zones
+    #[arg(long, value_name = "LAT,LON,RADIUS_KM", allow_hyphen_values = true)]
+    privacy_zone: Vec<PrivacyZone>,
+
+    /// How positions inside a --privacy-zone are hidden: blank removes them, fuzz
+    /// reports the centre of a grid cell twice the zone's radius across
+    #[arg(long, value_name = "MODE", default_value = "blank", requires = "privacy_zone")]
+    privacy_redaction: Redaction,
+
+    /// With --privacy-zone, also rewrite the GPS fields of the image files and remove
+    /// the XMP copies of the position, listed once every record is written and
+    /// modified on confirmation
+    #[arg(long, requires = "privacy_zone")]
+    privacy_zone_files: bool,
+
+    /// How --anonymize-files and --privacy-zone-files confirm their changes
+    #[command(flatten)]
+    confirmation: Confirmation,
+
+    /// Group frames shot in quick succession by one camera and report the group as
+    /// `burst_group_id`. Output is held back until every file has been read.
+    #[arg(long)]
+    detect_bursts: bool,
+
+    /// Longest gap between consecutive frames of a burst, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 1000, requires = "detect_bursts")]
+    burst_gap: u32,
+
+    /// Group photos into events separated by long gaps in capture time and report
+    /// each photo's `event_id`, followed by a summary of the events. Output is held
+    /// back until every file has been read.
+    #[arg(long)]
+    cluster_events: bool,
+
+    /// Longest gap between consecutive photos of an event, in minutes
+    #[arg(long, value_name = "MINUTES", default_value_t = 120, requires = "cluster_events")]
+    event_gap: u32,
+
+    /// Also start a new event when a photo was taken more than this many kilometres
+    /// from the previous photo with a GPS position
+    #[arg(long, value_name = "KM", requires = "cluster_events")]
+    event_distance: Option<f64>,
+
+    /// Write the event summary to this file as JSON instead of to stderr
+    #[arg(long, value_name = "FILE", requires = "cluster_events")]
+    events_output: Option<PathBuf>,
+
+    /// Match resized and re-encoded copies to their originals by ImageUniqueID, capture
+    /// time and perceptual hash, and report `original_of` and `derivative_of` relations.
+    /// Decodes each image, and output is held back until every file has been read.
+    #[arg(long)]
+    link_derivatives: bool,
+
+    /// Report `metadata_fingerprint`, a SHA-256 of the photo's metadata without the
+    /// fields describing the file or its encoding, so copies re-encoded at another
+    /// quality match and edits to the metadata show. Fingerprints are comparable
+    /// between runs with the same extraction options.
+    #[arg(long)]
+    metadata_fingerprint: bool,
+
+    /// Most bits in which the perceptual hashes of two copies of an image may differ
+    #[arg(long, value_name = "BITS", default_value_t = 6, requires = "link_derivatives")]
+    derivative_distance: u32,
+
+    /// Record each completed input in this file and skip recorded, unchanged inputs
+    /// when it is given again, so an interrupted run resumes where it stopped. SIGINT
+    /// and SIGTERM stop a run after the file in hand, once its outputs and this file
+    /// are written, with exit status 130 or 143
+    #[arg(long, env = "JME_STATE", value_name = "FILE")]
+    state: Option<PathBuf>,
+
+    /// Order of rows in combined output
+    #[arg(long, value_enum, default_value_t = SortBy::Input)]
+    sort_by: SortBy,
+
+    /// Skip files smaller than this many bytes (K, M and G suffixes are powers of 1024)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than this many bytes (K, M and G suffixes are powers of 1024)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only files dated at or after this date or RFC 3339 time (dates are UTC midnight)
+    #[arg(long, value_name = "TIME", value_parser = parse_time_bound)]
+    after: Option<DateTime<Utc>>,
+
+    /// Only files dated before this date or RFC 3339 time (dates are UTC midnight)
+    #[arg(long, value_name = "TIME", value_parser = parse_time_bound)]
+    before: Option<DateTime<Utc>>,
+
+    /// Timestamp that --after and --before apply to
+    #[arg(long, value_enum, default_value_t = DateField::Modified)]
+    date_field: DateField,
+
+    /// Run this command for each record once its outputs are written, e.g.
+    /// 'make-thumb {file} thumbs/{filename}' or 'upload --camera {camera_model|slug} {json_path}'.
+    /// Each word is a --config template over the record's flat fields, plus {file} for
+    /// the image and {json_path} for its JSON sidecar, or a temporary copy of the
+    /// record when there is none. No shell is involved; records missing a field the
+    /// command uses, or whose values would start an argument with '-', are skipped,
+    /// and failed commands are reported without stopping the run.
+    #[arg(long, value_name = "COMMAND", value_parser = parse_exec)]
+    exec: Option<ExecCommand>,
+
+    /// Most --exec commands running at once (default: the number of CPUs)
+    #[arg(long, env = "JME_EXEC_JOBS", value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    exec_jobs: Option<u16>,
+
+    /// Only records passing this comparison of flat field names, literals and the
+    /// date functions of --config templates, e.g. 'age_days(capture_time) > 365' or
+    /// 'format(capture_time, "%Y") == "2023"'. Repeat to require all of them.
+    #[arg(long = "where", value_name = "CONDITION", value_parser = parse_condition)]
+    conditions: Vec<Condition>,
+
+    /// Write JSON records with every field at the top level, as before output was
+    /// grouped into file, image, exif, gps, xmp, thermal, panorama, audio, os and analysis sections
+    #[arg(long, global = true)]
+    flat: bool,
+
+    /// Language for numbers and dates in the table and stats report, e.g. de or fr_FR;
+    /// JSON output is unaffected. The default C locale prints ISO 8601 dates.
+    #[arg(long, env = "JME_LOCALE", global = true, value_name = "LOCALE", value_parser = parse_locale, default_value = "C")]
+    locale: Locale,
+
+    /// Sign each JSON sidecar or JSON Lines record with an HMAC-SHA256 keyed by this
+    /// file, stored under `signature`; check them with `verify-signature`
+    #[arg(long, env = "JME_SIGN", value_name = "KEY_FILE", value_parser = parse_signing_key)]
+    sign: Option<SigningKey>,
+
+    /// Publish each record's JSON as it is extracted: kafka://host:9092/topic,
+    /// amqp://host/vhost?exchange=NAME&routing_key=KEY, or
+    /// redis://host/0?stream=NAME (or ?channel=NAME). Not with --dry-run.
+    #[cfg(feature = "queue")]
+    #[arg(long, env = "JME_PUBLISH", hide_env_values = true, value_name = "URL")]
+    publish: Option<publish::Endpoint>,
+
+    /// Upsert each record into a shared catalog keyed by input path, e.g.
+    /// postgres://user@host/photos (needs the postgres feature) or sqlite://photos.db
+    /// (needs the sqlite feature); the image_metadata table is created if missing.
+    /// Not with --dry-run.
+    #[arg(long, env = "JME_DB", hide_env_values = true, value_name = "URL", value_parser = catalog::parse_url)]
+    db: Option<String>,
+
+    /// In --format jsonl and --db output, list each camera body (model, serial and
+    /// firmware) once and refer to it from records by camera_id. JSON Lines output
+    /// gets a {"camera": {...}} line before the first record of each camera; --db
+    /// fills a cameras table next to image_metadata.
+    #[arg(long)]
+    camera_registry: bool,
+
+    /// Write a JSON report of the run to this file when it ends: each file's time in
+    /// the detect, parse, hash and write stages, bytes read and any error, with
+    /// totals, an error breakdown and throughput
+    #[arg(long, env = "JME_REPORT", value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// List files that took longer than this, e.g. 2s or 500ms, with the stage that
+    /// dominated (detect, parse, hash or write): on stderr when the run ends, and
+    /// under slow_files in the --report
+    #[arg(long, env = "JME_SLOW_THRESHOLD", value_name = "DURATION", value_parser = parse_threshold)]
+    slow_threshold: Option<std::time::Duration>,
+
+    /// Also write the raw thermal image of FLIR radiometric JPEGs to this directory,
+    /// as <name>.thermal.png (16-bit, byte-swapped as FLIR stores it) or .pgm
+    #[arg(long, value_name = "DIR")]
+    thermal_raw: Option<PathBuf>,
+
+    /// Write the full-size JPEG preview embedded in TIFF-based RAW files (CR2, NEF,
+    /// ARW, DNG) to this directory as <name>.preview.jpg, with the RAW's EXIF, and
+    /// extract metadata from it like any other JPEG
+    #[arg(long, value_name = "DIR")]
+    extract_preview: Option<PathBuf>,
+
+    /// Also write voice memos to this directory as <name>.wav, whether embedded
+    /// in the JPEG or recorded by the camera as a WAV file next to it
+    #[arg(long, value_name = "DIR")]
+    extract_audio: Option<PathBuf>,
+
+    /// Report where each field came from under `provenance`: the EXIF tag, IFD and
+    /// byte offset, XMP property, JPEG segment, filesystem, or derived
+    #[arg(long, env = "JME_PROVENANCE")]
+    provenance: bool,
+
+    /// Report the byte ranges (start and length) of each JPEG's EXIF, XMP and ICC
+    /// segments, its EXIF thumbnail and its scan data under `segment_offsets`, for
+    /// tools that read or strip segments without parsing the file
+    #[arg(long)]
+    emit_offsets: bool,
+
+    /// Report ratings, tags and comments set in the file manager: the Spotlight
+    /// attributes of each file on macOS, and on every platform the properties
+    /// Windows Explorer stores in the JPEG
+    #[arg(long)]
+    os_metadata: bool,
+
+    /// Extract metadata but only report which files would be written
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Keep top-level fields of an existing sidecar that extraction does not produce,
+    /// such as hand-added annotations. Into an existing .xmp sidecar, the properties
+    /// written replace their old values and every other property is kept.
+    #[arg(long)]
+    merge_existing: bool,
+
+    /// Flush each sidecar and its directory entry to disk before moving on, so
+    /// sidecars survive a power loss. Sidecars are always written to a temporary
+    /// file and renamed into place, so a crash never leaves a truncated one.
+    #[arg(long, env = "JME_DURABLE")]
+    durable: bool,
+
+    /// Where sidecars are written: next to each image, or in a content-addressable
+    /// store where identical metadata is kept once and renaming an image only
+    /// changes the index
+    #[arg(long, env = "JME_LAYOUT", value_enum, default_value_t = OutputLayout::Sidecar)]
+    layout: OutputLayout,
+
+    /// Root of the content-addressable store for --layout cas
+    #[arg(long, env = "JME_STORE_DIR", value_name = "DIR", default_value = "metadata")]
+    store_dir: PathBuf,
+
+    /// Compress sidecars with gzip, naming them <name>.json.gz, and likewise
+    /// JSON Lines and exiftool output on stdout
+    #[arg(long, env = "JME_GZIP", group = "compression")]
+    gzip: bool,
+
+    /// Compress sidecars and combined output with zstd, as <name>.json.zst
+    #[arg(long, env = "JME_ZSTD", group = "compression")]
+    zstd: bool,
+
+    /// Leave existing sidecars untouched and skip those files
+    #[arg(long, env = "JME_NO_CLOBBER", group = "overwrite_policy")]
+    no_clobber: bool,
+
+    /// Replace existing sidecars (the default, except for .xmp sidecars, which
+    /// editors also write and are only replaced with this flag)
+    #[arg(long, group = "overwrite_policy")]
+    overwrite: bool,
+
+    /// Rename existing sidecars to <name>.json.bak before writing
+    #[arg(long, group = "overwrite_policy")]
+    backup: bool,
+
+    /// Process symlinked files using their target's metadata (the default)
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// Behaviour when the filesystem does not record a creation time
+    #[arg(long, value_enum, default_value_t = CreatedFallback::Mtime)]
+    created_fallback: CreatedFallback,
+
+    /// Memory-map input files instead of reading them through a buffer
+    /// (always used for files over 32 MiB)
+    #[arg(long, env = "JME_MMAP")]
+    mmap: bool,
+
+    /// Give up on a file whose metadata takes longer than this many seconds to read,
+    /// reporting it as quarantined. Files that crash the parser are always quarantined.
+    #[arg(long, env = "JME_TIMEOUT", value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Refuse JPEG files whose metadata runs past this many bytes from the start, and
+    /// other formats whose boxes, chunks or IFDs take more than this many bytes to
+    /// read, to bound memory on untrusted input (K, M and G suffixes are powers of 1024)
+    #[arg(long, env = "JME_MAX_METADATA_SIZE", value_name = "SIZE", value_parser = parse_size)]
+    max_metadata_size: Option<u64>,
+
+    /// Read no faster than this many MiB per second on average, across all files
+    #[arg(long, env = "JME_THROTTLE", value_name = "MB/s", value_parser = parse_rate)]
+    throttle: Option<f64>,
+
+    /// Ask the OS to give this process's disk IO the lowest priority (idle class
+    /// on Linux, background band on macOS)
+    #[arg(long, env = "JME_NICE_IO")]
+    nice_io: bool,
+
+    /// Neither read nor update the extraction cache
+    #[arg(long, env = "JME_NO_CACHE")]
+    no_cache: bool,
+
+    /// Delete all cached extraction results before processing
+    #[arg(long)]
+    clear_cache: bool,
+
+    /// Write man pages for the command and each subcommand into this directory and exit
+    #[arg(long, value_name = "DIR")]
+    generate_man: Option<PathBuf>,
+
+    /// Print a JSON document of the input formats, output formats, field groups,
+    /// subcommands and Cargo features of this build, and exit
+    #[arg(long)]
+    capabilities: bool,
+
+    /// Skip inputs that are symbolic links
+    #[arg(long, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+}
+
+/// Parse a tag ID given as hex (`0x9286`) or decimal (`37510`)
+fn parse_tag_id(s: &str) -> Result<u16, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a 16-bit tag ID", s))
 }
 
-/// Metadata extracted from a JPEG image
-#[derive(Debug, Serialize)]
+/// Parse a byte count such as `500000`, `200K` or `1.5M`
+fn parse_size(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let (number, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 1u64 << 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    number.parse::<f64>().ok()
+        .filter(|n| n.is_finite() && *n >= 0.0)
+        .map(|n| (n * multiplier as f64) as u64)
+        .ok_or_else(|| format!("'{}' is not a size", s))
+}
+
+/// Parse a positive rate such as `50` or `2.5`
+fn parse_rate(s: &str) -> Result<f64, String> {
+    s.trim().parse::<f64>().ok()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+        .ok_or_else(|| format!("'{}' is not a positive rate", s))
+}
+
+/// Parse a positive duration such as `2s`, `1m30s` or `500ms`, or a number of seconds
+fn parse_threshold(s: &str) -> Result<std::time::Duration, String> {
+    let seconds = s.trim().parse::<f64>().ok().filter(|n| n.is_finite() && *n > 0.0);
+    match seconds {
+        Some(seconds) => Ok(std::time::Duration::from_secs_f64(seconds)),
+        None => timeshift::parse_offset(s.trim()).ok()
+            .and_then(|duration| duration.to_std().ok())
+            .filter(|duration| !duration.is_zero())
+            .ok_or_else(|| format!("'{}' is not a duration such as 2s or 500ms", s)),
+    }
+}
+
+fn parse_locale(s: &str) -> Result<Locale, String> {
+    Locale::parse(s).ok_or_else(|| {
+        format!("unknown locale '{}' (supported: {})", s, Locale::supported().collect::<Vec<_>>().join(", "))
+    })
+}
+
+fn parse_signing_key(path: &str) -> Result<SigningKey, String> {
+    SigningKey::load(Path::new(path)).map_err(|e| format!("{:#}", e))
+}
+
+fn parse_config(path: &str) -> Result<Config, String> {
+    let toml = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    Config::parse(&toml).map_err(|e| format!("{:#}", e))
+}
+
+fn parse_exec(s: &str) -> Result<ExecCommand, String> {
+    ExecCommand::parse(s).map_err(|e| format!("{:#}", e))
+}
+
+fn parse_condition(s: &str) -> Result<Condition, String> {
+    Condition::parse(s).map_err(|e| e.to_string())
+}
+
+fn parse_policy(path: &str) -> Result<Policy, String> {
+    Policy::load(Path::new(path)).map_err(|e| format!("{:#}", e))
+}
+
+fn parse_camera_db(path: &str) -> Result<BTreeMap<String, CameraSpec>, String> {
+    let toml = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    cameras::parse_overrides(&toml).map_err(|e| format!("{:#}", e))
+}
+
+/// Parse `YYYY-MM-DD` (UTC midnight) or an RFC 3339 timestamp
+fn parse_time_bound(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("'{}' is not a date (YYYY-MM-DD) or RFC 3339 time", s))
+}
+
+impl Args {
+    fn extract_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            tags: self.tags.clone(),
+            raw_values: self.raw_values,
+            analyze_colors: self.analyze_colors,
+            quality_metrics: self.quality_metrics,
+            perceptual_hash: self.link_derivatives,
+            analysis_size: self.analysis_size,
+            cameras: self.camera_db.clone().unwrap_or_default(),
+            provenance: self.provenance,
+            max_metadata_size: self.max_metadata_size,
+            windows_properties: self.os_metadata,
+            input_charset: self.input_charset,
+            leaf_keywords: self.leaf_keywords,
+            segment_offsets: self.emit_offsets,
+        }
+    }
+
+    /// The extraction cache, unless disabled or no cache directory can be determined
+    fn cache(&self) -> Option<cache::Cache> {
+        if self.no_cache {
+            return None;
+        }
+        cache::Cache::default_dir().map(cache::Cache::new)
+    }
+
+    /// Whether extracted metadata falls inside `--after`/`--before`. Only the capture
+    /// time needs checking here; modification times are filtered while walking inputs.
+    fn accepts_dates(&self, metadata: &ImageMetadata) -> bool {
+        self.date_field != DateField::Capture
+            || inputs::in_range(metadata.capture_time.unwrap_or(metadata.modified_time), self.after, self.before)
+    }
+
+    fn anonymizer(&self) -> Option<Anonymizer> {
+        static RANDOM_SALT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        if self.anonymize.is_empty() {
+            return None;
+        }
+        let salt = self.anonymize_salt.as_deref().unwrap_or_else(|| RANDOM_SALT.get_or_init(|| {
+            use std::hash::{BuildHasher, Hasher};
+            let random = || std::collections::hash_map::RandomState::new().build_hasher().finish();
+            format!("{:016x}{:016x}", random(), random())
+        }));
+        Some(Anonymizer::new(salt.as_bytes(), self.anonymize.iter().copied()))
+    }
+
+    /// Whether records are held back until every file has been read, to group them across files
+    fn groups_records(&self) -> bool {
+        self.detect_bursts || self.cluster_events || self.link_derivatives
+    }
+
+    /// Whether this run may write anything, including the extraction cache
+    fn writes_files(&self) -> bool {
+        #[cfg(feature = "tui")]
+        if matches!(self.command, Some(Command::Tui { .. })) {
+            return false;
+        }
+        !self.dry_run && !matches!(self.command, Some(Command::Audit { .. }))
+    }
+
+    /// The run-wide read limit, shared by every reader
+    fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout.map(std::time::Duration::from_secs)
+    }
+
+    fn throttle(&self) -> Option<&'static Throttle> {
+        static THROTTLE: std::sync::OnceLock<Throttle> = std::sync::OnceLock::new();
+        self.throttle.map(|rate| THROTTLE.get_or_init(|| Throttle::new(rate)))
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        if self.gzip {
+            Some(Compression::Gzip)
+        } else if self.zstd {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn overwrite_policy(&self) -> OverwritePolicy {
+        if self.no_clobber {
+            OverwritePolicy::NoClobber
+        } else if self.backup {
+            OverwritePolicy::Backup
+        } else {
+            OverwritePolicy::Overwrite
+        }
+    }
+}
+
+/// Metadata extracted from a JPEG image.
+///
+/// Fields serialize in declaration order and map-valued fields are sorted by
+/// key, so output for unchanged inputs is byte-for-byte reproducible.
+#[derive(Clone, Debug, Serialize)]
 struct ImageMetadata {
+    /// File name, or for a file read from an archive its path inside the archive;
+    /// bytes that are not UTF-8 are escaped as `\xNN`
     filename: String,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "filesystem::serialize_path")]
+    archive: Option<PathBuf>,
+    /// Absolute path with symlinks resolved
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "filesystem::serialize_path")]
+    canonical_path: Option<PathBuf>,
+    /// Path from the directory or glob prefix the file was found under, which
+    /// stays valid when a drive is mounted somewhere else
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "filesystem::serialize_path")]
+    relative_path: Option<PathBuf>,
+    /// UUID of the filesystem holding the file, on Linux; with relative_path it
+    /// locates the file on a removable drive again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume_id: Option<String>,
+    format: detect::ImageFormat,
     size: u64,
-    created_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_time: Option<DateTime<Utc>>,
     modified_time: DateTime<Utc>,
+    timestamp_source: filesystem::TimestampSources,
+    is_symlink: bool,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "filesystem::serialize_path")]
+    link_target: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inode: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    readonly: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    xattrs: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// Dimensions as displayed, swapped from width and height when the orientation
+    /// turns the image by 90 or 270 degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_height: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     orientation: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     capture_time: Option<DateTime<Utc>>,
+    /// The capture time as recorded, when a --config clock offset corrected capture_time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture_time_raw: Option<DateTime<Utc>>,
+    /// One timestamp every record has, to sort by: the first of DateTimeOriginal,
+    /// DateTimeDigitized, the GPS time, the file name's date and the modification time
+    best_time: DateTime<Utc>,
+    /// Which of those best_time is
+    best_time_source: TimeSource,
     #[serde(skip_serializing_if = "Option::is_none")]
     camera_model: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     camera_serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera_firmware: Option<String>,
+    /// The camera's entry in the --camera-registry, which then holds the three fields above
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_unique_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shutter_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flash: Option<Flash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    white_balance: Option<WhiteBalance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    focus: Option<Focus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enrichment: Option<Enrichment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gps: Option<GpsPosition>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hierarchical_keywords: Vec<String>,
+    /// Shared by the frames of one burst, with --detect-bursts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    burst_group_id: Option<String>,
+    /// Shared by the photos of one event, with --cluster-events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_id: Option<String>,
+    /// Inputs found to be resized or re-encoded copies of this one, with --link-derivatives
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    original_of: Vec<String>,
+    /// The input this one is a resized or re-encoded copy of, with --link-derivatives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    derivative_of: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    exif_extra: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Description::is_empty")]
+    description: Description,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    copyright: Option<String>,
+    /// Finder tags, comments and rating indexed by Spotlight, with --os-metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spotlight: Option<OsMetadata>,
+    /// Rating, tags and comments set in Windows Explorer, with --os-metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    windows_properties: Option<OsMetadata>,
+    /// Text fields decoded from a legacy character set, with that character set
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    transcoded_fields: BTreeMap<String, Charset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drone: Option<DroneMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thermal: Option<ThermalMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    panorama: Option<PanoramaMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    computational: Option<ComputationalMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio: Option<AudioNote>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    regions: Vec<Region>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    colors: Option<ColorStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<QualityMetrics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    perceptual_hash: Option<String>,
+    /// Hash of the canonical metadata, with --metadata-fingerprint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata_fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>,
+    /// EXIF, ICC and JFIF color space tagging, with any mismatches between them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_space: Option<ColorSpace>,
+    /// Every page of a multi-page TIFF
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pages: Vec<raw::Page>,
+    payload_breakdown: jpeg::PayloadBreakdown,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segment_offsets: Option<jpeg::SegmentOffsets>,
+    /// Output of registered custom extractors, keyed by extractor name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    extensions: BTreeMap<String, serde_json::Value>,
+    /// Fields computed from the others by the templates of --config
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    derived: BTreeMap<String, String>,
+    /// Where each field came from, with --provenance
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    provenance: BTreeMap<String, Source>,
+    /// Out-of-spec data that was skipped or worked around while reading
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Find a date/time matching `pattern` anywhere in the file stem.
+///
+/// Patterns without time components are accepted and resolve to midnight.
+fn capture_time_from_filename(path: &Path, pattern: &str) -> Option<DateTime<Utc>> {
+    let stem = path.file_stem()?.to_string_lossy();
+    stem.char_indices().find_map(|(i, _)| {
+        let candidate = &stem[i..];
+        NaiveDateTime::parse_and_remainder(candidate, pattern)
+            .map(|(dt, _)| dt)
+            .or_else(|_| {
+                NaiveDate::parse_and_remainder(candidate, pattern)
+                    .map(|(d, _)| d.and_hms_opt(0, 0, 0).unwrap())
+            })
+            .ok()
+            .map(|dt| Utc.from_utc_datetime(&dt))
+    })
+}
+
+/// Files larger than this are memory-mapped even without `--mmap`
+const MMAP_THRESHOLD: u64 = 32 * 1024 * 1024;
+
+/// Read content metadata from a file, memory-mapping it when requested or large
+fn read_content(path: &Path, size: u64, options: ExtractOptions, args: &Args) -> Result<ContentMetadata> {
+    let path = path.to_path_buf();
+    let throttle = args.throttle();
+    // Pages of a mapping are read behind our back, so throttled runs always stream
+    let mmap = throttle.is_none() && (args.mmap || size > MMAP_THRESHOLD);
+    with_timeout(args.timeout(), move || {
+        let file = File::open(&path)
+            .with_context(|| format!("Failed to open file {}", path.display()))?;
+        if mmap {
+            // SAFETY: the map is read-only and dropped before returning. If another process
+            // truncates the file meanwhile, reads may fault; that risk is accepted for speed.
+            let map = unsafe { memmap2::Mmap::map(&file) }?;
+            // Never throttled, but read through Throttled all the same to count the bytes touched
+            Ok(content::extract_content(&mut Throttled::new(std::io::Cursor::new(&map[..]), None), size, &options)?)
+        } else {
+            Ok(content::extract_content(&mut BufReader::new(Throttled::new(file, throttle)), size, &options)?)
+        }
+    })
+}
+
+/// Why a file was set aside rather than processed
+#[derive(Debug)]
+enum Quarantine {
+    TimedOut(std::time::Duration),
+    Panicked(String),
+}
+
+impl std::fmt::Display for Quarantine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Quarantine::TimedOut(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+            Quarantine::Panicked(message) => write!(f, "parser crashed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Quarantine {}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Timed-out threads left running at once before no more files are started,
+/// so a run of files that hang the parser cannot pile up threads without bound
+const MAX_ABANDONED_THREADS: usize = 8;
+
+/// Threads abandoned by [`with_timeout`] that are still running
+static ABANDONED_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Run `work` on its own thread and stop waiting for it after `timeout`. A thread
+/// that never finishes is abandoned to run on in the background, and while
+/// [`MAX_ABANDONED_THREADS`] are, new work is refused.
+fn with_timeout<T: Send + 'static>(
+    timeout: Option<std::time::Duration>,
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    run_with_timeout(&ABANDONED_THREADS, MAX_ABANDONED_THREADS, timeout, work)
+}
+
+fn run_with_timeout<T: Send + 'static>(
+    abandoned: &'static AtomicUsize,
+    max_abandoned: usize,
+    timeout: Option<std::time::Duration>,
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let Some(timeout) = timeout else {
+        return work();
+    };
+    let stuck = abandoned.load(Ordering::SeqCst);
+    if stuck >= max_abandoned {
+        anyhow::bail!("{} timed-out files are still being parsed, so no more are started", stuck);
+    }
+    // Whether the thread has finished, and whether it was given up on; whichever
+    // of the two happens second settles the count
+    let state = Arc::new(Mutex::new((false, false)));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let thread_state = Arc::clone(&state);
+    std::thread::spawn(move || {
+        let _ = sender.send(std::panic::catch_unwind(std::panic::AssertUnwindSafe(work)));
+        let mut state = thread_state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.0 = true;
+        if state.1 {
+            abandoned.fetch_sub(1, Ordering::SeqCst);
+        }
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(result)) => result,
+        Ok(Err(payload)) => Err(Quarantine::Panicked(panic_message(payload)).into()),
+        Err(_) => {
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            if !state.0 {
+                state.1 = true;
+                abandoned.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(Quarantine::TimedOut(timeout).into())
+        }
+    }
+}
+
+/// Run one file's processing so that a panic fails only that file
+fn isolate(work: impl FnOnce() -> Result<()>) -> Result<()> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(work))
+        .unwrap_or_else(|payload| Err(Quarantine::Panicked(panic_message(payload)).into()))
+}
+
+/// Report a file that failed, setting quarantined files and exceeded limits apart from ordinary errors
+fn report_failure(path: &Path, error: &anyhow::Error) {
+    if let Some(quarantine) = error.downcast_ref::<Quarantine>() {
+        eprintln!("Quarantined ({}): {}", quarantine, path.display());
+    } else if let Some(ExtractError::LimitExceeded(limit)) = error.downcast_ref() {
+        eprintln!("Limit exceeded ({}): {}", limit, path.display());
+    } else {
+        eprintln!("Error processing {}: {}", path.display(), error);
+    }
+}
+
+/// Collect all metadata for a single JPEG file
+fn extract_metadata(path: &Path, args: &Args, registry: &ExtractorRegistry) -> Result<ImageMetadata> {
+    let fs_metadata = extract_filesystem_metadata(path)?;
+    let cache = args.cache();
+    let options = args.extract_options();
+    let mut content = match cache.as_ref().and_then(|c| c.get(path, &fs_metadata, &options)) {
+        Some(content) => content,
+        None => {
+            let content = read_content(path, fs_metadata.size, options.clone(), args)
+                .with_context(|| format!("Failed to extract metadata from {}", path.display()))?;
+            if let Some(cache) = cache.as_ref().filter(|_| args.writes_files()) {
+                // The cache is best-effort; a failed write only costs a re-read next time
+                let _ = cache.put(path, &fs_metadata, &options, &content);
+            }
+            content
+        }
+    };
+
+    if let Some(dir) = args.thermal_raw.as_ref().filter(|_| has_raw_thermal(&content) && !args.dry_run) {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open file {}", path.display()))?;
+        save_raw_thermal(path, &mut BufReader::new(file), dir)?;
+    }
+    // Looked up on every run rather than cached, as the recording may be copied over later
+    if content.audio.is_none() {
+        content.audio = sidecar_audio(path)?;
+        if content.audio.is_some() && args.provenance {
+            content.provenance.insert("audio".to_string(), Source::Filesystem);
+        }
+    }
+    if let Some(dir) = args.extract_audio.as_ref().filter(|_| !args.dry_run) {
+        match content.audio.as_ref().map(|a| a.source) {
+            Some(AudioSource::Embedded) => {
+                let file = File::open(path)
+                    .with_context(|| format!("Failed to open file {}", path.display()))?;
+                save_embedded_audio(path, &mut BufReader::new(file), dir)?;
+            }
+            Some(AudioSource::Sidecar) => copy_sidecar_audio(path, dir)?,
+            None => {}
+        }
+    }
+    let extensions = if registry.is_empty() {
+        BTreeMap::new()
+    } else {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open file {}", path.display()))?;
+        registry.run(&mut BufReader::new(Throttled::new(file, args.throttle())), content.format)?
+    };
+    assemble_metadata(path, fs_metadata, content, extensions, args)
+}
+
+/// Collect all metadata for a JPEG read from an archive, without a cache
+fn extract_member_metadata(archive: &Path, member: archives::Member, args: &Args, registry: &ExtractorRegistry) -> Result<ImageMetadata> {
+    let bytes = std::sync::Arc::new(member.bytes);
+    let options = args.extract_options();
+    let content = with_timeout(args.timeout(), {
+        let bytes = bytes.clone();
+        move || Ok(content::extract_from_bytes(&bytes, &options)?)
+    })
+        .with_context(|| format!("Failed to extract metadata from {}", archives::member_path(archive, &member.name).display()))?;
+    if let Some(dir) = args.thermal_raw.as_ref().filter(|_| has_raw_thermal(&content) && !args.dry_run) {
+        save_raw_thermal(Path::new(&member.name), &mut bytes.as_slice(), dir)?;
+    }
+    if let Some(dir) = args.extract_audio.as_ref().filter(|_| content.audio.is_some() && !args.dry_run) {
+        save_embedded_audio(Path::new(&member.name), &mut bytes.as_slice(), dir)?;
+    }
+    let extensions = if registry.is_empty() {
+        BTreeMap::new()
+    } else {
+        registry.run(&mut std::io::Cursor::new(bytes.as_slice()), content.format)?
+    };
+    let mut metadata = assemble_metadata(Path::new(&member.name), member.metadata, content, extensions, args)?;
+    metadata.filename = member.name;
+    metadata.archive = Some(archive.to_path_buf());
+    Ok(metadata)
+}
+
+fn has_raw_thermal(content: &ContentMetadata) -> bool {
+    content.thermal.as_ref().is_some_and(|t| t.raw_image.is_some())
+}
+
+/// Write the raw thermal image of the FLIR JPEG `name` to `dir` as `<stem>.thermal.<ext>`
+fn save_raw_thermal(name: &Path, reader: &mut impl std::io::Read, dir: &Path) -> Result<()> {
+    let header = jpeg::read_header(reader)?;
+    let Some(raw) = thermal::raw_image(&header.segments) else {
+        return Ok(());
+    };
+    let stem = name.file_stem().unwrap_or(name.as_os_str()).to_string_lossy();
+    let out = dir.join(format!("{}.thermal.{}", stem, raw.extension));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(&out, raw.bytes).with_context(|| format!("Failed to write {}", out.display()))
+}
+
+/// Details of the WAV file recorded next to `path`, if there is one
+fn sidecar_audio(path: &Path) -> Result<Option<AudioNote>> {
+    let Some(wav) = audio::find_sidecar(path) else {
+        return Ok(None);
+    };
+    let mut header = Vec::new();
+    let file = File::open(&wav).with_context(|| format!("Failed to open file {}", wav.display()))?;
+    let size = file.metadata()?.len();
+    file.take(4096).read_to_end(&mut header)
+        .with_context(|| format!("Failed to read {}", wav.display()))?;
+    let name = wav.file_name().unwrap_or_default().to_string_lossy();
+    Ok(Some(audio::from_sidecar(&name, &header, size)))
+}
+
+/// Write the audio embedded in the JPEG `name` to `dir` as `<stem>.wav`
+fn save_embedded_audio(name: &Path, reader: &mut impl std::io::Read, dir: &Path) -> Result<()> {
+    let header = jpeg::read_header(reader)?;
+    let Some(wav) = audio::embedded_wav(&header.segments) else {
+        return Ok(());
+    };
+    let stem = name.file_stem().unwrap_or(name.as_os_str()).to_string_lossy();
+    let out = dir.join(format!("{}.wav", stem));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(&out, wav).with_context(|| format!("Failed to write {}", out.display()))
+}
+
+/// Write the embedded preview of the RAW file `path` to `dir` as `<stem>.preview.jpg`,
+/// returning its path; under `dry_run` only report it and return `None`
+fn save_preview(path: &Path, dir: &Path, dry_run: bool) -> Result<Option<PathBuf>> {
+    let stem = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy();
+    let out = dir.join(format!("{}.preview.jpg", stem));
+    if dry_run {
+        println!("Would extract preview: {}", out.display());
+        return Ok(None);
+    }
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let preview = raw::extract_preview(&data)?;
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(&out, preview).with_context(|| format!("Failed to write {}", out.display()))?;
+    Ok(Some(out))
+}
+
+/// Copy the WAV file recorded next to `path` into `dir`, unless it is already there
+fn copy_sidecar_audio(path: &Path, dir: &Path) -> Result<()> {
+    let Some(wav) = audio::find_sidecar(path) else {
+        return Ok(());
+    };
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let out = dir.join(wav.file_name().unwrap_or_default());
+    if fs::canonicalize(&out).ok() == Some(fs::canonicalize(&wav)?) {
+        return Ok(());
+    }
+    fs::copy(&wav, &out).with_context(|| format!("Failed to copy {} to {}", wav.display(), out.display()))?;
+    Ok(())
+}
+
+/// Combine filesystem and content metadata into a record for `path`
+fn assemble_metadata(
+    path: &Path,
+    fs_metadata: filesystem::FilesystemMetadata,
+    content: ContentMetadata,
+    extensions: BTreeMap<String, serde_json::Value>,
+    args: &Args,
+) -> Result<ImageMetadata> {
+    let filename_time = || args.date_from_filename
+        .as_deref()
+        .and_then(|pattern| capture_time_from_filename(path, pattern));
+    let capture_time = content.capture_time.or_else(filename_time);
+
+    let spotlight = args.os_metadata.then(|| os_metadata::spotlight(path)).flatten();
+    let mut provenance = content.provenance;
+    if args.provenance {
+        let filesystem_fields = [
+            ("filename", true),
+            ("size", true),
+            ("created_time", fs_metadata.created_time.is_some() || args.created_fallback == CreatedFallback::Mtime),
+            ("modified_time", true),
+            ("is_symlink", true),
+            ("link_target", fs_metadata.link_target.is_some()),
+            ("inode", fs_metadata.inode.is_some()),
+            ("device", fs_metadata.device.is_some()),
+            ("uid", fs_metadata.uid.is_some()),
+            ("gid", fs_metadata.gid.is_some()),
+            ("mode", fs_metadata.mode.is_some()),
+            ("readonly", true),
+            ("xattrs", !fs_metadata.xattrs.is_empty()),
+            ("spotlight", spotlight.is_some()),
+        ];
+        for (field, _) in filesystem_fields.iter().filter(|(_, present)| *present) {
+            provenance.insert(field.to_string(), Source::Filesystem);
+        }
+        if content.capture_time.is_none() && capture_time.is_some() {
+            provenance.insert("capture_time".to_string(), Source::Filename);
+        }
+    }
+    // The first of TimeSource's sources that the file has; the modification time always exists
+    let (best_time, best_time_source) = content.best_time.zip(content.best_time_source)
+        .or_else(|| Some((filename_time()?, TimeSource::Filename)))
+        .unwrap_or((fs_metadata.modified_time, TimeSource::FilesystemModified));
+    if args.provenance {
+        let source = match best_time_source {
+            TimeSource::Filename => Some(Source::Filename),
+            TimeSource::FilesystemModified => Some(Source::Filesystem),
+            _ => None,
+        };
+        if let Some(source) = source {
+            provenance.insert("best_time".to_string(), source);
+        }
+        if let Some(source) = provenance.get("best_time") {
+            provenance.insert("best_time_source".to_string(), Source::derived([source.clone()]));
+        }
+    }
+    // Correct the clock of cameras known to drift, so multi-body shoots sort together
+    let clock_offset = args.config.as_ref()
+        .zip(content.camera_serial.as_deref())
+        .and_then(|(config, serial)| config.clock_offset(serial));
+    let (capture_time, capture_time_raw) = match (capture_time, clock_offset) {
+        (Some(raw), Some(offset)) => {
+            if let Some(source) = provenance.remove("capture_time") {
+                provenance.insert("capture_time_raw".to_string(), source.clone());
+                provenance.insert("capture_time".to_string(), Source::derived([source]));
+            }
+            let corrected = raw.checked_add_signed(offset)
+                .with_context(|| format!("The clock offset {} moves the capture time {} out of range", offset, raw))?;
+            (Some(corrected), Some(raw))
+        }
+        (capture_time, _) => (capture_time, None),
+    };
+    // GPS time is kept by the satellites and the modification time by the computer, not the camera
+    let best_time = match (best_time_source, clock_offset) {
+        (TimeSource::ExifOriginal | TimeSource::ExifDigitized | TimeSource::Filename, Some(offset)) => {
+            if let Some(source) = provenance.remove("best_time") {
+                provenance.insert("best_time".to_string(), Source::derived([source]));
+            }
+            best_time.checked_add_signed(offset)
+                .with_context(|| format!("The clock offset {} moves {} out of range", offset, best_time))?
+        }
+        _ => best_time,
+    };
+
+    let (created_time, created_source) = match (fs_metadata.created_time, args.created_fallback) {
+        (Some(time), _) => (Some(time), Some(filesystem::TimestampSource::BirthTime)),
+        (None, CreatedFallback::Mtime) => {
+            (Some(fs_metadata.modified_time), Some(filesystem::TimestampSource::ModifiedTime))
+        }
+        (None, CreatedFallback::Omit) => (None, None),
+    };
+
+    Ok(ImageMetadata {
+        filename: filesystem::path_text(path.file_name().ok_or_else(|| anyhow::anyhow!("Invalid filename"))?),
+        archive: None,
+        canonical_path: fs_metadata.canonical_path,
+        relative_path: None,
+        volume_id: fs_metadata.volume_id,
+        format: content.format,
+        size: fs_metadata.size,
+        created_time,
+        modified_time: fs_metadata.modified_time,
+        timestamp_source: filesystem::TimestampSources { created_time: created_source },
+        is_symlink: fs_metadata.is_symlink,
+        link_target: fs_metadata.link_target,
+        inode: fs_metadata.inode,
+        device: fs_metadata.device,
+        uid: fs_metadata.uid,
+        gid: fs_metadata.gid,
+        mode: fs_metadata.mode,
+        readonly: fs_metadata.readonly,
+        xattrs: fs_metadata.xattrs,
+        width: content.width,
+        height: content.height,
+        display_width: content.display_width,
+        display_height: content.display_height,
+        orientation: content.orientation,
+        capture_time,
+        capture_time_raw,
+        best_time,
+        best_time_source,
+        camera_model: content.camera_model,
+        camera_serial: content.camera_serial,
+        camera_firmware: content.camera_firmware,
+        camera_id: None,
+        image_unique_id: content.image_unique_id,
+        sequence_number: content.sequence_number,
+        shutter_count: content.shutter_count,
+        flash: content.flash,
+        white_balance: content.white_balance,
+        focus: content.focus,
+        enrichment: content.enrichment,
+        gps: content.gps,
+        keywords: content.keywords,
+        hierarchical_keywords: content.hierarchical_keywords,
+        burst_group_id: None,
+        event_id: None,
+        original_of: Vec::new(),
+        derivative_of: None,
+        exif_extra: content.exif_extra,
+        description: content.description,
+        artist: content.artist,
+        copyright: content.copyright,
+        spotlight,
+        windows_properties: content.windows_properties,
+        transcoded_fields: content.transcoded_fields,
+        drone: content.drone,
+        thermal: content.thermal,
+        panorama: content.panorama,
+        computational: content.computational,
+        audio: content.audio,
+        regions: content.regions,
+        colors: content.colors,
+        quality: content.quality,
+        perceptual_hash: content.perceptual_hash,
+        metadata_fingerprint: None,
+        encoding: content.encoding,
+        color_space: content.color_space,
+        pages: content.pages,
+        payload_breakdown: content.payload_breakdown,
+        segment_offsets: content.segment_offsets,
+        extensions,
+        derived: BTreeMap::new(),
+        provenance,
+        warnings: content.warnings,
+    })
+}
+
+/// Extract metadata for a single JPEG file and hand it to `sink`
+fn process_file(
+    job: &Job,
+    args: &Args,
+    registry: &ExtractorRegistry,
+    sink: &mut dyn Sink,
+    timer: &mut FileTimer,
+    pending: &mut Vec<Change>,
+    compliance: Option<&mut Compliance>,
+) -> Result<()> {
+    let metadata = timer.time(Stage::Parse, || extract_metadata(&job.path, args, registry))?;
+    timer.time(Stage::Write, || deliver(job, metadata, args, sink, pending, compliance))
+}
+
+/// Apply the date and --where filters, anonymization and derived fields to a record and hand it to `sink`.
+/// Rewrites of the file itself are added to `pending`, to confirm once the run is over, and
+/// with --require the record is checked against `compliance`.
+fn deliver(
+    job: &Job,
+    mut metadata: ImageMetadata,
+    args: &Args,
+    sink: &mut dyn Sink,
+    pending: &mut Vec<Change>,
+    compliance: Option<&mut Compliance>,
+) -> Result<()> {
+    for warning in &metadata.warnings {
+        eprintln!("Warning: {}: {}", job.path.display(), warning);
+    }
+    metadata.relative_path = job.root.as_ref()
+        .and_then(|root| job.path.strip_prefix(root).ok())
+        .map(Path::to_path_buf);
+    if !args.accepts_dates(&metadata) {
+        eprintln!("Skipped (outside date range): {}", job.path.display());
+        return Ok(());
+    }
+    if !args.conditions.is_empty() {
+        let record = serde_json::to_value(&metadata)?;
+        if !args.conditions.iter().all(|condition| condition.matches(&record)) {
+            eprintln!("Skipped (not matching --where): {}", job.path.display());
+            return Ok(());
+        }
+    }
+    let position = metadata.gps.as_ref().map(|gps| (gps.latitude, gps.longitude));
+    if let Some(((latitude, longitude), zone)) = position.and_then(|(lat, lon)| Some(((lat, lon), privacy::zone_of(&args.privacy_zone, lat, lon)?))) {
+        let redacted = privacy::redact(zone, args.privacy_redaction, latitude, longitude);
+        metadata.gps = redacted.map(|(latitude, longitude)| GpsPosition { latitude, longitude, ..Default::default() });
+        if metadata.provenance.remove("gps").is_some() && redacted.is_some() {
+            metadata.provenance.insert("gps".to_string(), Source::derived([]));
+        }
+        if args.privacy_zone_files {
+            if metadata.archive.is_some() {
+                eprintln!("Skipped rewriting (inside an archive): {}", job.path.display());
+            } else {
+                pending.push(plan_redact(&job.path, redacted)?);
+            }
+        }
+    }
+    if let Some(anonymizer) = args.anonymizer() {
+        anonymize_metadata(&mut metadata, &anonymizer);
+        if args.anonymize_files {
+            if metadata.archive.is_some() {
+                eprintln!("Skipped rewriting (inside an archive): {}", job.path.display());
+            } else {
+                pending.push(plan_anonymize(&job.path, &args.anonymize)?);
+            }
+        }
+    }
+    if args.metadata_fingerprint {
+        // Over anonymized values too, so the fingerprint cannot be matched against the originals
+        metadata.metadata_fingerprint = Some(schema::metadata_fingerprint(&serde_json::to_value(&metadata)?));
+        if !metadata.provenance.is_empty() {
+            metadata.provenance.insert("metadata_fingerprint".to_string(), Source::derived([]));
+        }
+    }
+    if let Some(config) = args.config.as_ref().filter(|c| !c.derived.is_empty()) {
+        // Templates see anonymized values, so they cannot reintroduce what was removed
+        metadata.derived = config.derive(&serde_json::to_value(&metadata)?);
+    }
+    if let Some(compliance) = compliance {
+        let missing = compliance.check(&serde_json::to_value(&metadata)?);
+        if !missing.is_empty() {
+            eprintln!("Missing required fields: {}: {}", job.path.display(), missing.join(", "));
+        }
+    }
+    sink.write(job, metadata)
+}
+
+/// Write the --cluster-events summary to --events-output as JSON, or list it on stderr
+fn write_events(events: &[Event], args: &Args) -> Result<()> {
+    let Some(path) = &args.events_output else {
+        eprintln!("\nEvents: {}", events.len());
+        for event in events {
+            eprintln!("  {}: {} photos, {} to {}", event.id, event.photos, event.start.to_rfc3339(), event.end.to_rfc3339());
+        }
+        return Ok(());
+    };
+    if args.dry_run {
+        println!("Would write events: {}", path.display());
+        return Ok(());
+    }
+    let temp_path = write_temp(path, serde_json::to_string_pretty(events)?.as_bytes(), args.durable)
+        .with_context(|| format!("Failed to write events to {}", path.display()))?;
+    fs::rename(&temp_path, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Replace identifying fields of a record with pseudonyms, or drop them for GPS
+fn anonymize_metadata(metadata: &mut ImageMetadata, anonymizer: &Anonymizer) {
+    if anonymizer.covers(Category::CameraSerial) {
+        metadata.camera_serial = metadata.camera_serial.as_deref().map(|s| anonymizer.pseudonym(s));
+        metadata.artist = metadata.artist.as_deref().map(|s| anonymizer.pseudonym(s));
+        if let Some(thermal) = metadata.thermal.as_mut() {
+            thermal.camera_serial = thermal.camera_serial.as_deref().map(|s| anonymizer.pseudonym(s));
+        }
+    }
+    if anonymizer.covers(Category::Body) {
+        metadata.camera_model = metadata.camera_model.as_deref().map(|s| anonymizer.pseudonym(s));
+        if let Some(thermal) = metadata.thermal.as_mut() {
+            thermal.camera_model = thermal.camera_model.as_deref().map(|s| anonymizer.pseudonym(s));
+        }
+    }
+    if anonymizer.covers(Category::Gps) {
+        metadata.gps = None;
+        metadata.drone = None;
+        metadata.provenance.remove("gps");
+        metadata.provenance.remove("drone");
+    }
+    // Tags requested with --tag are keyed by number only, so check every context
+    metadata.exif_extra.retain(|key, value| {
+        let number = u16::from_str_radix(key.trim_start_matches("0x"), 16).unwrap_or_default();
+        let category = [exif::Context::Tiff, exif::Context::Exif, exif::Context::Gps]
+            .into_iter()
+            .find_map(|context| Anonymizer::category_of(exif::Tag(context, number)))
+            .filter(|&category| anonymizer.covers(category));
+        match category {
+            Some(Category::Gps) => {
+                metadata.provenance.remove(&format!("exif_extra.{}", key));
+                false
+            }
+            Some(_) => {
+                *value = anonymizer.pseudonym(value);
+                true
+            }
+            None => true,
+        }
+    });
+}
+
+/// The rewrite of a file's identifying EXIF fields, maker note and XMP properties for --anonymize-files
+fn plan_anonymize(path: &Path, categories: &[Category]) -> Result<Change> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let summary = format!("Anonymize {}", categories.iter().map(Category::to_string).collect::<Vec<_>>().join(", "));
+    Ok(Change::new(path, &bytes, summary, Action::Anonymize))
+}
+
+/// The rewrite of a file's GPS fields for --privacy-zone-files, keeping only a fuzzed position if there is one
+fn plan_redact(path: &Path, redacted: Option<(f64, f64)>) -> Result<Change> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let summary = match redacted {
+        Some((latitude, longitude)) => format!("Replace the GPS position with {:.6}, {:.6}", latitude, longitude),
+        None => "Remove the GPS position".to_string(),
+    };
+    Ok(Change::new(path, &bytes, summary, Action::Redact { position: redacted }))
+}
+
+/// Serialize a record with the requested timestamp style, the job's field selection
+/// (by flat field name) and the section layout unless --flat
+fn metadata_value(job: &Job, metadata: &ImageMetadata, args: &Args) -> Result<serde_json::Value> {
+    let mut value = serde_json::to_value(metadata)?;
+    timestamps::apply(&mut value, args.time_format, args.timezone);
+    select_fields(job, &mut value);
+    Ok(if args.flat { value } else { layout::nest(value) })
+}
+
+/// Keep only the fields of a flat record that the job selects, by field or section name
+fn select_fields(job: &Job, value: &mut serde_json::Value) {
+    let (Some(fields), Some(object)) = (&job.fields, value.as_object_mut()) else {
+        return;
+    };
+    // A section name selects every field in it
+    let fields: Vec<&str> = fields.iter()
+        .flat_map(|f| layout::section_fields(f).map_or(vec![f.as_str()], <[&str]>::to_vec))
+        .collect();
+    object.retain(|key, _| fields.contains(&key.as_str()));
+    // Provenance keys such as `description.user_comment` follow their top-level field
+    if let Some(serde_json::Value::Object(provenance)) = object.get_mut("provenance") {
+        provenance.retain(|key, _| fields.iter().any(|f| key.split('.').next() == Some(*f)));
+    }
+}
+
+/// The contents of a record's .json or .xmp sidecar, merged into the existing
+/// sidecar at `existing` if given, then compressed with --gzip or --zstd
+fn sidecar_contents(job: &Job, metadata: &ImageMetadata, args: &Args, existing: Option<&Path>) -> Result<Vec<u8>> {
+    let contents = if job.format.unwrap_or(args.format) == OutputFormat::Xmp {
+        let value = record_value(job, metadata, args)?;
+        match existing {
+            Some(existing) => {
+                let packet = compression::read_to_string(existing)?;
+                xmp_write::merge(&packet, &value)
+                    .with_context(|| format!("Failed to parse existing sidecar {}", existing.display()))?
+            }
+            None => xmp_write::sidecar(&value),
+        }
+    } else {
+        let mut value = record_value(job, metadata, args)?;
+        if let Some(existing) = existing {
+            let json = compression::read_to_string(existing)?;
+            let existing: serde_json::Value = serde_json::from_str(&json)
+                .with_context(|| format!("Failed to parse existing sidecar {}", existing.display()))?;
+            merge_existing(&mut value, existing);
+        }
+        if let Some(key) = &args.sign {
+            key.sign(&mut value);
+        }
+        serde_json::to_string_pretty(&value)?
+    };
+    Ok(match args.compression() {
+        Some(compression) => compression.compress(contents.as_bytes())?,
+        None => contents.into_bytes(),
+    })
+}
+
+/// The record a sidecar is written from, before any merging, signing or compression
+fn record_value(job: &Job, metadata: &ImageMetadata, args: &Args) -> Result<serde_json::Value> {
+    if job.format.unwrap_or(args.format) != OutputFormat::Xmp {
+        return metadata_value(job, metadata, args);
+    }
+    // XMP dates have their own format, so skip the --time-format conversion
+    let mut value = serde_json::to_value(metadata)?;
+    select_fields(job, &mut value);
+    Ok(value)
+}
+
+/// Extension of a record's sidecar, including any compression extension
+fn sidecar_extension(job: &Job, args: &Args) -> String {
+    let extension = if job.format.unwrap_or(args.format) == OutputFormat::Xmp { "xmp" } else { "json" };
+    match args.compression() {
+        Some(compression) => format!("{}.{}", extension, compression.extension()),
+        None => extension.to_string(),
+    }
+}
+
+/// Where a record's sidecar goes: the image path with a .json or .xmp extension,
+/// unless the manifest names one
+fn sidecar_path(job: &Job, args: &Args) -> PathBuf {
+    match &job.output {
+        Some(output) => compression::with_extension(output, args.compression()),
+        None => archives::output_base(&job.path).with_extension(sidecar_extension(job, args)),
+    }
+}
+
+/// Whether a job's record is written to a .json sidecar at [`sidecar_path`]
+fn writes_json_sidecar(job: &Job, args: &Args) -> bool {
+    job.format.unwrap_or(args.format) == OutputFormat::Json && args.layout != OutputLayout::Cas
+}
+
+/// Write a record to its .json or .xmp sidecar, honouring the overwrite policy and --dry-run
+fn write_sidecar(job: &Job, metadata: &ImageMetadata, args: &Args) -> Result<()> {
+    let output_path = sidecar_path(job, args);
+
+    let exists = output_path.exists();
+    let policy = args.overwrite_policy();
+    if exists && policy == OverwritePolicy::NoClobber {
+        println!("Skipped (sidecar exists): {}", output_path.display());
+        return Ok(());
+    }
+    // An .xmp sidecar may be an editor's, holding edits we do not write
+    let xmp = job.format.unwrap_or(args.format) == OutputFormat::Xmp;
+    if exists && xmp && policy == OverwritePolicy::Overwrite && !args.overwrite && !args.merge_existing {
+        println!("Skipped (XMP sidecar exists; use --merge-existing, --overwrite or --backup): {}", output_path.display());
+        return Ok(());
+    }
+    let existing = (exists && args.merge_existing).then_some(output_path.as_path());
+    let contents = sidecar_contents(job, metadata, args, existing)?;
+    if args.dry_run {
+        let action = match (exists, policy) {
+            (false, _) => "create",
+            (true, OverwritePolicy::Backup) => "back up and overwrite",
+            (true, _) => "overwrite",
+        };
+        println!("Would {}: {}", action, output_path.display());
+        return Ok(());
+    }
+    if let Some(parent) = output_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let temp_path = write_temp(&output_path, &contents, args.durable)
+        .with_context(|| format!("Failed to write metadata to {}", output_path.display()))?;
+    if exists && policy == OverwritePolicy::Backup {
+        let backup_path = backup_path(&output_path);
+        if let Err(e) = fs::rename(&output_path, &backup_path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e).with_context(|| format!("Failed to back up {} to {}", output_path.display(), backup_path.display()));
+        }
+    }
+    fs::rename(&temp_path, &output_path)
+        .with_context(|| format!("Failed to replace {}", output_path.display()))?;
+    if args.durable {
+        sync_parent(&output_path)?;
+    }
+
+    println!("Processed: {}", job.path.display());
+    Ok(())
+}
+
+/// Write a record into the content-addressable store and point its input at it
+/// in `index`. Objects are keyed by the canonical JSON of the record, so one with
+/// the same record is already there and is left as is.
+fn write_object(job: &Job, metadata: &ImageMetadata, args: &Args, index: &mut cas::Index) -> Result<()> {
+    let hash = cas::hash(&cas::canonical(&record_value(job, metadata, args)?));
+    let object_path = cas::object_path(&args.store_dir, &hash, &sidecar_extension(job, args));
+    if args.dry_run {
+        let action = if object_path.exists() { "reuse" } else { "create" };
+        println!("Would {}: {}", action, object_path.display());
+        return Ok(());
+    }
+    if !object_path.exists() {
+        let contents = sidecar_contents(job, metadata, args, None)?;
+        let parent = object_path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        let temp_path = write_temp(&object_path, &contents, args.durable)
+            .with_context(|| format!("Failed to write metadata to {}", object_path.display()))?;
+        fs::rename(&temp_path, &object_path)
+            .with_context(|| format!("Failed to replace {}", object_path.display()))?;
+        if args.durable {
+            sync_parent(&object_path)?;
+        }
+    }
+    index.insert(filesystem::path_text(job.path.as_os_str()), hash);
+
+    println!("Processed: {}", job.path.display());
+    Ok(())
+}
+
+/// Names tried for a temporary file next to an output before giving up
+const TEMP_ATTEMPTS: usize = 16;
+
+/// Write `contents` to a temporary file next to `path` for renaming into place,
+/// removing it again if the write fails (e.g. the disk is full). The file gets a
+/// name unique to this process and is created afresh, never through an existing
+/// file or link, so concurrent runs writing the same output do not collide.
+fn write_temp(path: &Path, contents: &[u8], durable: bool) -> Result<PathBuf> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    for _ in 0..TEMP_ATTEMPTS {
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(format!(".{}-{}.tmp", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+        let temp_path = PathBuf::from(temp_name);
+        let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", temp_path.display())),
+        };
+        let written = std::io::Write::write_all(&mut file, contents).and_then(|()| if durable { file.sync_all() } else { Ok(()) });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e).with_context(|| format!("Failed to write {}", temp_path.display()));
+        }
+        return Ok(temp_path);
+    }
+    anyhow::bail!("Failed to create a temporary file next to {}", path.display())
+}
+
+/// Flush the directory entry of a renamed file; a no-op where directories cannot be opened
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if cfg!(unix) {
+        File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to sync directory {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// Shallow merge: keep top-level fields of an existing sidecar that extraction did not
+/// produce (e.g. hand-added annotations), appended after the extracted fields
+fn merge_existing(value: &mut serde_json::Value, existing: serde_json::Value) {
+    let (Some(object), serde_json::Value::Object(existing)) = (value.as_object_mut(), existing) else {
+        return;
+    };
+    for (key, field) in existing {
+        object.entry(key).or_insert(field);
+    }
+}
+
+/// Path an existing sidecar is moved to under `--backup`
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// Load metadata for comparison from either an image or a previously written sidecar
+fn load_metadata_value(path: &Path, args: &Args, registry: &ExtractorRegistry) -> Result<serde_json::Value> {
+    if compression::is_json(path) {
+        let json = compression::read_to_string(path)?;
+        let mut value: serde_json::Value = serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        // A signature differs whenever anything else does, so it is not compared
+        if let Some(object) = value.as_object_mut() {
+            object.shift_remove(signing::SIGNATURE_FIELD);
+        }
+        Ok(value)
+    } else {
+        let mut value = serde_json::to_value(extract_metadata(path, args, registry)?)?;
+        timestamps::apply(&mut value, args.time_format, args.timezone);
+        Ok(if args.flat { value } else { layout::nest(value) })
+    }
+}
+
+/// Print differing fields between two metadata sources, returning whether any differ
+fn run_diff(left: &Path, right: &Path, args: &Args, registry: &ExtractorRegistry) -> Result<bool> {
+    let diffs = diff::diff_metadata(
+        &load_metadata_value(left, args, registry)?,
+        &load_metadata_value(right, args, registry)?,
+    );
+
+    println!("--- {}", left.display());
+    println!("+++ {}", right.display());
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+    Ok(!diffs.is_empty())
+}
+
+/// How an image's metadata has drifted from its sidecar; empty if it still matches.
+/// Only fields the sidecar holds are compared, so sidecars written with a field
+/// selection or hand-added fields validate too.
+fn sidecar_drift(path: &Path, args: &Args, registry: &ExtractorRegistry) -> Result<Vec<String>> {
+    let sidecar_path = archives::output_base(path).with_extension("json");
+    // Sidecars written with --gzip or --zstd are found too
+    let sidecar_path = compression::find(&sidecar_path).unwrap_or(sidecar_path);
+    let sidecar_modified = fs::metadata(&sidecar_path)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("No sidecar {}", sidecar_path.display()))?;
+    let mut sidecar = load_metadata_value(&sidecar_path, args, registry)?;
+    let mut current = load_metadata_value(path, args, registry)?;
+    // Where the image was found from, which validating from another directory or mount changes
+    for value in [&mut sidecar, &mut current] {
+        let file = match value.get_mut("file") {
+            Some(section) => section,
+            None => value,
+        };
+        if let Some(fields) = file.as_object_mut() {
+            fields.remove("canonical_path");
+            fields.remove("relative_path");
+        }
+    }
+
+    let mut drift = Vec::new();
+    let image_modified = fs::metadata(path).and_then(|m| m.modified())
+        .with_context(|| format!("Failed to read metadata for {}", path.display()))?;
+    if image_modified > sidecar_modified {
+        drift.push(format!("image modified after its sidecar was written ({})", DateTime::<Utc>::from(image_modified).to_rfc3339()));
+    }
+    if let (Some(current), Some(sidecar)) = (current.as_object_mut(), sidecar.as_object()) {
+        current.retain(|key, _| sidecar.contains_key(key));
+        // Sections are narrowed too, for sidecars written with a field selection
+        for (key, value) in current.iter_mut().filter(|(key, _)| layout::is_section(key)) {
+            if let (Some(section), Some(kept)) = (value.as_object_mut(), sidecar[key].as_object()) {
+                section.retain(|field, _| kept.contains_key(field));
+            }
+        }
+    }
+    drift.extend(diff::diff_metadata(&sidecar, &current).iter().map(ToString::to_string));
+    Ok(drift)
+}
+
+/// Report sidecar drift for every image, returning whether any was found
+fn run_validate(files: &[PathBuf], args: &Args, registry: &ExtractorRegistry) -> Result<bool> {
+    let mut drifted = false;
+    for path in inputs::expand_inputs(files, &inputs::Filters::default())? {
+        match sidecar_drift(&path, args, registry) {
+            Ok(drift) if drift.is_empty() => println!("OK: {}", path.display()),
+            Ok(drift) => {
+                drifted = true;
+                println!("DRIFT: {}", path.display());
+                for line in drift {
+                    println!("  {}", line);
+                }
+            }
+            Err(e) => {
+                drifted = true;
+                println!("FAILED: {}: {:#}", path.display(), e);
+            }
+        }
+    }
+    Ok(drifted)
+}
+
+/// Check every image against `policy`, returning whether any broke it or could not be read
+fn run_audit(files: &[PathBuf], policy: &Policy, json: bool, args: &Args, registry: &ExtractorRegistry) -> Result<bool> {
+    let mut failed = false;
+    for path in inputs::expand_inputs(files, &inputs::Filters::default())? {
+        let violations = extract_metadata(&path, args, registry)
+            .and_then(|metadata| Ok(policy.check(&serde_json::to_value(metadata)?)));
+        failed |= !violations.as_ref().is_ok_and(Vec::is_empty);
+        let path_text = filesystem::path_text(path.as_os_str());
+        match (violations, json) {
+            (Ok(violations), true) => println!("{}", serde_json::json!({"path": path_text, "violations": violations})),
+            (Err(e), true) => println!("{}", serde_json::json!({"path": path_text, "error": format!("{:#}", e)})),
+            (Ok(violations), false) if violations.is_empty() => println!("OK: {}", path_text),
+            (Ok(violations), false) => {
+                println!("VIOLATION: {}", path_text);
+                for violation in violations {
+                    println!("  {}", violation);
+                }
+            }
+            (Err(e), false) => println!("FAILED: {}: {:#}", path_text, e),
+        }
+    }
+    Ok(failed)
+}
+
+/// Upsert every metadata sidecar among `files` into the catalog at `db`
+fn import_sidecars(files: &[PathBuf], db: &str) -> Result<()> {
+    let patterns = ["*.json", "*.json.gz", "*.json.zst"].map(str::to_string);
+    let filters = inputs::Filters::new(&patterns, &[], &[])?;
+    let mut catalog = catalog::Catalog::open(db)?;
+    let mut imported = 0;
+    for path in inputs::expand_inputs(files, &filters)? {
+        let record = compression::read_to_string(&path)
+            .and_then(|json| serde_json::from_str::<serde_json::Value>(&json)
+                .with_context(|| format!("Failed to parse {}", path.display())));
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("Error processing {}: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        let Some(key) = catalog::sidecar_key(&path, &record) else {
+            eprintln!("Skipped (not a metadata sidecar): {}", path.display());
+            continue;
+        };
+        catalog.add(filesystem::path_text(key.as_os_str()), record)?;
+        imported += 1;
+    }
+    catalog.flush()?;
+    println!("Imported {} sidecars", imported);
+    Ok(())
+}
+
+/// Aggregate the EXIF settings of `paths`; unreadable files count under an unknown camera
+fn collect_stats(paths: &[PathBuf]) -> Stats {
+    let mut stats = Stats::default();
+    for path in paths {
+        let shot = File::open(path)
+            .with_context(|| format!("Failed to open file {}", path.display()))
+            .and_then(|file| Ok(Shot::read(&mut BufReader::new(file))?));
+        match shot {
+            Ok(shot) => stats.add(&shot),
+            Err(e) => {
+                eprintln!("Error processing {}: {}", path.display(), e);
+                stats.add(&Shot::default());
+            }
+        }
+    }
+    stats
+}
+
+/// Parse a camera clock offset given as `+HH:MM[:SS]` or a number of seconds
+fn parse_clock_offset(s: &str) -> Result<chrono::Duration, String> {
+    let invalid = || format!("'{}' is not an offset like +02:00 or a number of seconds", s);
+    if let Ok(seconds) = s.parse::<i64>() {
+        return chrono::Duration::try_seconds(seconds).ok_or_else(invalid);
+    }
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid()),
+    };
+    let parts: Vec<i64> = rest.split(':')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let seconds = match parts[..] {
+        [h, m] => h.checked_mul(3600).zip(m.checked_mul(60)).and_then(|(h, m)| h.checked_add(m)),
+        [h, m, sec] => h.checked_mul(3600).zip(m.checked_mul(60)).and_then(|(h, m)| h.checked_add(m)?.checked_add(sec)),
+        _ => return Err(invalid()),
+    };
+    seconds.and_then(|seconds| chrono::Duration::try_seconds(sign * seconds)).ok_or_else(invalid)
+}
+
+fn parse_shift(s: &str) -> Result<chrono::Duration, String> {
+    timeshift::parse_offset(s).map_err(|e| e.to_string())
+}
+
+/// Validate an EXIF OffsetTime value, `+HH:MM` or `-HH:MM`
+fn parse_exif_offset(s: &str) -> Result<String, String> {
+    let valid = s.len() == 6
+        && matches!(s.as_bytes()[0], b'+' | b'-')
+        && s.as_bytes()[3] == b':'
+        && s[1..3].bytes().chain(s[4..].bytes()).all(|b| b.is_ascii_digit());
+    if valid { Ok(s.to_string()) } else { Err(format!("'{}' is not a zone like +02:00", s)) }
+}
+
+/// The capture times `timeshift` would correct in one photo, if any
+fn plan_timeshift(path: &Path, offset: chrono::Duration, timezone: Option<&str>) -> Result<Option<Change>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&bytes))
+        .with_context(|| format!("No EXIF data in {}", path.display()))?;
+    let (fields, shifts) = timeshift::shifted_fields(&exif, offset, timezone)?;
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let summary = shifts.iter()
+        .map(|shift| format!("{} {} -> {}", shift.tag, shift.before.format("%Y:%m:%d %H:%M:%S"), shift.after.format("%Y:%m:%d %H:%M:%S")))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let action = Action::Timeshift { offset_ms: offset.num_milliseconds(), timezone: timezone.map(str::to_string) };
+    Ok(Some(Change::new(path, &bytes, summary, action)))
+}
+
+/// Replace a file's contents by writing a sibling temporary file and renaming it
+/// over, synced so a crash leaves either the old or the new image
+fn replace_file(path: &Path, contents: Vec<u8>) -> Result<()> {
+    let temp_path = write_temp(path, &contents, true)?;
+    let replaced = fs::metadata(path)
+        .and_then(|metadata| fs::set_permissions(&temp_path, metadata.permissions()))
+        .with_context(|| format!("Failed to copy the permissions of {}", path.display()))
+        .and_then(|()| fs::rename(&temp_path, path).with_context(|| format!("Failed to replace {}", path.display())));
+    if replaced.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    replaced?;
+    sync_parent(path)
+}
+
+/// Match one photo's capture time against the track for the GPS fields `geotag` would write
+fn plan_geotag(path: &Path, track: &Track, clock_offset: chrono::Duration, max_gap: chrono::Duration) -> Result<Option<Change>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let exif = read_exif_metadata(&mut bytes.as_slice(), &ExtractOptions::default())?;
+    let Some(capture_time) = exif.capture_time else {
+        println!("Skipped (no capture time): {}", path.display());
+        return Ok(None);
+    };
+    // Capture times are read as if the camera clock were UTC
+    let Some(point) = track.locate(capture_time - clock_offset, max_gap) else {
+        println!("Skipped (not covered by track): {}", path.display());
+        return Ok(None);
+    };
+    let position = match point.elevation {
+        Some(elevation) => format!("{:.6}, {:.6}, {:.1} m", point.latitude, point.longitude, elevation),
+        None => format!("{:.6}, {:.6}", point.latitude, point.longitude),
+    };
+    Ok(Some(Change::new(path, &bytes, format!("Geotag at {}", position), Action::Geotag { point })))
+}
+
+/// Whether one file's EXIF thumbnail needs regenerating
+fn plan_fix_thumbnail(path: &Path, force: bool) -> Result<Option<Change>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let state = thumbnail::check(&bytes)?;
+    if state == ThumbnailState::Current && !force {
+        println!("Up to date: {}", path.display());
+        return Ok(None);
+    }
+    let reason = match state {
+        ThumbnailState::Missing => "missing",
+        ThumbnailState::Stale => "stale",
+        ThumbnailState::Current => "forced",
+    };
+    Ok(Some(Change::new(path, &bytes, format!("Regenerate thumbnail ({})", reason), Action::FixThumbnail)))
 }
 
-/// Extract filesystem metadata from a file
-fn extract_filesystem_metadata(path: &Path) -> Result<FilesystemMetadata> {
-    let metadata = fs::metadata(path)
-        .with_context(|| format!("Failed to read metadata for {}", path.display()))?;
+/// The metadata segments of `from` that `copy-meta` would copy into `to`
+fn plan_copy_meta(from: &Path, to: &Path, fields: &[SegmentKind]) -> Result<Option<Change>> {
+    let source = fs::read(from).with_context(|| format!("Failed to read {}", from.display()))?;
+    let target = fs::read(to).with_context(|| format!("Failed to read {}", to.display()))?;
+    let (_, copied) = transplant::copy_segments(&source, &target, fields)?;
+    for kind in fields.iter().filter(|kind| !copied.contains(kind)) {
+        println!("Skipped (no {} in the source): {}", kind, from.display());
+    }
+    if copied.is_empty() {
+        return Ok(None);
+    }
+    let summary = format!("Copy {} from {}", copied.iter().map(SegmentKind::to_string).collect::<Vec<_>>().join(", "), from.display());
+    let from = std::path::absolute(from).with_context(|| format!("Failed to resolve {}", from.display()))?;
+    Ok(Some(Change::new(to, &target, summary, Action::CopyMeta { from, fields: copied }).with_source(&source)))
+}
 
-    let created_time = metadata.created()
-        .with_context(|| format!("Failed to get creation time for {}", path.display()))?;
-    let modified_time = metadata.modified()
-        .with_context(|| format!("Failed to get modification time for {}", path.display()))?;
+/// Make one planned change, unless its file has been modified since it was planned.
+/// Anonymizing needs the run's anonymizer, as its salt is not saved in plans.
+fn apply_change(change: &Change, anonymizer: Option<&Anonymizer>) -> Result<()> {
+    let path = &change.path;
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    change.check(&bytes)?;
+    let contents = match &change.action {
+        Action::Timeshift { offset_ms, timezone } => {
+            let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&bytes))
+                .with_context(|| format!("No EXIF data in {}", path.display()))?;
+            let (fields, _) = timeshift::shifted_fields(&exif, chrono::Duration::milliseconds(*offset_ms), timezone.as_deref())?;
+            exif_write::rewrite(&bytes, &fields, None)?
+        }
+        Action::Geotag { point } => {
+            exif_write::rewrite_with(&bytes, &gpx::exif_fields(point), None, |f| !gpx::FIX_TAGS.contains(&f.tag))?
+        }
+        Action::FixThumbnail => thumbnail::replace(&bytes, &thumbnail::generate(&bytes)?)?,
+        Action::CopyMeta { from, fields } => {
+            let source = fs::read(from).with_context(|| format!("Failed to read {}", from.display()))?;
+            change.check_source(from, &source)?;
+            transplant::copy_segments(&source, &bytes, fields)?.0
+        }
+        Action::Redact { position } => privacy::rewrite(&bytes, *position)
+            .with_context(|| format!("Cannot redact {}", path.display()))?,
+        Action::Anonymize => {
+            let anonymizer = anonymizer.context("Anonymizing needs --anonymize and the salt of the run that planned it")?;
+            anonymizer.rewrite(&bytes).with_context(|| format!("Cannot anonymize {}", path.display()))?
+        }
+    };
+    replace_file(path, contents)
+}
 
-    Ok(FilesystemMetadata {
-        size: metadata.len(),
-        created_time: DateTime::from(created_time),
-        modified_time: DateTime::from(modified_time),
-    })
+/// Plan a change for each input, reporting files that cannot be read
+fn plan_changes(files: &[PathBuf], plan: impl Fn(&Path) -> Result<Option<Change>>) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    for path in inputs::expand_inputs(files, &inputs::Filters::default())? {
+        match plan(&path) {
+            Ok(change) => changes.extend(change),
+            Err(e) => eprintln!("Error processing {}: {}", path.display(), e),
+        }
+    }
+    Ok(changes)
 }
 
-/// Extract EXIF metadata from a JPEG file
-fn extract_exif_metadata(path: &Path) -> Result<ExifMetadata> {
-    let file = std::fs::File::open(path)
-        .with_context(|| format!("Failed to open file {}", path.display()))?;
-    
-    let mut bufreader = std::io::BufReader::new(file);
-    let exifreader = Reader::new();
-    let exif = exifreader.read_from_container(&mut bufreader)?;
+/// Save the changes with --plan, list them with --dry-run, or confirm and make them
+fn run_changes(changes: Vec<Change>, options: &WriteOptions) -> Result<()> {
+    let plan = Plan::new(changes);
+    if let Some(path) = &options.plan {
+        plan.write(path)?;
+        println!("Saved {} changes to {}; make them with `apply {}`", plan.changes.len(), path.display(), path.display());
+        return Ok(());
+    }
+    if options.dry_run {
+        print!("{}", plan.preview(usize::MAX));
+        return Ok(());
+    }
+    apply_plan(&plan, &options.confirmation, None)
+}
 
-    let orientation = exif.get_field(Tag::Orientation, In::PRIMARY)
-        .and_then(|field| field.value.get_uint(0));
+/// List the first changes, ask for confirmation and make them
+fn apply_plan(plan: &Plan, confirmation: &Confirmation, anonymizer: Option<&Anonymizer>) -> Result<()> {
+    if plan.changes.is_empty() {
+        println!("Nothing to change");
+        return Ok(());
+    }
+    print!("{}", plan.preview(confirmation.preview));
+    if !plan::confirm(plan.changes.len(), confirmation.yes)? {
+        println!("No files were modified");
+        return Ok(());
+    }
+    for change in &plan.changes {
+        match apply_change(change, anonymizer) {
+            Ok(()) => println!("Updated: {}", change.path.display()),
+            Err(e) => eprintln!("Error processing {}: {:#}", change.path.display(), e),
+        }
+    }
+    Ok(())
+}
 
-    let capture_time = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
-        .and_then(|field| {
-            let s = field.display_value().with_unit(&exif).to_string();
-            chrono::NaiveDateTime::parse_from_str(&s, "%Y:%m:%d %H:%M:%S").ok()
-                .map(|dt| Utc.from_utc_datetime(&dt))
-        });
+/// Sort combined output rows. The sort is stable, so ties keep their input order.
+fn sort_rows(rows: &mut [ImageMetadata], sort_by: SortBy) {
+    sort_rows_by(rows, sort_by, |m| m);
+}
 
-    let camera_model = exif.get_field(Tag::Model, In::PRIMARY)
-        .map(|field| field.display_value().with_unit(&exif).to_string());
+/// [`sort_rows`] for rows that carry a record alongside other data
+fn sort_rows_by<T>(rows: &mut [T], sort_by: SortBy, metadata: fn(&T) -> &ImageMetadata) {
+    match sort_by {
+        SortBy::Input => {}
+        SortBy::Name => rows.sort_by(|a, b| metadata(a).filename.cmp(&metadata(b).filename)),
+        SortBy::Size => rows.sort_by_key(|r| metadata(r).size),
+        SortBy::CaptureTime => rows.sort_by_key(|r| {
+            let m = metadata(r);
+            (m.capture_time.is_none(), m.capture_time)
+        }),
+    }
+}
 
-    let camera_serial = exif.get_field(Tag::BodySerialNumber, In::PRIMARY)
-        .map(|field| field.display_value().with_unit(&exif).to_string());
+/// Render metadata as an aligned plain-text table, with capture times shown in `zone`
+fn format_table(rows: &[ImageMetadata], zone: Zone, locale: &Locale) -> String {
+    let headers = ["FILENAME", "SIZE", "CAPTURE TIME", "CAMERA", "DIMENSIONS"];
+    let cells: Vec<[String; 5]> = rows.iter()
+        .map(|m| [
+            m.filename.clone(),
+            locale.integer(m.size),
+            m.capture_time
+                .map(|t| locale.datetime(zone.convert(t).naive_local(), true))
+                .unwrap_or_else(|| "-".to_string()),
+            m.camera_model.as_deref().unwrap_or("-").to_string(),
+            match (m.width, m.height) {
+                (Some(w), Some(h)) => format!("{}x{}", w, h),
+                _ => "-".to_string(),
+            },
+        ])
+        .collect();
 
-    Ok(ExifMetadata {
-        orientation,
-        capture_time,
-        camera_model,
-        camera_serial,
-    })
+    let mut widths = headers.map(|h| h.len());
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    let mut push_row = |row: &[&str]| {
+        let line: Vec<String> = row.iter().zip(widths)
+            .enumerate()
+            // Right-align the numeric size column
+            .map(|(i, (cell, width))| if i == 1 {
+                format!("{:>width$}", cell, width = width)
+            } else {
+                format!("{:<width$}", cell, width = width)
+            })
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    };
+    push_row(&headers);
+    let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+    push_row(&rule.iter().map(String::as_str).collect::<Vec<_>>());
+    for row in &cells {
+        push_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+    out
 }
 
-/// Process a single JPEG file and generate its metadata JSON
-fn process_file(path: &Path) -> Result<()> {
-    let fs_metadata = extract_filesystem_metadata(path)?;
-    let exif_metadata = extract_exif_metadata(path)?;
+/// The sinks for a job: its output format's, then the --publish and --db exports
+fn output_sink<'s>(
+    job: &Job,
+    args: &Args,
+    sinks: [&'s mut dyn Sink; 4],
+    exports: &'s mut [Box<dyn Sink + '_>],
+) -> Fanout<'s> {
+    let [sidecars, table, lines, exiftool] = sinks;
+    let output = match job.format.unwrap_or(args.format) {
+        OutputFormat::Json | OutputFormat::Xmp => sidecars,
+        OutputFormat::Table => table,
+        OutputFormat::Jsonl => lines,
+        OutputFormat::Exiftool => exiftool,
+    };
+    Fanout::new(std::iter::once(output).chain(exports.iter_mut().map(|export| export.as_mut() as &mut dyn Sink)))
+}
 
-    let metadata = ImageMetadata {
-        filename: path.file_name()
-            .and_then(|name| name.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?
-            .to_string(),
-        size: fs_metadata.size,
-        created_time: fs_metadata.created_time,
-        modified_time: fs_metadata.modified_time,
-        orientation: exif_metadata.orientation,
-        capture_time: exif_metadata.capture_time,
-        camera_model: exif_metadata.camera_model,
-        camera_serial: exif_metadata.camera_serial,
-    };
-
-    // Create output path by replacing extension with .json
-    let output_path: PathBuf = path.with_extension("json");
-    
-    // Write JSON to file
-    let json: String = serde_json::to_string_pretty(&metadata)?;
-    fs::write(&output_path, json)
-        .with_context(|| format!("Failed to write metadata to {}", output_path.display()))?;
+/// Every destination of a run's records: the sink of each output format, the
+/// --db and --publish exports, and the sink holding records back for grouping
+struct Outputs<'a> {
+    sidecars: SidecarSink<'a>,
+    table: TableSink,
+    lines: JsonLinesSink<'a, compression::Output>,
+    exiftool: ExiftoolSink<compression::Output>,
+    exports: Vec<Box<dyn Sink + 'a>>,
+    groups: GroupingSink,
+}
 
-    println!("Processed: {}", path.display());
-    Ok(())
+impl Outputs<'_> {
+    /// Write out the grouped records, then finish every sink and end any
+    /// compressed stream
+    fn finish(mut self, args: &Args, mut report: Option<&mut Report>) -> Result<()> {
+        self.groups.finish()?;
+        if args.cluster_events {
+            write_events(self.groups.events(), args)?;
+        }
+        for (job, metadata) in self.groups.into_records() {
+            let started = std::time::Instant::now();
+            let sinks: [&mut dyn Sink; 4] = [&mut self.sidecars, &mut self.table, &mut self.lines, &mut self.exiftool];
+            let result = output_sink(&job, args, sinks, &mut self.exports).write(&job, metadata);
+            if let Some(report) = report.as_mut() {
+                report.add(&job.path, Stage::Write, started.elapsed());
+            }
+            if let Err(e) = result {
+                eprintln!("Error processing {}: {}", job.path.display(), e);
+            }
+        }
+        for export in &mut self.exports {
+            export.finish()?;
+        }
+        self.sidecars.finish()?;
+        self.table.finish()?;
+        self.lines.finish()?;
+        self.exiftool.finish()?;
+        self.lines.into_writer().finish().context("Failed to write JSON Lines output")?;
+        self.exiftool.into_writer().finish().context("Failed to write exiftool output")
+    }
 }
 
-/// Checks if the file is a valid JPEG
-fn is_jpeg(path: &PathBuf) -> Result<bool> {
-    let mut file = File::open(path)?;
-    let mut buffer = [0; 2];
-    file.read_exact(&mut buffer)?;
-    
-    // JPEG files start with FF D8
-    Ok(buffer == [0xFF, 0xD8])
+/// Per-run state the input loop updates, reported once the run ends
+#[derive(Default)]
+struct Run {
+    compliance: Option<Compliance>,
+    checksums: Option<ChecksumManifest>,
+    report: Option<Report>,
+    /// Rewrites for --anonymize-files and --privacy-zone-files, confirmed after the run
+    pending: Vec<Change>,
+    /// Inputs that `pending` rewrites, hashed once they are
+    rewritten: Vec<PathBuf>,
+    non_jpeg_files: Vec<(PathBuf, detect::ImageFormat)>,
+    /// Inputs skipped as completed in an earlier run
+    resumed: usize,
+    /// The signal that stopped the loop early
+    interrupted: Option<i32>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let mut non_jpeg_files = Vec::new();
+/// Extract metadata from each input, or each member of an input archive, and
+/// hand the records to `outputs`. Stops between files once a signal arrives.
+fn process_inputs(
+    jobs: &[Job],
+    args: &Args,
+    registry: &ExtractorRegistry,
+    filters: &inputs::Filters,
+    state: Option<&RunState>,
+    outputs: &mut Outputs,
+    run: &mut Run,
+) {
+    let already_done = |path: &Path| state.is_some_and(|s| s.is_done(path));
+    let record_done = |path: &Path| match state.filter(|_| !args.dry_run) {
+        Some(state) => state.record(path),
+        None => Ok(()),
+    };
+    // Hardlinked copies share a device and inode, so only the first one is processed
+    let mut seen_files = std::collections::HashSet::new();
 
     // Check if the files are valid JPEG images and extract metadata from the valid ones
-    for path in &args.files {
+    for (index, job) in jobs.iter().enumerate() {
+        if let Some(signal) = shutdown::requested() {
+            eprintln!("Received {}, stopping with {} inputs left; finishing output", shutdown::name(signal), jobs.len() - index);
+            run.interrupted = Some(signal);
+            break;
+        }
+        let path = &job.path;
+        if let Some((archive, member)) = archives::archive_input(path) {
+            let wanted = |name: &str, size, modified| match &member {
+                Some(member) => name == member,
+                None => filters.accepts_member(name, size, modified)
+                    && !already_done(&archives::member_path(&archive, name)),
+            };
+            let mut found = false;
+            let result = archives::for_each_member(&archive, args.throttle(), wanted, |entry| {
+                found = true;
+                // The rest of the archive is left for the next run
+                if shutdown::requested().is_some() {
+                    return ControlFlow::Break(());
+                }
+                let member_job = Job {
+                    path: archives::member_path(&archive, &entry.name),
+                    // An output path given for a whole archive cannot apply to each member
+                    output: member.as_ref().and(job.output.clone()),
+                    ..job.clone()
+                };
+                let mut fanout;
+                let sink: &mut dyn Sink = if args.groups_records() {
+                    &mut outputs.groups
+                } else {
+                    fanout = output_sink(&member_job, args, [&mut outputs.sidecars, &mut outputs.table, &mut outputs.lines, &mut outputs.exiftool], &mut outputs.exports);
+                    &mut fanout
+                };
+                let mut timer = FileTimer::read_ahead(entry.bytes.len() as u64);
+                let result = isolate(|| {
+                    let metadata = timer.time(Stage::Parse, || extract_member_metadata(&archive, entry, args, registry))?;
+                    timer.time(Stage::Write, || deliver(&member_job, metadata, args, sink, &mut run.pending, run.compliance.as_mut()))
+                })
+                    .and_then(|()| record_done(&member_job.path));
+                if let Err(e) = &result {
+                    report_failure(&member_job.path, e);
+                }
+                if let Some(report) = run.report.as_mut() {
+                    report.record(&member_job.path, timer, &result);
+                }
+                ControlFlow::Continue(())
+            });
+            if let (Ok(ControlFlow::Break(())), Some(signal)) = (&result, shutdown::requested()) {
+                eprintln!("Received {}, stopping inside {} with {} inputs left; finishing output",
+                    shutdown::name(signal), archive.display(), jobs.len() - index - 1);
+                run.interrupted = Some(signal);
+                break;
+            }
+            let result = result.and_then(|_| match (&member, run.checksums.as_mut()) {
+                (Some(member), _) if !found => Err(anyhow::anyhow!("No file {} in archive", member)),
+                (None, Some(checksums)) => checksums.add(&archive),
+                _ => Ok(()),
+            });
+            if let Err(e) = result {
+                eprintln!("Error processing {}: {}", path.display(), e);
+            }
+            continue;
+        }
         if !path.exists() {
             continue;
         }
-        if !is_jpeg(path)? {
-            non_jpeg_files.push(path.clone());
+        if already_done(path) {
+            run.resumed += 1;
+            continue;
+        }
+        if args.no_follow_symlinks && path.is_symlink() {
+            eprintln!("Skipped (symlink): {}", path.display());
+            continue;
+        }
+        if let Some(identity) = fs::metadata(path).ok().as_ref().and_then(file_identity) {
+            if !seen_files.insert(identity) {
+                eprintln!("Skipped (same file as an earlier input): {}", path.display());
+                continue;
+            }
+        }
+        let mut timer = FileTimer::start();
+        let format = match timer.time(Stage::Detect, || detect::detect_format(path)) {
+            Ok(format) => format,
+            Err(e) => {
+                eprintln!("Error processing {}: {}", path.display(), e);
+                if let Some(report) = run.report.as_mut() {
+                    report.record(path, timer, &Err(e.into()));
+                }
+                continue;
+            }
+        };
+        // RAW files are read through their embedded preview, which is written out first
+        let preview_job;
+        let job = match &args.extract_preview {
+            Some(dir) if format == detect::ImageFormat::Tiff => match save_preview(path, dir, args.dry_run) {
+                Ok(Some(preview)) => {
+                    preview_job = Job { path: preview, ..job.clone() };
+                    &preview_job
+                }
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("Error processing {}: {}", path.display(), e);
+                    if let Some(report) = run.report.as_mut() {
+                        report.record(path, timer, &Err(e));
+                    }
+                    continue;
+                }
+            },
+            _ if !format.is_supported() => {
+                if let Some(report) = run.report.as_mut() {
+                    let error = match format {
+                        detect::ImageFormat::Unknown => ExtractError::NotAnImage("not a recognised image format".to_string()),
+                        format => ExtractError::Unsupported(format!("image format {}", format)),
+                    };
+                    report.record(path, timer, &Err(error.into()));
+                }
+                run.non_jpeg_files.push((path.clone(), format));
+                continue;
+            }
+            _ => job,
+        };
+        let mut fanout;
+        let sink: &mut dyn Sink = if args.groups_records() {
+            &mut outputs.groups
+        } else {
+            fanout = output_sink(job, args, [&mut outputs.sidecars, &mut outputs.table, &mut outputs.lines, &mut outputs.exiftool], &mut outputs.exports);
+            &mut fanout
+        };
+        // Files to be rewritten are hashed once they are
+        let planned = run.pending.len();
+        let result = isolate(|| process_file(job, args, registry, sink, &mut timer, &mut run.pending, run.compliance.as_mut()))
+            .and_then(|()| {
+                if run.pending.len() > planned {
+                    run.rewritten.push(path.clone());
+                    return Ok(());
+                }
+                timer.time(Stage::Hash, || run.checksums.as_mut().map_or(Ok(()), |c| c.add(path)))
+            })
+            .and_then(|()| record_done(path));
+        if let Err(e) = &result {
+            report_failure(path, e);
+        }
+        if let Some(report) = run.report.as_mut() {
+            report.record(&job.path, timer, &result);
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let registry = ExtractorRegistry::new();
+    if args.nice_io {
+        if let Err(e) = throttle::lower_io_priority() {
+            eprintln!("Warning: could not lower IO priority: {}", e);
+        }
+    }
+
+    match &args.command {
+        Some(Command::Diff { left, right }) => {
+            let differs = run_diff(left, right, &args, &registry)?;
+            std::process::exit(if differs { 1 } else { 0 });
+        }
+        Some(Command::Timeshift { files, offset, set_timezone, write }) => {
+            let changes = plan_changes(files, |path| plan_timeshift(path, *offset, set_timezone.as_deref()))?;
+            return run_changes(changes, write);
+        }
+        Some(Command::Stats { files, json }) => {
+            let stats = collect_stats(&inputs::expand_inputs(files, &inputs::Filters::default())?);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&stats.to_json())?);
+            } else {
+                print!("{}", stats.report(&args.locale));
+            }
+            return Ok(());
+        }
+        Some(Command::Geotag { files, gpx, clock_offset, max_gap, write }) => {
+            let xml = fs::read_to_string(gpx)
+                .with_context(|| format!("Failed to read {}", gpx.display()))?;
+            let track = Track::parse(&xml)?;
+            if track.is_empty() {
+                anyhow::bail!("No timestamped track points in {}", gpx.display());
+            }
+            let max_gap = chrono::Duration::seconds(*max_gap);
+            let changes = plan_changes(files, |path| plan_geotag(path, &track, *clock_offset, max_gap))?;
+            return run_changes(changes, write);
+        }
+        Some(Command::GenFixture { spec, output_dir }) => {
+            let toml = fs::read_to_string(spec).with_context(|| format!("Failed to read {}", spec.display()))?;
+            let corpus = fixture::parse_corpus(&toml).with_context(|| format!("Invalid fixture spec {}", spec.display()))?;
+            fs::create_dir_all(output_dir).with_context(|| format!("Failed to create {}", output_dir.display()))?;
+            for (name, fixture) in &corpus {
+                if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+                    anyhow::bail!("Fixture name {:?} cannot be used as a file name", name);
+                }
+                let bytes = fixture::generate(fixture).with_context(|| format!("In fixture {}", name))?;
+                let path = output_dir.join(format!("{}.jpg", name));
+                fs::write(&path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+                println!("Wrote {}", path.display());
+            }
+            return Ok(());
+        }
+        Some(Command::ImportSidecars { files, db }) => {
+            return import_sidecars(files, db);
+        }
+        Some(Command::Audit { files, policy, json }) => {
+            let failed = run_audit(files, policy, *json, &args, &registry)?;
+            std::process::exit(if failed { 1 } else { 0 });
+        }
+        Some(Command::Validate { files }) => {
+            let drifted = run_validate(files, &args, &registry)?;
+            std::process::exit(if drifted { 1 } else { 0 });
+        }
+        #[cfg(feature = "tui")]
+        Some(Command::Tui { files }) => {
+            let paths = inputs::expand_inputs(files, &inputs::Filters::default())?;
+            eprintln!("Reading {} files...", paths.len());
+            let entries = paths.into_iter()
+                .map(|path| {
+                    let metadata = extract_metadata(&path, &args, &registry).and_then(|metadata| {
+                        let mut value = serde_json::to_value(metadata)?;
+                        timestamps::apply(&mut value, args.time_format, args.timezone);
+                        Ok(value)
+                    });
+                    tui::Entry::new(path, metadata)
+                })
+                .collect();
+            return tui::run(entries);
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::VerifySignature { files, key }) => {
+            let mut failed = false;
+            for path in files {
+                for (record, outcome) in signing::verify_file(path, key)? {
+                    match outcome {
+                        Ok(()) => println!("OK: {}", record),
+                        Err(e) => {
+                            println!("FAILED: {}: {}", record, e);
+                            failed = true;
+                        }
+                    }
+                }
+            }
+            std::process::exit(if failed { 1 } else { 0 });
+        }
+        Some(Command::CopyMeta { from, to, fields, write }) => {
+            return run_changes(plan_copy_meta(from, to, fields)?.into_iter().collect(), write);
+        }
+        Some(Command::FixThumbnail { files, force, write }) => {
+            let changes = plan_changes(files, |path| plan_fix_thumbnail(path, *force))?;
+            return run_changes(changes, write);
+        }
+        Some(Command::Apply { plan, confirmation }) => {
+            return apply_plan(&Plan::load(plan)?, confirmation, None);
+        }
+        None => {}
+    }
+
+    if let Some(dir) = &args.generate_man {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        clap_mangen::generate_to(Args::command(), dir)
+            .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+        println!("Wrote man pages to {}", dir.display());
+        return Ok(());
+    }
+
+    if args.capabilities {
+        println!("{}", serde_json::to_string_pretty(&capabilities::capabilities(&registry))?);
+        return Ok(());
+    }
+
+    if args.clear_cache {
+        if let Some(dir) = cache::Cache::default_dir() {
+            cache::Cache::new(dir).clear()?;
+        }
+    }
+
+    if args.sign.is_some() && !matches!(args.format, OutputFormat::Json | OutputFormat::Jsonl) {
+        anyhow::bail!("--sign only applies to --format json and jsonl");
+    }
+    if args.layout == OutputLayout::Cas && args.merge_existing {
+        anyhow::bail!("--merge-existing cannot be used with --layout cas, whose objects are never rewritten");
+    }
+    if args.format == OutputFormat::Jsonl && args.sort_by != SortBy::Input {
+        anyhow::bail!("--sort-by cannot be used with --format jsonl, which streams records as they complete");
+    }
+
+    let exports = sink::exports(&args)?;
+    // Only the stream of the chosen format is compressed, so stdout holds a single one
+    let stdout = |format| -> Result<compression::Output> {
+        match args.compression().filter(|_| args.format == format) {
+            Some(compression) => compression.writer(std::io::stdout()),
+            None => Ok(compression::Output::plain(std::io::stdout())),
+        }
+    };
+    let mut outputs = Outputs {
+        sidecars: SidecarSink::new(&args),
+        table: TableSink::new(args.sort_by, args.timezone, args.locale, args.format == OutputFormat::Table),
+        lines: JsonLinesSink::new(stdout(OutputFormat::Jsonl)?, &args),
+        exiftool: ExiftoolSink::new(stdout(OutputFormat::Exiftool)?, args.sort_by, args.format == OutputFormat::Exiftool),
+        exports,
+        groups: GroupingSink::new(
+            args.detect_bursts.then(|| chrono::Duration::milliseconds(args.burst_gap.into())),
+            args.cluster_events.then(|| (chrono::Duration::minutes(args.event_gap.into()), args.event_distance)),
+            args.link_derivatives.then_some(args.derivative_distance),
+        ),
+    };
+    let mut run = Run {
+        compliance: (!args.require.is_empty()).then(|| Compliance::new(args.require.clone())),
+        checksums: args.manifest_format.map(|format| {
+            let path = args.manifest_output.clone().unwrap_or_else(|| format.default_path().into());
+            ChecksumManifest::new(format, path, args.throttle())
+        }),
+        report: (args.report.is_some() || args.slow_threshold.is_some()).then(|| Report::new(args.slow_threshold)),
+        ..Run::default()
+    };
+
+    let mut filters = inputs::Filters::new(&args.include, &args.exclude, &args.exclude_dir)?;
+    filters.min_size = args.min_size;
+    filters.max_size = args.max_size;
+    filters.include_raw = args.extract_preview.is_some();
+    if args.date_field == DateField::Modified {
+        filters.modified_after = args.after;
+        filters.modified_before = args.before;
+    }
+    let mut jobs: Vec<Job> = inputs::expand_inputs_with_roots(&args.files, &filters)?
+        .into_iter()
+        .map(|(path, root)| Job { root: Some(root), ..Job::new(path) })
+        .collect();
+    if let Some(manifest) = &args.manifest {
+        jobs.extend(manifest::read_manifest(manifest)?);
+    }
+
+    // Table and exiftool output, burst detection and event clustering only write
+    // records at the end, so inputs count as done once that has happened
+    let buffered = args.groups_records() || jobs.iter()
+        .any(|job| matches!(job.format.unwrap_or(args.format), OutputFormat::Table | OutputFormat::Exiftool));
+    let state = args.state.as_deref().map(|path| RunState::open(path, buffered)).transpose()?;
+
+    // A signal stops the loop between files; everything below still runs, so
+    // combined output, the catalog, the state file and reports are complete
+    shutdown::install();
+    process_inputs(&jobs, &args, &registry, &filters, state.as_ref(), &mut outputs, &mut run);
+
+    outputs.finish(&args, run.report.as_mut())?;
+    if let Some(state) = state.as_ref().filter(|_| !args.dry_run) {
+        state.flush()?;
+    }
+    if run.resumed > 0 {
+        eprintln!("Skipped {} inputs completed in an earlier run", run.resumed);
+    }
+    if !run.pending.is_empty() && args.dry_run {
+        print!("{}", Plan::new(std::mem::take(&mut run.pending)).preview(usize::MAX));
+    } else if !run.pending.is_empty() {
+        apply_plan(&Plan::new(std::mem::take(&mut run.pending)), &args.confirmation, args.anonymizer().as_ref())?;
+    }
+    if let Some(checksums) = run.checksums.as_mut() {
+        for path in &run.rewritten {
+            if let Err(e) = checksums.add(path) {
+                eprintln!("Error processing {}: {}", path.display(), e);
+            }
         }
-        else if let Err(e) = process_file(&path) {
-            eprintln!("Error processing {}: {}", path.display(), e);
+    }
+    if let Some(checksums) = &run.checksums {
+        checksums.write(args.dry_run)?;
+    }
+    if let Some((report, path)) = run.report.as_ref().zip(args.report.as_ref()) {
+        report.write(path, args.durable)?;
+    }
+    let slow_files = run.report.as_ref().map(Report::slow_files).unwrap_or_default();
+    if !slow_files.is_empty() {
+        eprintln!("\nFiles slower than {:?}:", args.slow_threshold.unwrap_or_default());
+        for file in slow_files {
+            eprintln!("  - {} ({:.2}s, {:.2}s in {})", file.path, file.seconds, file.stage_seconds, file.stage);
         }
     }
 
     // If there are any non-JPEG files, print error and exit
-    if !non_jpeg_files.is_empty() {
+    if !run.non_jpeg_files.is_empty() {
         eprintln!("\nThe following files are not valid JPEG images:");
-        for path in non_jpeg_files {
-            eprintln!("  - {}", path.display());
+        for (path, format) in run.non_jpeg_files {
+            eprintln!("  - {} ({})", path.display(), format);
         }
     }
+    let compliance_failed = run.compliance.is_some_and(|compliance| {
+        eprint!("\n{}", compliance.summary());
+        compliance.has_failures()
+    });
+    if let Some(signal) = run.interrupted {
+        std::process::exit(shutdown::exit_code(signal));
+    }
+    if compliance_failed {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -174,15 +2910,15 @@ mod tests {
     use std::path::PathBuf;
 
     #[test]
-    fn test_is_jpeg_true() {
+    fn test_detect_format_jpeg() {
         let path = PathBuf::from("images/JAM26284.jpg");
-        assert_eq!(is_jpeg(&path).unwrap(), true);
+        assert!(detect::detect_format(&path).unwrap().is_jpeg());
     }
 
     #[test]
-    fn test_is_jpeg_false() {
+    fn test_detect_format_non_jpeg() {
         let path = PathBuf::from("images/non-jpeg.png");
-        assert_eq!(is_jpeg(&path).unwrap(), false);
+        assert_eq!(detect::detect_format(&path).unwrap(), detect::ImageFormat::Unknown);
     }
 
     #[test]
@@ -192,26 +2928,638 @@ mod tests {
         assert!(meta.size == 3014190);
         
         let expected_time = Utc.with_ymd_and_hms(2020, 8, 13, 10, 57, 7).unwrap();
-        assert_eq!(meta.created_time, expected_time);
+        assert_eq!(meta.created_time, Some(expected_time));
         assert_eq!(meta.modified_time, expected_time);
     }
 
     #[test]
     fn test_extract_exif_metadata() {
         let path = PathBuf::from("images/JAM26284.jpg");
-        let exif = extract_exif_metadata(&path).unwrap();
+        let exif = read_exif_metadata(&mut BufReader::new(File::open(&path).unwrap()), &ExtractOptions::default()).unwrap();
         assert_eq!(exif.orientation, Some(1));
-        assert_eq!(exif.camera_model, Some("\"Canon EOS 5D Mark IV\"".to_string()));
-        assert_eq!(exif.camera_serial, Some("\"025021000535\"".to_string()));
+        assert_eq!(exif.camera_model, Some("Canon EOS 5D Mark IV".to_string()));
+        assert_eq!(exif.camera_serial, Some("025021000535".to_string()));
+        assert_eq!(exif.flash.map(|f| (f.fired, f.mode)), Some((false, jpeg_metadata_extractor::lighting::FlashMode::CompulsorySuppression)));
+        assert_eq!(exif.white_balance.and_then(|wb| wb.mode).as_deref(), Some("auto"));
     }
 
     #[test]
-    fn test_process_file() {
+    fn test_capture_time_from_exif() {
+        let path = PathBuf::from("images/JAM26284.jpg");
+        let exif = read_exif_metadata(&mut BufReader::new(File::open(&path).unwrap()), &ExtractOptions::default()).unwrap();
+        assert!(exif.capture_time.is_some());
+    }
+
+    #[test]
+    fn test_capture_time_from_filename() {
+        let path = PathBuf::from("scans/IMG_20190704_153000.jpg");
+        let expected = Utc.with_ymd_and_hms(2019, 7, 4, 15, 30, 0).unwrap();
+        assert_eq!(capture_time_from_filename(&path, "%Y%m%d_%H%M%S"), Some(expected));
+
+        let path = PathBuf::from("scans/1987-05-02 birthday.jpg");
+        let expected = Utc.with_ymd_and_hms(1987, 5, 2, 0, 0, 0).unwrap();
+        assert_eq!(capture_time_from_filename(&path, "%Y-%m-%d"), Some(expected));
+
+        assert_eq!(capture_time_from_filename(&path, "%Y%m%d_%H%M%S"), None);
+    }
+
+    #[test]
+    fn test_extract_dimensions() {
         let path = PathBuf::from("images/JAM26284.jpg");
+        let segments = jpeg::read_segments(&mut BufReader::new(File::open(&path).unwrap())).unwrap();
+        assert_eq!(jpeg::dimensions(&segments), Some((5040, 3360)));
+    }
+
+    #[test]
+    fn test_payload_breakdown() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "images/JAM26284.jpg"]);
+        let metadata = extract_metadata(Path::new("images/JAM26284.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+        let b = &metadata.payload_breakdown;
+        assert_eq!(b.icc, 578);
+        assert!(b.exif > 0 && b.xmp > 0 && b.image_data > 0);
+        assert_eq!(b.exif + b.xmp + b.icc + b.thumbnail + b.image_data + b.other, metadata.size);
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        let mut args = Args::parse_from(["jpeg-metadata-extractor", "--provenance", "images/JAM26284.jpg"]);
+        let raw = extract_metadata(Path::new("images/JAM26284.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+        assert_eq!(raw.capture_time_raw, None);
+
+        args.config = Some(Config::parse("[clock_offsets]\n025021000535 = \"-00:02:13\"\n").unwrap());
+        let metadata = extract_metadata(Path::new("images/JAM26284.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+        assert_eq!(metadata.capture_time_raw, raw.capture_time);
+        assert_eq!(metadata.capture_time, raw.capture_time.map(|t| t - chrono::Duration::seconds(133)));
+        assert_eq!(metadata.provenance["capture_time_raw"], raw.provenance["capture_time"]);
+        assert!(matches!(metadata.provenance["capture_time"], Source::Derived { .. }));
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_best_time() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--provenance", "images/JAM26284.jpg"]);
+        let metadata = extract_metadata(Path::new("images/JAM26284.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+        assert_eq!((Some(metadata.best_time), metadata.best_time_source), (metadata.capture_time, TimeSource::ExifOriginal));
+        assert_eq!(metadata.provenance["best_time"], metadata.provenance["capture_time"]);
+
+        // A scan without EXIF falls back to its file name's date, then to its modification time
+        let dir = std::env::temp_dir().join(format!("jme-best-time-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let scan = dir.join("IMG_20190704_153000.jpg");
+        fs::write(&scan, thumbnail::generate(&fs::read("images/JAM26284.jpg").unwrap()).unwrap()).unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--date-from-filename", "%Y%m%d_%H%M%S", scan.to_str().unwrap()]);
+        let metadata = extract_metadata(&scan, &args, &ExtractorRegistry::new()).unwrap();
+        assert_eq!(metadata.best_time, Utc.with_ymd_and_hms(2019, 7, 4, 15, 30, 0).unwrap());
+        assert_eq!(metadata.best_time_source, TimeSource::Filename);
+
+        let args = Args::parse_from(["jpeg-metadata-extractor", scan.to_str().unwrap()]);
+        let metadata = extract_metadata(&scan, &args, &ExtractorRegistry::new()).unwrap();
+        assert_eq!((metadata.best_time, metadata.best_time_source), (metadata.modified_time, TimeSource::FilesystemModified));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_fingerprint() {
+        let dir = std::env::temp_dir().join(format!("jme-fingerprint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let copy = dir.join("copy.jpg");
+        fs::copy("images/JAM26284.jpg", &copy).unwrap();
+        let fingerprint = |path: &Path| {
+            let args = Args::parse_from(["jpeg-metadata-extractor", "--metadata-fingerprint", "--format", "table", path.to_str().unwrap()]);
+            let mut sink = GroupingSink::new(None, None, None);
+            process_file(&Job::new(path.to_path_buf()), &args, &ExtractorRegistry::new(), &mut sink, &mut FileTimer::start(), &mut Vec::new(), None).unwrap();
+            sink.into_records().remove(0).1.metadata_fingerprint.unwrap()
+        };
+        let original = fingerprint(Path::new("images/JAM26284.jpg"));
+        let renamed = fingerprint(&copy);
+        let other = fingerprint(Path::new("images/JAM19896.jpg"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(original, renamed);
+        assert_ne!(original, other);
+    }
+
+    #[test]
+    fn test_format_table() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "images/JAM26284.jpg"]);
+        let metadata = extract_metadata(Path::new("images/JAM26284.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+        let table = format_table(std::slice::from_ref(&metadata), Zone::Utc, &Locale::default());
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("FILENAME"));
+        assert!(lines[2].contains("Canon EOS 5D Mark IV"));
+        assert!(lines[2].ends_with("5040x3360"));
+
+        let german = format_table(&[metadata], Zone::Utc, &Locale::parse("de").unwrap());
+        assert!(german.lines().nth(2).unwrap().contains(&Locale::parse("de").unwrap().integer(fs::metadata("images/JAM26284.jpg").unwrap().len())));
+    }
+
+    #[test]
+    fn test_diff_image_against_sidecar() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "images/JAM19896.jpg"]);
+        let image = load_metadata_value(Path::new("images/JAM19896.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+
+        let mut sidecar = image.clone();
+        sidecar["exif"]["camera_model"] = serde_json::json!("Edited");
+        sidecar["exif"].as_object_mut().unwrap().remove("orientation");
+
+        let diffs = diff::diff_metadata(&image, &sidecar);
+        let fields: Vec<&str> = diffs.iter().map(|d| d.field.as_str()).collect();
+        assert_eq!(fields, ["exif.camera_model", "exif.orientation"]);
+        assert!(diffs[1].right.is_none());
+        assert!(diff::diff_metadata(&image, &image).is_empty());
+    }
+
+    #[test]
+    fn test_sidecar_drift() {
+        let dir = std::env::temp_dir().join(format!("jme-validate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("a.jpg");
+        fs::copy("images/JAM19896.jpg", &image).unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", image.to_str().unwrap()]);
+        let registry = ExtractorRegistry::new();
+
+        let mut sidecar = load_metadata_value(&image, &args, &registry).unwrap();
+        sidecar["exif"].as_object_mut().unwrap().remove("orientation");
+        fs::write(dir.join("a.json"), sidecar.to_string()).unwrap();
+        let clean = sidecar_drift(&image, &args, &registry).unwrap();
+
+        sidecar["exif"]["camera_model"] = serde_json::json!("Edited");
+        fs::write(dir.join("a.json"), sidecar.to_string()).unwrap();
+        let edited = sidecar_drift(&image, &args, &registry).unwrap();
+        fs::remove_file(dir.join("a.json")).unwrap();
+        let missing = sidecar_drift(&image, &args, &registry);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Fields left out of the sidecar are not drift
+        assert!(clean.is_empty(), "{:?}", clean);
+        assert_eq!(edited.len(), 1);
+        assert!(edited[0].starts_with("~ exif.camera_model: \"Edited\""));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_completions_and_man_pages() {
+        Args::command().debug_assert();
+        let mut bash = Vec::new();
+        clap_complete::generate(clap_complete::Shell::Bash, &mut Args::command(), "jpeg-metadata-extractor", &mut bash);
+        let bash = String::from_utf8(bash).unwrap();
+        assert!(bash.contains("verify-signature") && bash.contains("--generate-man"));
+
+        let dir = std::env::temp_dir().join(format!("jme-man-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        clap_mangen::generate_to(Args::command(), &dir).unwrap();
+        let page = fs::read_to_string(dir.join("jpeg-metadata-extractor-validate.1")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(page.contains(".TH") && page.contains("sidecar"));
+    }
+
+    #[test]
+    fn test_process_file_dry_run() {
+        let path = PathBuf::from("images/JAM19896.jpg");
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--dry-run", "images/JAM19896.jpg"]);
+        assert!(process_file(&Job::new(path.clone()), &args, &ExtractorRegistry::new(), &mut SidecarSink::new(&args), &mut FileTimer::start(), &mut Vec::new(), None).is_ok());
+        assert!(!path.with_extension("json").exists());
+    }
+
+    #[test]
+    fn test_require() {
+        let path = PathBuf::from("images/JAM19896.jpg");
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--dry-run", "--require", "filename,no_such_field", "images/JAM19896.jpg"]);
+        let mut compliance = Compliance::new(args.require.clone());
+        process_file(&Job::new(path), &args, &ExtractorRegistry::new(), &mut SidecarSink::new(&args), &mut FileTimer::start(), &mut Vec::new(), Some(&mut compliance)).unwrap();
+        assert!(compliance.has_failures());
+        assert_eq!(compliance.summary(), "Compliance: 0 of 1 files have all required fields\n  filename: missing in 0\n  no_such_field: missing in 1\n");
+    }
+
+    #[test]
+    fn test_merge_existing() {
+        let mut value = serde_json::json!({"filename": "a.jpg", "width": 10});
+        let existing = serde_json::json!({"album": "Holiday", "width": 5, "people": ["Sam"]});
+        merge_existing(&mut value, existing);
+        assert_eq!(value, serde_json::json!({"filename": "a.jpg", "width": 10, "album": "Holiday", "people": ["Sam"]}));
+        let keys: Vec<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["filename", "width", "album", "people"]);
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_fix_thumbnail() {
+        let dir = std::env::temp_dir().join(format!("jme-thumb-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        fs::copy("images/JAM19896.jpg", &path).unwrap();
+
+        apply_change(&plan_fix_thumbnail(&path, true).unwrap().unwrap(), None).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(thumbnail::check(&bytes).unwrap(), ThumbnailState::Current);
+        let exif = read_exif_metadata(&mut bytes.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(exif.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+    }
+
+    #[test]
+    fn test_geotag() {
+        let dir = std::env::temp_dir().join(format!("jme-geotag-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        // An earlier fix whose altitude and time must not survive the new position
+        let bytes = fs::read("images/JAM26284.jpg").unwrap();
+        let mut fields = gpx::position_fields(1.0, 2.0, Some(500.0));
+        fields.push(exif::Field { tag: exif::Tag::GPSDateStamp, ifd_num: exif::In::PRIMARY, value: exif::Value::Ascii(vec![b"2000:01:01".to_vec()]) });
+        fs::write(&path, exif_write::rewrite(&bytes, &fields, None).unwrap()).unwrap();
+        let capture_time = read_exif_metadata(&mut BufReader::new(File::open(&path).unwrap()), &ExtractOptions::default())
+            .unwrap().capture_time.unwrap();
+        // Camera two hours ahead of UTC; the track brackets the corrected time
+        let utc = capture_time - chrono::Duration::hours(2);
+        let gpx = format!(r#"<gpx><trk><trkseg>
+            <trkpt lat="10" lon="20"><time>{}</time></trkpt>
+            <trkpt lat="12" lon="-20"><time>{}</time></trkpt>
+            </trkseg></trk></gpx>"#,
+            (utc - chrono::Duration::seconds(10)).to_rfc3339(),
+            (utc + chrono::Duration::seconds(10)).to_rfc3339());
+        let track = Track::parse(&gpx).unwrap();
+
+        let offset = parse_clock_offset("+02:00").unwrap();
+        apply_change(&plan_geotag(&path, &track, offset, chrono::Duration::minutes(5)).unwrap().unwrap(), None).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&bytes)).unwrap();
+        let latitude = exif.get_field(exif::Tag::GPSLatitude, exif::In::PRIMARY).unwrap();
+        assert_eq!(latitude.display_value().to_string(), "11 deg 0 min 0 sec");
+        let longitude_ref = exif.get_field(exif::Tag::GPSLongitudeRef, exif::In::PRIMARY).unwrap();
+        assert_eq!(longitude_ref.display_value().to_string(), "E");
+        assert!(exif.get_field(exif::Tag::GPSAltitude, exif::In::PRIMARY).is_none());
+        let date = exif.get_field(exif::Tag::GPSDateStamp, exif::In::PRIMARY).unwrap();
+        assert_eq!(date.display_value().to_string(), utc.format("%Y-%m-%d").to_string());
+        let model = exif.get_field(exif::Tag::Model, exif::In::PRIMARY).unwrap();
+        assert!(model.display_value().to_string().contains("Canon EOS 5D Mark IV"));
+        assert_eq!(parse_clock_offset("-3600"), Ok(chrono::Duration::hours(-1)));
+        assert!(parse_clock_offset("9223372036854775807").is_err());
+        assert!(parse_clock_offset("+9223372036854775807:00").is_err());
+    }
+
+    #[test]
+    fn test_timeshift() {
+        let dir = std::env::temp_dir().join(format!("jme-timeshift-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        fs::copy("images/JAM26284.jpg", &path).unwrap();
+        let read = |path: &Path| read_exif_metadata(&mut BufReader::new(File::open(path).unwrap()), &ExtractOptions::default())
+            .unwrap().capture_time.unwrap();
+        let before = read(&path);
+
+        let change = plan_timeshift(&path, timeshift::parse_offset("-1h30m").unwrap(), Some("+02:00")).unwrap().unwrap();
+        assert_eq!(read(&path), before);
+        apply_change(&change, None).unwrap();
+        let after = read(&path);
+        // Applying the plan again would shift the times twice
+        assert!(apply_change(&change, None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(before - after, chrono::Duration::minutes(90));
+        assert!(parse_exif_offset("+0200").is_err());
+    }
+
+    #[test]
+    fn test_anonymize() {
+        let dir = std::env::temp_dir().join(format!("jme-anon-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        fs::copy("images/JAM26284.jpg", &path).unwrap();
+        let path_arg = path.to_str().unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--anonymize", "camera_serial,gps", "--anonymize-salt", "s",
+            "--anonymize-files", "--yes", "--tag", "0xA431", path_arg]);
+        let mut out = Vec::new();
+        let mut pending = Vec::new();
+        process_file(&Job::new(path.clone()), &args, &ExtractorRegistry::new(), &mut JsonLinesSink::new(&mut out, &args), &mut FileTimer::start(), &mut pending, None).unwrap();
+        assert!(matches!(pending[..], [Change { action: Action::Anonymize, .. }]));
+        apply_plan(&Plan::new(pending), &args.confirmation, args.anonymizer().as_ref()).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let reread = read_exif_metadata(&mut BufReader::new(File::open(&path).unwrap()), &ExtractOptions::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let pseudonym = Anonymizer::new(b"s", [Category::CameraSerial]).pseudonym("025021000535");
+        assert_eq!(value["exif"]["camera_serial"], pseudonym.as_str());
+        assert_eq!(value["exif"]["extra"]["0xA431"], pseudonym.as_str());
+        assert_eq!(value["exif"]["camera_model"], "Canon EOS 5D Mark IV");
+        assert_eq!(reread.camera_serial.as_deref(), Some(pseudonym.as_str()));
+        assert!(Args::try_parse_from(["jpeg-metadata-extractor", "--anonymize", "lens", "a.jpg"]).is_err());
+    }
+
+    #[test]
+    fn test_camera_db() {
+        let path = std::env::temp_dir().join(format!("jme-cameras-{}.toml", std::process::id()));
+        fs::write(&path, "[\"Canon EOS 5D Mark IV\"]\nsensor_width = 22.5\nsensor_height = 15.0\n").unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--camera-db", path.to_str().unwrap(), "images/JAM26284.jpg"]);
+        fs::remove_file(&path).unwrap();
+
+        let metadata = extract_metadata(Path::new("images/JAM26284.jpg"), &args, &ExtractorRegistry::new()).unwrap();
+        let enrichment = metadata.enrichment.unwrap();
+        assert_eq!((enrichment.crop_factor, enrichment.megapixels), (1.6, None));
+        let builtin = extract_metadata(Path::new("images/JAM26284.jpg"), &Args::parse_from(["jpeg-metadata-extractor", "x"]), &ExtractorRegistry::new()).unwrap();
+        assert_eq!(builtin.enrichment.unwrap().megapixels, Some(30.4));
+        assert!(Args::try_parse_from(["jpeg-metadata-extractor", "--camera-db", "missing.toml", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_xmp_sidecar() {
+        let dir = std::env::temp_dir().join(format!("jme-xmp-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        fs::copy("images/JAM26284.jpg", &path).unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--format", "xmp", path.to_str().unwrap()]);
+        let metadata = extract_metadata(&path, &args, &ExtractorRegistry::new()).unwrap();
+        write_sidecar(&Job::new(path.clone()), &metadata, &args).unwrap();
+        let packet = fs::read_to_string(dir.join("copy.xmp")).unwrap();
+        let json_written = dir.join("copy.json").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!json_written);
+        assert!(packet.starts_with("<?xpacket begin="));
+        assert!(packet.contains("<tiff:Model>Canon EOS 5D Mark IV</tiff:Model>"));
+        assert!(packet.contains("<aux:SerialNumber>025021000535</aux:SerialNumber>"));
+
+        // A field selection by section name applies to XMP as it does to JSON
+        let selected = |fields: &[&str]| {
+            let job = Job { fields: Some(fields.iter().map(|f| f.to_string()).collect()), ..Job::new(path.clone()) };
+            xmp_write::sidecar(&record_value(&job, &metadata, &args).unwrap())
+        };
+        assert!(selected(&["exif"]).contains("<tiff:Model>"));
+        assert!(selected(&["camera_serial"]).contains("<aux:SerialNumber>"));
+        assert!(!selected(&["camera_serial"]).contains("<tiff:Model>"));
+        assert!(!selected(&["gps", "xmp"]).contains("<tiff:Model>"));
+    }
+
+    #[test]
+    fn test_sidecar_replaced_atomically() {
+        let dir = std::env::temp_dir().join(format!("jme-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        fs::copy("images/JAM19896.jpg", &path).unwrap();
+        fs::write(dir.join("copy.json"), "{\"old\": true}").unwrap();
+        // A user's own file with the name a fixed temporary name would have
+        fs::write(dir.join("copy.json.tmp"), "mine").unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--durable", "--backup", path.to_str().unwrap()]);
+        let metadata = extract_metadata(&path, &args, &ExtractorRegistry::new()).unwrap();
+        write_sidecar(&Job::new(path.clone()), &metadata, &args).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("copy.json")).unwrap()).unwrap();
+        let backup = fs::read_to_string(dir.join("copy.json.bak")).unwrap();
+        let user_file = fs::read_to_string(dir.join("copy.json.tmp")).unwrap();
+        let temp_left = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .any(|name| name != "copy.json.tmp" && name.to_string_lossy().ends_with(".tmp"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written["file"]["filename"], "copy.jpg");
+        assert_eq!(backup, "{\"old\": true}");
+        assert_eq!(user_file, "mine");
+        assert!(!temp_left);
+    }
+
+    #[test]
+    fn test_compressed_sidecar() {
+        let dir = std::env::temp_dir().join(format!("jme-gzip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("copy.jpg");
+        fs::copy("images/JAM19896.jpg", &path).unwrap();
+        let registry = ExtractorRegistry::new();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--zstd", path.to_str().unwrap()]);
+        let metadata = extract_metadata(&path, &args, &registry).unwrap();
+        write_sidecar(&Job::new(path.clone()), &metadata, &args).unwrap();
+        let drift = sidecar_drift(&path, &args, &registry).unwrap();
+
+        fs::write(dir.join("copy.json.gz"), Compression::Gzip.compress(b"{\"note\": \"keep\"}").unwrap()).unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--gzip", "--merge-existing", path.to_str().unwrap()]);
+        write_sidecar(&Job::new(path.clone()), &metadata, &args).unwrap();
+        let compressed = fs::read(dir.join("copy.json.gz")).unwrap();
+        let merged = load_metadata_value(&dir.join("copy.json.gz"), &args, &registry).unwrap();
+        let plain_written = dir.join("copy.json").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(drift.is_empty(), "{:?}", drift);
+        assert!(compressed.starts_with(&[0x1F, 0x8B]));
+        assert!(!plain_written);
+        assert_eq!(merged["file"]["filename"], "copy.jpg");
+        assert_eq!(merged["note"], "keep");
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let timeout = Some(std::time::Duration::from_millis(50));
+        let hung = with_timeout(timeout, || {
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            Ok(())
+        });
+        assert!(matches!(hung.unwrap_err().downcast_ref(), Some(Quarantine::TimedOut(_))));
+        let crashed = with_timeout(timeout, || -> Result<()> { panic!("bad marker") });
+        assert_eq!(crashed.unwrap_err().to_string(), "parser crashed: bad marker");
+        assert_eq!(with_timeout(timeout, || Ok(7)).unwrap(), 7);
+
+        let crashed = isolate(|| panic!("bad tag"));
+        assert!(matches!(crashed.unwrap_err().downcast_ref(), Some(Quarantine::Panicked(_))));
+
+        // Work is refused while too many abandoned threads are still running
+        static ABANDONED: AtomicUsize = AtomicUsize::new(0);
+        let hang = || {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            Ok(())
+        };
+        assert!(run_with_timeout(&ABANDONED, 1, timeout, hang).is_err());
+        assert_eq!(ABANDONED.load(Ordering::SeqCst), 1);
+        let refused = run_with_timeout(&ABANDONED, 1, timeout, || Ok(7)).unwrap_err();
+        assert!(refused.to_string().contains("still being parsed"), "{}", refused);
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert_eq!(ABANDONED.load(Ordering::SeqCst), 0);
+        assert_eq!(run_with_timeout(&ABANDONED, 1, timeout, || Ok(7)).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_archive_member() {
+        let dir = std::env::temp_dir().join(format!("jme-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("shoot.tar");
+        let mut tar = tar::Builder::new(File::create(&archive).unwrap());
+        tar.append_path_with_name("images/JAM26284.jpg", "day1/a.jpg").unwrap();
+        tar.into_inner().unwrap();
+
+        let args = Args::parse_from(["jpeg-metadata-extractor", archive.to_str().unwrap()]);
+        let mut records = Vec::new();
+        archives::for_each_member(&archive, None, |_, _, _| true, |member| {
+            records.push(extract_member_metadata(&archive, member, &args, &ExtractorRegistry::new()).unwrap());
+            ControlFlow::Continue(())
+        }).unwrap().continue_value().unwrap();
+        let job = Job::new(archives::member_path(&archive, "day1/a.jpg"));
+        write_sidecar(&job, &records[0], &args).unwrap();
+        let sidecar = fs::read_to_string(dir.join("shoot").join("day1").join("a.json")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(records[0].filename, "day1/a.jpg");
+        assert_eq!(records[0].archive.as_deref(), Some(archive.as_path()));
+        assert_eq!(records[0].camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert!(sidecar.contains("\"filename\": \"day1/a.jpg\""));
+    }
+
+    #[test]
+    fn test_collect_stats() {
+        let stats = collect_stats(&[PathBuf::from("images/JAM19896.jpg"), PathBuf::from("images/JAM26284.jpg")]);
+        assert_eq!(stats.files(), 2);
+        assert_eq!(stats.to_json()["cameras"].as_object().unwrap().values().map(|v| v.as_u64().unwrap()).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn test_overwrite_policy() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "a.jpg"]);
+        assert_eq!(args.overwrite_policy(), OverwritePolicy::Overwrite);
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--backup", "a.jpg"]);
+        assert_eq!(args.overwrite_policy(), OverwritePolicy::Backup);
+        assert!(Args::try_parse_from(["jpeg-metadata-extractor", "--backup", "--no-clobber", "a.jpg"]).is_err());
+        assert_eq!(backup_path(Path::new("images/a.json")), PathBuf::from("images/a.json.bak"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_metadata() {
+        let dir = std::env::temp_dir().join(format!("jme-symlink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("link.jpg");
+        let target = fs::canonicalize("images/JAM19896.jpg").unwrap();
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let meta = extract_filesystem_metadata(&link).unwrap();
+        let original = extract_filesystem_metadata(&target).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(meta.is_symlink);
+        assert_eq!(meta.link_target, Some(target));
+        assert_eq!(meta.size, 3014190);
+        assert_eq!((meta.device, meta.inode), (original.device, original.inode));
+        assert!(!original.is_symlink);
+    }
+
+    #[test]
+    fn test_created_time_source() {
+        let path = Path::new("images/JAM26284.jpg");
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--created-fallback", "omit", "images/JAM26284.jpg"]);
+        let metadata = extract_metadata(path, &args, &ExtractorRegistry::new()).unwrap();
+        let fs_metadata = extract_filesystem_metadata(path).unwrap();
+        assert_eq!(metadata.created_time, fs_metadata.created_time);
+        match fs_metadata.created_time {
+            Some(_) => assert_eq!(metadata.timestamp_source.created_time, Some(filesystem::TimestampSource::BirthTime)),
+            None => assert_eq!(metadata.timestamp_source.created_time, None),
+        }
+    }
+
+    #[test]
+    fn test_process_file_with_overrides() {
+        let dir = std::env::temp_dir().join(format!("jme-job-{}", std::process::id()));
+        let job = Job {
+            path: PathBuf::from("images/JAM19896.jpg"),
+            output: Some(dir.join("nested/out.json")),
+            fields: Some(vec!["filename".to_string(), "relative_path".to_string(), "width".to_string(), "xmp".to_string()]),
+            format: None,
+            root: Some(PathBuf::from("images")),
+        };
+        let args = Args::parse_from(["jpeg-metadata-extractor", "images/JAM19896.jpg"]);
+        process_file(&job, &args, &ExtractorRegistry::new(), &mut SidecarSink::new(&args), &mut FileTimer::start(), &mut Vec::new(), None).unwrap();
+
+        let json = fs::read_to_string(dir.join("nested/out.json")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value, serde_json::json!({"file": {"filename": "JAM19896.jpg", "relative_path": "JAM19896.jpg"}, "image": {"width": 5040}}));
+    }
+
+    #[test]
+    fn test_extract_metadata_mmap_matches_buffered() {
+        let path = Path::new("images/JAM19896.jpg");
+        let buffered = Args::parse_from(["jpeg-metadata-extractor", "--no-cache", "images/JAM19896.jpg"]);
+        let mapped = Args::parse_from(["jpeg-metadata-extractor", "--mmap", "--no-cache", "images/JAM19896.jpg"]);
+        let registry = ExtractorRegistry::new();
+        assert_eq!(
+            serde_json::to_value(extract_metadata(path, &buffered, &registry).unwrap()).unwrap(),
+            serde_json::to_value(extract_metadata(path, &mapped, &registry).unwrap()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_sort_rows() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "images"]);
+        let registry = ExtractorRegistry::new();
+        let extract = |name: &str| extract_metadata(&Path::new("images").join(name), &args, &registry).unwrap();
+        let mut rows = vec![extract("JAM26284.jpg"), extract("JAM19896.jpg")];
+        rows[0].capture_time = None;
+
+        sort_rows(&mut rows, SortBy::Input);
+        assert_eq!(rows[0].filename, "JAM26284.jpg");
+        sort_rows(&mut rows, SortBy::Size);
+        assert_eq!(rows[0].filename, "JAM26284.jpg");
+        sort_rows(&mut rows, SortBy::CaptureTime);
+        assert_eq!(rows[0].filename, "JAM19896.jpg");
+        sort_rows(&mut rows, SortBy::Name);
+        assert_eq!(rows[0].filename, "JAM19896.jpg");
+    }
+
+    #[test]
+    fn test_parse_size_and_time_bound() {
+        assert_eq!(parse_size("500000"), Ok(500_000));
+        assert_eq!(parse_size("200K"), Ok(200 * 1024));
+        assert_eq!(parse_size("1.5m"), Ok(1024 * 1536));
+        assert!(parse_size("big").is_err());
+
+        assert_eq!(parse_time_bound("2024-05-10"), Ok(Utc.with_ymd_and_hms(2024, 5, 10, 0, 0, 0).unwrap()));
+        assert_eq!(parse_time_bound("2024-05-10T12:00:00+02:00"), Ok(Utc.with_ymd_and_hms(2024, 5, 10, 10, 0, 0).unwrap()));
+        assert!(parse_time_bound("last week").is_err());
+
+        assert_eq!(parse_threshold("2s"), Ok(std::time::Duration::from_secs(2)));
+        assert_eq!(parse_threshold("1m500ms"), Ok(std::time::Duration::from_millis(60_500)));
+        assert_eq!(parse_threshold("0.25"), Ok(std::time::Duration::from_millis(250)));
+        assert!(parse_threshold("-2s").is_err() && parse_threshold("0ms").is_err());
+    }
+
+    #[test]
+    fn test_parse_tag_id() {
+        assert_eq!(parse_tag_id("0x9286"), Ok(0x9286));
+        assert_eq!(parse_tag_id("0XA431"), Ok(0xA431));
+        assert_eq!(parse_tag_id("274"), Ok(274));
+        assert!(parse_tag_id("0x10000").is_err());
+    }
+
+    #[test]
+    fn test_environment_options() {
+        // The variables are set only for a child running this test again, since
+        // setting them here would race with the other tests reading the environment
+        if std::env::var_os("JME_TEST_CHILD").is_none() {
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "tests::test_environment_options", "--quiet"])
+                .env("JME_TEST_CHILD", "1")
+                .env("JME_REPORT", "from-env.json")
+                .env("JME_EXEC_JOBS", "3")
+                .stdout(std::process::Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+        let from_env = Args::parse_from(["jpeg-metadata-extractor", "images/JAM26284.jpg"]);
+        let from_flag = Args::parse_from(["jpeg-metadata-extractor", "--report", "flag.json", "images/JAM26284.jpg"]);
+        assert_eq!(from_env.report, Some(PathBuf::from("from-env.json")));
+        assert_eq!(from_env.exec_jobs, Some(3));
+        assert_eq!(from_flag.report, Some(PathBuf::from("flag.json")));
+    }
+
+    #[test]
+    fn test_process_file() {
+        let dir = std::env::temp_dir().join(format!("jme-process-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("JAM26284.jpg");
+        fs::copy("images/JAM26284.jpg", &path).unwrap();
+        let args = Args::parse_from(["jpeg-metadata-extractor", path.to_str().unwrap()]);
         // Should not panic or error
-        assert!(process_file(&path).is_ok());
+        let result = process_file(&Job::new(path.clone()), &args, &ExtractorRegistry::new(), &mut SidecarSink::new(&args), &mut FileTimer::start(), &mut Vec::new(), None);
         // Optionally, check that the output JSON file was created
-        let json_path = path.with_extension("json");
-        assert!(json_path.exists());
+        let json_written = path.with_extension("json").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok());
+        assert!(json_written);
     }
 } 
\ No newline at end of file
@@ -0,0 +1,286 @@
+use exif::{Exif, In, Tag, Value};
+
+/// Nikon type 3 maker notes embed their own TIFF header after this
+const NIKON_SIGNATURE: &[u8] = b"Nikon\0";
+/// Sony maker notes are a bare IFD after either of these, with offsets relative
+/// to the EXIF data as the camera wrote it
+const SONY_SIGNATURES: [&[u8]; 2] = [b"SONY DSC \0\0\0", b"SONY CAM \0\0\0"];
+/// Older Pentax maker notes: byte order at 4, IFD at 6
+const PENTAX_AOC_SIGNATURE: &[u8] = b"AOC\0";
+/// Newer Pentax maker notes: byte order at 8, IFD at 10, offsets relative to the note
+const PENTAX_SIGNATURE: &[u8] = b"PENTAX \0";
+
+const CANON_FIRMWARE_VERSION: u16 = 0x0007;
+const NIKON_SHUTTER_COUNT: u16 = 0x00A7;
+const SONY_TAG_9050: u16 = 0x9050;
+/// Position of the 24-bit shutter count in Sony's deciphered 0x9050 block
+const SONY_SHUTTER_COUNT_OFFSET: usize = 0x3A;
+const PENTAX_DATE: u16 = 0x0006;
+const PENTAX_TIME: u16 = 0x0007;
+const PENTAX_SHUTTER_COUNT: u16 = 0x005D;
+
+/// Shutter actuations from a Nikon, Sony or Pentax maker note
+pub fn shutter_count(exif: &Exif) -> Option<u32> {
+    let field = exif.get_field(Tag::MakerNote, In::PRIMARY)?;
+    let Value::Undefined(note, offset) = &field.value else {
+        return None;
+    };
+    from_maker_note(note, *offset as usize, exif.buf(), exif.little_endian())
+}
+
+/// Firmware version from a Canon maker note, e.g. `1.0.1`
+pub fn firmware(exif: &Exif) -> Option<String> {
+    let make = exif.get_field(Tag::Make, In::PRIMARY)?;
+    if !matches!(&make.value, Value::Ascii(values) if values.first().is_some_and(|make| make.starts_with(b"Canon"))) {
+        return None;
+    }
+    let field = exif.get_field(Tag::MakerNote, In::PRIMARY)?;
+    let Value::Undefined(_, offset) = &field.value else {
+        return None;
+    };
+    canon_firmware(exif.buf(), *offset as usize, exif.little_endian())
+}
+
+/// Canon maker notes are a bare IFD at `offset` in the EXIF TIFF data `tiff`,
+/// sharing its byte order, with value offsets relative to the TIFF data
+fn canon_firmware(tiff: &[u8], offset: usize, little_endian: bool) -> Option<String> {
+    let ifd = Ifd { buf: tiff, start: offset, base: 0, little_endian };
+    firmware_version(&String::from_utf8_lossy(ifd.value(CANON_FIRMWARE_VERSION)?))
+}
+
+/// A firmware version without the `Firmware Version` Canon writes before it
+pub fn firmware_version(text: &str) -> Option<String> {
+    let text = text.trim_end_matches('\0').trim();
+    let version = text.strip_prefix("Firmware Version").unwrap_or(text).trim();
+    Some(version.to_string()).filter(|v| !v.is_empty())
+}
+
+/// The firmware version in a Software tag as cameras without a Canon-style maker
+/// note write it: `Ver.1.10` (Nikon), `ILCE-7M3 v3.01` (Sony), `Digital Camera
+/// X-T3 Ver4.10` (Fujifilm) or `Version 1.00` (Pentax, Olympus). Editors that
+/// rewrite the tag give their own name and a bare version, which is not matched.
+pub fn software_firmware(software: &str) -> Option<String> {
+    let words: Vec<&str> = software.trim_end_matches('\0').split_whitespace().collect();
+    let (last, before) = words.split_last()?;
+    let lower = last.to_ascii_lowercase();
+    let version = match before.last() {
+        Some(word) if word.eq_ignore_ascii_case("version") || word.eq_ignore_ascii_case("ver.") => last,
+        _ => {
+            let digits = lower.strip_prefix("ver.").or_else(|| lower.strip_prefix("ver")).or_else(|| lower.strip_prefix('v'))?;
+            &last[last.len() - digits.len()..]
+        }
+    };
+    let is_version = version.split('.').count() >= 2
+        && version.split('.').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    is_version.then(|| version.to_string())
+}
+
+/// Decode the shutter count from maker note bytes found at `offset` in the
+/// EXIF TIFF data `tiff`, whose byte order Sony notes share
+fn from_maker_note(note: &[u8], offset: usize, tiff: &[u8], little_endian: bool) -> Option<u32> {
+    if let Some(header) = note.strip_prefix(NIKON_SIGNATURE) {
+        // Version (4 bytes) then a TIFF header that note offsets are relative to
+        let tiff = header.get(4..)?;
+        let little_endian = byte_order(tiff)?;
+        let start = read_u32(tiff, 4, little_endian)? as usize;
+        let ifd = Ifd { buf: tiff, start, base: 0, little_endian };
+        return ifd.u32(NIKON_SHUTTER_COUNT);
+    }
+    if let Some(header) = SONY_SIGNATURES.iter().find(|s| note.starts_with(s)) {
+        let mut ifd = Ifd { buf: tiff, start: offset + header.len(), base: 0, little_endian };
+        // Editors that move the note leave its offsets pointing where it was. The
+        // first value stored outside the IFD normally follows it directly, so a
+        // first value outside the note gives how far it moved.
+        let (end, note_end) = (ifd.end()?, offset + note.len());
+        if let Some(first) = ifd.first_value_offset().filter(|first| !(end..note_end).contains(first)) {
+            ifd.base = end as isize - first as isize;
+        }
+        let block: Vec<u8> = ifd.value(SONY_TAG_9050)?.iter().map(|&b| sony_decipher(b)).collect();
+        return read_u32(&block, SONY_SHUTTER_COUNT_OFFSET, true).map(|count| count & 0x00FF_FFFF);
+    }
+    let ifd = if note.starts_with(PENTAX_AOC_SIGNATURE) {
+        Ifd { buf: note, start: 6, base: 0, little_endian: byte_order(&note[4..]).unwrap_or(false) }
+    } else if note.starts_with(PENTAX_SIGNATURE) {
+        Ifd { buf: note, start: 10, base: 0, little_endian: byte_order(note.get(8..)?)? }
+    } else {
+        return None;
+    };
+    let count = ifd.u32(PENTAX_SHUTTER_COUNT)?;
+    // The count is stored XORed with the capture date and the complement of the time
+    let date = ifd.value(PENTAX_DATE).and_then(|v| read_u32(v, 0, false))?;
+    let time = ifd.value(PENTAX_TIME).filter(|v| v.len() >= 3)?;
+    let time = u32::from_be_bytes([time[0], time[1], time[2], 0]);
+    Some(count ^ date ^ !time)
+}
+
+/// Little-endian for "II", big-endian for "MM"
+fn byte_order(bytes: &[u8]) -> Option<bool> {
+    match bytes.get(..2)? {
+        b"II" => Some(true),
+        b"MM" => Some(false),
+        _ => None,
+    }
+}
+
+fn read_u16(buf: &[u8], pos: usize, little_endian: bool) -> Option<u16> {
+    let bytes = buf.get(pos..pos + 2)?.try_into().ok()?;
+    Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+}
+
+fn read_u32(buf: &[u8], pos: usize, little_endian: bool) -> Option<u32> {
+    let bytes = buf.get(pos..pos + 4)?.try_into().ok()?;
+    Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+}
+
+/// Sony enciphers some maker note blocks by cubing each byte modulo 249
+fn sony_decipher(byte: u8) -> u8 {
+    if byte >= 249 {
+        return byte;
+    }
+    (0..249u32).find(|&b| b * b * b % 249 == byte as u32).unwrap_or(byte as u32) as u8
+}
+
+/// A TIFF-style IFD at `start` in `buf`, whose value offsets are relative to `base`
+struct Ifd<'a> {
+    buf: &'a [u8],
+    start: usize,
+    base: isize,
+    little_endian: bool,
+}
+
+impl<'a> Ifd<'a> {
+    /// Positions of the 12-byte entries
+    fn entries(&self) -> Option<impl Iterator<Item = usize>> {
+        let count = read_u16(self.buf, self.start, self.little_endian)? as usize;
+        let start = self.start;
+        Some((0..count).map(move |i| start + 2 + i * 12))
+    }
+
+    /// Position just past the entries and the next IFD offset
+    fn end(&self) -> Option<usize> {
+        Some(self.start + 2 + read_u16(self.buf, self.start, self.little_endian)? as usize * 12 + 4)
+    }
+
+    /// Byte length of the value of the entry at `entry`
+    fn value_len(&self, entry: usize) -> Option<usize> {
+        let unit: usize = match read_u16(self.buf, entry + 2, self.little_endian)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        unit.checked_mul(read_u32(self.buf, entry + 4, self.little_endian)? as usize)
+    }
+
+    /// The smallest offset of a value stored outside the IFD, before `base` is applied
+    fn first_value_offset(&self) -> Option<usize> {
+        self.entries()?
+            .filter(|&entry| self.value_len(entry).is_some_and(|len| len > 4))
+            .filter_map(|entry| read_u32(self.buf, entry + 8, self.little_endian))
+            .map(|offset| offset as usize)
+            .min()
+    }
+
+    /// Raw bytes of the value of `tag`
+    fn value(&self, tag: u16) -> Option<&'a [u8]> {
+        let entry = self.entries()?.find(|&entry| read_u16(self.buf, entry, self.little_endian) == Some(tag))?;
+        let len = self.value_len(entry)?;
+        let pos = if len <= 4 {
+            entry + 8
+        } else {
+            let offset = read_u32(self.buf, entry + 8, self.little_endian)? as usize;
+            usize::try_from(self.base.checked_add_unsigned(offset)?).ok()?
+        };
+        self.buf.get(pos..pos.checked_add(len)?)
+    }
+
+    fn u32(&self, tag: u16) -> Option<u32> {
+        self.value(tag).and_then(|v| read_u32(v, 0, self.little_endian))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A big-endian IFD of (tag, type, count, inline value) entries
+    fn ifd(entries: &[(u16, u16, u32, [u8; 4])]) -> Vec<u8> {
+        let mut out = (entries.len() as u16).to_be_bytes().to_vec();
+        for (tag, kind, count, value) in entries {
+            out.extend(tag.to_be_bytes());
+            out.extend(kind.to_be_bytes());
+            out.extend(count.to_be_bytes());
+            out.extend(value);
+        }
+        out
+    }
+
+    #[test]
+    fn test_nikon_and_pentax() {
+        let mut nikon = b"Nikon\0\x02\x10\0\0MM\0\x2a\0\0\0\x08".to_vec();
+        nikon.extend(ifd(&[(NIKON_SHUTTER_COUNT, 4, 1, 12345u32.to_be_bytes())]));
+        assert_eq!(from_maker_note(&nikon, 0, &[], false), Some(12345));
+
+        let (date, time) = ([0x07, 0xE8, 0x05, 0x0A], [10, 30, 15, 0]);
+        let stored = 4321 ^ u32::from_be_bytes(date) ^ !u32::from_be_bytes(time);
+        let mut pentax = b"AOC\0MM".to_vec();
+        pentax.extend(ifd(&[
+            (PENTAX_DATE, 7, 4, date),
+            (PENTAX_TIME, 7, 3, time),
+            (PENTAX_SHUTTER_COUNT, 4, 1, stored.to_be_bytes()),
+        ]));
+        assert_eq!(from_maker_note(&pentax, 0, &[], false), Some(4321));
+        assert_eq!(from_maker_note(b"Canon", 0, &[], false), None);
+    }
+
+    #[test]
+    fn test_canon_firmware() {
+        let version = b"Firmware Version 1.0.4\0\0";
+        let mut tiff = vec![0u8; 8];
+        let note_at = tiff.len();
+        tiff.extend(ifd(&[(CANON_FIRMWARE_VERSION, 2, version.len() as u32, (note_at as u32 + 14).to_be_bytes())]));
+        tiff.extend(version);
+        assert_eq!(canon_firmware(&tiff, note_at, false).as_deref(), Some("1.0.4"));
+        assert_eq!(firmware_version(" Ver.1.10 ").as_deref(), Some("Ver.1.10"));
+        assert_eq!(firmware_version("Firmware Version\0"), None);
+    }
+
+    #[test]
+    fn test_software_firmware() {
+        assert_eq!(software_firmware("Ver.1.10 ").as_deref(), Some("1.10"));
+        assert_eq!(software_firmware("ILCE-7M3 v3.01").as_deref(), Some("3.01"));
+        assert_eq!(software_firmware("Digital Camera X-T3 Ver4.10").as_deref(), Some("4.10"));
+        assert_eq!(software_firmware("Version 1.00\0").as_deref(), Some("1.00"));
+        assert_eq!(software_firmware("Adobe Photoshop Lightroom Classic 9.0 (Windows)"), None);
+        assert_eq!(software_firmware("GIMP 2.10.22"), None);
+        assert_eq!(software_firmware("darktable 4.6"), None);
+        assert_eq!(software_firmware("Ver.beta"), None);
+    }
+
+    #[test]
+    fn test_sony() {
+        let encipher = |b: u8| if b >= 249 { b } else { ((b as u32).pow(3) % 249) as u8 };
+        let mut block = vec![0u8; 0x40];
+        block[SONY_SHUTTER_COUNT_OFFSET..SONY_SHUTTER_COUNT_OFFSET + 4].copy_from_slice(&[0x39, 0x30, 0x00, 0xAA]);
+        let block: Vec<u8> = block.into_iter().map(encipher).collect();
+
+        // Maker note at offset 8 of the TIFF data, its 0x9050 block right after the IFD
+        let mut tiff = vec![0u8; 8];
+        tiff.extend(b"SONY DSC \0\0\0");
+        let block_offset = (tiff.len() + 2 + 12 + 4) as u32;
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(SONY_TAG_9050.to_le_bytes());
+        tiff.extend(7u16.to_le_bytes());
+        tiff.extend((block.len() as u32).to_le_bytes());
+        tiff.extend(block_offset.to_le_bytes());
+        tiff.extend([0; 4]);
+        tiff.extend(block);
+        assert_eq!(from_maker_note(&tiff[8..], 8, &tiff, true), Some(12345));
+
+        // The same note moved 6 bytes further into the EXIF data by an editor,
+        // its offsets left unchanged
+        let moved = [&tiff[..8], &[0; 6], &tiff[8..]].concat();
+        assert_eq!(from_maker_note(&moved[14..], 14, &moved, true), Some(12345));
+    }
+}
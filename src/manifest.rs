@@ -0,0 +1,101 @@
+use crate::OutputFormat;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A file to process, with optional per-file overrides from a manifest
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Job {
+    pub path: PathBuf,
+    /// Sidecar path to write instead of `<path>.json`
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// Fields to keep in the output, by flat field name or section name; all fields when absent
+    #[serde(default)]
+    pub fields: Option<Vec<String>>,
+    #[serde(default)]
+    pub format: Option<OutputFormat>,
+    /// Directory the input was found under, which `relative_path` is reported from
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+}
+
+impl Job {
+    pub fn new(path: PathBuf) -> Self {
+        Job { path, ..Default::default() }
+    }
+}
+
+/// One row of a CSV manifest; `fields` is a `;`-separated list
+#[derive(Deserialize)]
+struct CsvRow {
+    path: PathBuf,
+    #[serde(default)]
+    output: Option<PathBuf>,
+    #[serde(default)]
+    fields: Option<String>,
+    #[serde(default)]
+    format: Option<OutputFormat>,
+}
+
+impl From<CsvRow> for Job {
+    fn from(row: CsvRow) -> Self {
+        let fields = row.fields
+            .map(|f| f.split(';').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect());
+        Job { path: row.path, output: row.output, fields, format: row.format, root: None }
+    }
+}
+
+/// Read jobs from a JSON array or CSV file (chosen by extension).
+///
+/// Relative paths are resolved against the manifest's directory.
+pub fn read_manifest(path: &Path) -> Result<Vec<Job>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+    let mut jobs: Vec<Job> = match extension.as_str() {
+        "json" => serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))?,
+        "csv" => csv::Reader::from_reader(text.as_bytes())
+            .deserialize::<CsvRow>()
+            .map(|row| row.map(Job::from))
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))?,
+        _ => bail!("Manifest {} must have a .json or .csv extension", path.display()),
+    };
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    for job in &mut jobs {
+        job.path = base.join(&job.path);
+        job.output = job.output.as_ref().map(|output| base.join(output));
+    }
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_csv_and_json_manifests() {
+        let dir = std::env::temp_dir().join(format!("jme-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let csv_path = dir.join("jobs.csv");
+        fs::write(&csv_path, "path,output,fields,format\na.jpg,out/a.json,filename;size,\nb.jpg,,,table\n").unwrap();
+        let jobs = read_manifest(&csv_path).unwrap();
+        assert_eq!(jobs[0].path, dir.join("a.jpg"));
+        assert_eq!(jobs[0].output, Some(dir.join("out/a.json")));
+        assert_eq!(jobs[0].fields, Some(vec!["filename".to_string(), "size".to_string()]));
+        assert_eq!(jobs[1], Job { path: dir.join("b.jpg"), format: Some(OutputFormat::Table), ..Default::default() });
+
+        let json_path = dir.join("jobs.json");
+        fs::write(&json_path, r#"[{"path": "/abs/c.jpg", "fields": ["camera_model"]}]"#).unwrap();
+        let jobs = read_manifest(&json_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(jobs[0].path, PathBuf::from("/abs/c.jpg"));
+        assert_eq!(jobs[0].fields, Some(vec!["camera_model".to_string()]));
+    }
+}
@@ -0,0 +1,173 @@
+use exif::{Context, Exif, In, Tag, Value};
+use roxmltree::{Document, Node, ParsingOptions};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Ratings, tags and comments set in the file manager rather than by the camera
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OsMetadata {
+    /// Stars from 0 to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl OsMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == OsMetadata::default()
+    }
+}
+
+/// Windows-only tags that Explorer writes for the Title, Comments, Authors and
+/// Tags properties, as NUL-terminated UTF-16LE bytes
+const XP_TITLE: u16 = 0x9C9B;
+const XP_COMMENT: u16 = 0x9C9C;
+const XP_AUTHOR: u16 = 0x9C9D;
+const XP_KEYWORDS: u16 = 0x9C9E;
+/// Star rating, which Explorer also writes to XMP
+const RATING: u16 = 0x4746;
+/// Every tag [`windows_properties`] reads
+pub const WINDOWS_TAGS: [u16; 5] = [RATING, XP_TITLE, XP_COMMENT, XP_AUTHOR, XP_KEYWORDS];
+
+/// Properties set in Windows Explorer. The Windows Property System stores
+/// them in the JPEG itself, so they can be read on any platform.
+pub fn windows_properties(exif: &Exif) -> Option<OsMetadata> {
+    let text = |id| match &exif.get_field(Tag(Context::Tiff, id), In::PRIMARY)?.value {
+        Value::Byte(bytes) => Some(decode_utf16le(bytes)).filter(|text| !text.is_empty()),
+        _ => None,
+    };
+    let list = |id| text(id).map(|text| split_list(&text)).unwrap_or_default();
+    let metadata = OsMetadata {
+        rating: exif.get_field(Tag(Context::Tiff, RATING), In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .map(|stars| stars.min(5) as u8),
+        title: text(XP_TITLE),
+        comment: text(XP_COMMENT),
+        authors: list(XP_AUTHOR),
+        tags: list(XP_KEYWORDS),
+    };
+    (!metadata.is_empty()).then_some(metadata)
+}
+
+fn decode_utf16le(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    String::from_utf16_lossy(&units).trim_end_matches('\0').trim().to_string()
+}
+
+/// Explorer separates multiple values with semicolons
+fn split_list(text: &str) -> Vec<String> {
+    text.split(';').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+}
+
+/// Spotlight attributes read for [`spotlight`]
+const SPOTLIGHT_ATTRIBUTES: &[&str] = &["kMDItemStarRating", "kMDItemTitle", "kMDItemFinderComment", "kMDItemAuthors", "kMDItemUserTags"];
+
+/// Attributes Spotlight has indexed for the file, including Finder tags and
+/// comments. Files Spotlight has not indexed, e.g. on excluded volumes, have none.
+#[cfg(target_os = "macos")]
+pub fn spotlight(path: &Path) -> Option<OsMetadata> {
+    let mut command = std::process::Command::new("mdls");
+    command.args(["-plist", "-"]);
+    for attribute in SPOTLIGHT_ATTRIBUTES {
+        command.args(["-name", attribute]);
+    }
+    let output = command.arg(path).stderr(std::process::Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_spotlight(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Spotlight only exists on macOS
+#[cfg(not(target_os = "macos"))]
+pub fn spotlight(_path: &Path) -> Option<OsMetadata> {
+    None
+}
+
+/// Read the property list printed by `mdls -plist -`
+pub fn parse_spotlight(plist: &str) -> Option<OsMetadata> {
+    let options = ParsingOptions { allow_dtd: true, ..ParsingOptions::default() };
+    let doc = Document::parse_with_options(plist, options).ok()?;
+    let dict = doc.descendants().find(|node| node.has_tag_name("dict"))?;
+    let mut values = dict.children().filter(Node::is_element);
+    let mut metadata = OsMetadata::default();
+    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+        let text = || value.text().map(str::trim).filter(|text| !text.is_empty()).map(str::to_string);
+        let strings = || value.children()
+            .filter(|node| node.has_tag_name("string"))
+            .filter_map(|node| node.text())
+            .map(str::to_string)
+            .collect();
+        match key.text().filter(|key| SPOTLIGHT_ATTRIBUTES.contains(key)) {
+            Some("kMDItemStarRating") => metadata.rating = text().and_then(|t| t.parse::<u8>().ok()).map(|stars| stars.min(5)),
+            Some("kMDItemTitle") => metadata.title = text(),
+            Some("kMDItemFinderComment") => metadata.comment = text(),
+            Some("kMDItemAuthors") => metadata.authors = strings(),
+            Some("kMDItemUserTags") => metadata.tags = strings(),
+            _ => {}
+        }
+    }
+    (!metadata.is_empty()).then_some(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::Field;
+
+    #[test]
+    fn test_windows_properties() {
+        let utf16 = |text: &str| text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+        let field = |id, value| Field { tag: Tag(Context::Tiff, id), ifd_num: In::PRIMARY, value };
+        let fields = [
+            field(XP_TITLE, Value::Byte(utf16("Harbour"))),
+            field(XP_KEYWORDS, Value::Byte(utf16("boats; sunset;"))),
+            field(RATING, Value::Short(vec![4])),
+        ];
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let tagged = crate::exif_write::rewrite(&bytes, &fields, None).unwrap();
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&tagged)).unwrap();
+        let expected = OsMetadata {
+            rating: Some(4),
+            title: Some("Harbour".to_string()),
+            tags: vec!["boats".to_string(), "sunset".to_string()],
+            ..OsMetadata::default()
+        };
+        assert_eq!(windows_properties(&exif), Some(expected));
+
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(windows_properties(&exif), None);
+    }
+
+    #[test]
+    fn test_parse_spotlight() {
+        let plist = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>kMDItemFinderComment</key>
+	<string>Print for the album</string>
+	<key>kMDItemStarRating</key>
+	<integer>3</integer>
+	<key>kMDItemUserTags</key>
+	<array>
+		<string>Red</string>
+		<string>Family</string>
+	</array>
+</dict>
+</plist>"#;
+        let metadata = parse_spotlight(plist).unwrap();
+        assert_eq!(metadata.comment.as_deref(), Some("Print for the album"));
+        assert_eq!(metadata.rating, Some(3));
+        assert_eq!(metadata.tags, ["Red", "Family"]);
+        assert_eq!(metadata.title, None);
+        assert_eq!(parse_spotlight("<plist version=\"1.0\"><dict/></plist>"), None);
+    }
+}
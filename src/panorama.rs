@@ -0,0 +1,118 @@
+use crate::xmp;
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+
+/// Google Photo Sphere XMP namespace (`GPano:`)
+pub const GPANO_NS: &str = "http://ns.google.com/photos/1.0/panorama/";
+
+/// Photo Sphere properties of a 360° or partial panorama. Angles are in degrees
+/// and sizes in pixels; the cropped area is where this image sits in the full pano.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PanoramaMetadata {
+    /// `equirectangular` for photo spheres, or e.g. `cylindrical`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projection_type: Option<String>,
+    /// Whether the image covers the full 360° by 180° of its panorama
+    pub full_sphere: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_panorama_viewer: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cropped_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cropped_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cropped_left: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cropped_top: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_heading: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_pitch: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_roll: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_view_heading: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_view_pitch: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_view_roll: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_horizontal_fov: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_software: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stitching_software: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_photos_count: Option<u32>,
+}
+
+/// Photo Sphere metadata, if the XMP has any GPano property
+pub fn from_xmp(doc: &Document) -> Option<PanoramaMetadata> {
+    let text = |name| xmp::property(doc, GPANO_NS, name).filter(|v| !v.is_empty());
+    let pixels = |name| text(name).and_then(|v| v.parse::<u32>().ok());
+    let degrees = |name| text(name).and_then(|v| v.parse::<f64>().ok());
+    let mut panorama = PanoramaMetadata {
+        projection_type: text("ProjectionType"),
+        full_sphere: false,
+        use_panorama_viewer: text("UsePanoramaViewer").map(|v| v.eq_ignore_ascii_case("true")),
+        full_width: pixels("FullPanoWidthPixels"),
+        full_height: pixels("FullPanoHeightPixels"),
+        cropped_width: pixels("CroppedAreaImageWidthPixels"),
+        cropped_height: pixels("CroppedAreaImageHeightPixels"),
+        cropped_left: pixels("CroppedAreaLeftPixels"),
+        cropped_top: pixels("CroppedAreaTopPixels"),
+        pose_heading: degrees("PoseHeadingDegrees"),
+        pose_pitch: degrees("PosePitchDegrees"),
+        pose_roll: degrees("PoseRollDegrees"),
+        initial_view_heading: degrees("InitialViewHeadingDegrees"),
+        initial_view_pitch: degrees("InitialViewPitchDegrees"),
+        initial_view_roll: degrees("InitialViewRollDegrees"),
+        initial_horizontal_fov: degrees("InitialHorizontalFOVDegrees"),
+        capture_software: text("CaptureSoftware"),
+        stitching_software: text("StitchingSoftware"),
+        source_photos_count: pixels("SourcePhotosCount"),
+    };
+    if panorama == PanoramaMetadata::default() {
+        return None;
+    }
+    // An equirectangular pano spans 360° across and 180° down; a crop covering all of it is a full sphere
+    panorama.full_sphere = panorama.projection_type.as_deref().is_some_and(|p| p.eq_ignore_ascii_case("equirectangular"))
+        && panorama.full_width.is_some_and(|w| Some(w) == panorama.full_height.map(|h| h * 2))
+        && panorama.cropped_width.or(panorama.full_width) == panorama.full_width
+        && panorama.cropped_height.or(panorama.full_height) == panorama.full_height;
+    Some(panorama)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpano_xmp() {
+        let packet = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:GPano="http://ns.google.com/photos/1.0/panorama/"
+  GPano:ProjectionType="equirectangular" GPano:UsePanoramaViewer="True"
+  GPano:FullPanoWidthPixels="8000" GPano:FullPanoHeightPixels="4000"
+  GPano:CroppedAreaImageWidthPixels="8000" GPano:CroppedAreaImageHeightPixels="4000"
+  GPano:CroppedAreaLeftPixels="0" GPano:CroppedAreaTopPixels="0" GPano:PoseHeadingDegrees="271.5">
+  <GPano:StitchingSoftware>Photo Sphere</GPano:StitchingSoftware>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let panorama = from_xmp(&xmp::parse(packet).unwrap()).unwrap();
+        assert_eq!(panorama.projection_type.as_deref(), Some("equirectangular"));
+        assert!(panorama.full_sphere);
+        assert_eq!(panorama.use_panorama_viewer, Some(true));
+        assert_eq!((panorama.full_width, panorama.full_height), (Some(8000), Some(4000)));
+        assert_eq!(panorama.pose_heading, Some(271.5));
+        assert_eq!(panorama.stitching_software.as_deref(), Some("Photo Sphere"));
+
+        let cropped = packet.replace(r#"CroppedAreaImageHeightPixels="4000""#, r#"CroppedAreaImageHeightPixels="1800""#);
+        assert!(!from_xmp(&xmp::parse(&cropped).unwrap()).unwrap().full_sphere);
+        let plain = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="0"/></rdf:RDF></x:xmpmeta>"#;
+        assert_eq!(from_xmp(&xmp::parse(plain).unwrap()), None);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::error::{ExtractError, Result};
+
+/// An image decoded to 8-bit RGB for pixel analysis
+#[derive(Debug)]
+pub struct Preview {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major RGB triples
+    pub rgb: Vec<u8>,
+}
+
+impl Preview {
+    pub fn pixels(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+        self.rgb.chunks_exact(3).map(|p| [p[0], p[1], p[2]])
+    }
+}
+
+/// Rec. 601 luma of an RGB pixel, 0 to 255
+pub fn luma([r, g, b]: [u8; 3]) -> f64 {
+    0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+}
+
+/// Decode a JPEG to RGB. When `min_size` is non-zero the decoder scales the
+/// inverse DCT by 1/2, 1/4 or 1/8 to the smallest image that is still at least
+/// `min_size` pixels on each side, which is far cheaper than a full decode.
+/// Zero decodes at full resolution.
+#[cfg(feature = "decode")]
+pub fn decode_preview(bytes: &[u8], min_size: u16) -> Result<Preview> {
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    let mut decoder = Decoder::new(bytes);
+    let no_frame = || ExtractError::parse("JPEG header", "no start of frame segment");
+    decoder.read_info()?;
+    if min_size > 0 {
+        let info = decoder.info().ok_or_else(no_frame)?;
+        decoder.scale(min_size.min(info.width), min_size.min(info.height))?;
+    }
+    let data = decoder.decode()?;
+    let info = decoder.info().ok_or_else(no_frame)?;
+
+    let rgb = match info.pixel_format {
+        PixelFormat::RGB24 => data,
+        PixelFormat::L8 => data.iter().flat_map(|&l| [l, l, l]).collect(),
+        // Big-endian 16-bit samples; keep the high byte
+        PixelFormat::L16 => data.chunks_exact(2).flat_map(|l| [l[0], l[0], l[0]]).collect(),
+        // Adobe-style inverted CMYK, as almost all CMYK JPEGs are; an approximation
+        // without colour management, which is plenty for statistics
+        PixelFormat::CMYK32 => data.chunks_exact(4)
+            .flat_map(|p| {
+                let k = p[3] as u16;
+                [0, 1, 2].map(|i| (p[i] as u16 * k / 255) as u8)
+            })
+            .collect(),
+    };
+    Ok(Preview { width: info.width.into(), height: info.height.into(), rgb })
+}
+
+#[cfg(not(feature = "decode"))]
+pub fn decode_preview(_bytes: &[u8], _min_size: u16) -> Result<Preview> {
+    Err(without_decode("pixel analysis"))
+}
+
+/// Encode RGB as a baseline JPEG; `what` names the image in errors
+#[cfg(feature = "decode")]
+pub fn encode(rgb: &[u8], width: u16, height: u16, quality: u8, what: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    jpeg_encoder::Encoder::new(&mut out, quality)
+        .encode(rgb, width, height, jpeg_encoder::ColorType::Rgb)
+        .map_err(|e| ExtractError::Encode(format!("{}: {}", what, e)))?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "decode"))]
+pub fn encode(_rgb: &[u8], _width: u16, _height: u16, _quality: u8, what: &str) -> Result<Vec<u8>> {
+    Err(without_decode(what))
+}
+
+#[cfg(not(feature = "decode"))]
+fn without_decode(what: &str) -> ExtractError {
+    ExtractError::Unsupported(format!("{} in a build without the decode feature", what))
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_preview_scaled() {
+        let bytes = std::fs::read("images/JAM19896.jpg").unwrap();
+        let preview = decode_preview(&bytes, 256).unwrap();
+        // 5040x3360 scaled by 1/8
+        assert_eq!((preview.width, preview.height), (630, 420));
+        assert_eq!(preview.rgb.len(), 630 * 420 * 3);
+    }
+}
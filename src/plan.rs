@@ -0,0 +1,159 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use jpeg_metadata_extractor::gpx::TrackPoint;
+use jpeg_metadata_extractor::transplant::SegmentKind;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// How a change rewrites its file; redone from the file's current contents when applied
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Timeshift { offset_ms: i64, timezone: Option<String> },
+    Geotag { point: TrackPoint },
+    FixThumbnail,
+    CopyMeta { from: PathBuf, fields: Vec<SegmentKind> },
+    /// Remove the GPS fields, writing back a fuzzed position if there is one
+    Redact { position: Option<(f64, f64)> },
+    /// Pseudonymize or remove the identifying fields of the run's --anonymize categories
+    Anonymize,
+}
+
+/// One file a write subcommand would rewrite
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub path: PathBuf,
+    /// What the change does, as shown before asking for confirmation
+    pub summary: String,
+    #[serde(flatten)]
+    pub action: Action,
+    /// SHA-256 of the file when the change was planned
+    pub sha256: String,
+    /// SHA-256 of the file the change copies from, for `copy_meta`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_sha256: Option<String>,
+}
+
+impl Change {
+    /// A change to `path`, held as an absolute path so a saved plan can be
+    /// applied from any directory
+    pub fn new(path: &Path, contents: &[u8], summary: String, action: Action) -> Self {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        Change { path, summary, action, sha256: digest(contents), source_sha256: None }
+    }
+
+    /// Record the contents of the file the change copies from, checked like the file's own
+    pub fn with_source(self, contents: &[u8]) -> Self {
+        Change { source_sha256: Some(digest(contents)), ..self }
+    }
+
+    /// Fail if the file no longer has the contents the change was planned for
+    pub fn check(&self, contents: &[u8]) -> Result<()> {
+        if digest(contents) != self.sha256 {
+            bail!("{} has changed since the plan was made", self.path.display());
+        }
+        Ok(())
+    }
+
+    /// Fail if the file the change copies from no longer has the contents it was planned with
+    pub fn check_source(&self, from: &Path, contents: &[u8]) -> Result<()> {
+        if self.source_sha256.as_ref().is_some_and(|sha256| *sha256 != digest(contents)) {
+            bail!("{} has changed since the plan was made", from.display());
+        }
+        Ok(())
+    }
+}
+
+/// The changes of one run of a write subcommand, to confirm, save or apply
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub created: DateTime<Utc>,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    pub fn new(changes: Vec<Change>) -> Self {
+        Plan { created: Utc::now(), changes }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid plan {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The first `limit` changes, one per line, and how many more there are
+    pub fn preview(&self, limit: usize) -> String {
+        let mut out: String = self.changes.iter()
+            .take(limit)
+            .map(|change| format!("{}: {}\n", change.path.display(), change.summary))
+            .collect();
+        if self.changes.len() > limit {
+            out.push_str(&format!("... and {} more\n", self.changes.len() - limit));
+        }
+        out
+    }
+}
+
+/// Ask before rewriting `count` files. `--yes` answers for the user; without it
+/// the answer must come from a terminal, so scripts cannot modify files by accident.
+pub fn confirm(count: usize, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        bail!("Refusing to modify {} files without --yes when not running interactively", count);
+    }
+    eprint!("Modify {} files? [y/N] ", count);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+fn digest(contents: &[u8]) -> String {
+    Sha256::digest(contents).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_round_trip() {
+        let change = |name: &str, action| Change::new(Path::new(name), b"old", format!("change {}", name), action);
+        let plan = Plan::new(vec![
+            change("a.jpg", Action::Timeshift { offset_ms: -5000, timezone: Some("+02:00".to_string()) }),
+            change("b.jpg", Action::FixThumbnail),
+            change("c.jpg", Action::CopyMeta { from: PathBuf::from("/src.jpg"), fields: vec![SegmentKind::Exif, SegmentKind::Xmp] }).with_source(b"source"),
+            change("d.jpg", Action::Redact { position: Some((51.5, -0.1)) }),
+        ]);
+        let path = std::env::temp_dir().join(format!("jme-plan-{}.json", std::process::id()));
+        plan.write(&path).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let loaded = Plan::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(value["changes"][2]["action"], "copy_meta");
+        assert_eq!(value["changes"][2]["fields"], serde_json::json!(["exif", "xmp"]));
+        assert_eq!(value["changes"][3]["position"], serde_json::json!([51.5, -0.1]));
+        assert_eq!(loaded.changes, plan.changes);
+
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(plan.changes[0].path, cwd.join("a.jpg"));
+        let preview = format!("{}: change a.jpg\n{}: change b.jpg\n... and 2 more\n", cwd.join("a.jpg").display(), cwd.join("b.jpg").display());
+        assert_eq!(plan.preview(2), preview);
+        assert!(plan.changes[0].check(b"old").is_ok());
+        assert!(plan.changes[0].check(b"new").is_err());
+        let source = Path::new("/src.jpg");
+        assert!(plan.changes[2].check_source(source, b"source").is_ok() && plan.changes[2].check_source(source, b"edited").is_err());
+        assert!(plan.changes[0].check_source(source, b"anything").is_ok());
+        assert!(confirm(3, true).unwrap());
+    }
+}
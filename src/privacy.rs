@@ -0,0 +1,183 @@
+use crate::drone::DJI_NS;
+use crate::error::Result;
+use crate::events::distance_km;
+use crate::exif_write;
+use crate::gpx;
+use crate::xmp_write;
+use exif::{Context, Field};
+use std::str::FromStr;
+
+/// Namespace of the XMP copies of the EXIF fields, including `exif:GPSLatitude`
+const EXIF_NS: &str = "http://ns.adobe.com/exif/1.0/";
+
+/// Kilometres per degree of latitude, and of longitude at the equator
+const KM_PER_DEGREE: f64 = 111.32;
+
+/// A circle around a sensitive place, such as home, within which photos
+/// should not reveal where they were taken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrivacyZone {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+impl FromStr for PrivacyZone {
+    type Err = String;
+
+    /// Parse `LAT,LON,RADIUS_KM`, e.g. `51.5074,-0.1278,0.5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<f64> = s.split(',')
+            .map(|part| part.trim().parse::<f64>().map_err(|_| format!("invalid number '{}' in privacy zone", part.trim())))
+            .collect::<Result<_, _>>()?;
+        let &[latitude, longitude, radius_km] = parts.as_slice() else {
+            return Err(format!("expected LAT,LON,RADIUS_KM, got '{}'", s));
+        };
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(format!("coordinates out of range in '{}'", s));
+        }
+        if !(radius_km > 0.0 && radius_km.is_finite()) {
+            return Err(format!("radius must be a positive number of kilometres in '{}'", s));
+        }
+        Ok(PrivacyZone { latitude, longitude, radius_km })
+    }
+}
+
+impl PrivacyZone {
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        distance_km((self.latitude, self.longitude), (latitude, longitude)) <= self.radius_km
+    }
+
+    /// The centre of the grid cell holding the position, with cells twice the
+    /// zone's radius across. Every photo in a cell gets the same coarse position,
+    /// so unlike random noise, many photos cannot be averaged back to the true
+    /// one, and the cell does not give away the zone's centre.
+    pub fn fuzz(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let snap = |value: f64, step: f64| ((value / step).floor() + 0.5) * step;
+        let lat_step = 2.0 * self.radius_km / KM_PER_DEGREE;
+        let fuzzed_lat = snap(latitude, lat_step).clamp(-90.0, 90.0);
+        // Longitude cells narrow towards the poles, so widen them to keep their size
+        let lon_step = (lat_step / fuzzed_lat.to_radians().cos().max(0.01)).min(360.0);
+        let fuzzed_lon = snap(longitude + 180.0, lon_step).rem_euclid(360.0) - 180.0;
+        let round = |value: f64| (value * 1e7).round() / 1e7;
+        (round(fuzzed_lat), round(fuzzed_lon))
+    }
+}
+
+/// How positions inside a privacy zone are hidden
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Redaction {
+    /// Remove the position entirely
+    #[default]
+    Blank,
+    /// Report a coarse position instead, see [`PrivacyZone::fuzz`]
+    Fuzz,
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blank" => Ok(Redaction::Blank),
+            "fuzz" => Ok(Redaction::Fuzz),
+            _ => Err(format!("unknown redaction '{}' (expected blank or fuzz)", s)),
+        }
+    }
+}
+
+/// The first zone containing the position, if any
+pub fn zone_of(zones: &[PrivacyZone], latitude: f64, longitude: f64) -> Option<&PrivacyZone> {
+    zones.iter().find(|zone| zone.contains(latitude, longitude))
+}
+
+/// Where a position inside `zone` is reported instead: `None` when blanked
+pub fn redact(zone: &PrivacyZone, redaction: Redaction, latitude: f64, longitude: f64) -> Option<(f64, f64)> {
+    match redaction {
+        Redaction::Blank => None,
+        Redaction::Fuzz => Some(zone.fuzz(latitude, longitude)),
+    }
+}
+
+/// Edits that apply a redaction to a file's EXIF: every GPS field is dropped,
+/// including altitude and timestamps, and a fuzzed position is written in their place
+pub fn exif_edits(redacted: Option<(f64, f64)>) -> (Vec<Field>, impl Fn(&Field) -> bool) {
+    let fields = redacted
+        .map(|(latitude, longitude)| gpx::position_fields(latitude, longitude, None))
+        .unwrap_or_default();
+    (fields, |f: &Field| f.tag.context() != Context::Gps)
+}
+
+/// A JPEG with a redaction applied: its EXIF as by [`exif_edits`], and the XMP
+/// copies of the position (`exif:GPS*` and the drone-dji properties) removed.
+/// Fails when the file has XMP that cannot be rewritten, rather than leave them behind.
+pub fn rewrite(bytes: &[u8], redacted: Option<(f64, f64)>) -> Result<Vec<u8>> {
+    let (fields, keep) = exif_edits(redacted);
+    let rewritten = exif_write::rewrite_with(bytes, &fields, None, keep)?;
+    xmp_write::remove_jpeg_properties(&rewritten, |namespace, name| {
+        (namespace == EXIF_NS && name.starts_with("GPS")) || namespace == DJI_NS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::{self, Segment, SOI, XMP_SIGNATURE};
+    use crate::xmp;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_zone() {
+        let zone: PrivacyZone = "51.5074, -0.1278, 0.5".parse().unwrap();
+        assert_eq!(zone, PrivacyZone { latitude: 51.5074, longitude: -0.1278, radius_km: 0.5 });
+        assert!("51.5,-0.1".parse::<PrivacyZone>().is_err());
+        assert!("95,0,1".parse::<PrivacyZone>().is_err());
+        assert!("51.5,-0.1,0".parse::<PrivacyZone>().is_err());
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let zone = PrivacyZone { latitude: 51.5074, longitude: -0.1278, radius_km: 1.0 };
+        assert!(zone.contains(51.51, -0.13));
+        assert!(!zone.contains(51.6, -0.13));
+        assert_eq!(zone_of(&[zone], 51.6, -0.13), None);
+
+        // Nearby photos share a cell, and the reported position stays within about a cell of the truth
+        let a = zone.fuzz(51.5074, -0.1278);
+        assert_eq!(a, zone.fuzz(51.5076, -0.1276));
+        assert_ne!(a, (51.5074, -0.1278));
+        assert!(distance_km(a, (51.5074, -0.1278)) < 3.0);
+        assert_eq!(redact(&zone, Redaction::Blank, 51.5074, -0.1278), None);
+        assert_eq!(redact(&zone, Redaction::Fuzz, 51.5074, -0.1278), Some(a));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        // The sample with a GPS position in its XMP packet as well
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let segments = jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap();
+        let mut tagged = vec![0xFF, SOI];
+        for segment in &segments {
+            let mut data = segment.data.clone();
+            if segment.is_app(1, XMP_SIGNATURE) {
+                let packet = String::from_utf8(data.split_off(XMP_SIGNATURE.len())).unwrap().replacen("xmlns:dc=",
+                    r#"xmlns:exif="http://ns.adobe.com/exif/1.0/" exif:GPSLatitude="51,30.444N" exif:GPSLongitude="0,7.668W" xmlns:dc="#, 1);
+                data.extend(packet.as_bytes());
+            }
+            jpeg::push_segment(&mut tagged, &Segment { marker: segment.marker, data, offset: None }).unwrap();
+        }
+        tagged.extend(&bytes[jpeg::header_len(&segments) as usize..]);
+        assert!(tagged.windows(8).any(|w| w == b"30.444N\"".as_slice()));
+
+        let rewritten = rewrite(&tagged, Some((51.5, -0.13))).unwrap();
+        let segments = jpeg::read_segments(&mut Cursor::new(&rewritten)).unwrap();
+        let packet = xmp::packet(&segments).unwrap();
+        let doc = xmp::parse(&packet).unwrap();
+        assert_eq!(xmp::property(&doc, EXIF_NS, "GPSLatitude"), None);
+        assert_eq!(xmp::property(&doc, EXIF_NS, "GPSLongitude"), None);
+        assert!(xmp::property(&doc, xmp::AUX_NS, "SerialNumber").is_some());
+        let exif = exif::Reader::new().read_from_container(&mut Cursor::new(&rewritten)).unwrap();
+        assert!(exif.get_field(exif::Tag::GPSLatitude, exif::In::PRIMARY).is_some());
+        assert!(!rewritten.windows(8).any(|w| w == b"30.444N\"".as_slice()));
+    }
+}
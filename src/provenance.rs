@@ -0,0 +1,180 @@
+use exif::{Context, In, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// IFD pointer tags followed when locating values
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const INTEROP_IFD_POINTER: u16 = 0xA005;
+
+/// Where an output field's value was read from. Offsets are in bytes from the
+/// start of the file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum Source {
+    /// An EXIF tag; `offset` is where its value is stored
+    Exif {
+        tag: String,
+        /// Numeric tag ID, e.g. `0x0110`
+        id: String,
+        /// `IFD0`, `IFD1`, `ExifIFD`, `GPS` or `InteropIFD`
+        ifd: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+    },
+    /// An XMP property, as `prefix:Name`
+    Xmp { property: String },
+    /// A JPEG marker segment; `offset` is where its marker starts
+    Jpeg { segment: String, offset: u64 },
+    /// The file's directory entry or inode, or the archive entry holding it
+    Filesystem,
+    /// The file name
+    Filename,
+    /// Computed from other values
+    Derived {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        from: Vec<Source>,
+    },
+}
+
+impl Source {
+    pub fn xmp(property: &str) -> Self {
+        Source::Xmp { property: property.to_string() }
+    }
+
+    pub fn derived(from: impl IntoIterator<Item = Source>) -> Self {
+        Source::Derived { from: from.into_iter().collect() }
+    }
+}
+
+/// File offsets of every value in a TIFF-structured EXIF block, for [`Source::Exif`]
+#[derive(Debug, Default)]
+pub struct ExifLocations {
+    /// Keyed by IFD context, IFD number (0 primary, 1 thumbnail) and tag number
+    values: HashMap<(Context, u16, u16), u64>,
+}
+
+impl ExifLocations {
+    /// Walk the IFDs of `tiff`, which starts at `base` in the file
+    pub fn parse(tiff: &[u8], base: u64) -> Self {
+        let mut locations = ExifLocations::default();
+        let Some(little_endian) = (match tiff.get(..2) {
+            Some(b"II") => Some(true),
+            Some(b"MM") => Some(false),
+            _ => None,
+        }) else {
+            return locations;
+        };
+        let mut walker = Walker { tiff, base, little_endian, values: &mut locations.values, visited: 0 };
+        if let Some(ifd0) = walker.u32(4) {
+            if let Some(ifd1) = walker.walk(ifd0 as usize, Context::Tiff, 0) {
+                walker.walk(ifd1, Context::Tiff, 1);
+            }
+        }
+        locations
+    }
+
+    /// The source of a tag read from `ifd`
+    pub fn source(&self, tag: Tag, ifd: In) -> Source {
+        let ifd_name = match (tag.context(), ifd.index()) {
+            (Context::Tiff, 0) => "IFD0",
+            (Context::Tiff, _) => "IFD1",
+            (Context::Exif, _) => "ExifIFD",
+            (Context::Gps, _) => "GPS",
+            (Context::Interop, _) => "InteropIFD",
+            _ => "unknown",
+        };
+        let name = tag.to_string();
+        Source::Exif {
+            // Unknown tags display as "Tag(Exif, 37393)"
+            tag: if name.starts_with("Tag(") { format!("0x{:04X}", tag.number()) } else { name },
+            id: format!("0x{:04X}", tag.number()),
+            ifd: ifd_name.to_string(),
+            offset: self.values.get(&(tag.context(), ifd.index(), tag.number())).copied(),
+        }
+    }
+}
+
+struct Walker<'a> {
+    tiff: &'a [u8],
+    base: u64,
+    little_endian: bool,
+    values: &'a mut HashMap<(Context, u16, u16), u64>,
+    /// IFDs walked so far, to stop on pointer loops
+    visited: usize,
+}
+
+impl Walker<'_> {
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.tiff.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = self.tiff.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    /// Record the value offsets of one IFD and the IFDs it points to, returning
+    /// the offset of the next IFD in the chain
+    fn walk(&mut self, start: usize, context: Context, index: u16) -> Option<usize> {
+        self.visited += 1;
+        if start == 0 || self.visited > 8 {
+            return None;
+        }
+        let count = self.u16(start)? as usize;
+        for entry in (0..count).map(|i| start + 2 + i * 12) {
+            let (Some(tag), Some(kind), Some(n)) = (self.u16(entry), self.u16(entry + 2), self.u32(entry + 4)) else {
+                break;
+            };
+            let unit: u64 = match kind {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue,
+            };
+            let value = if unit * u64::from(n) <= 4 {
+                entry as u64 + 8
+            } else {
+                let Some(pointer) = self.u32(entry + 8) else { continue };
+                u64::from(pointer)
+            };
+            self.values.insert((context, index, tag), self.base + value);
+            let child = match (context, tag) {
+                (Context::Tiff, EXIF_IFD_POINTER) => Some(Context::Exif),
+                (Context::Tiff, GPS_IFD_POINTER) => Some(Context::Gps),
+                (Context::Exif, INTEROP_IFD_POINTER) => Some(Context::Interop),
+                _ => None,
+            };
+            if let (Some(child), Some(pointer)) = (child, self.u32(entry + 8)) {
+                self.walk(pointer as usize, child, index);
+            }
+        }
+        self.u32(start + 2 + count * 12).map(|next| next as usize).filter(|&next| next != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exif_locations() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let segments = crate::jpeg::read_segments(&mut bytes.as_slice()).unwrap();
+        let index = segments.iter().position(|s| s.is_app(1, crate::jpeg::EXIF_SIGNATURE)).unwrap();
+        let tiff_start = crate::jpeg::segment_offset(&segments, index) + 4 + crate::jpeg::EXIF_SIGNATURE.len() as u64;
+        let tiff = &segments[index].data[crate::jpeg::EXIF_SIGNATURE.len()..];
+        let locations = ExifLocations::parse(tiff, tiff_start);
+
+        let Source::Exif { tag, ifd, offset, .. } = locations.source(Tag::Model, In::PRIMARY) else { unreachable!() };
+        assert_eq!((tag.as_str(), ifd.as_str()), ("Model", "IFD0"));
+        let offset = offset.unwrap() as usize;
+        assert_eq!(&bytes[offset..offset + 20], b"Canon EOS 5D Mark IV");
+
+        let Source::Exif { ifd, offset, .. } = locations.source(Tag::BodySerialNumber, In::PRIMARY) else { unreachable!() };
+        assert_eq!(ifd, "ExifIFD");
+        assert_eq!(&bytes[offset.unwrap() as usize..][..12], b"025021000535");
+    }
+}
@@ -0,0 +1,60 @@
+//! Python bindings, built as the `jpeg_metadata_extractor` extension module.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyList, PyString};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// extract(path_or_bytes) -> dict
+///
+/// Extract metadata from a file path (str or os.PathLike) or an in-memory
+/// bytes object.
+#[pyfunction]
+fn extract<'py>(py: Python<'py>, path_or_bytes: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    let bytes = if let Ok(bytes) = path_or_bytes.downcast::<PyBytes>() {
+        bytes.as_bytes().to_vec()
+    } else {
+        let path: PathBuf = path_or_bytes.extract()?;
+        std::fs::read(&path)?
+    };
+
+    let metadata = py
+        .allow_threads(|| crate::content::extract_from_bytes(&bytes, &Default::default()))
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let value = serde_json::to_value(metadata).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    to_python(py, &value)
+}
+
+/// Convert a JSON value into the equivalent Python object
+fn to_python<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => PyBool::new(py, *b).to_owned().into_any(),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => u.into_pyobject(py)?.into_any(),
+            (None, Some(i)) => i.into_pyobject(py)?.into_any(),
+            _ => n.as_f64().unwrap_or(f64::NAN).into_pyobject(py)?.into_any(),
+        },
+        Value::String(s) => PyString::new(py, s).into_any(),
+        Value::Array(items) => {
+            let list = PyList::empty(py);
+            for item in items {
+                list.append(to_python(py, item)?)?;
+            }
+            list.into_any()
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, item) in map {
+                dict.set_item(key, to_python(py, item)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+#[pymodule]
+fn jpeg_metadata_extractor(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(extract, m)?)
+}
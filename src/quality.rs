@@ -0,0 +1,83 @@
+use crate::pixels::{luma, Preview};
+use serde::{Deserialize, Serialize};
+
+/// Luma at or above which a pixel counts as a clipped highlight
+const HIGHLIGHT_THRESHOLD: f64 = 250.0;
+/// Luma at or below which a pixel counts as a clipped shadow
+const SHADOW_THRESHOLD: f64 = 5.0;
+
+/// Heuristics for culling: focus and exposure
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QualityMetrics {
+    /// Variance of the Laplacian of luma; higher is sharper. Only comparable
+    /// between images analysed at the same `analysis_size`.
+    pub sharpness: f64,
+    /// Percentage of pixels with luma of 250 or more
+    pub clipped_highlights_pct: f64,
+    /// Percentage of pixels with luma of 5 or less
+    pub clipped_shadows_pct: f64,
+}
+
+/// Compute quality metrics from decoded pixels
+pub fn analyze(preview: &Preview) -> QualityMetrics {
+    let (width, height) = (preview.width as usize, preview.height as usize);
+    let lumas: Vec<f64> = preview.pixels().map(luma).collect();
+    let total = lumas.len().max(1) as f64;
+
+    let highlights = lumas.iter().filter(|&&y| y >= HIGHLIGHT_THRESHOLD).count();
+    let shadows = lumas.iter().filter(|&&y| y <= SHADOW_THRESHOLD).count();
+
+    // 4-neighbour Laplacian over interior pixels
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    let mut count = 0usize;
+    for row in 1..height.saturating_sub(1) {
+        for col in 1..width.saturating_sub(1) {
+            let i = row * width + col;
+            let laplacian = lumas[i - width] + lumas[i + width] + lumas[i - 1] + lumas[i + 1] - 4.0 * lumas[i];
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+            count += 1;
+        }
+    }
+    let sharpness = if count == 0 {
+        0.0
+    } else {
+        let mean = sum / count as f64;
+        sum_sq / count as f64 - mean * mean
+    };
+
+    QualityMetrics {
+        sharpness: round(sharpness),
+        clipped_highlights_pct: round(highlights as f64 * 100.0 / total),
+        clipped_shadows_pct: round(shadows as f64 * 100.0 / total),
+    }
+}
+
+/// Round to 2 decimal places so output stays readable and stable
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preview(width: u32, height: u32, luma: impl Fn(u32, u32) -> u8) -> Preview {
+        let rgb = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| [luma(x, y); 3])
+            .collect();
+        Preview { width, height, rgb }
+    }
+
+    #[test]
+    fn test_quality_metrics() {
+        let flat = analyze(&preview(8, 8, |_, _| 128));
+        assert_eq!(flat, QualityMetrics { sharpness: 0.0, clipped_highlights_pct: 0.0, clipped_shadows_pct: 0.0 });
+
+        let checker = analyze(&preview(8, 8, |x, y| if (x + y) % 2 == 0 { 255 } else { 0 }));
+        assert!(checker.sharpness > 1000.0);
+        assert_eq!((checker.clipped_highlights_pct, checker.clipped_shadows_pct), (50.0, 50.0));
+    }
+}
@@ -0,0 +1,686 @@
+use crate::error::{ExtractError, Result};
+use crate::exif_write;
+use crate::jpeg;
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Rational, Reader, SRational, Tag, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
+const NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const IMAGE_WIDTH: u16 = 0x0100;
+const IMAGE_LENGTH: u16 = 0x0101;
+const COMPRESSION: u16 = 0x0103;
+const IMAGE_DESCRIPTION: u16 = 0x010E;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const PAGE_NAME: u16 = 0x011D;
+const PAGE_NUMBER: u16 = 0x0129;
+const TILE_OFFSETS: u16 = 0x0144;
+const TILE_BYTE_COUNTS: u16 = 0x0145;
+const SUB_IFDS: u16 = 0x014A;
+const XMP: u16 = 0x02BC;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const INTEROP_IFD: u16 = 0xA005;
+/// Compression values of strips that may hold a JPEG: old-style and new-style JPEG
+const JPEG_COMPRESSION: [u64; 2] = [6, 7];
+/// An IFD entry's tag and the offset of the entry
+type Entry = (u16, usize);
+
+/// Most IFDs followed in one file; real RAW files have fewer than ten
+const MAX_IFDS: usize = 64;
+/// Most pages listed for one file; longer documents are cut short with a warning
+pub const MAX_PAGES: usize = 10_000;
+/// Most strip or tile sizes summed per page
+const MAX_STRIPS: usize = 1 << 20;
+/// Most entries read from one BigTIFF IFD, whose count may claim billions
+const MAX_ENTRIES: usize = u16::MAX as usize;
+
+/// TIFF tags of IFD0 worth keeping with a preview; the rest describe the raw image data
+const IFD0_TAGS: [Tag; 8] = [
+    Tag::Make, Tag::Model, Tag::Orientation, Tag::DateTime, Tag::ImageDescription, Tag::Artist, Tag::Copyright, Tag::Software,
+];
+
+/// Bytes of a TIFF file: the whole file, or only the parts holding its metadata
+pub trait TiffData {
+    /// The bytes in `range`, if they are all held
+    fn get(&self, range: Range<usize>) -> Option<&[u8]>;
+    /// Length of the file
+    fn file_len(&self) -> usize;
+}
+
+impl TiffData for [u8] {
+    fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        <[u8]>::get(self, range)
+    }
+
+    fn file_len(&self) -> usize {
+        self.len()
+    }
+}
+
+/// The IFDs of a TIFF file and the values they point to, read one by one from
+/// wherever they lie, without the strips and tiles of image data between them
+#[derive(Debug, Default)]
+pub struct Sparse {
+    /// Bytes read, by their offset in the file
+    chunks: BTreeMap<usize, Vec<u8>>,
+    len: usize,
+    big_endian: bool,
+    big: bool,
+}
+
+impl TiffData for Sparse {
+    fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        let (&start, chunk) = self.chunks.range(..=range.start).rev()
+            .find(|(&start, chunk)| range.end <= start + chunk.len())?;
+        chunk.get(range.start - start..range.end - start)
+    }
+
+    fn file_len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Sparse {
+    /// Read the IFD chain of a TIFF file of `len` bytes, and the Exif, GPS and
+    /// interoperability IFDs, through `read_at(offset, len)`, which may return
+    /// fewer bytes where the file ends. Every value stored out of line is read
+    /// but strip and tile offsets, which only locate image data.
+    pub fn read(len: u64, mut read_at: impl FnMut(u64, usize) -> Result<Vec<u8>>) -> Result<Sparse> {
+        let mut sparse = Sparse { len: usize::try_from(len).unwrap_or(usize::MAX), ..Default::default() };
+        sparse.fetch(0, 16, &mut read_at)?;
+        let Some(tiff) = Tiff::open(&sparse) else {
+            return Ok(sparse);
+        };
+        let (count_len, entry_len, inline_len) = (if tiff.big { 8 } else { 2 }, tiff.entry_len(), tiff.inline_len());
+        let mut pending: Vec<usize> = tiff.first_ifd().into_iter().collect();
+        (sparse.big_endian, sparse.big) = (tiff.big_endian, tiff.big);
+        let mut visited = HashSet::new();
+        while let Some(ifd) = pending.pop() {
+            if ifd == 0 || visited.len() >= MAX_PAGES + MAX_IFDS || !visited.insert(ifd) {
+                continue;
+            }
+            sparse.fetch(ifd, count_len, &mut read_at)?;
+            let count = if sparse.big { sparse.tiff().u64_at(ifd).and_then(|c| usize::try_from(c).ok()) } else { sparse.tiff().u16_at(ifd).map(usize::from) };
+            let Some(count) = count else {
+                continue;
+            };
+            sparse.fetch(ifd, count_len + count.min(MAX_ENTRIES) * entry_len + inline_len, &mut read_at)?;
+            let Some((entries, next)) = sparse.tiff().ifd(ifd) else {
+                continue;
+            };
+            for (tag, at) in entries {
+                if matches!(tag, STRIP_OFFSETS | TILE_OFFSETS) {
+                    continue;
+                }
+                let tiff = sparse.tiff();
+                let value = tiff.u16_at(at + 2).and_then(type_size).and_then(|size| {
+                    let (count, start) = tiff.value_location(at, size)?;
+                    Some((count.checked_mul(size)?, start))
+                });
+                if let Some((len, start)) = value.filter(|&(len, _)| len > inline_len) {
+                    sparse.fetch(start, len, &mut read_at)?;
+                }
+                if matches!(tag, EXIF_IFD | GPS_IFD | INTEROP_IFD) {
+                    pending.extend(sparse.tiff().values(at).into_iter().filter_map(|offset| usize::try_from(offset).ok()));
+                }
+            }
+            pending.extend(next);
+        }
+        Ok(sparse)
+    }
+
+    /// The structures read so far, in the file's byte order
+    fn tiff(&self) -> Tiff<'_, Sparse> {
+        Tiff { data: self, big_endian: self.big_endian, big: self.big }
+    }
+
+    /// Read `len` bytes at `offset` unless they are already held
+    fn fetch(&mut self, offset: usize, len: usize, read_at: &mut impl FnMut(u64, usize) -> Result<Vec<u8>>) -> Result<()> {
+        let Some(end) = offset.checked_add(len).filter(|_| offset < self.len) else {
+            return Ok(());
+        };
+        if self.get(offset..end.min(self.len)).is_none() {
+            self.chunks.insert(offset, read_at(offset as u64, len)?);
+        }
+        Ok(())
+    }
+
+    /// The bytes held laid out at their offsets, with zeros for the image data
+    /// between them, unless that would take more than `max_gap` zeros
+    pub fn dense(&self, max_gap: usize) -> Option<Vec<u8>> {
+        let mut covered = 0;
+        let mut end = 0;
+        for (&start, chunk) in &self.chunks {
+            let chunk_end = start + chunk.len();
+            covered += chunk_end.saturating_sub(start.max(end));
+            end = end.max(chunk_end);
+        }
+        if end - covered > max_gap {
+            return None;
+        }
+        let mut out = vec![0; end];
+        for (&start, chunk) in &self.chunks {
+            out[start..start + chunk.len()].copy_from_slice(chunk);
+        }
+        Some(out)
+    }
+}
+
+/// Bytes taken by one value of a TIFF type, including BigTIFF's 64-bit types
+fn type_size(value_type: u16) -> Option<usize> {
+    match value_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 | 16 | 17 | 18 => Some(8),
+        _ => None,
+    }
+}
+
+/// Reads TIFF structures in the file's byte order, from a classic TIFF or a
+/// BigTIFF, whose 64-bit offsets let panorama and scan exports pass 4 GiB
+struct Tiff<'a, D: TiffData + ?Sized = [u8]> {
+    data: &'a D,
+    big_endian: bool,
+    big: bool,
+}
+
+impl<'a, D: TiffData + ?Sized> Tiff<'a, D> {
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let b = self.data.get(at..at.checked_add(2)?)?;
+        Some(if self.big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let b = self.data.get(at..at.checked_add(4)?)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    fn u64_at(&self, at: usize) -> Option<u64> {
+        let b: [u8; 8] = self.data.get(at..at.checked_add(8)?)?.try_into().ok()?;
+        Some(if self.big_endian { u64::from_be_bytes(b) } else { u64::from_le_bytes(b) })
+    }
+
+    /// An offset or count: 8 bytes in a BigTIFF, 4 in a classic TIFF
+    fn word_at(&self, at: usize) -> Option<u64> {
+        if self.big { self.u64_at(at) } else { self.u32_at(at).map(u64::from) }
+    }
+
+    /// Offset of a position in the file, if it can be addressed on this platform
+    fn offset_at(&self, at: usize) -> Option<usize> {
+        usize::try_from(self.word_at(at)?).ok()
+    }
+
+    /// Bytes of an IFD entry, and of the value stored inline in it
+    fn entry_len(&self) -> usize {
+        if self.big { 20 } else { 12 }
+    }
+
+    fn inline_len(&self) -> usize {
+        if self.big { 8 } else { 4 }
+    }
+
+    /// An entry's value count, and where its values start: inline or at its offset
+    fn value_location(&self, entry: usize, size: usize) -> Option<(usize, usize)> {
+        let count = usize::try_from(self.word_at(entry + 4)?).ok()?;
+        let value = entry + 4 + self.inline_len();
+        let start = if size.checked_mul(count)? <= self.inline_len() { value } else { self.offset_at(value)? };
+        Some((count, start))
+    }
+
+    /// Offset of the first IFD
+    fn first_ifd(&self) -> Option<usize> {
+        self.offset_at(if self.big { 8 } else { 4 })
+    }
+
+    /// Values of a SHORT, LONG or LONG8 entry, read inline or from its offset
+    fn values(&self, entry: usize) -> Vec<u64> {
+        self.values_up_to(entry, MAX_IFDS)
+    }
+
+    /// Like [`Tiff::values`], reading at most `max` values
+    fn values_up_to(&self, entry: usize, max: usize) -> Vec<u64> {
+        let size = match self.u16_at(entry + 2) {
+            Some(3) => 2,
+            Some(4 | 13) => 4,
+            Some(16 | 18) => 8,
+            _ => return Vec::new(),
+        };
+        let Some((count, start)) = self.value_location(entry, size) else {
+            return Vec::new();
+        };
+        (0..count.min(max))
+            .map_while(|i| match size {
+                2 => self.u16_at(start + i * 2).map(u64::from),
+                4 => self.u32_at(start + i * 4).map(u64::from),
+                _ => self.u64_at(start + i * 8),
+            })
+            .collect()
+    }
+
+    /// The first value of an entry, if it fits in 32 bits
+    fn first_u32(&self, entry: usize) -> Option<u32> {
+        self.values(entry).first().and_then(|&v| u32::try_from(v).ok())
+    }
+
+    /// Bytes of a BYTE, ASCII or UNDEFINED entry, read inline or from its offset
+    fn bytes(&self, entry: usize) -> Option<&'a [u8]> {
+        if !matches!(self.u16_at(entry + 2)?, 1 | 2 | 7) {
+            return None;
+        }
+        let (count, start) = self.value_location(entry, 1)?;
+        self.data.get(start..start.checked_add(count)?)
+    }
+
+    /// Text of an ASCII entry, without its terminating NULs
+    fn text(&self, entry: usize) -> Option<String> {
+        let text = String::from_utf8_lossy(self.bytes(entry)?).trim_end_matches('\0').trim().to_string();
+        Some(text).filter(|t| !t.is_empty())
+    }
+
+    /// The entries of the IFD at `ifd`, as (tag, entry offset), and the offset of the next IFD
+    fn ifd(&self, ifd: usize) -> Option<(Vec<Entry>, Option<usize>)> {
+        let (count, first) = if self.big {
+            (usize::try_from(self.u64_at(ifd)?).ok()?, ifd + 8)
+        } else {
+            (self.u16_at(ifd)? as usize, ifd + 2)
+        };
+        let entries = (0..count)
+            .map(|i| first + i * self.entry_len())
+            .map_while(|at| Some((self.u16_at(at)?, at)))
+            .collect::<Vec<_>>();
+        let next = first.checked_add(entries.len() * self.entry_len())
+            .and_then(|at| self.offset_at(at))
+            .filter(|&next| next != 0);
+        Some((entries, next))
+    }
+
+    /// The value of an entry of any classic TIFF type, as kamadak-exif holds it.
+    /// BigTIFF's 64-bit types give `None`.
+    fn value(&self, entry: usize) -> Option<Value> {
+        let value_type = self.u16_at(entry + 2)?;
+        let size = match value_type {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        let (count, start) = self.value_location(entry, size)?;
+        let bytes = self.data.get(start..start.checked_add(count.checked_mul(size)?)?)?;
+        let u16s = || (0..count).filter_map(|i| self.u16_at(start + i * 2)).collect::<Vec<_>>();
+        let u32s = || (0..count * size / 4).filter_map(|i| self.u32_at(start + i * 4)).collect::<Vec<_>>();
+        Some(match value_type {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => Value::Ascii(bytes.split(|&b| b == 0).filter(|s| !s.is_empty()).map(<[u8]>::to_vec).collect()),
+            3 => Value::Short(u16s()),
+            4 => Value::Long(u32s()),
+            5 => Value::Rational(u32s().chunks_exact(2).map(|r| Rational { num: r[0], denom: r[1] }).collect()),
+            6 => Value::SByte(bytes.iter().map(|&b| b as i8).collect()),
+            7 => Value::Undefined(bytes.to_vec(), 0),
+            8 => Value::SShort(u16s().into_iter().map(|v| v as i16).collect()),
+            9 => Value::SLong(u32s().into_iter().map(|v| v as i32).collect()),
+            10 => Value::SRational(u32s().chunks_exact(2).map(|r| SRational { num: r[0] as i32, denom: r[1] as i32 }).collect()),
+            11 => Value::Float(u32s().into_iter().map(f32::from_bits).collect()),
+            _ => Value::Double((0..count).filter_map(|i| self.u64_at(start + i * 8)).map(f64::from_bits).collect()),
+        })
+    }
+
+    fn open(data: &'a D) -> Option<Tiff<'a, D>> {
+        let (big_endian, big) = match data.get(0..4)? {
+            b"MM\0*" => (true, false),
+            b"II*\0" => (false, false),
+            b"MM\0+" => (true, true),
+            b"II+\0" => (false, true),
+            _ => return None,
+        };
+        let tiff = Tiff { data, big_endian, big };
+        // A BigTIFF's header gives 8-byte offsets and a reserved zero
+        if big && (tiff.u16_at(4)? != 8 || tiff.u16_at(6)? != 0) {
+            return None;
+        }
+        Some(tiff)
+    }
+}
+
+/// One image in a TIFF file's chain of IFDs: a page of a multi-page document,
+/// or in a RAW file the raw data, a preview or a thumbnail
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// TIFF compression code, e.g. 1 for none, 5 for LZW, 7 for JPEG
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_name: Option<String>,
+    /// Number of the page as recorded by the scanner, counting from 0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// A reduced-resolution copy of another page, such as a preview
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reduced_resolution: bool,
+}
+
+/// The pages of a TIFF file, in the order of its IFD chain, with the bytes of
+/// image data they hold in total. Sub-IFDs are not pages and are left out.
+pub fn pages<D: TiffData + ?Sized>(data: &D) -> (Vec<Page>, u64) {
+    let mut pages = Vec::new();
+    let mut image_data = 0u64;
+    let Some(tiff) = Tiff::open(data) else {
+        return (pages, image_data);
+    };
+    let mut next = tiff.first_ifd();
+    let mut visited = HashSet::new();
+    while let Some(ifd) = next.filter(|&ifd| pages.len() < MAX_PAGES && visited.insert(ifd)) {
+        let Some((entries, following)) = tiff.ifd(ifd) else {
+            break;
+        };
+        let entry = |tag| entries.iter().find(|(t, _)| *t == tag).map(|&(_, at)| at);
+        let first = |tag| entry(tag).and_then(|at| tiff.first_u32(at));
+        let total = |tag| entry(tag).map_or(0, |at| tiff.values_up_to(at, MAX_STRIPS).into_iter().fold(0u64, u64::saturating_add));
+        image_data = image_data.saturating_add(total(STRIP_BYTE_COUNTS)).saturating_add(total(TILE_BYTE_COUNTS)) + first(JPEG_LENGTH).map_or(0, u64::from);
+        pages.push(Page {
+            width: first(IMAGE_WIDTH),
+            height: first(IMAGE_LENGTH),
+            compression: first(COMPRESSION),
+            page_name: entry(PAGE_NAME).and_then(|at| tiff.text(at)),
+            page_number: first(PAGE_NUMBER),
+            description: entry(IMAGE_DESCRIPTION).and_then(|at| tiff.text(at)),
+            reduced_resolution: first(NEW_SUBFILE_TYPE).is_some_and(|t| t & 1 != 0),
+        });
+        next = following;
+    }
+    (pages, image_data.min(data.file_len() as u64))
+}
+
+/// The XMP packet of a TIFF file, held in IFD0's XMP tag
+pub fn xmp_packet<D: TiffData + ?Sized>(data: &D) -> Option<&[u8]> {
+    let tiff = Tiff::open(data)?;
+    let (entries, _) = tiff.ifd(tiff.first_ifd()?)?;
+    let &(_, at) = entries.iter().find(|(tag, _)| *tag == XMP)?;
+    tiff.bytes(at)
+}
+
+/// Whether `data` starts like a TIFF file, as CR2, NEF, ARW and DNG files do, or a BigTIFF
+pub fn is_tiff(data: &[u8]) -> bool {
+    data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") || is_bigtiff(data)
+}
+
+/// Whether `data` starts like a BigTIFF file
+pub fn is_bigtiff(data: &[u8]) -> bool {
+    data.starts_with(b"II+\0") || data.starts_with(b"MM\0+")
+}
+
+/// The EXIF fields of a TIFF's first page, its IFD0, Exif IFD and GPS IFD,
+/// written out as a classic TIFF block for kamadak-exif, which reads no
+/// BigTIFF and needs the block whole. Only the metadata is copied, so the block
+/// stays small however large the file; values of BigTIFF's 64-bit types are left
+/// out, and maker notes using absolute offsets no longer resolve.
+pub fn classic_exif<D: TiffData + ?Sized>(data: &D) -> Option<Vec<u8>> {
+    let tiff = Tiff::open(data)?;
+    let (ifd0, _) = tiff.ifd(tiff.first_ifd()?)?;
+    let sub_ifd = |tag| {
+        let &(_, at) = ifd0.iter().find(|(t, _)| *t == tag)?;
+        let offset = usize::try_from(*tiff.values(at).first()?).ok()?;
+        tiff.ifd(offset).map(|(entries, _)| entries)
+    };
+    let mut fields = Vec::new();
+    for (context, entries) in [(Context::Tiff, Some(ifd0.clone())), (Context::Exif, sub_ifd(EXIF_IFD)), (Context::Gps, sub_ifd(GPS_IFD))] {
+        for (tag, at) in entries.unwrap_or_default() {
+            // The data layout of the BigTIFF means nothing in the classic block
+            if context == Context::Tiff && matches!(tag, SUB_IFDS | XMP | EXIF_IFD | GPS_IFD) {
+                continue;
+            }
+            if let Some(value) = tiff.value(at) {
+                fields.push(Field { tag: Tag(context, tag), ifd_num: In::PRIMARY, value });
+            }
+        }
+    }
+    let mut writer = Writer::new();
+    fields.iter().for_each(|field| writer.push_field(field));
+    let mut block = std::io::Cursor::new(Vec::new());
+    writer.write(&mut block, !tiff.big_endian).ok()?;
+    Some(block.into_inner())
+}
+
+/// Byte range of the largest JPEG preview embedded in a TIFF-based RAW file,
+/// found by following every IFD and sub-IFD. Candidates that are not JPEGs a
+/// viewer can show, such as lossless-JPEG raw data, are skipped.
+pub fn largest_preview(data: &[u8]) -> Option<Range<usize>> {
+    let tiff = Tiff::open(data)?;
+    let mut pending = vec![tiff.first_ifd()?];
+    let mut visited = HashSet::new();
+    let mut candidates = Vec::new();
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 || visited.len() >= MAX_IFDS || !visited.insert(ifd) {
+            continue;
+        }
+        let Some((entries, next)) = tiff.ifd(ifd) else {
+            continue;
+        };
+        let first = |tag| entries.iter().find(|(t, _)| *t == tag).and_then(|&(_, at)| tiff.values(at).first().copied());
+        let all = |tag| entries.iter().find(|(t, _)| *t == tag).map(|&(_, at)| tiff.values(at)).unwrap_or_default();
+
+        if let (Some(offset), Some(length)) = (first(JPEG_OFFSET), first(JPEG_LENGTH)) {
+            candidates.push((offset, length));
+        }
+        let (offsets, counts) = (all(STRIP_OFFSETS), all(STRIP_BYTE_COUNTS));
+        if first(COMPRESSION).is_some_and(|c| JPEG_COMPRESSION.contains(&c)) && offsets.len() == 1 && counts.len() == 1 {
+            candidates.push((offsets[0], counts[0]));
+        }
+        pending.extend(all(SUB_IFDS).into_iter().filter_map(|offset| usize::try_from(offset).ok()));
+        pending.extend(next);
+    }
+
+    candidates.into_iter()
+        .filter_map(|(offset, length)| Some(usize::try_from(offset).ok()?..usize::try_from(offset.saturating_add(length)).ok()?))
+        .filter(|range| range.end <= data.len() && is_viewable_jpeg(&data[range.clone()]))
+        .max_by_key(|range| range.len())
+}
+
+/// Whether `bytes` is a baseline, extended or progressive JPEG rather than lossless
+fn is_viewable_jpeg(bytes: &[u8]) -> bool {
+    jpeg::read_segments(&mut &bytes[..])
+        .is_ok_and(|segments| segments.iter().any(|s| matches!(s.marker, 0xC0..=0xC2)))
+}
+
+/// The largest embedded preview of a TIFF-based RAW file, with the RAW's EXIF,
+/// GPS and descriptive TIFF fields written into it. Maker notes are left out,
+/// as their offsets point into the RAW file.
+pub fn extract_preview(data: &[u8]) -> Result<Vec<u8>> {
+    let range = largest_preview(data)
+        .ok_or_else(|| ExtractError::Unsupported("RAW file without a JPEG preview".to_string()))?;
+    let tiff = if is_bigtiff(data) { classic_exif(data) } else { None };
+    let exif = Reader::new().read_raw(tiff.unwrap_or_else(|| data.to_vec()))?;
+    let fields: Vec<exif::Field> = exif.fields()
+        .filter(|f| f.ifd_num == In::PRIMARY && f.tag != Tag::MakerNote)
+        .filter(|f| matches!(f.tag.context(), Context::Exif | Context::Gps) || IFD0_TAGS.contains(&f.tag))
+        .cloned()
+        .collect();
+    exif_write::rewrite(&data[range], &fields, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::ExtractOptions;
+    #[cfg(feature = "decode")]
+    use crate::exif_metadata::read_exif_metadata;
+
+    /// A little-endian TIFF whose IFD0 holds `small` as a JPEG strip and whose
+    /// sub-IFD points at `large`, laid out as in a CR2 or NEF file
+    #[cfg(feature = "decode")]
+    fn raw_file(small: &[u8], large: &[u8]) -> Vec<u8> {
+        let artist = b"Raw Shooter\0";
+        let artist_at = 8 + 2 + 5 * 12 + 4;
+        let sub_ifd_at = artist_at + artist.len();
+        let small_at = sub_ifd_at + 2 + 2 * 12 + 4;
+        let large_at = small_at + small.len();
+        let entry = |tag: u16, value_type: u16, count: u32, value: u32| {
+            [tag.to_le_bytes().as_slice(), &value_type.to_le_bytes(), &count.to_le_bytes(), &value.to_le_bytes()].concat()
+        };
+        let mut out = b"II*\0".to_vec();
+        out.extend(8u32.to_le_bytes());
+        out.extend(5u16.to_le_bytes());
+        out.extend(entry(COMPRESSION, 3, 1, 6));
+        out.extend(entry(STRIP_OFFSETS, 4, 1, small_at as u32));
+        out.extend(entry(STRIP_BYTE_COUNTS, 4, 1, small.len() as u32));
+        out.extend(entry(0x013B, 2, artist.len() as u32, artist_at as u32));
+        out.extend(entry(SUB_IFDS, 4, 1, sub_ifd_at as u32));
+        out.extend(0u32.to_le_bytes());
+        out.extend(artist);
+        out.extend(2u16.to_le_bytes());
+        out.extend(entry(JPEG_OFFSET, 4, 1, large_at as u32));
+        out.extend(entry(JPEG_LENGTH, 4, 1, large.len() as u32));
+        out.extend(0u32.to_le_bytes());
+        out.extend(small);
+        out.extend(large);
+        out
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_extract_preview() {
+        let large = std::fs::read("images/JAM26284.jpg").unwrap();
+        let small = crate::thumbnail::generate(&large).unwrap();
+        let raw = raw_file(&small, &large);
+        assert!(is_tiff(&raw));
+
+        let range = largest_preview(&raw).unwrap();
+        assert_eq!(&raw[range], large.as_slice());
+
+        let preview = extract_preview(&raw).unwrap();
+        let exif = read_exif_metadata(&mut preview.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(exif.artist.as_deref(), Some("Raw Shooter"));
+        assert_eq!(exif.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+
+        // A preview cut short is skipped in favour of the smaller one
+        let truncated = raw_file(&small, &large[..1000]);
+        assert_eq!(largest_preview(&truncated).map(|r| r.len()), Some(small.len()));
+        assert_eq!(largest_preview(b"II*\0\xff\xff\xff\xff"), None);
+    }
+
+    /// A little-endian TIFF with one IFD per page in its chain, as a scanner writes
+    /// a multi-page document, each with a named page of uncompressed strip data
+    fn multi_page(pages: &[(u32, u32, &str)]) -> Vec<u8> {
+        let entry = |tag: u16, value_type: u16, count: u32, value: u32| {
+            [tag.to_le_bytes().as_slice(), &value_type.to_le_bytes(), &count.to_le_bytes(), &value.to_le_bytes()].concat()
+        };
+        let mut out = b"II*\0".to_vec();
+        out.extend(8u32.to_le_bytes());
+        for (number, &(width, height, name)) in pages.iter().enumerate() {
+            let name = format!("{}\0", name);
+            let name_at = out.len() + 2 + 7 * 12 + 4;
+            let strip_at = name_at + name.len();
+            let strip_len = width * height;
+            let next = if number + 1 < pages.len() { strip_at as u32 + strip_len } else { 0 };
+            out.extend(7u16.to_le_bytes());
+            out.extend(entry(IMAGE_WIDTH, 4, 1, width));
+            out.extend(entry(IMAGE_LENGTH, 4, 1, height));
+            out.extend(entry(COMPRESSION, 3, 1, 1));
+            out.extend(entry(STRIP_OFFSETS, 4, 1, strip_at as u32));
+            out.extend(entry(STRIP_BYTE_COUNTS, 4, 1, strip_len));
+            out.extend(entry(PAGE_NAME, 2, name.len() as u32, name_at as u32));
+            out.extend(entry(PAGE_NUMBER, 3, 2, number as u32 | (pages.len() as u32) << 16));
+            out.extend(next.to_le_bytes());
+            out.extend(name.as_bytes());
+            out.extend(std::iter::repeat_n(0x80, strip_len as usize));
+        }
+        out
+    }
+
+    #[test]
+    fn test_pages() {
+        let tiff = multi_page(&[(40, 30, "Invoice"), (40, 32, "Terms and conditions"), (20, 15, "Signatures")]);
+        let (pages, image_data) = pages(tiff.as_slice());
+        assert_eq!(pages.len(), 3);
+        assert_eq!(image_data, 40 * 30 + 40 * 32 + 20 * 15);
+        assert_eq!(pages[1], Page {
+            width: Some(40),
+            height: Some(32),
+            compression: Some(1),
+            page_name: Some("Terms and conditions".to_string()),
+            page_number: Some(1),
+            ..Page::default()
+        });
+        assert_eq!(pages[2].page_name.as_deref(), Some("Signatures"));
+
+        let content = crate::content::extract_from_bytes(&tiff, &ExtractOptions::default()).unwrap();
+        assert_eq!((content.width, content.height), (Some(40), Some(30)));
+        assert_eq!(content.pages, pages);
+        assert_eq!(content.payload_breakdown.image_data, image_data);
+
+        // A chain that loops back on itself ends at the repeated IFD
+        let mut looped = multi_page(&[(4, 4, "Only")]);
+        let next = 8 + 2 + 7 * 12;
+        looped[next..next + 4].copy_from_slice(&8u32.to_le_bytes());
+        assert_eq!(super::pages(looped.as_slice()).0.len(), 1);
+    }
+
+    /// A little-endian BigTIFF with 64-bit offsets and counts, whose one page
+    /// declares more than 4 GiB of strip data, as a panorama export does
+    fn big_tiff() -> Vec<u8> {
+        let model = b"Stitcher\0";
+        let taken = b"2024:05:06 07:08:09\0";
+        let model_at = 16 + 8 + 6 * 20 + 8;
+        let exif_at = model_at + model.len();
+        let taken_at = exif_at + 8 + 20 + 8;
+        let entry = |tag: u16, value_type: u16, count: u64, value: u64| {
+            [tag.to_le_bytes().as_slice(), &value_type.to_le_bytes(), &count.to_le_bytes(), &value.to_le_bytes()].concat()
+        };
+        let mut out = b"II+\0".to_vec();
+        out.extend(8u16.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(16u64.to_le_bytes());
+        out.extend(6u64.to_le_bytes());
+        out.extend(entry(IMAGE_WIDTH, 4, 1, 70_000));
+        out.extend(entry(IMAGE_LENGTH, 3, 1, 9_000));
+        out.extend(entry(0x0110, 2, model.len() as u64, model_at as u64));
+        out.extend(entry(0x0112, 3, 1, 6));
+        out.extend(entry(STRIP_BYTE_COUNTS, 16, 1, 5_000_000_000));
+        out.extend(entry(EXIF_IFD, 18, 1, exif_at as u64));
+        out.extend(0u64.to_le_bytes());
+        out.extend(model);
+        out.extend(1u64.to_le_bytes());
+        out.extend(entry(0x9003, 2, taken.len() as u64, taken_at as u64));
+        out.extend(0u64.to_le_bytes());
+        out.extend(taken);
+        out
+    }
+
+    #[test]
+    fn test_bigtiff() {
+        let big = big_tiff();
+        assert!(is_tiff(&big) && is_bigtiff(&big));
+        let tiff = Tiff::open(big.as_slice()).unwrap();
+        let (entries, next) = tiff.ifd(tiff.first_ifd().unwrap()).unwrap();
+        assert_eq!((entries.len(), next), (6, None));
+        let &(_, strips) = entries.iter().find(|(tag, _)| *tag == STRIP_BYTE_COUNTS).unwrap();
+        assert_eq!(tiff.values(strips), [5_000_000_000]);
+
+        let (pages, _) = pages(big.as_slice());
+        assert_eq!((pages[0].width, pages[0].height), (Some(70_000), Some(9_000)));
+
+        let content = crate::content::extract_from_bytes(&big, &ExtractOptions::default()).unwrap();
+        assert_eq!(content.format, crate::detect::ImageFormat::Tiff);
+        assert_eq!((content.display_width, content.display_height), (Some(9_000), Some(70_000)));
+        assert_eq!(content.camera_model.as_deref(), Some("Stitcher"));
+        assert_eq!(content.capture_time.unwrap().to_rfc3339(), "2024-05-06T07:08:09+00:00");
+
+        // A BigTIFF header must give 8-byte offsets
+        let mut odd = big.clone();
+        odd[4] = 4;
+        assert!(Tiff::open(odd.as_slice()).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::xmp;
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+
+/// Metadata Working Group regions namespace (`mwg-rs:`)
+pub const MWG_RS_NS: &str = "http://www.metadataworkinggroup.com/schemas/regions/";
+/// Adobe area struct namespace (`stArea:`), used by MWG region areas
+pub const ST_AREA_NS: &str = "http://ns.adobe.com/xmp/sType/Area#";
+/// Microsoft Photo namespace (`MP:`)
+pub const MP_NS: &str = "http://ns.microsoft.com/photo/1.2/";
+/// Microsoft Photo RegionInfo struct namespace (`MPRI:`)
+pub const MPRI_NS: &str = "http://ns.microsoft.com/photo/1.2/t/RegionInfo#";
+/// Microsoft Photo Region struct namespace (`MPReg:`)
+pub const MPREG_NS: &str = "http://ns.microsoft.com/photo/1.2/t/Region#";
+
+/// Which XMP schema a region was read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionSource {
+    /// MWG Regions, written by Picasa, Lightroom and digiKam
+    Mwg,
+    /// Microsoft People tags, written by Windows Photo Gallery and digiKam
+    Microsoft,
+}
+
+/// A named rectangle in the image, usually a tagged face. Coordinates are
+/// normalized to the image size (0 to 1) with `x`/`y` at the top-left corner,
+/// whatever convention the source schema uses.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// MWG region type such as Face, Pet or Focus; Microsoft regions are always Face
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    pub source: RegionSource,
+}
+
+/// All MWG and Microsoft regions in the XMP, MWG first
+pub fn from_xmp(doc: &Document) -> Vec<Region> {
+    let mut regions = Vec::new();
+    for info in doc.descendants().filter(|n| n.has_tag_name((MWG_RS_NS, "Regions"))) {
+        if let Some(list) = xmp::child(info, MWG_RS_NS, "RegionList") {
+            regions.extend(xmp::list_items(list).filter_map(mwg_region));
+        }
+    }
+    for info in doc.descendants().filter(|n| n.has_tag_name((MP_NS, "RegionInfo"))) {
+        if let Some(list) = xmp::child(info, MPRI_NS, "Regions") {
+            regions.extend(xmp::list_items(list).filter_map(microsoft_region));
+        }
+    }
+    regions
+}
+
+/// An MWG region; its area is centre-based and must be in normalized units
+fn mwg_region(item: Node) -> Option<Region> {
+    let area = xmp::child(item, MWG_RS_NS, "Area")?;
+    if xmp::field(area, ST_AREA_NS, "unit").is_some_and(|unit| unit != "normalized") {
+        return None;
+    }
+    let number = |name| xmp::field(area, ST_AREA_NS, name).and_then(|v| v.parse::<f64>().ok());
+    let (w, h) = (number("w")?, number("h")?);
+    Some(Region {
+        name: xmp::field(item, MWG_RS_NS, "Name").filter(|n| !n.is_empty()),
+        kind: xmp::field(item, MWG_RS_NS, "Type").filter(|t| !t.is_empty()),
+        x: number("x")? - w / 2.0,
+        y: number("y")? - h / 2.0,
+        w,
+        h,
+        source: RegionSource::Mwg,
+    })
+}
+
+/// A Microsoft People region, whose rectangle is "x, y, w, h" from the top-left
+fn microsoft_region(item: Node) -> Option<Region> {
+    let rectangle = xmp::field(item, MPREG_NS, "Rectangle")?;
+    let values: Vec<f64> = rectangle.split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    let [x, y, w, h] = values[..] else {
+        return None;
+    };
+    Some(Region {
+        name: xmp::field(item, MPREG_NS, "PersonDisplayName").filter(|n| !n.is_empty()),
+        kind: Some("Face".to_string()),
+        x,
+        y,
+        w,
+        h,
+        source: RegionSource::Microsoft,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description
+    xmlns:mwg-rs="http://www.metadataworkinggroup.com/schemas/regions/"
+    xmlns:stArea="http://ns.adobe.com/xmp/sType/Area#"
+    xmlns:MP="http://ns.microsoft.com/photo/1.2/"
+    xmlns:MPRI="http://ns.microsoft.com/photo/1.2/t/RegionInfo#"
+    xmlns:MPReg="http://ns.microsoft.com/photo/1.2/t/Region#">
+  <mwg-rs:Regions rdf:parseType="Resource">
+    <mwg-rs:RegionList><rdf:Bag>
+      <rdf:li rdf:parseType="Resource">
+        <mwg-rs:Name>Sam</mwg-rs:Name>
+        <mwg-rs:Type>Face</mwg-rs:Type>
+        <mwg-rs:Area stArea:x="0.5" stArea:y="0.4" stArea:w="0.2" stArea:h="0.3" stArea:unit="normalized"/>
+      </rdf:li>
+      <rdf:li><rdf:Description mwg-rs:Type="Focus">
+        <mwg-rs:Area><rdf:Description stArea:x="0.5" stArea:y="0.5" stArea:w="0" stArea:h="0"/></mwg-rs:Area>
+      </rdf:Description></rdf:li>
+    </rdf:Bag></mwg-rs:RegionList>
+  </mwg-rs:Regions>
+  <MP:RegionInfo rdf:parseType="Resource">
+    <MPRI:Regions><rdf:Bag>
+      <rdf:li MPReg:Rectangle="0.1, 0.2, 0.3, 0.4" MPReg:PersonDisplayName="Alex"/>
+    </rdf:Bag></MPRI:Regions>
+  </MP:RegionInfo>
+</rdf:Description>
+</rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    fn test_regions_from_xmp() {
+        let doc = xmp::parse(PACKET).unwrap();
+        let regions = from_xmp(&doc);
+        assert_eq!(regions.len(), 3);
+
+        let sam = &regions[0];
+        assert_eq!((sam.name.as_deref(), sam.kind.as_deref()), (Some("Sam"), Some("Face")));
+        assert!((sam.x - 0.4).abs() < 1e-9 && (sam.y - 0.25).abs() < 1e-9);
+        assert_eq!((sam.w, sam.h, sam.source), (0.2, 0.3, RegionSource::Mwg));
+
+        assert_eq!(regions[1].kind.as_deref(), Some("Focus"));
+        assert_eq!(regions[1].name, None);
+
+        assert_eq!(regions[2], Region {
+            name: Some("Alex".to_string()),
+            kind: Some("Face".to_string()),
+            x: 0.1, y: 0.2, w: 0.3, h: 0.4,
+            source: RegionSource::Microsoft,
+        });
+    }
+}
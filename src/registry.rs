@@ -0,0 +1,59 @@
+use jpeg_metadata_extractor::provenance::Source;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+use crate::ImageMetadata;
+
+/// A camera body as --camera-registry lists it once, for records to refer to by `id`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Camera {
+    /// The first 16 hex digits of the SHA-256 of the model, serial and firmware,
+    /// so the same body gets the same ID in every run and every catalog
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<String>,
+}
+
+impl Camera {
+    fn new(model: Option<String>, serial: Option<String>, firmware: Option<String>) -> Self {
+        let mut hasher = Sha256::new();
+        for part in [&model, &serial, &firmware] {
+            hasher.update(part.as_deref().unwrap_or_default());
+            hasher.update([0]);
+        }
+        let id = hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        Camera { id, model, serial, firmware }
+    }
+}
+
+/// The cameras a sink has written so far in this run
+#[derive(Debug, Default)]
+pub struct CameraRegistry {
+    seen: HashSet<String>,
+}
+
+impl CameraRegistry {
+    /// Move a record's camera model, serial and firmware into a [`Camera`],
+    /// leaving its ID in `camera_id`. Returns the camera the first time it is
+    /// seen, for the sink to write before the record; records without any of
+    /// the three are left alone.
+    pub fn register(&mut self, metadata: &mut ImageMetadata) -> Option<Camera> {
+        if metadata.camera_model.is_none() && metadata.camera_serial.is_none() && metadata.camera_firmware.is_none() {
+            return None;
+        }
+        let camera = Camera::new(metadata.camera_model.take(), metadata.camera_serial.take(), metadata.camera_firmware.take());
+        let sources: Vec<Source> = ["camera_model", "camera_serial", "camera_firmware"].into_iter()
+            .filter_map(|field| metadata.provenance.remove(field))
+            .collect();
+        if !sources.is_empty() {
+            metadata.provenance.insert("camera_id".to_string(), Source::derived(sources));
+        }
+        metadata.camera_id = Some(camera.id.clone());
+        self.seen.insert(camera.id.clone()).then_some(camera)
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use jpeg_metadata_extractor::error::ExtractError;
+use jpeg_metadata_extractor::filesystem;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{throttle, Quarantine};
+
+/// The stages of processing one file, timed separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Sniffing the file's format
+    Detect,
+    /// Reading and assembling its metadata
+    Parse,
+    /// Hashing it for the checksum manifest
+    Hash,
+    /// Filtering the record and writing it to its outputs
+    Write,
+}
+
+/// Seconds spent in each stage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct StageTimes {
+    pub detect: f64,
+    pub parse: f64,
+    pub hash: f64,
+    pub write: f64,
+}
+
+impl StageTimes {
+    fn add(&mut self, stage: Stage, elapsed: Duration) {
+        let seconds = match stage {
+            Stage::Detect => &mut self.detect,
+            Stage::Parse => &mut self.parse,
+            Stage::Hash => &mut self.hash,
+            Stage::Write => &mut self.write,
+        };
+        *seconds += elapsed.as_secs_f64();
+    }
+
+    /// The stage that took longest, with its time
+    fn dominant(&self) -> (&'static str, f64) {
+        [("detect", self.detect), ("parse", self.parse), ("hash", self.hash), ("write", self.write)]
+            .into_iter()
+            .fold(("detect", f64::NEG_INFINITY), |slowest, stage| if stage.1 > slowest.1 { stage } else { slowest })
+    }
+
+    fn sum(&mut self, other: &StageTimes) {
+        self.detect += other.detect;
+        self.parse += other.parse;
+        self.hash += other.hash;
+        self.write += other.write;
+    }
+}
+
+/// Times the stages of one file and counts the bytes read meanwhile
+#[derive(Debug)]
+pub struct FileTimer {
+    started: Instant,
+    /// [`throttle::bytes_read`] when the file was started
+    bytes_before: u64,
+    /// Bytes read, when known up front, e.g. for an archive member read ahead of time
+    bytes: Option<u64>,
+    stages: StageTimes,
+}
+
+impl FileTimer {
+    pub fn start() -> Self {
+        FileTimer { started: Instant::now(), bytes_before: throttle::bytes_read(), bytes: None, stages: StageTimes::default() }
+    }
+
+    /// A timer for a file that has already been read into memory
+    pub fn read_ahead(bytes: u64) -> Self {
+        FileTimer { bytes: Some(bytes), ..FileTimer::start() }
+    }
+
+    pub fn time<T>(&mut self, stage: Stage, work: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = work();
+        self.stages.add(stage, started.elapsed());
+        result
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// How one file went
+#[derive(Debug, Serialize)]
+struct FileReport {
+    path: String,
+    bytes_read: u64,
+    seconds: f64,
+    stages: StageTimes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<&'static str>,
+}
+
+/// A file that took longer than --slow-threshold, and the stage that dominated
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SlowFile {
+    pub path: String,
+    pub seconds: f64,
+    pub stage: &'static str,
+    pub stage_seconds: f64,
+}
+
+/// Timing, bytes read and failures of every file in a run, written as JSON for
+/// tracking performance across runs
+#[derive(Debug)]
+pub struct Report {
+    started: DateTime<Utc>,
+    clock: Instant,
+    files: Vec<FileReport>,
+    /// Index into `files` by input path, for time spent after a file was recorded
+    index: HashMap<PathBuf, usize>,
+    /// Files taking longer than this are listed under `slow_files`
+    slow_threshold: Option<Duration>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report::new(None)
+    }
+}
+
+impl Report {
+    pub fn new(slow_threshold: Option<Duration>) -> Self {
+        Report { started: Utc::now(), clock: Instant::now(), files: Vec::new(), index: HashMap::new(), slow_threshold }
+    }
+
+    /// Record a file once it has been processed
+    pub fn record(&mut self, path: &Path, timer: FileTimer, result: &Result<()>) {
+        let bytes_read = timer.bytes.unwrap_or_else(|| throttle::bytes_read().saturating_sub(timer.bytes_before));
+        self.index.insert(path.to_path_buf(), self.files.len());
+        self.files.push(FileReport {
+            path: filesystem::path_text(path.as_os_str()),
+            bytes_read,
+            seconds: timer.elapsed().as_secs_f64(),
+            stages: timer.stages,
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            error_kind: result.as_ref().err().map(error_kind),
+        });
+    }
+
+    /// Add time spent on a recorded file, e.g. writing a record held back for grouping
+    pub fn add(&mut self, path: &Path, stage: Stage, elapsed: Duration) {
+        if let Some(&i) = self.index.get(path) {
+            let file = &mut self.files[i];
+            file.stages.add(stage, elapsed);
+            file.seconds += elapsed.as_secs_f64();
+        }
+    }
+
+    /// Files over the slow threshold, slowest first; none without a threshold
+    pub fn slow_files(&self) -> Vec<SlowFile> {
+        let Some(threshold) = self.slow_threshold else {
+            return Vec::new();
+        };
+        let mut slow: Vec<SlowFile> = self.files.iter()
+            .filter(|file| file.seconds > threshold.as_secs_f64())
+            .map(|file| {
+                let (stage, stage_seconds) = file.stages.dominant();
+                SlowFile { path: file.path.clone(), seconds: file.seconds, stage, stage_seconds }
+            })
+            .collect();
+        slow.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+        slow
+    }
+
+    pub fn render(&self) -> Result<String> {
+        let elapsed = self.clock.elapsed().as_secs_f64();
+        let mut stages = StageTimes::default();
+        let mut errors: BTreeMap<&str, u64> = BTreeMap::new();
+        for file in &self.files {
+            stages.sum(&file.stages);
+            if let Some(kind) = file.error_kind {
+                *errors.entry(kind).or_default() += 1;
+            }
+        }
+        let failed: u64 = errors.values().sum();
+        let bytes_read: u64 = self.files.iter().map(|f| f.bytes_read).sum();
+        let per_second = |n: u64| if elapsed > 0.0 { n as f64 / elapsed } else { 0.0 };
+        let mut report = serde_json::json!({
+            "started": self.started,
+            "elapsed_seconds": elapsed,
+            "files": self.files.len(),
+            "succeeded": self.files.len() as u64 - failed,
+            "failed": failed,
+            "bytes_read": bytes_read,
+            "files_per_second": per_second(self.files.len() as u64),
+            "bytes_per_second": per_second(bytes_read),
+            "stages": stages,
+            "errors": errors,
+            "per_file": self.files,
+        });
+        if let Some(threshold) = self.slow_threshold {
+            report["slow_threshold_seconds"] = threshold.as_secs_f64().into();
+            report["slow_files"] = serde_json::to_value(self.slow_files())?;
+        }
+        Ok(serde_json::to_string_pretty(&report)?)
+    }
+
+    pub fn write(&self, path: &Path, durable: bool) -> Result<()> {
+        let temp_path = crate::write_temp(path, self.render()?.as_bytes(), durable)
+            .with_context(|| format!("Failed to write report to {}", path.display()))?;
+        fs::rename(&temp_path, path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+}
+
+/// A short name for the kind of failure, to count failures by
+fn error_kind(error: &anyhow::Error) -> &'static str {
+    if let Some(quarantine) = error.downcast_ref::<Quarantine>() {
+        return match quarantine {
+            Quarantine::TimedOut(_) => "timed_out",
+            Quarantine::Panicked(_) => "panicked",
+        };
+    }
+    match error.downcast_ref::<ExtractError>() {
+        Some(ExtractError::Io(_)) => "io",
+        Some(ExtractError::NotAnImage(_)) => "not_an_image",
+        Some(ExtractError::NoExif) => "no_exif",
+        Some(ExtractError::ParseError { .. }) => "parse",
+        Some(ExtractError::Unsupported(_)) => "unsupported",
+        Some(ExtractError::LimitExceeded(_)) => "limit_exceeded",
+        Some(ExtractError::Encode(_)) => "encode",
+        Some(ExtractError::Extractor { .. }) => "extractor",
+        None if error.downcast_ref::<std::io::Error>().is_some() => "io",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut report = Report::default();
+        let mut timer = FileTimer::read_ahead(1000);
+        timer.stages.add(Stage::Parse, Duration::from_millis(20));
+        report.record(Path::new("a.jpg"), timer, &Ok(()));
+        report.add(Path::new("a.jpg"), Stage::Write, Duration::from_millis(5));
+        report.record(Path::new("b.jpg"), FileTimer::read_ahead(10), &Err(ExtractError::NoExif.into()));
+        report.record(Path::new("c.jpg"), FileTimer::read_ahead(0), &Err(Quarantine::TimedOut(Duration::from_secs(1)).into()));
+        report.record(Path::new("d.jpg"), FileTimer::read_ahead(0), &Err(anyhow::Error::new(ExtractError::NoExif).context("In d.jpg")));
+
+        let value: serde_json::Value = serde_json::from_str(&report.render().unwrap()).unwrap();
+        assert_eq!((value["files"].as_u64(), value["succeeded"].as_u64(), value["failed"].as_u64()), (Some(4), Some(1), Some(3)));
+        assert_eq!(value["bytes_read"], 1010);
+        assert_eq!(value["errors"], serde_json::json!({"no_exif": 2, "timed_out": 1}));
+        assert!(value["stages"]["parse"].as_f64().unwrap() >= 0.02);
+        assert!(value["per_file"][0]["stages"]["write"].as_f64().unwrap() >= 0.005);
+        assert_eq!(value["per_file"][3]["error"], "In d.jpg: No EXIF data");
+    }
+
+    #[test]
+    fn test_slow_files() {
+        let mut report = Report::new(Some(Duration::from_millis(50)));
+        let mut fast = FileTimer::read_ahead(0);
+        fast.stages.add(Stage::Parse, Duration::from_millis(10));
+        report.record(Path::new("fast.jpg"), fast, &Ok(()));
+        let mut slow = FileTimer::read_ahead(0);
+        slow.stages.add(Stage::Parse, Duration::from_millis(10));
+        slow.stages.add(Stage::Hash, Duration::from_millis(80));
+        report.record(Path::new("slow.jpg"), slow, &Ok(()));
+        report.add(Path::new("slow.jpg"), Stage::Write, Duration::from_millis(100));
+        // Time spent after recording counts as well
+        report.record(Path::new("late.jpg"), FileTimer::read_ahead(0), &Ok(()));
+        report.add(Path::new("late.jpg"), Stage::Write, Duration::from_millis(60));
+
+        let slow_files = report.slow_files();
+        assert_eq!(slow_files.iter().map(|f| (f.path.as_str(), f.stage)).collect::<Vec<_>>(), [("slow.jpg", "write"), ("late.jpg", "write")]);
+        assert!(slow_files[0].stage_seconds >= 0.1);
+
+        let value: serde_json::Value = serde_json::from_str(&report.render().unwrap()).unwrap();
+        assert_eq!(value["slow_threshold_seconds"], 0.05);
+        assert_eq!(value["slow_files"][1]["path"], "late.jpg");
+        assert!(Report::default().render().unwrap().find("slow_files").is_none());
+    }
+}
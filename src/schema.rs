@@ -0,0 +1,62 @@
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Record fields left out of [`metadata_fingerprint`]: those describing the file
+/// rather than the photo, those re-encoding changes, those depending on the rest
+/// of the batch, and bookkeeping about the extraction itself
+pub const FINGERPRINT_EXCLUDED: &[&str] = &[
+    "filename", "archive", "canonical_path", "relative_path", "volume_id", "size", "created_time", "modified_time", "timestamp_source", "is_symlink",
+    "link_target", "inode", "device", "uid", "gid", "mode", "readonly", "xattrs", "spotlight",
+    "best_time", "best_time_source", "encoding", "color_space", "payload_breakdown", "segment_offsets", "colors", "quality", "perceptual_hash",
+    "burst_group_id", "event_id", "original_of", "derivative_of",
+    "extensions", "derived", "provenance", "warnings", "signature", "metadata_fingerprint",
+];
+
+/// Compact JSON with object members sorted by key, so a record serializes the
+/// same however its members were ordered or spaced when written. Signatures and
+/// fingerprints are computed over this form.
+pub fn canonical_json(value: &Value) -> String {
+    fn sorted(value: &Value) -> Value {
+        match value {
+            Value::Object(object) => {
+                let mut keys: Vec<&String> = object.keys().collect();
+                keys.sort();
+                Value::Object(keys.into_iter().map(|k| (k.clone(), sorted(&object[k]))).collect::<Map<_, _>>())
+            }
+            Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+            other => other.clone(),
+        }
+    }
+    sorted(value).to_string()
+}
+
+/// SHA-256 over the canonical JSON of a flat record's metadata, without the
+/// [`FINGERPRINT_EXCLUDED`] fields. Timestamps must still be in the RFC 3339 UTC
+/// form records are built with, so the fingerprint does not depend on --time-format.
+/// Copies of a photo re-encoded at another quality share the fingerprint, and
+/// any edit to its metadata changes it.
+pub fn metadata_fingerprint(record: &Value) -> String {
+    let mut record = record.clone();
+    if let Some(object) = record.as_object_mut() {
+        object.retain(|key, value| !FINGERPRINT_EXCLUDED.contains(&key.as_str()) && !value.is_null());
+    }
+    Sha256::digest(canonical_json(&record)).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_fingerprint() {
+        let record = json!({"filename": "a.jpg", "size": 100, "capture_time": "2024-05-10T14:03:21Z", "gps": {"latitude": 48.1, "longitude": 2.5}});
+        let reencoded = json!({"gps": {"longitude": 2.5, "latitude": 48.1}, "capture_time": "2024-05-10T14:03:21Z", "filename": "a-small.jpg", "size": 40});
+        assert_eq!(metadata_fingerprint(&record), metadata_fingerprint(&reencoded));
+        assert_eq!(metadata_fingerprint(&record).len(), 64);
+
+        let edited = json!({"filename": "a.jpg", "size": 100, "capture_time": "2024-05-10T14:03:22Z", "gps": {"latitude": 48.1, "longitude": 2.5}});
+        assert_ne!(metadata_fingerprint(&record), metadata_fingerprint(&edited));
+        assert_eq!(canonical_json(&json!({"b": [{"d": 1, "c": 2}], "a": null})), r#"{"a":null,"b":[{"c":2,"d":1}]}"#);
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The signal that asked the run to stop, or 0
+static REQUESTED: AtomicI32 = AtomicI32::new(0);
+
+/// Catch SIGINT and SIGTERM so a run can finish the file in hand and write its
+/// combined output, catalog, state file and reports before exiting. A second
+/// signal stops the process at once. Other platforms keep the default handling.
+pub fn install() {
+    #[cfg(unix)]
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let handler = on_signal as extern "C" fn(libc::c_int);
+        // SAFETY: the handler only stores to an atomic and calls signal(), both async-signal-safe
+        unsafe {
+            libc::signal(signal, handler as libc::sighandler_t);
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    REQUESTED.store(signal, Ordering::SeqCst);
+    // SAFETY: restoring the default disposition is async-signal-safe
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+/// The signal received since [`install`], if any
+pub fn requested() -> Option<i32> {
+    match REQUESTED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Exit status of a run stopped by `signal`, as shells report it: 128 plus the signal number
+pub fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
+
+/// The signal's name for messages, e.g. SIGINT
+pub fn name(signal: i32) -> String {
+    #[cfg(unix)]
+    {
+        match signal {
+            libc::SIGINT => return "SIGINT".to_string(),
+            libc::SIGTERM => return "SIGTERM".to_string(),
+            _ => {}
+        }
+    }
+    format!("signal {}", signal)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_requests_shutdown() {
+        install();
+        // SAFETY: raise() delivers the signal to this thread, whose handler is installed above
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        assert_eq!(requested(), Some(libc::SIGTERM));
+        assert_eq!(exit_code(libc::SIGTERM), 143);
+        assert_eq!(name(libc::SIGTERM), "SIGTERM");
+    }
+}
@@ -0,0 +1,135 @@
+use crate::compression;
+use crate::schema::canonical_json;
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Member of a signed record holding its signature
+pub const SIGNATURE_FIELD: &str = "signature";
+const ALGORITHM: &str = "HMAC-SHA256";
+
+/// Secret shared by whoever signs reports and whoever verifies them
+#[derive(Clone)]
+pub struct SigningKey {
+    secret: Vec<u8>,
+    /// Short fingerprint of the secret, so a report signed with another key is
+    /// told apart from one that was altered
+    key_id: String,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("key_id", &self.key_id).finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    /// Use the whole key file, less surrounding whitespace, as the secret
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read key file {}", path.display()))?;
+        Self::new(bytes.trim_ascii())
+    }
+
+    pub fn new(secret: &[u8]) -> Result<Self> {
+        if secret.is_empty() {
+            bail!("Signing key is empty");
+        }
+        let key_id = hex(&Sha256::digest(secret)[..8]);
+        Ok(SigningKey { secret: secret.to_vec(), key_id })
+    }
+
+    fn mac(&self, value: &Value) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(canonical_json(value).as_bytes());
+        mac
+    }
+
+    /// Add a signature over every other member of `record`, replacing any earlier one
+    pub fn sign(&self, record: &mut Value) {
+        let Some(object) = record.as_object_mut() else {
+            return;
+        };
+        object.shift_remove(SIGNATURE_FIELD);
+        let signature = hex(&self.mac(record).finalize().into_bytes());
+        record.as_object_mut().unwrap().insert(SIGNATURE_FIELD.to_string(), serde_json::json!({
+            "algorithm": ALGORITHM,
+            "key_id": self.key_id,
+            "value": signature,
+        }));
+    }
+
+    /// Check the signature of a record written by [`SigningKey::sign`]
+    pub fn verify(&self, record: &Value) -> Result<()> {
+        let mut unsigned = record.clone();
+        let signature = unsigned.as_object_mut()
+            .and_then(|object| object.shift_remove(SIGNATURE_FIELD))
+            .context("Not signed")?;
+        let field = |name| signature.get(name).and_then(Value::as_str);
+        if field("algorithm") != Some(ALGORITHM) {
+            bail!("Unsupported signature algorithm {}", signature.get("algorithm").unwrap_or(&Value::Null));
+        }
+        let value = field("value").and_then(unhex).context("Malformed signature")?;
+        if field("key_id").is_some_and(|id| id != self.key_id) {
+            bail!("Signed with a different key ({})", field("key_id").unwrap_or_default());
+        }
+        self.mac(&unsigned).verify_slice(&value).map_err(|_| anyhow::anyhow!("Signature does not match; the record was altered"))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Check every signed record in a sidecar or JSON Lines file, returning a
+/// description and the outcome of each
+pub fn verify_file(path: &Path, key: &SigningKey) -> Result<Vec<(String, Result<()>)>> {
+    let text = compression::read_to_string(path)?;
+    if let Ok(record) = serde_json::from_str::<Value>(&text) {
+        return Ok(vec![(path.display().to_string(), key.verify(&record))]);
+    }
+    Ok(text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let outcome = serde_json::from_str(line).context("Not valid JSON").and_then(|record| key.verify(&record));
+            (format!("{} line {}", path.display(), i + 1), outcome)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::new(b"evidence locker").unwrap();
+        let mut record = serde_json::json!({"filename": "a.jpg", "size": 10, "gps": {"longitude": 2.5, "latitude": 48.1}});
+        key.sign(&mut record);
+        key.verify(&record).unwrap();
+
+        // Reordering and reformatting keeps the signature valid
+        let reordered: Value = serde_json::from_str(&serde_json::to_string_pretty(&serde_json::json!({
+            "signature": record["signature"], "gps": {"latitude": 48.1, "longitude": 2.5}, "size": 10, "filename": "a.jpg",
+        })).unwrap()).unwrap();
+        key.verify(&reordered).unwrap();
+
+        let mut altered = record.clone();
+        altered["size"] = 11.into();
+        assert!(key.verify(&altered).unwrap_err().to_string().contains("altered"));
+        let other = SigningKey::new(b"another key").unwrap();
+        assert!(other.verify(&record).unwrap_err().to_string().contains("different key"));
+        assert!(key.verify(&serde_json::json!({"size": 10})).is_err());
+    }
+}
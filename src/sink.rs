@@ -0,0 +1,513 @@
+use anyhow::{Context, Result};
+use jpeg_metadata_extractor::burst::{self, Frame};
+use jpeg_metadata_extractor::compat;
+use jpeg_metadata_extractor::derivatives::{self, Image};
+use jpeg_metadata_extractor::events::{self, Event, Photo};
+use jpeg_metadata_extractor::filesystem;
+use jpeg_metadata_extractor::locale::Locale;
+use jpeg_metadata_extractor::provenance::Source;
+use std::io::Write;
+
+use crate::cas;
+use crate::catalog::Catalog;
+use crate::exec::ExecSink;
+use crate::manifest::Job;
+#[cfg(feature = "queue")]
+use crate::publish::Publisher;
+use crate::registry::CameraRegistry;
+use crate::timestamps::Zone;
+use crate::{format_table, metadata_value, sort_rows, sort_rows_by, write_object, write_sidecar, Args, ImageMetadata, OutputLayout, SortBy};
+
+/// Destination for extracted metadata records
+pub trait Sink {
+    /// Handle the record for one input
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()>;
+
+    /// Called once after all inputs, for sinks that need to see every record
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a .json sidecar per image, or with --layout cas an object in the
+/// content-addressable store, whose index is written at the end
+pub struct SidecarSink<'a> {
+    args: &'a Args,
+    /// Opened on the first record, so runs that write no sidecars never read it
+    index: Option<cas::Index>,
+}
+
+impl<'a> SidecarSink<'a> {
+    pub fn new(args: &'a Args) -> Self {
+        SidecarSink { args, index: None }
+    }
+}
+
+impl Sink for SidecarSink<'_> {
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()> {
+        if self.args.layout != OutputLayout::Cas {
+            return write_sidecar(job, &metadata, self.args);
+        }
+        let index = match &mut self.index {
+            Some(index) => index,
+            None => self.index.insert(cas::Index::open(&self.args.store_dir)?),
+        };
+        write_object(job, &metadata, self.args, index)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.index.as_ref().filter(|_| !self.args.dry_run) {
+            Some(index) => index.write(self.args.durable),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Collects rows and prints them as one aligned table at the end, since column
+/// widths and sorting depend on every row
+pub struct TableSink {
+    rows: Vec<ImageMetadata>,
+    sort_by: SortBy,
+    zone: Zone,
+    locale: Locale,
+    /// Print the header even when no rows were collected
+    always: bool,
+}
+
+impl TableSink {
+    pub fn new(sort_by: SortBy, zone: Zone, locale: Locale, always: bool) -> Self {
+        TableSink { rows: Vec::new(), sort_by, zone, locale, always }
+    }
+}
+
+impl Sink for TableSink {
+    fn write(&mut self, _job: &Job, metadata: ImageMetadata) -> Result<()> {
+        self.rows.push(metadata);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if !self.rows.is_empty() || self.always {
+            sort_rows(&mut self.rows, self.sort_by);
+            print!("{}", format_table(&self.rows, self.zone, &self.locale));
+        }
+        Ok(())
+    }
+}
+
+/// Holds every record back so bursts, events and derivatives can be found across files;
+/// once finished, the records carry their `burst_group_id`, `event_id` and
+/// `original_of`/`derivative_of` relations and go to their output sinks
+pub struct GroupingSink {
+    records: Vec<(Job, ImageMetadata)>,
+    /// Longest gap between frames of a burst, when detecting bursts
+    burst_gap: Option<chrono::Duration>,
+    /// Longest gap and distance in km between photos of an event, when clustering events
+    event_limits: Option<(chrono::Duration, Option<f64>)>,
+    /// Most bits apart the perceptual hashes of copies may be, when linking derivatives
+    derivative_distance: Option<u32>,
+    events: Vec<Event>,
+}
+
+impl GroupingSink {
+    pub fn new(
+        burst_gap: Option<chrono::Duration>,
+        event_limits: Option<(chrono::Duration, Option<f64>)>,
+        derivative_distance: Option<u32>,
+    ) -> Self {
+        GroupingSink { records: Vec::new(), burst_gap, event_limits, derivative_distance, events: Vec::new() }
+    }
+
+    /// Summaries of the events found, once finished
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn into_records(self) -> Vec<(Job, ImageMetadata)> {
+        self.records
+    }
+}
+
+impl Sink for GroupingSink {
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()> {
+        self.records.push((job.clone(), metadata));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(max_gap) = self.burst_gap {
+            let frames: Vec<Frame> = self.records.iter()
+                .map(|(_, m)| Frame {
+                    camera: (m.camera_model.as_deref(), m.camera_serial.as_deref()),
+                    capture_time: m.capture_time,
+                    sequence_number: m.sequence_number,
+                    file_number: burst::file_number(&m.filename),
+                })
+                .collect();
+            let ids = burst::detect(&frames, max_gap);
+            for ((_, metadata), id) in self.records.iter_mut().zip(ids) {
+                if id.is_some() && !metadata.provenance.is_empty() {
+                    metadata.provenance.insert("burst_group_id".to_string(), Source::derived([]));
+                }
+                metadata.burst_group_id = id;
+            }
+        }
+        if let Some((max_gap, max_distance)) = self.event_limits {
+            let photos: Vec<Photo> = self.records.iter()
+                .map(|(_, m)| Photo {
+                    capture_time: m.capture_time,
+                    position: m.gps.as_ref().map(|gps| (gps.latitude, gps.longitude)),
+                })
+                .collect();
+            let (ids, events) = events::cluster(&photos, max_gap, max_distance);
+            for ((_, metadata), id) in self.records.iter_mut().zip(ids) {
+                if id.is_some() && !metadata.provenance.is_empty() {
+                    metadata.provenance.insert("event_id".to_string(), Source::derived([]));
+                }
+                metadata.event_id = id;
+            }
+            self.events = events;
+        }
+        if let Some(max_distance) = self.derivative_distance {
+            let images: Vec<Image> = self.records.iter()
+                .map(|(_, m)| Image {
+                    unique_id: m.image_unique_id.as_deref(),
+                    capture_time: m.capture_time,
+                    perceptual_hash: m.perceptual_hash.as_deref().and_then(derivatives::parse_hash),
+                    pixels: m.width.zip(m.height).map(|(w, h)| w as u64 * h as u64),
+                    size: m.size,
+                })
+                .collect();
+            let originals = derivatives::link(&images, max_distance);
+            let paths: Vec<String> = self.records.iter().map(|(job, _)| filesystem::path_text(job.path.as_os_str())).collect();
+            for (i, original) in originals.into_iter().enumerate() {
+                let Some(original) = original else { continue };
+                self.records[original].1.original_of.push(paths[i].clone());
+                self.records[i].1.derivative_of = Some(paths[original].clone());
+            }
+            for (_, metadata) in self.records.iter_mut().filter(|(_, m)| !m.provenance.is_empty()) {
+                for (field, present) in [("original_of", !metadata.original_of.is_empty()), ("derivative_of", metadata.derivative_of.is_some())] {
+                    if present {
+                        metadata.provenance.insert(field.to_string(), Source::derived([]));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streams one JSON object per line as each record completes. Every line is
+/// flushed immediately, so memory use stays flat and a slow reader throttles the scan.
+pub struct JsonLinesSink<'a, W: Write> {
+    writer: W,
+    args: &'a Args,
+    /// With --camera-registry, the cameras already written
+    cameras: Option<CameraRegistry>,
+}
+
+impl<'a, W: Write> JsonLinesSink<'a, W> {
+    pub fn new(writer: W, args: &'a Args) -> Self {
+        JsonLinesSink { writer, args, cameras: args.camera_registry.then(CameraRegistry::default) }
+    }
+
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for JsonLinesSink<'_, W> {
+    fn write(&mut self, job: &Job, mut metadata: ImageMetadata) -> Result<()> {
+        if let Some(camera) = self.cameras.as_mut().and_then(|cameras| cameras.register(&mut metadata)) {
+            serde_json::to_writer(&mut self.writer, &serde_json::json!({ "camera": camera }))?;
+            writeln!(self.writer)?;
+        }
+        let value = signed_value(job, &metadata, self.args)?;
+        serde_json::to_writer(&mut self.writer, &value)?;
+        writeln!(self.writer)?;
+        self.writer.flush().context("Failed to write JSON Lines output")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush().context("Failed to write JSON Lines output")
+    }
+}
+
+/// Collects records and prints them as one JSON array in the layout of
+/// `exiftool -json`, so scripts written against exiftool can read it
+pub struct ExiftoolSink<W: Write> {
+    writer: W,
+    rows: Vec<(Job, ImageMetadata)>,
+    sort_by: SortBy,
+    /// Print the empty array even when no rows were collected
+    always: bool,
+}
+
+impl<W: Write> ExiftoolSink<W> {
+    pub fn new(writer: W, sort_by: SortBy, always: bool) -> Self {
+        ExiftoolSink { writer, rows: Vec::new(), sort_by, always }
+    }
+
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for ExiftoolSink<W> {
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()> {
+        self.rows.push((job.clone(), metadata));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.rows.is_empty() && !self.always {
+            return Ok(());
+        }
+        sort_rows_by(&mut self.rows, self.sort_by, |(_, m)| m);
+        let objects = self.rows.iter()
+            .map(|(job, metadata)| {
+                let value = serde_json::to_value(metadata)?;
+                Ok(compat::exiftool_object(&value, &job.path.display().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        serde_json::to_writer_pretty(&mut self.writer, &objects)?;
+        writeln!(self.writer)?;
+        self.writer.flush().context("Failed to write exiftool output")
+    }
+}
+
+/// Upserts each record into the --db catalog, keyed by its input path
+pub struct CatalogSink<'a> {
+    catalog: Catalog,
+    args: &'a Args,
+    /// With --camera-registry, the cameras already added
+    cameras: Option<CameraRegistry>,
+}
+
+impl<'a> CatalogSink<'a> {
+    pub fn new(catalog: Catalog, args: &'a Args) -> Self {
+        CatalogSink { catalog, args, cameras: args.camera_registry.then(CameraRegistry::default) }
+    }
+}
+
+impl Sink for CatalogSink<'_> {
+    fn write(&mut self, job: &Job, mut metadata: ImageMetadata) -> Result<()> {
+        if let Some(camera) = self.cameras.as_mut().and_then(|cameras| cameras.register(&mut metadata)) {
+            self.catalog.add_camera(camera);
+        }
+        let value = signed_value(job, &metadata, self.args)?;
+        self.catalog.add(filesystem::path_text(job.path.as_os_str()), value)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.catalog.flush()
+    }
+}
+
+/// Publishes each record to the --publish queue as soon as it completes
+#[cfg(feature = "queue")]
+pub struct QueueSink<'a> {
+    publisher: Publisher,
+    args: &'a Args,
+}
+
+#[cfg(feature = "queue")]
+impl<'a> QueueSink<'a> {
+    pub fn new(publisher: Publisher, args: &'a Args) -> Self {
+        QueueSink { publisher, args }
+    }
+}
+
+#[cfg(feature = "queue")]
+impl Sink for QueueSink<'_> {
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()> {
+        let value = signed_value(job, &metadata, self.args)?;
+        self.publisher.publish(&filesystem::path_text(job.path.as_os_str()), &value)
+    }
+}
+
+/// The sinks every record goes to besides its output format's: the --publish
+/// queue and the --db catalog, each connected up front so a bad URL fails the
+/// run before any file is read, then the --exec command. With --dry-run only
+/// the command, which lists what it would run.
+pub fn exports(args: &Args) -> Result<Vec<Box<dyn Sink + '_>>> {
+    let mut sinks: Vec<Box<dyn Sink + '_>> = Vec::new();
+    if !args.dry_run {
+        #[cfg(feature = "queue")]
+        if let Some(endpoint) = &args.publish {
+            sinks.push(Box::new(QueueSink::new(Publisher::connect(endpoint)?, args)));
+        }
+        if let Some(url) = &args.db {
+            sinks.push(Box::new(CatalogSink::new(Catalog::open(url)?, args)));
+        }
+    }
+    if let Some(command) = &args.exec {
+        sinks.push(Box::new(ExecSink::new(command.clone(), args)));
+    }
+    Ok(sinks)
+}
+
+/// Hands each record to several sinks in turn, e.g. a job's output format's and the exports
+pub struct Fanout<'a> {
+    sinks: Vec<&'a mut dyn Sink>,
+}
+
+impl<'a> Fanout<'a> {
+    pub fn new(sinks: impl IntoIterator<Item = &'a mut dyn Sink>) -> Self {
+        Fanout { sinks: sinks.into_iter().collect() }
+    }
+}
+
+impl Sink for Fanout<'_> {
+    fn write(&mut self, job: &Job, metadata: ImageMetadata) -> Result<()> {
+        let Some((last, rest)) = self.sinks.split_last_mut() else { return Ok(()) };
+        for sink in rest {
+            sink.write(job, metadata.clone())?;
+        }
+        last.write(job, metadata)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.finish())
+    }
+}
+
+/// A record's JSON as in its sidecar, signed with --sign
+fn signed_value(job: &Job, metadata: &ImageMetadata, args: &Args) -> Result<serde_json::Value> {
+    let mut value = metadata_value(job, metadata, args)?;
+    if let Some(key) = &args.sign {
+        key.sign(&mut value);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract_metadata, ExtractorRegistry};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_json_lines_sink() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--format", "jsonl", "--time-format", "unix", "images"]);
+        let registry = ExtractorRegistry::new();
+        let mut out = Vec::new();
+        let mut sink = JsonLinesSink::new(&mut out, &args);
+        for name in ["images/JAM19896.jpg", "images/JAM26284.jpg"] {
+            let job = Job::new(PathBuf::from(name));
+            let metadata = extract_metadata(&job.path, &args, &registry).unwrap();
+            sink.write(&job, metadata).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["file"]["filename"], "JAM26284.jpg");
+        assert!(lines[1]["file"]["modified_time"].is_i64());
+
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--format", "jsonl", "--flat", "images"]);
+        let mut out = Vec::new();
+        let job = Job::new(PathBuf::from("images/JAM26284.jpg"));
+        JsonLinesSink::new(&mut out, &args).write(&job, extract_metadata(&job.path, &args, &registry).unwrap()).unwrap();
+        let flat: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(flat["filename"], "JAM26284.jpg");
+    }
+
+    #[test]
+    fn test_camera_registry() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--format", "jsonl", "--camera-registry", "--provenance", "images"]);
+        let registry = ExtractorRegistry::new();
+        let mut out = Vec::new();
+        let mut sink = JsonLinesSink::new(&mut out, &args);
+        for name in ["images/JAM26284.jpg", "images/JAM26284.jpg"] {
+            let job = Job::new(PathBuf::from(name));
+            sink.write(&job, extract_metadata(&job.path, &args, &registry).unwrap()).unwrap();
+        }
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        let camera = &lines[0]["camera"];
+        assert_eq!(camera["model"], "Canon EOS 5D Mark IV");
+        assert_eq!(camera["serial"], "025021000535");
+        assert_eq!(camera["firmware"], "1.0.1");
+        for record in &lines[1..] {
+            assert_eq!(record["exif"]["camera_id"], camera["id"]);
+            assert!(record["exif"].get("camera_model").is_none());
+            assert!(record["provenance"]["exif.camera_id"].is_object());
+        }
+    }
+
+    #[test]
+    fn test_exiftool_sink() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--format", "exiftool", "images"]);
+        let registry = ExtractorRegistry::new();
+        let mut out = Vec::new();
+        let mut sink = ExiftoolSink::new(&mut out, SortBy::Name, true);
+        for name in ["images/JAM26284.jpg", "images/JAM19896.jpg"] {
+            let job = Job::new(PathBuf::from(name));
+            sink.write(&job, extract_metadata(&job.path, &args, &registry).unwrap()).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let objects: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(objects[0]["SourceFile"], "images/JAM19896.jpg");
+        assert_eq!(objects[1]["Model"], "Canon EOS 5D Mark IV");
+        assert_eq!(objects[1]["MIMEType"], "image/jpeg");
+    }
+
+    #[test]
+    fn test_grouping_sink() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--detect-bursts", "images"]);
+        let registry = ExtractorRegistry::new();
+        let job = Job::new(PathBuf::from("images/JAM26284.jpg"));
+        let metadata = extract_metadata(&job.path, &args, &registry).unwrap();
+        let mut next = extract_metadata(&job.path, &args, &registry).unwrap();
+        next.filename = "JAM26285.jpg".to_string();
+        next.capture_time = metadata.capture_time.map(|t| t + chrono::Duration::milliseconds(200));
+        let mut later = extract_metadata(&job.path, &args, &registry).unwrap();
+        later.filename = "JAM26400.jpg".to_string();
+        later.capture_time = metadata.capture_time.map(|t| t + chrono::Duration::hours(3));
+
+        let mut sink = GroupingSink::new(Some(chrono::Duration::seconds(1)), Some((chrono::Duration::hours(2), None)), None);
+        sink.write(&job, next).unwrap();
+        sink.write(&job, later).unwrap();
+        sink.write(&job, metadata).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(sink.events().iter().map(|e| e.photos).collect::<Vec<_>>(), [2, 1]);
+        let ids: Vec<_> = sink.into_records().into_iter().map(|(_, m)| (m.burst_group_id, m.event_id)).collect();
+        let id = |n: &str| Some(n.to_string());
+        assert_eq!(ids, [(id("burst-1"), id("event-1")), (None, id("event-2")), (id("burst-1"), id("event-1"))]);
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_link_derivatives() {
+        let args = Args::parse_from(["jpeg-metadata-extractor", "--link-derivatives", "images"]);
+        let registry = ExtractorRegistry::new();
+        let original = Job::new(PathBuf::from("images/JAM26284.jpg"));
+        let metadata = extract_metadata(&original.path, &args, &registry).unwrap();
+        assert_eq!(metadata.perceptual_hash.as_ref().map(String::len), Some(16));
+        let mut resized = extract_metadata(&original.path, &args, &registry).unwrap();
+        (resized.width, resized.height) = (metadata.width.map(|w| w / 4), metadata.height.map(|h| h / 4));
+        let other = Job::new(PathBuf::from("images/JAM19896.jpg"));
+
+        let mut sink = GroupingSink::new(None, None, Some(6));
+        sink.write(&Job::new(PathBuf::from("web/JAM26284.jpg")), resized).unwrap();
+        sink.write(&original, metadata).unwrap();
+        sink.write(&other, extract_metadata(&other.path, &args, &registry).unwrap()).unwrap();
+        sink.finish().unwrap();
+        let links: Vec<_> = sink.into_records().into_iter().map(|(_, m)| (m.original_of, m.derivative_of)).collect();
+        assert_eq!(links, [
+            (vec![], Some("images/JAM26284.jpg".to_string())),
+            (vec!["web/JAM26284.jpg".to_string()], None),
+            (vec![], None),
+        ]);
+    }
+}
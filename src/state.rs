@@ -0,0 +1,132 @@
+use crate::archives;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One completed input and the file state it was processed from
+#[derive(Serialize, Deserialize)]
+struct Completed {
+    path: PathBuf,
+    size: u64,
+    modified_time: DateTime<Utc>,
+}
+
+/// Progress of a run, kept in a `--state` file so an interrupted run can resume.
+///
+/// The file holds one JSON object per completed input and is appended to and
+/// flushed as each one finishes, so a run killed at any point loses at most
+/// the line being written, which is ignored on the next load. Inputs that
+/// changed since they were recorded are processed again.
+pub struct RunState {
+    path: PathBuf,
+    done: HashMap<PathBuf, (u64, DateTime<Utc>)>,
+    file: File,
+    /// Completions held back until [`RunState::flush`], for output that is only
+    /// written once every input has been read
+    deferred: Option<RefCell<Vec<Completed>>>,
+}
+
+impl RunState {
+    /// Load the state file, creating it if needed. With `defer`, completions are
+    /// only saved by [`RunState::flush`].
+    pub fn open(path: &Path, defer: bool) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read state file {}", path.display())),
+        };
+        let done = text.lines()
+            .filter_map(|line| serde_json::from_str::<Completed>(line).ok())
+            .map(|c| (c.path, (c.size, c.modified_time)))
+            .collect();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open state file {}", path.display()))?;
+        // Start on a fresh line if the last run died mid-write
+        if !text.is_empty() && !text.ends_with('\n') {
+            writeln!(file).with_context(|| format!("Failed to write state file {}", path.display()))?;
+        }
+        Ok(RunState { path: path.to_path_buf(), done, file, deferred: defer.then(RefCell::default) })
+    }
+
+    /// Whether `path` was completed by an earlier run and is unchanged since
+    pub fn is_done(&self, path: &Path) -> bool {
+        let recorded = self.done.get(path);
+        recorded.is_some() && stamp(path).is_some_and(|stamp| recorded == Some(&stamp))
+    }
+
+    /// Record `path` as complete
+    pub fn record(&self, path: &Path) -> Result<()> {
+        let Some((size, modified_time)) = stamp(path) else {
+            return Ok(());
+        };
+        let completed = Completed { path: path.to_path_buf(), size, modified_time };
+        match &self.deferred {
+            Some(deferred) => {
+                deferred.borrow_mut().push(completed);
+                Ok(())
+            }
+            None => self.append(&[completed]),
+        }
+    }
+
+    /// Save completions held back by `defer`
+    pub fn flush(&self) -> Result<()> {
+        let deferred = self.deferred.as_ref().map(|d| d.take()).unwrap_or_default();
+        self.append(&deferred)
+    }
+
+    fn append(&self, completed: &[Completed]) -> Result<()> {
+        let mut lines = String::new();
+        for entry in completed {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        let mut file = &self.file;
+        file.write_all(lines.as_bytes())
+            .and_then(|()| file.flush())
+            .with_context(|| format!("Failed to write state file {}", self.path.display()))
+    }
+}
+
+/// Size and modification time of a file, or of the archive holding it
+fn stamp(path: &Path) -> Option<(u64, DateTime<Utc>)> {
+    let file = match archives::archive_input(path) {
+        Some((archive, Some(_))) => archive,
+        _ => path.to_path_buf(),
+    };
+    let metadata = fs::metadata(file).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume() {
+        let dir = std::env::temp_dir().join(format!("jme-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("a.jpg");
+        fs::write(&image, "one").unwrap();
+        let state_path = dir.join("run.json");
+
+        let state = RunState::open(&state_path, false).unwrap();
+        assert!(!state.is_done(&image));
+        state.record(&image).unwrap();
+        // A torn final line from a killed run is ignored
+        fs::OpenOptions::new().append(true).open(&state_path).unwrap().write_all(b"{\"path\":").unwrap();
+
+        let resumed = RunState::open(&state_path, true).unwrap();
+        assert_eq!(resumed.done.len(), 1);
+        assert!(resumed.is_done(&image));
+        fs::write(&image, "changed").unwrap();
+        let changed = !RunState::open(&state_path, false).unwrap().is_done(&image);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(changed);
+    }
+}
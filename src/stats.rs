@@ -0,0 +1,200 @@
+use crate::error::Result;
+use crate::locale::Locale;
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
+use exif::{In, Reader, Tag, Value};
+use serde_json::{json, Map};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Seek};
+
+/// Width of the longest bar in the console report
+const BAR_WIDTH: usize = 40;
+
+/// The EXIF settings of one photo that batch statistics count
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Shot {
+    pub camera: Option<String>,
+    pub capture_time: Option<DateTime<Utc>>,
+    /// Millimetres
+    pub focal_length: Option<f64>,
+    /// F-number
+    pub aperture: Option<f64>,
+    pub iso: Option<u32>,
+}
+
+impl Shot {
+    /// Read the counted settings from a JPEG's EXIF data
+    pub fn read<R: BufRead + Seek>(reader: &mut R) -> Result<Self> {
+        let exif = Reader::new().read_from_container(reader)?;
+        let rational = |tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(v) => v.first().filter(|r| r.denom != 0).map(|r| r.to_f64()),
+            _ => None,
+        };
+        let camera = exif.get_field(Tag::Model, In::PRIMARY).and_then(|f| match &f.value {
+            Value::Ascii(v) => v.first().map(|s| String::from_utf8_lossy(s).trim_end_matches('\0').trim().to_string()),
+            _ => None,
+        });
+        let capture_time = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).and_then(|f| match &f.value {
+            Value::Ascii(v) => {
+                let text = String::from_utf8_lossy(v.first()?).to_string();
+                chrono::NaiveDateTime::parse_from_str(text.trim_end_matches('\0'), "%Y:%m:%d %H:%M:%S").ok()
+            }
+            _ => None,
+        });
+        Ok(Shot {
+            camera: camera.filter(|c| !c.is_empty()),
+            capture_time: capture_time.map(|t| t.and_utc()),
+            focal_length: rational(Tag::FocalLength).filter(|&f| f > 0.0),
+            aperture: rational(Tag::FNumber).filter(|&n| n > 0.0),
+            iso: exif.get_field(Tag::PhotographicSensitivity, In::PRIMARY).and_then(|f| f.value.get_uint(0)),
+        })
+    }
+}
+
+/// Counts over a batch of photos
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    files: usize,
+    cameras: BTreeMap<String, usize>,
+    /// Rounded to whole millimetres
+    focal_lengths: BTreeMap<u32, usize>,
+    /// In tenths of a stop number, so f/2.8 is 28
+    apertures: BTreeMap<u32, usize>,
+    isos: BTreeMap<u32, usize>,
+    /// Keyed by capture time truncated to the hour
+    shots_per_hour: BTreeMap<NaiveDateTime, usize>,
+}
+
+impl Stats {
+    pub fn add(&mut self, shot: &Shot) {
+        self.files += 1;
+        let camera = shot.camera.clone().unwrap_or_else(|| "unknown".to_string());
+        *self.cameras.entry(camera).or_default() += 1;
+        if let Some(focal_length) = shot.focal_length {
+            *self.focal_lengths.entry(focal_length.round() as u32).or_default() += 1;
+        }
+        if let Some(aperture) = shot.aperture {
+            *self.apertures.entry((aperture * 10.0).round() as u32).or_default() += 1;
+        }
+        if let Some(iso) = shot.iso {
+            *self.isos.entry(iso).or_default() += 1;
+        }
+        if let Some(time) = shot.capture_time {
+            let hour = time.naive_utc().date().and_hms_opt(time.hour(), 0, 0).unwrap();
+            *self.shots_per_hour.entry(hour).or_default() += 1;
+        }
+    }
+
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Histograms as JSON objects, in ascending key order
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "files": self.files,
+            "cameras": histogram(self.cameras.iter().map(|(k, &v)| (k.clone(), v))),
+            "focal_lengths": histogram(self.focal_lengths.iter().map(|(k, &v)| (k.to_string(), v))),
+            "apertures": histogram(self.apertures.iter().map(|(&k, &v)| (aperture_label(k, &Locale::default()), v))),
+            "isos": histogram(self.isos.iter().map(|(k, &v)| (k.to_string(), v))),
+            "shots_per_hour": histogram(self.shots_per_hour.iter().map(|(k, &v)| (k.format("%Y-%m-%d %H:00").to_string(), v))),
+        })
+    }
+
+    /// Sections of the console report as (title, rows)
+    fn sections(&self, locale: &Locale) -> [(&str, Vec<(String, usize)>); 5] {
+        [
+            ("Cameras", self.cameras.iter().map(|(k, &v)| (k.clone(), v)).collect()),
+            ("Focal length (mm)", self.focal_lengths.iter().map(|(k, &v)| (k.to_string(), v)).collect()),
+            ("Aperture", self.apertures.iter().map(|(&k, &v)| (aperture_label(k, locale), v)).collect()),
+            ("ISO", self.isos.iter().map(|(k, &v)| (k.to_string(), v)).collect()),
+            ("Shots per hour", self.shots_per_hour.iter().map(|(k, &v)| (locale.datetime(*k, false), v)).collect()),
+        ]
+    }
+
+    /// Console report with a bar chart per histogram, numbers and dates written for `locale`
+    pub fn report(&self, locale: &Locale) -> String {
+        let mut out = format!("Files: {}\n", locale.integer(self.files as u64));
+        for (title, rows) in self.sections(locale) {
+            if rows.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\n{}:\n", title));
+            let label_width = rows.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+            let max = rows.iter().map(|&(_, count)| count).max().unwrap_or(1);
+            for (label, count) in &rows {
+                let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max));
+                let padding = " ".repeat(label_width - label.chars().count());
+                out.push_str(&format!("  {}{}  {:>5}  {}\n", label, padding, locale.integer(*count as u64), bar));
+            }
+        }
+        out
+    }
+}
+
+fn histogram(entries: impl Iterator<Item = (String, usize)>) -> serde_json::Value {
+    entries.map(|(k, v)| (k, v.into())).collect::<Map<_, _>>().into()
+}
+
+/// `f/2.8` from 28; whole stops print without a decimal, as `f/8`
+fn aperture_label(tenths: u32, locale: &Locale) -> String {
+    if tenths.is_multiple_of(10) {
+        format!("f/{}", tenths / 10)
+    } else {
+        format!("f/{}", locale.decimal(f64::from(tenths) / 10.0, 1))
+    }
+}
+
+impl fmt::Display for Stats {
+    /// Console report with a bar chart per histogram
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.report(&Locale::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_read_shot() {
+        let mut reader = std::io::BufReader::new(std::fs::File::open("images/JAM26284.jpg").unwrap());
+        let shot = Shot::read(&mut reader).unwrap();
+        assert_eq!(shot.camera.as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert!(shot.capture_time.is_some() && shot.iso.is_some() && shot.focal_length.is_some());
+    }
+
+    #[test]
+    fn test_stats() {
+        let at = |h| Some(Utc.with_ymd_and_hms(2024, 5, 10, h, 15, 0).unwrap());
+        let shot = |focal_length, aperture, iso, hour| Shot {
+            camera: Some("X-T5".to_string()),
+            capture_time: at(hour),
+            focal_length: Some(focal_length),
+            aperture: Some(aperture),
+            iso: Some(iso),
+        };
+        let mut stats = Stats::default();
+        stats.add(&shot(23.0, 2.8, 200, 9));
+        stats.add(&shot(56.0, 8.0, 100, 9));
+        stats.add(&shot(23.4, 2.8, 3200, 14));
+        stats.add(&Shot::default());
+
+        let json = stats.to_json();
+        assert_eq!(json["files"], 4);
+        assert_eq!(json["cameras"], json!({"X-T5": 3, "unknown": 1}));
+        assert_eq!(json["focal_lengths"], json!({"23": 2, "56": 1}));
+        assert_eq!(json["apertures"], json!({"f/2.8": 2, "f/8": 1}));
+        assert_eq!(json["shots_per_hour"], json!({"2024-05-10 09:00": 2, "2024-05-10 14:00": 1}));
+        // ISO keys stay in numeric order
+        let isos: Vec<&String> = json["isos"].as_object().unwrap().keys().collect();
+        assert_eq!(isos, ["100", "200", "3200"]);
+
+        let report = stats.to_string();
+        assert!(report.starts_with("Files: 4\n"));
+        assert!(report.contains(&format!("  f/2.8      2  {}\n", "#".repeat(BAR_WIDTH))));
+        let german = stats.report(&Locale::parse("de").unwrap());
+        assert!(german.contains("  f/2,8  ") && german.contains("  10. Mai 2024 14:00  "));
+    }
+}
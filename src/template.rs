@@ -0,0 +1,436 @@
+use crate::error::{ExtractError, Result};
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use std::cmp::Ordering;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// A string with `{field:format|filter}` placeholders filled in from a metadata
+/// record, e.g. `{capture_time:%Y%m%d}-{camera_serial|hash8}`.
+///
+/// Fields are dotted paths into the record (`gps.latitude`, `exif_extra.0x0110`),
+/// or date functions of them: `age_days(capture_time)`, the whole days since a
+/// timestamp, and `format(capture_time, "%Y-%m")`. Formats are strftime patterns
+/// for timestamps, `.N` for decimal places or `0N` for zero padding. Filters are
+/// `lower`, `upper`, `slug`, `hashN` (the first N hex digits of a SHA-256) and
+/// `default(text)` for a missing field. `{{` and `}}` are literal braces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Field { expr: Expr, format: Option<String>, filters: Vec<Filter> },
+}
+
+/// A value computed from a record: a field, a literal or a function call
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Field(Vec<String>),
+    Text(String),
+    Number(f64),
+    /// Whole days from the timestamp to now
+    AgeDays(Box<Expr>),
+    /// The timestamp in a strftime pattern
+    Format(Box<Expr>, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Filter {
+    Lower,
+    Upper,
+    Slug,
+    Hash(usize),
+    Default(String),
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                ('{', _) => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(invalid(format!("unclosed '{{' in {:?}", text))),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&placeholder)?);
+                }
+                ('}', _) => return Err(invalid(format!("unmatched '}}' in {:?}", text))),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Dotted paths of the fields the template reads, in order
+    pub fn fields(&self) -> Vec<String> {
+        self.parts.iter()
+            .filter_map(|part| match part {
+                Part::Field { expr, .. } => expr.field(),
+                Part::Text(_) => None,
+            })
+            .map(|path| path.join("."))
+            .collect()
+    }
+
+    /// Fill in the template from `record`, or `None` if a field it uses is
+    /// missing and has no `default`
+    pub fn render(&self, record: &Value) -> Option<String> {
+        self.render_at(record, Utc::now())
+    }
+
+    /// Like [`Template::render`], measuring ages up to `now`
+    pub fn render_at(&self, record: &Value, now: DateTime<Utc>) -> Option<String> {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field { expr, format, filters } => {
+                    let value = expr.evaluate(record, now).map(|v| apply_format(&v, format.as_deref()));
+                    let mut text = match value {
+                        Some(text) => text,
+                        None => filters.iter().find_map(|f| match f {
+                            Filter::Default(text) => Some(text.clone()),
+                            _ => None,
+                        })?,
+                    };
+                    for filter in filters {
+                        text = filter.apply(&text);
+                    }
+                    out.push_str(&text);
+                }
+            }
+        }
+        Some(out)
+    }
+}
+
+fn invalid(reason: String) -> ExtractError {
+    ExtractError::parse("template", reason)
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part> {
+    let (expr, rest) = Expr::parse(placeholder).map_err(|e| match e {
+        ExtractError::ParseError { reason, .. } => invalid(format!("{} in {{{}}}", reason, placeholder)),
+        e => e,
+    })?;
+    if !rest.is_empty() && !rest.starts_with([':', '|']) {
+        return Err(invalid(format!("unexpected {:?} in {{{}}}", rest, placeholder)));
+    }
+    let mut pieces = rest.split('|');
+    let format = pieces.next().and_then(|head| head.strip_prefix(':')).map(str::to_string);
+    if let Some(format) = &format {
+        let valid = if format.starts_with('%') {
+            StrftimeItems::new(format).parse().is_ok()
+        } else {
+            number_format(format).is_some()
+        };
+        if !valid {
+            return Err(invalid(format!("format {:?} in {{{}}}", format, placeholder)));
+        }
+    }
+    let filters = pieces.map(Filter::parse).collect::<Result<_>>()?;
+    Ok(Part::Field { expr, format, filters })
+}
+
+impl Expr {
+    /// Parse an expression from the start of `text`, returning it and the rest of
+    /// `text` after any whitespace
+    fn parse(text: &str) -> Result<(Self, &str)> {
+        let text = text.trim_start();
+        if let Some(quoted) = text.strip_prefix('"') {
+            let (literal, rest) = quoted.split_once('"').ok_or_else(|| invalid("unclosed '\"'".to_string()))?;
+            return Ok((Expr::Text(literal.to_string()), rest.trim_start()));
+        }
+        let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))).unwrap_or(text.len());
+        let (word, rest) = text.split_at(end);
+        let rest = rest.trim_start();
+        if word.is_empty() {
+            return Err(invalid("empty field name".to_string()));
+        }
+        if word.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            let number = word.parse().map_err(|_| invalid(format!("{:?} is not a number", word)))?;
+            return Ok((Expr::Number(number), rest));
+        }
+        let Some(mut rest) = rest.strip_prefix('(') else {
+            return Ok((Expr::Field(word.split('.').map(str::to_string).collect()), rest));
+        };
+        let mut args = Vec::new();
+        loop {
+            let (arg, after) = Expr::parse(rest)?;
+            args.push(arg);
+            match after.chars().next() {
+                Some(',') => rest = &after[1..],
+                Some(')') => {
+                    rest = after[1..].trim_start();
+                    break;
+                }
+                _ => return Err(invalid(format!("expected ',' or ')' after the arguments of {}", word))),
+            }
+        }
+        let expr = match (word, <[Expr; 1]>::try_from(args)) {
+            ("age_days", Ok([arg])) => Expr::AgeDays(Box::new(arg)),
+            ("format", Err(args)) => match <[Expr; 2]>::try_from(args) {
+                Ok([arg, Expr::Text(pattern)]) if StrftimeItems::new(&pattern).parse().is_ok() => Expr::Format(Box::new(arg), pattern),
+                _ => return Err(invalid("format takes a timestamp and a strftime pattern, as in format(capture_time, \"%Y-%m\")".to_string())),
+            },
+            ("age_days", _) => return Err(invalid("age_days takes one timestamp".to_string())),
+            _ => return Err(invalid(format!("unknown function {:?}", word))),
+        };
+        Ok((expr, rest))
+    }
+
+    /// The field the expression reads, directly or through a function
+    fn field(&self) -> Option<&[String]> {
+        match self {
+            Expr::Field(path) => Some(path),
+            Expr::AgeDays(arg) | Expr::Format(arg, _) => arg.field(),
+            Expr::Text(_) | Expr::Number(_) => None,
+        }
+    }
+
+    /// The value of the expression for `record`, or `None` if a field it uses is
+    /// missing or is not a timestamp where one is needed
+    fn evaluate(&self, record: &Value, now: DateTime<Utc>) -> Option<Value> {
+        match self {
+            Expr::Field(path) => lookup(record, path).cloned(),
+            Expr::Text(text) => Some(Value::String(text.clone())),
+            Expr::Number(n) => serde_json::Number::from_f64(*n).map(Value::Number),
+            Expr::AgeDays(arg) => {
+                let time = parse_time(arg.evaluate(record, now)?.as_str()?)?;
+                Some(Value::from((now - time.with_timezone(&Utc)).num_days()))
+            }
+            Expr::Format(arg, pattern) => {
+                let time = parse_time(arg.evaluate(record, now)?.as_str()?)?;
+                Some(Value::String(time.format(pattern).to_string()))
+            }
+        }
+    }
+}
+
+/// A comparison of two expressions that a record passes or fails, e.g.
+/// `age_days(capture_time) > 365` or `camera_model == "X-T5"`. Operators are
+/// `==`, `!=`, `<`, `<=`, `>` and `>=`. Numbers compare as numbers, timestamps
+/// in time order and anything else as text; a record missing a field fails.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    left: Expr,
+    operator: &'static str,
+    right: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self> {
+        let reason = |e| match e {
+            ExtractError::ParseError { reason, .. } => ExtractError::parse("condition", format!("{} in {:?}", reason, text)),
+            e => e,
+        };
+        let (left, rest) = Expr::parse(text).map_err(reason)?;
+        let operator = ["==", "!=", "<=", ">=", "<", ">"].into_iter()
+            .find(|op| rest.starts_with(op))
+            .ok_or_else(|| ExtractError::parse("condition", format!("expected a comparison such as age_days(capture_time) > 365, not {:?}", text)))?;
+        let (right, rest) = Expr::parse(&rest[operator.len()..]).map_err(reason)?;
+        if !rest.is_empty() {
+            return Err(ExtractError::parse("condition", format!("unexpected {:?} in {:?}", rest, text)));
+        }
+        Ok(Condition { left, operator, right })
+    }
+
+    /// Whether `record` passes the comparison
+    pub fn matches(&self, record: &Value) -> bool {
+        self.matches_at(record, Utc::now())
+    }
+
+    /// Like [`Condition::matches`], measuring ages up to `now`
+    pub fn matches_at(&self, record: &Value, now: DateTime<Utc>) -> bool {
+        let (Some(left), Some(right)) = (self.left.evaluate(record, now), self.right.evaluate(record, now)) else {
+            return false;
+        };
+        let text = |value: &Value| apply_format(value, None);
+        let ordering = match (left.as_f64(), right.as_f64()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => match (parse_time(&text(&left)), parse_time(&text(&right))) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => Some(text(&left).cmp(&text(&right))),
+            },
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.operator {
+            "==" => ordering == Ordering::Equal,
+            "!=" => ordering != Ordering::Equal,
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            _ => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// An RFC 3339 timestamp, or a date and time without a zone or a bare date, taken as UTC
+fn parse_time(text: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time);
+    }
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|date| date.and_time(Default::default())))
+        .ok()
+        .map(|time| time.and_utc().fixed_offset())
+}
+
+impl Filter {
+    fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if let Some(fallback) = text.strip_prefix("default(").and_then(|t| t.strip_suffix(')')) {
+            return Ok(Filter::Default(fallback.to_string()));
+        }
+        if let Some(digits) = text.strip_prefix("hash") {
+            return match digits.parse() {
+                Ok(n @ 1..=64) => Ok(Filter::Hash(n)),
+                _ => Err(invalid(format!("hash takes 1 to 64 digits, as in hash8: {:?}", text))),
+            };
+        }
+        match text {
+            "lower" => Ok(Filter::Lower),
+            "upper" => Ok(Filter::Upper),
+            "slug" => Ok(Filter::Slug),
+            _ => Err(invalid(format!("unknown filter {:?}", text))),
+        }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match self {
+            Filter::Lower => text.to_lowercase(),
+            Filter::Upper => text.to_uppercase(),
+            // Runs of anything but ASCII letters and digits become one hyphen
+            Filter::Slug => text.split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join("-")
+                .to_lowercase(),
+            Filter::Hash(digits) => {
+                let hex: String = Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+                hex[..*digits].to_string()
+            }
+            Filter::Default(_) => text.to_string(),
+        }
+    }
+}
+
+/// `.N` decimal places or `0N` zero padding
+fn number_format(format: &str) -> Option<(bool, usize)> {
+    match format.strip_prefix('.') {
+        Some(places) => places.parse().ok().map(|n| (true, n)),
+        None => format.strip_prefix('0')?.parse().ok().map(|n| (false, n)),
+    }
+}
+
+fn lookup<'a>(record: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(record, |value, key| match value {
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            _ => value.get(key),
+        })
+        .filter(|value| !value.is_null())
+}
+
+fn apply_format(value: &Value, format: Option<&str>) -> String {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    match format {
+        None => text,
+        Some(pattern) if pattern.starts_with('%') => DateTime::parse_from_rfc3339(&text)
+            .map(|time| time.format(pattern).to_string())
+            .unwrap_or(text),
+        Some(format) => match (number_format(format), value.as_f64()) {
+            (Some((true, places)), Some(n)) => format!("{:.*}", places, n),
+            (Some((false, width)), Some(n)) if value.is_i64() || value.is_u64() => format!("{:0width$}", n as i64, width = width),
+            (Some((false, width)), Some(n)) => format!("{:0width$}", n, width = width),
+            _ => text,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let record = json!({
+            "capture_time": "2024-05-10T14:15:00Z", "camera_serial": "025021000535", "camera_model": "Canon EOS 5D Mark IV",
+            "sequence_number": 7, "gps": {"latitude": 48.123456}, "keywords": ["Paris", "night"],
+        });
+        let render = |text: &str| Template::parse(text).unwrap().render(&record);
+        let serial_hash = &Template::parse("{x|hash64}").unwrap().render(&json!({"x": "025021000535"})).unwrap()[..8];
+        assert_eq!(render("{capture_time:%Y%m%d}-{camera_serial|hash8}").unwrap(), format!("20240510-{}", serial_hash));
+        assert_eq!(render("{camera_model|slug}_{sequence_number:04}").as_deref(), Some("canon-eos-5d-mark-iv_0007"));
+        assert_eq!(render("{gps.latitude:.2} {keywords.0|upper} {{literal}}").as_deref(), Some("48.12 PARIS {literal}"));
+        assert_eq!(render("{lens}"), None);
+        assert_eq!(render("{lens|default(unknown)}").as_deref(), Some("unknown"));
+
+        assert!(Template::parse("{camera_model|reverse}").is_err());
+        assert!(Template::parse("{capture_time:%Q}").is_err());
+        assert!(Template::parse("a}").is_err() && Template::parse("{a").is_err());
+    }
+
+    #[test]
+    fn test_date_functions() {
+        let record = json!({"capture_time": "2023-05-10T23:15:00+02:00", "modified_time": "2024-05-10", "camera_model": "X-T5"});
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().with_timezone(&Utc);
+        let render = |text: &str| Template::parse(text).unwrap().render_at(&record, now);
+        assert_eq!(render("{format(capture_time, \"%Y-%m\")}/{age_days(capture_time)}").as_deref(), Some("2023-05/365"));
+        assert_eq!(render("{format( capture_time , \"%H:%M\" )|lower}").as_deref(), Some("23:15"));
+        assert_eq!(render("{age_days(modified_time):04}").as_deref(), Some("0000"));
+        assert_eq!(render("{age_days(camera_model)}"), None);
+        assert_eq!(render("{age_days(lens)|default(new)}").as_deref(), Some("new"));
+        let template = Template::parse("{format(capture_time, \"%Y\")}-{gps.latitude|default(x)}-{\"json_path\"}").unwrap();
+        assert_eq!(template.fields(), ["capture_time", "gps.latitude"]);
+
+        assert!(Template::parse("{age_days(capture_time, modified_time)}").is_err());
+        assert!(Template::parse("{format(capture_time, %Y)}").is_err());
+        assert!(Template::parse("{year(capture_time)}").is_err());
+        assert!(Template::parse("{age_days(capture_time}").is_err());
+    }
+
+    #[test]
+    fn test_conditions() {
+        let record = json!({"capture_time": "2023-05-10T23:15:00+02:00", "camera_model": "X-T5", "iso": 800});
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().with_timezone(&Utc);
+        let matches = |text: &str| Condition::parse(text).unwrap().matches_at(&record, now);
+        assert!(matches("age_days(capture_time) >= 365") && !matches("age_days(capture_time) > 365"));
+        assert!(matches("age_days(capture_time)<400"));
+        assert!(matches("camera_model == \"X-T5\"") && matches("iso <= 800") && matches("iso != 100"));
+        assert!(matches("capture_time < \"2023-05-11\"") && !matches("capture_time < \"2023-05-10T21:00:00Z\""));
+        assert!(matches("format(capture_time, \"%Y\") == \"2023\""));
+        // A missing field fails every comparison
+        assert!(!matches("age_days(lens) > 0") && !matches("lens != \"x\""));
+
+        assert!(Condition::parse("age_days(capture_time)").is_err());
+        assert!(Condition::parse("iso > 100 and").is_err());
+        assert!(Condition::parse("iso =~ 100").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::jpeg::Segment;
+use serde::{Deserialize, Serialize};
+
+/// Signature of a FLIR APP1 segment; the FFF file is split across these
+pub const FLIR_SIGNATURE: &[u8] = b"FLIR\0";
+/// Bytes before each chunk of the FFF file: signature, a version byte, chunk index and last index
+const CHUNK_HEADER_LEN: usize = 8;
+const FFF_SIGNATURE: &[u8] = b"FFF\0";
+const RECORD_RAW_DATA: u16 = 0x01;
+const RECORD_CAMERA_INFO: u16 = 0x20;
+const KELVIN_OFFSET: f64 = 273.15;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG";
+
+/// Radiometric settings of a FLIR thermal image, from its CameraInfo record.
+/// Temperatures are in degrees Celsius and distances in metres.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThermalMetadata {
+    pub vendor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lens_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emissivity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_distance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reflected_temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atmospheric_temperature: Option<f64>,
+    /// Percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_humidity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ir_window_temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ir_window_transmission: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calibration: Option<Calibration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_image: Option<RawImageInfo>,
+}
+
+/// Constants for converting raw sensor values to temperatures with Planck's law
+/// and correcting for atmospheric transmission
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub planck_r1: f64,
+    pub planck_r2: f64,
+    pub planck_b: f64,
+    pub planck_f: f64,
+    pub planck_o: f64,
+    pub atmospheric_trans_alpha1: f64,
+    pub atmospheric_trans_alpha2: f64,
+    pub atmospheric_trans_beta1: f64,
+    pub atmospheric_trans_beta2: f64,
+    pub atmospheric_trans_x: f64,
+    /// Raw sensor value range the camera reports for the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_value_min: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_value_max: Option<u16>,
+}
+
+/// Dimensions and encoding of the embedded raw thermal image
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RawImageInfo {
+    pub width: u16,
+    pub height: u16,
+    /// `png`, or `raw` for uncompressed 16-bit samples
+    pub format: String,
+}
+
+/// The raw thermal image, as a file that can be written out
+pub struct RawThermal {
+    pub width: u16,
+    pub height: u16,
+    /// The embedded 16-bit PNG as stored (FLIR writes its samples byte-swapped),
+    /// or uncompressed samples converted to a 16-bit binary PGM
+    pub bytes: Vec<u8>,
+    /// File extension for `bytes`: `png` or `pgm`
+    pub extension: &'static str,
+}
+
+/// Whether a segment is part of a FLIR FFF file
+pub fn is_flir_segment(segment: &Segment) -> bool {
+    segment.is_app(1, FLIR_SIGNATURE) && segment.data.len() >= CHUNK_HEADER_LEN
+}
+
+/// FLIR metadata, if the image carries an FFF file with a CameraInfo record
+pub fn from_segments(segments: &[Segment]) -> Option<ThermalMetadata> {
+    let fff = fff(segments)?;
+    let records = records(&fff);
+    let info = records.iter().find(|r| r.kind == RECORD_CAMERA_INFO)?;
+    let mut thermal = camera_info(&record_bytes(info.data));
+    thermal.raw_image = records.iter()
+        .find(|r| r.kind == RECORD_RAW_DATA)
+        .and_then(|r| {
+            let (width, height, image) = raw_header(r.data)?;
+            let format = if image.starts_with(PNG_SIGNATURE) { "png" } else { "raw" };
+            Some(RawImageInfo { width, height, format: format.to_string() })
+        });
+    Some(thermal)
+}
+
+/// The raw thermal image, if the image carries one
+pub fn raw_image(segments: &[Segment]) -> Option<RawThermal> {
+    let fff = fff(segments)?;
+    records(&fff).iter().find(|r| r.kind == RECORD_RAW_DATA).and_then(|r| raw_data(r.data))
+}
+
+/// Reassemble the FFF file from its APP1 chunks, in chunk index order
+fn fff(segments: &[Segment]) -> Option<Vec<u8>> {
+    let mut chunks: Vec<&Segment> = segments.iter().filter(|s| is_flir_segment(s)).collect();
+    chunks.sort_by_key(|s| s.data[6]);
+    let fff: Vec<u8> = chunks.iter().flat_map(|s| &s.data[CHUNK_HEADER_LEN..]).copied().collect();
+    fff.starts_with(FFF_SIGNATURE).then_some(fff)
+}
+
+struct Record<'a> {
+    kind: u16,
+    data: &'a [u8],
+}
+
+/// Entries of the FFF record directory. The header is big-endian unless its
+/// version number only makes sense little-endian.
+fn records(fff: &[u8]) -> Vec<Record<'_>> {
+    let big = Bytes { data: fff, little_endian: false };
+    let header = match big.u32(0x14) {
+        Some(100..=199) => big,
+        _ => Bytes { data: fff, little_endian: true },
+    };
+    let (Some(directory), Some(count)) = (header.u32(0x18), header.u32(0x1C)) else {
+        return Vec::new();
+    };
+    (0..count as usize)
+        .map_while(|i| {
+            let entry = directory as usize + i * 0x20;
+            let kind = header.u16(entry)?;
+            let offset = header.u32(entry + 0x0C)? as usize;
+            let len = header.u32(entry + 0x10)? as usize;
+            Some((kind, fff.get(offset..offset.checked_add(len)?)))
+        })
+        .filter_map(|(kind, data)| Some(Record { kind, data: data? }))
+        .filter(|record| record.kind != 0)
+        .collect()
+}
+
+/// A record's own byte order: its first 16-bit value is 2
+fn record_bytes(data: &[u8]) -> Bytes<'_> {
+    let little = Bytes { data, little_endian: true };
+    let little_endian = little.u16(0).is_some_and(|v| v < 0x100);
+    Bytes { data, little_endian }
+}
+
+fn camera_info(info: &Bytes) -> ThermalMetadata {
+    let celsius = |pos| info.f64(pos).map(|k| ((k - KELVIN_OFFSET) * 100.0).round() / 100.0);
+    let calibration = (|| Some(Calibration {
+        planck_r1: info.f64(0x58)?,
+        planck_b: info.f64(0x5C)?,
+        planck_f: info.f64(0x60)?,
+        atmospheric_trans_alpha1: info.f64(0x70)?,
+        atmospheric_trans_alpha2: info.f64(0x74)?,
+        atmospheric_trans_beta1: info.f64(0x78)?,
+        atmospheric_trans_beta2: info.f64(0x7C)?,
+        atmospheric_trans_x: info.f64(0x80)?,
+        planck_o: info.i32(0x308)? as f64,
+        planck_r2: info.f64(0x30C)?,
+        raw_value_min: info.u16(0x310),
+        raw_value_max: info.u16(0x312),
+    }))();
+    ThermalMetadata {
+        vendor: "FLIR".to_string(),
+        camera_model: info.string(0xD4, 32),
+        camera_serial: info.string(0x104, 16),
+        lens_model: info.string(0x170, 32),
+        emissivity: info.f64(0x20),
+        object_distance: info.f64(0x24),
+        reflected_temperature: celsius(0x28),
+        atmospheric_temperature: celsius(0x2C),
+        // Stored as a fraction by most cameras and a percentage by some
+        relative_humidity: info.f64(0x3C).map(|h| if h <= 2.0 { h * 100.0 } else { h }),
+        ir_window_temperature: celsius(0x30),
+        ir_window_transmission: info.f64(0x34),
+        calibration,
+        raw_image: None,
+    }
+}
+
+/// Width, height and image data of a raw data record
+fn raw_header(data: &[u8]) -> Option<(u16, u16, &[u8])> {
+    let raw = record_bytes(data);
+    Some((raw.u16(2)?, raw.u16(4)?, data.get(0x20..)?))
+}
+
+fn raw_data(data: &[u8]) -> Option<RawThermal> {
+    let (width, height, image) = raw_header(data)?;
+    let little_endian = record_bytes(data).little_endian;
+    if image.starts_with(PNG_SIGNATURE) {
+        return Some(RawThermal { width, height, bytes: image.to_vec(), extension: "png" });
+    }
+    let samples = image.get(..width as usize * height as usize * 2)?;
+    let mut pgm = format!("P5\n{} {}\n65535\n", width, height).into_bytes();
+    for sample in samples.chunks_exact(2) {
+        let value = if little_endian { u16::from_le_bytes([sample[0], sample[1]]) } else { u16::from_be_bytes([sample[0], sample[1]]) };
+        pgm.extend(value.to_be_bytes());
+    }
+    Some(RawThermal { width, height, bytes: pgm, extension: "pgm" })
+}
+
+struct Bytes<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Bytes<'_> {
+    fn array<const N: usize>(&self, pos: usize) -> Option<[u8; N]> {
+        let mut bytes: [u8; N] = self.data.get(pos..pos + N)?.try_into().ok()?;
+        if !self.little_endian {
+            bytes.reverse();
+        }
+        Some(bytes)
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        self.array(pos).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        self.array(pos).map(u32::from_le_bytes)
+    }
+
+    fn i32(&self, pos: usize) -> Option<i32> {
+        self.array(pos).map(i32::from_le_bytes)
+    }
+
+    /// A 32-bit float, widened via its shortest decimal form so 0.95 stays 0.95
+    fn f64(&self, pos: usize) -> Option<f64> {
+        let value = self.array(pos).map(f32::from_le_bytes).filter(|v| v.is_finite())?;
+        value.to_string().parse().ok()
+    }
+
+    fn string(&self, pos: usize, len: usize) -> Option<String> {
+        let bytes = self.data.get(pos..pos + len)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian CameraInfo record and a big-endian raw data record in a
+    /// big-endian FFF file, split across two APP1 chunks given out of order
+    fn flir_segments() -> Vec<Segment> {
+        let mut info = vec![0u8; 0x320];
+        info[0..2].copy_from_slice(&2u16.to_le_bytes());
+        let mut put = |pos: usize, value: f32| info[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+        put(0x20, 0.95);
+        put(0x24, 1.0);
+        put(0x28, 293.15);
+        put(0x2C, 295.15);
+        put(0x3C, 0.5);
+        put(0x58, 21106.77);
+        put(0x5C, 1501.0);
+        put(0x60, 1.0);
+        put(0x30C, 0.012545258);
+        info[0x308..0x30C].copy_from_slice(&(-7340i32).to_le_bytes());
+        info[0xD4..0xD4 + 7].copy_from_slice(b"FLIR E5");
+
+        let mut raw = vec![0u8; 0x20];
+        raw[0..2].copy_from_slice(&2u16.to_be_bytes());
+        raw[2..4].copy_from_slice(&2u16.to_be_bytes());
+        raw[4..6].copy_from_slice(&1u16.to_be_bytes());
+        raw.extend([0x12, 0x34, 0x56, 0x78]);
+
+        let mut fff = vec![0u8; 0x40];
+        fff[..4].copy_from_slice(FFF_SIGNATURE);
+        fff[0x14..0x18].copy_from_slice(&100u32.to_be_bytes());
+        fff[0x18..0x1C].copy_from_slice(&0x40u32.to_be_bytes());
+        fff[0x1C..0x20].copy_from_slice(&2u32.to_be_bytes());
+        let data_start = 0x40 + 2 * 0x20;
+        for (kind, offset, len) in [(RECORD_CAMERA_INFO, data_start, info.len()), (RECORD_RAW_DATA, data_start + info.len(), raw.len())] {
+            let mut entry = vec![0u8; 0x20];
+            entry[0..2].copy_from_slice(&kind.to_be_bytes());
+            entry[0x0C..0x10].copy_from_slice(&(offset as u32).to_be_bytes());
+            entry[0x10..0x14].copy_from_slice(&(len as u32).to_be_bytes());
+            fff.extend(entry);
+        }
+        fff.extend(info);
+        fff.extend(raw);
+
+        let (first, second) = fff.split_at(300);
+        let chunk = |index: u8, bytes: &[u8]| Segment { marker: 0xE1, data: [b"FLIR\0\x01", &[index, 1][..], bytes].concat(), offset: None };
+        vec![chunk(1, second), chunk(0, first)]
+    }
+
+    #[test]
+    fn test_flir() {
+        let segments = flir_segments();
+        let thermal = from_segments(&segments).unwrap();
+        assert_eq!(thermal.camera_model.as_deref(), Some("FLIR E5"));
+        assert_eq!(thermal.emissivity, Some(0.95));
+        assert_eq!(thermal.reflected_temperature, Some(20.0));
+        assert_eq!(thermal.atmospheric_temperature, Some(22.0));
+        assert_eq!(thermal.relative_humidity, Some(50.0));
+        let calibration = thermal.calibration.unwrap();
+        assert_eq!((calibration.planck_r1, calibration.planck_b, calibration.planck_o), (21106.77, 1501.0, -7340.0));
+        assert_eq!(calibration.planck_r2, 0.012545258);
+        assert_eq!(thermal.raw_image, Some(RawImageInfo { width: 2, height: 1, format: "raw".to_string() }));
+
+        let raw = raw_image(&segments).unwrap();
+        assert_eq!(raw.extension, "pgm");
+        assert!(raw.bytes.ends_with(b"65535\n\x12\x34\x56\x78"));
+        assert!(from_segments(&segments[..1]).is_none());
+    }
+}
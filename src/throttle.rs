@@ -0,0 +1,130 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Unused allowance is only banked for this long, so an idle spell cannot be
+/// followed by a full-speed burst
+const MAX_CREDIT: Duration = Duration::from_secs(1);
+
+/// Bytes read through every [`Throttled`] reader so far, throttled or not
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Total bytes read from inputs so far, for per-file counts in a run report
+pub fn bytes_read() -> u64 {
+    BYTES_READ.load(Ordering::Relaxed)
+}
+
+/// Caps the read rate of a whole run, shared by every reader wrapped in [`Throttled`]
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_second: f64,
+    /// Start of the current window and bytes read since
+    window: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub fn new(megabytes_per_second: f64) -> Self {
+        Throttle {
+            bytes_per_second: megabytes_per_second * 1024.0 * 1024.0,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Account for `bytes` just read, sleeping until the average rate is back under the limit
+    pub fn consume(&self, bytes: u64) {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let (start, read) = &mut *window;
+        if now.duration_since(*start) > Duration::from_secs_f64(*read as f64 / self.bytes_per_second) + MAX_CREDIT {
+            *start = now;
+            *read = 0;
+        }
+        *read += bytes;
+        let due = *start + Duration::from_secs_f64(*read as f64 / self.bytes_per_second);
+        if let Some(wait) = due.checked_duration_since(now) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// A reader whose reads count against a [`Throttle`], or pass straight through without one
+pub struct Throttled<'a, R> {
+    inner: R,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, R> Throttled<'a, R> {
+    pub fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        Throttled { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        BYTES_READ.fetch_add(n as u64, Ordering::Relaxed);
+        if let Some(throttle) = self.throttle {
+            throttle.consume(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Throttled<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Ask the OS to serve this process's disk IO after everyone else's: the idle
+/// IO class on Linux, the background band on macOS
+pub fn lower_io_priority() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        // SAFETY: ioprio_set only reads its integer arguments
+        let result = unsafe {
+            libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT)
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(target_os = "macos")]
+    {
+        // SAFETY: setpriority only reads its integer arguments
+        if unsafe { libc::setpriority(libc::PRIO_DARWIN_PROCESS, 0, libc::PRIO_DARWIN_BG) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "IO priority hints are not supported on this platform"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled_read() {
+        // 1 MB/s, so reading 256 KiB after the first takes about a quarter second
+        let throttle = Throttle::new(1.0);
+        let data = vec![0u8; 512 * 1024];
+        let mut reader = Throttled::new(data.as_slice(), Some(&throttle));
+        let mut chunk = vec![0u8; 256 * 1024];
+        reader.read_exact(&mut chunk).unwrap();
+        let start = Instant::now();
+        reader.read_exact(&mut chunk).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let mut unthrottled = Throttled::new(data.as_slice(), None);
+        assert_eq!(io::copy(&mut unthrottled, &mut io::sink()).unwrap(), data.len() as u64);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::error::{ExtractError, Result};
+use crate::exif_write;
+use crate::jpeg::{self, Segment, EXIF_SIGNATURE};
+use crate::pixels::{self, Preview};
+use exif::{In, Reader, Tag};
+use std::io::Cursor;
+
+/// Longest side of generated thumbnails; EXIF readers expect about 160x120
+pub const THUMBNAIL_SIZE: u32 = 160;
+/// Relative aspect ratio difference beyond which a thumbnail no longer matches its image
+const ASPECT_TOLERANCE: f64 = 0.02;
+/// JPEG quality of generated thumbnails
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Condition of a JPEG's embedded EXIF thumbnail
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThumbnailState {
+    Missing,
+    /// Present but unreadable, or its shape no longer matches the main image
+    /// (e.g. after a crop)
+    Stale,
+    Current,
+}
+
+/// Compare the embedded thumbnail with the main image
+pub fn check(bytes: &[u8]) -> Result<ThumbnailState> {
+    let segments = jpeg::read_segments(&mut Cursor::new(bytes))?;
+    let (width, height) = jpeg::dimensions(&segments)
+        .ok_or_else(|| ExtractError::parse("JPEG header", "no start of frame segment"))?;
+    let Some(thumbnail) = embedded(&segments)? else {
+        return Ok(ThumbnailState::Missing);
+    };
+    let thumbnail_dimensions = jpeg::read_segments(&mut Cursor::new(&thumbnail))
+        .ok()
+        .and_then(|segments| jpeg::dimensions(&segments));
+    let Some((thumb_width, thumb_height)) = thumbnail_dimensions.filter(|&(w, h)| w > 0 && h > 0) else {
+        return Ok(ThumbnailState::Stale);
+    };
+    let aspect = width as f64 / height as f64;
+    let thumb_aspect = thumb_width as f64 / thumb_height as f64;
+    Ok(if (thumb_aspect - aspect).abs() / aspect > ASPECT_TOLERANCE {
+        ThumbnailState::Stale
+    } else {
+        ThumbnailState::Current
+    })
+}
+
+/// The embedded thumbnail's JPEG data, if there is one
+pub(crate) fn embedded(segments: &[Segment]) -> Result<Option<Vec<u8>>> {
+    let Some(app1) = segments.iter().find(|s| s.is_app(1, EXIF_SIGNATURE)) else {
+        return Ok(None);
+    };
+    let exif = Reader::new().read_raw(app1.data[EXIF_SIGNATURE.len()..].to_vec())?;
+    let uint = |tag| exif.get_field(tag, In::THUMBNAIL).and_then(|f| f.value.get_uint(0));
+    let (Some(offset), Some(len)) = (uint(Tag::JPEGInterchangeFormat), uint(Tag::JPEGInterchangeFormatLength)) else {
+        return Ok(None);
+    };
+    let Some(end) = offset.checked_add(len) else {
+        return Ok(None);
+    };
+    Ok(exif.buf().get(offset as usize..end as usize).map(<[u8]>::to_vec))
+}
+
+/// Render a new thumbnail from the main image, fitting within 160x160
+pub fn generate(bytes: &[u8]) -> Result<Vec<u8>> {
+    let preview = pixels::decode_preview(bytes, THUMBNAIL_SIZE as u16)?;
+    let resized = fit(&preview, THUMBNAIL_SIZE);
+    pixels::encode(&resized.rgb, resized.width as u16, resized.height as u16, THUMBNAIL_QUALITY, "thumbnail")
+}
+
+/// Box-filter downscale so the longest side is at most `size`
+fn fit(image: &Preview, size: u32) -> Preview {
+    let scale = (size as f64 / image.width.max(image.height) as f64).min(1.0);
+    let width = ((image.width as f64 * scale).round() as u32).max(1);
+    let height = ((image.height as f64 * scale).round() as u32).max(1);
+    // Source rows or columns covered by output index `i` of `n`, at least one wide
+    let span = |i: u32, n: u32, source: u32| {
+        let start = i * source / n;
+        (start, ((i + 1) * source / n).max(start + 1))
+    };
+    let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        let (y0, y1) = span(y, height, image.height);
+        for x in 0..width {
+            let (x0, x1) = span(x, width, image.width);
+            let mut sums = [0u64; 3];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = ((sy * image.width + sx) * 3) as usize;
+                    for (sum, value) in sums.iter_mut().zip(&image.rgb[i..i + 3]) {
+                        *sum += *value as u64;
+                    }
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u64;
+            rgb.extend(sums.map(|sum| (sum / count) as u8));
+        }
+    }
+    Preview { width, height, rgb }
+}
+
+/// Rewrite a JPEG with `thumbnail` embedded in its EXIF segment, creating the
+/// segment if the file has none; see [`exif_write::rewrite`]
+pub fn replace(bytes: &[u8], thumbnail: &[u8]) -> Result<Vec<u8>> {
+    exif_write::rewrite(bytes, &[], Some(thumbnail))
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_thumbnail() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let thumbnail = generate(&bytes).unwrap();
+        let fixed = replace(&bytes, &thumbnail).unwrap();
+        assert_eq!(check(&fixed).unwrap(), ThumbnailState::Current);
+
+        let segments = jpeg::read_segments(&mut Cursor::new(&fixed)).unwrap();
+        assert_eq!(embedded(&segments).unwrap(), Some(thumbnail.clone()));
+        let (w, h) = jpeg::dimensions(&jpeg::read_segments(&mut Cursor::new(&thumbnail)).unwrap()).unwrap();
+        assert_eq!(w.max(h), THUMBNAIL_SIZE);
+
+        // Scan data is carried over unchanged
+        let original = jpeg::header_len(&jpeg::read_segments(&mut Cursor::new(&bytes)).unwrap()) as usize;
+        assert!(fixed.ends_with(&bytes[original..]));
+
+        // A thumbnail offset and length that overflow when added are ignored
+        let mut tiff = b"II*\0\x08\0\0\0\0\0\x0e\0\0\0\x02\0".to_vec();
+        for (tag, value) in [(0x0201u16, 0xFFFF_FFF0u32), (0x0202, 0x20)] {
+            tiff.extend([&tag.to_le_bytes()[..], &4u16.to_le_bytes(), &1u32.to_le_bytes(), &value.to_le_bytes()].concat());
+        }
+        tiff.extend([0; 4]);
+        let segment = Segment { marker: 0xE1, data: [EXIF_SIGNATURE, &tiff].concat(), offset: None };
+        assert_eq!(embedded(&[segment]).unwrap(), None);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::error::{ExtractError, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use exif::{Exif, Field, In, Tag, Value};
+
+/// Capture timestamps a clock correction applies to, with their sub-second tags
+const SHIFTED: [(Tag, Tag); 2] = [
+    (Tag::DateTimeOriginal, Tag::SubSecTimeOriginal),
+    (Tag::DateTimeDigitized, Tag::SubSecTimeDigitized),
+];
+/// Time zone tags paired with the timestamps above
+const OFFSET_TAGS: [Tag; 2] = [Tag::OffsetTimeOriginal, Tag::OffsetTimeDigitized];
+
+/// One timestamp before and after shifting
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shift {
+    pub tag: Tag,
+    pub before: NaiveDateTime,
+    pub after: NaiveDateTime,
+}
+
+/// Parse an offset such as `-1h30m`, `+2d`, `90s` or `-250ms`
+pub fn parse_offset(s: &str) -> Result<Duration> {
+    let invalid = || ExtractError::parse("offset", format!("expected something like -1h30m, +2d or 90s, not '{}'", s));
+    let (sign, mut rest) = match s.as_bytes().first() {
+        Some(b'-') => (-1, &s[1..]),
+        Some(b'+') => (1, &s[1..]),
+        _ => (1, s),
+    };
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut total = Duration::zero();
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let value: i64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit_len = rest[digits..].find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len() - digits);
+        let part = match &rest[digits..digits + unit_len] {
+            "d" => Duration::try_days(value),
+            "h" => Duration::try_hours(value),
+            "m" => Duration::try_minutes(value),
+            "s" => Duration::try_seconds(value),
+            "ms" => Duration::try_milliseconds(value),
+            _ => return Err(invalid()),
+        };
+        total = part.and_then(|part| total.checked_add(&part)).ok_or_else(invalid)?;
+        rest = &rest[digits + unit_len..];
+    }
+    Ok(total * sign)
+}
+
+/// Replacement fields moving the capture timestamps by `offset`. Sub-second
+/// tags are carried along, keeping their precision (at least milliseconds when
+/// the offset needs it). With `timezone` (e.g. `+02:00`) the matching
+/// OffsetTime tags are set as well; otherwise they are left as they were.
+pub fn shifted_fields(exif: &Exif, offset: Duration, timezone: Option<&str>) -> Result<(Vec<Field>, Vec<Shift>)> {
+    let mut fields = Vec::new();
+    let mut shifts = Vec::new();
+    let ascii = |tag, text: String| Field { tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![text.into_bytes()]) };
+
+    for (tag, subsec_tag) in SHIFTED {
+        let Some(before) = exif.get_field(tag, In::PRIMARY).and_then(|f| ascii_value(&f.value)) else {
+            continue;
+        };
+        let before = parse_exif_datetime(tag, &before)?;
+        let subsec = exif.get_field(subsec_tag, In::PRIMARY)
+            .and_then(|f| ascii_value(&f.value))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()));
+        let digits = subsec.as_ref().map_or(0, String::len)
+            .max(if offset.subsec_nanos() != 0 { 3 } else { 0 })
+            .min(9);
+        let nanos = subsec.as_deref()
+            .map(|s| format!("{:0<9}", &s[..s.len().min(9)]).parse::<i64>().unwrap_or(0))
+            .unwrap_or(0);
+
+        let after = before.checked_add_signed(Duration::nanoseconds(nanos))
+            .and_then(|t| t.checked_add_signed(offset))
+            .ok_or_else(|| ExtractError::parse(tag.to_string(), format!("'{}' shifted by {} is out of range", before, offset)))?;
+        let after_seconds = after.with_nanosecond(0).unwrap_or(after);
+        fields.push(ascii(tag, after_seconds.format("%Y:%m:%d %H:%M:%S").to_string()));
+        if digits > 0 {
+            let fraction = format!("{:09}", after.nanosecond());
+            fields.push(ascii(subsec_tag, fraction[..digits].to_string()));
+        }
+        shifts.push(Shift { tag, before, after: after_seconds });
+    }
+    if shifts.is_empty() {
+        return Err(ExtractError::parse("DateTimeOriginal", "neither it nor DateTimeDigitized is set, so there is nothing to shift"));
+    }
+    if let Some(timezone) = timezone {
+        fields.extend(OFFSET_TAGS.map(|tag| ascii(tag, timezone.to_string())));
+    }
+    Ok((fields, shifts))
+}
+
+/// First string of an ASCII value, without its NUL terminator
+fn ascii_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Ascii(values) => values.first().map(|v| String::from_utf8_lossy(v).trim_end_matches('\0').to_string()),
+        _ => None,
+    }
+}
+
+fn parse_exif_datetime(tag: Tag, text: &str) -> Result<NaiveDateTime> {
+    let invalid = || ExtractError::parse(tag.to_string(), format!("'{}' is not a date and time", text));
+    let dt = exif::DateTime::from_ascii(text.as_bytes()).map_err(|_| invalid())?;
+    NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())
+        .and_then(|date| date.and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into()))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::Reader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("-1h30m").unwrap(), -Duration::minutes(90));
+        assert_eq!(parse_offset("+2d").unwrap(), Duration::days(2));
+        assert_eq!(parse_offset("90s250ms").unwrap(), Duration::milliseconds(90_250));
+        assert!(parse_offset("1x").is_err());
+        assert!(parse_offset("-").is_err());
+        assert!(parse_offset("h").is_err());
+        assert!(parse_offset("9999999999999999d").is_err());
+        assert!(parse_offset("9000000000000000000ms9000000000000000000ms").is_err());
+    }
+
+    #[test]
+    fn test_shifted_fields() {
+        let bytes = std::fs::read("images/JAM26284.jpg").unwrap();
+        let exif = Reader::new().read_from_container(&mut Cursor::new(&bytes)).unwrap();
+        let (fields, shifts) = shifted_fields(&exif, Duration::milliseconds(-1500), Some("+02:00")).unwrap();
+        let original = &shifts[0];
+        assert_eq!(original.tag, Tag::DateTimeOriginal);
+        assert!(original.after < original.before);
+
+        let subsec = fields.iter().find(|f| f.tag == Tag::SubSecTimeOriginal).unwrap();
+        assert_eq!(ascii_value(&subsec.value).map(|s| s.len() >= 3), Some(true));
+        assert!(fields.iter().any(|f| f.tag == Tag::OffsetTimeOriginal));
+        assert!(shifted_fields(&exif, Duration::MAX, None).is_err());
+    }
+}
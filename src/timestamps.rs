@@ -0,0 +1,132 @@
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Top-level output fields holding timestamps
+pub const TIMESTAMP_FIELDS: [&str; 5] = ["created_time", "modified_time", "capture_time", "capture_time_raw", "best_time"];
+
+/// How timestamps are written to the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimeFormat {
+    /// e.g. 2019-04-27T14:05:09Z
+    #[default]
+    Rfc3339,
+    /// Whole seconds since the Unix epoch
+    Unix,
+    /// Milliseconds since the Unix epoch
+    UnixMs,
+    /// EXIF style, e.g. 2019:04:27 14:05:09 (no offset)
+    Exif,
+}
+
+/// Time zone timestamps are shown in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Zone {
+    #[default]
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    /// Parse `utc`, `local` or an offset such as `+02:00` / `-0530`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utc" | "z" => return Ok(Zone::Utc),
+            "local" => return Ok(Zone::Local),
+            _ => {}
+        }
+        let invalid = || format!("expected local, utc or an offset like +HH:MM, got '{}'", s);
+        let (sign, rest) = match s.as_bytes().first() {
+            Some(b'+') => (1, &s[1..]),
+            Some(b'-') => (-1, &s[1..]),
+            _ => return Err(invalid()),
+        };
+        let digits = rest.replace(':', "");
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let hours: i32 = digits[..2].parse().map_err(|_| invalid())?;
+        let minutes: i32 = digits[2..].parse().map_err(|_| invalid())?;
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Zone::Fixed)
+            .ok_or_else(invalid)
+    }
+}
+
+impl Zone {
+    pub fn convert(self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Utc => time.fixed_offset(),
+            Zone::Local => time.with_timezone(&Local).fixed_offset(),
+            Zone::Fixed(offset) => time.with_timezone(&offset),
+        }
+    }
+}
+
+/// Render one timestamp
+pub fn format_time(time: DateTime<Utc>, format: TimeFormat, zone: Zone) -> Value {
+    match format {
+        TimeFormat::Unix => time.timestamp().into(),
+        TimeFormat::UnixMs => time.timestamp_millis().into(),
+        TimeFormat::Rfc3339 => {
+            // Matches chrono's serde output, so the UTC default is unchanged
+            zone.convert(time).to_rfc3339_opts(SecondsFormat::AutoSi, matches!(zone, Zone::Utc)).into()
+        }
+        TimeFormat::Exif => zone.convert(time).format("%Y:%m:%d %H:%M:%S").to_string().into(),
+    }
+}
+
+/// Rewrite the serialized timestamp fields of a metadata object
+pub fn apply(value: &mut Value, format: TimeFormat, zone: Zone) {
+    if format == TimeFormat::Rfc3339 && zone == Zone::Utc {
+        return;
+    }
+    let Some(object) = value.as_object_mut() else { return };
+    for field in TIMESTAMP_FIELDS {
+        if let Some(slot) = object.get_mut(field) {
+            if let Some(time) = slot.as_str().and_then(|s| DateTime::parse_from_rfc3339(s).ok()) {
+                *slot = format_time(time.with_timezone(&Utc), format, zone);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_zone() {
+        assert_eq!("UTC".parse::<Zone>(), Ok(Zone::Utc));
+        assert_eq!("+05:30".parse::<Zone>(), Ok(Zone::Fixed(FixedOffset::east_opt(19800).unwrap())));
+        assert_eq!("-0800".parse::<Zone>(), Ok(Zone::Fixed(FixedOffset::west_opt(28800).unwrap())));
+        assert!("5:30".parse::<Zone>().is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let time = Utc.with_ymd_and_hms(2019, 4, 27, 14, 5, 9).unwrap();
+        let mut value = serde_json::json!({"modified_time": time, "capture_time": null, "size": 1});
+        let original = value.clone();
+        apply(&mut value, TimeFormat::Rfc3339, Zone::Utc);
+        assert_eq!(value, original);
+
+        let mut ms = original.clone();
+        apply(&mut ms, TimeFormat::UnixMs, Zone::Utc);
+        assert_eq!(ms["modified_time"], 1556373909000i64);
+        assert_eq!(ms["capture_time"], Value::Null);
+
+        let offset = "+02:00".parse().unwrap();
+        let mut exif = original.clone();
+        apply(&mut exif, TimeFormat::Exif, offset);
+        assert_eq!(exif["modified_time"], "2019:04:27 16:05:09");
+        let mut rfc = original;
+        apply(&mut rfc, TimeFormat::Rfc3339, offset);
+        assert_eq!(rfc["modified_time"], "2019-04-27T16:05:09+02:00");
+    }
+}
@@ -0,0 +1,148 @@
+use crate::error::Result;
+use crate::exif_write;
+use crate::jpeg::{self, Segment, EXIF_SIGNATURE, ICC_SIGNATURE, SOI, XMP_EXTENSION_SIGNATURE, XMP_SIGNATURE};
+use exif::{Field, In, Reader, Tag, Value};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// A kind of metadata stored in its own APPn segments
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    Exif,
+    /// ICC colour profile, possibly split over several segments
+    Icc,
+    /// Standard XMP packet and any extended XMP segments
+    Xmp,
+}
+
+impl SegmentKind {
+    /// Whether `segment` holds this kind of metadata
+    pub fn matches(self, segment: &Segment) -> bool {
+        match self {
+            SegmentKind::Exif => segment.is_app(1, EXIF_SIGNATURE),
+            SegmentKind::Icc => segment.is_app(2, ICC_SIGNATURE),
+            SegmentKind::Xmp => segment.is_app(1, XMP_SIGNATURE) || segment.is_app(1, XMP_EXTENSION_SIGNATURE),
+        }
+    }
+}
+
+impl FromStr for SegmentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exif" => Ok(SegmentKind::Exif),
+            "icc" => Ok(SegmentKind::Icc),
+            "xmp" => Ok(SegmentKind::Xmp),
+            _ => Err(format!("unknown field '{}' (expected exif, icc or xmp)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for SegmentKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SegmentKind::Exif => "exif",
+            SegmentKind::Icc => "icc",
+            SegmentKind::Xmp => "xmp",
+        })
+    }
+}
+
+/// Copy the `kinds` segments of `source` into `target`, replacing the target's
+/// own segments of those kinds. Kinds the source lacks are left as they are in
+/// the target.
+///
+/// Returns the new target bytes and the kinds that were copied. The segments are
+/// copied verbatim, so EXIF fields describing the image itself, such as its
+/// dimensions and thumbnail, still describe the source. The exception is the
+/// orientation, which keeps the target's value (1 if it had none), since it says
+/// how the target's own pixels are to be displayed.
+pub fn copy_segments(source: &[u8], target: &[u8], kinds: &[SegmentKind]) -> Result<(Vec<u8>, Vec<SegmentKind>)> {
+    let source_segments = jpeg::read_segments(&mut Cursor::new(source))?;
+    let target_segments = jpeg::read_segments(&mut Cursor::new(target))?;
+
+    let copied: Vec<SegmentKind> = kinds.iter().copied()
+        .filter(|kind| source_segments.iter().any(|s| kind.matches(s)))
+        .collect();
+    let is_copied = |segment: &Segment| copied.iter().any(|kind| kind.matches(segment));
+    let transplanted: Vec<&Segment> = source_segments.iter().filter(|s| is_copied(s)).collect();
+
+    let mut out = vec![0xFF, SOI];
+    let mut written = transplanted.is_empty();
+    for segment in &target_segments {
+        // Copied segments take the place of the ones they replace, or else go
+        // after any JFIF APP0 segments
+        if !written && (is_copied(segment) || segment.marker != 0xE0) {
+            transplanted.iter().try_for_each(|s| jpeg::push_segment(&mut out, s))?;
+            written = true;
+        }
+        if !is_copied(segment) {
+            jpeg::push_segment(&mut out, segment)?;
+        }
+    }
+    out.extend(&target[jpeg::header_len(&target_segments) as usize..]);
+
+    let target_orientation = orientation(&target_segments);
+    if copied.contains(&SegmentKind::Exif) && orientation(&source_segments) != target_orientation {
+        let field = Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![target_orientation]) };
+        out = exif_write::rewrite(&out, &[field], None)?;
+    }
+    Ok((out, copied))
+}
+
+/// The EXIF orientation of a JPEG, 1 (upright) when it has none
+fn orientation(segments: &[Segment]) -> u16 {
+    segments.iter()
+        .find(|s| s.is_app(1, EXIF_SIGNATURE))
+        .and_then(|s| Reader::new().read_raw(s.data[EXIF_SIGNATURE.len()..].to_vec()).ok())
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0))
+        .and_then(|value| u16::try_from(value).ok())
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif_metadata::read_exif_metadata;
+    use crate::content::ExtractOptions;
+
+    #[test]
+    fn test_copy_segments() {
+        let source = std::fs::read("images/JAM26284.jpg").unwrap();
+        let segments = jpeg::read_segments(&mut Cursor::new(&source)).unwrap();
+        // The same image with its EXIF stripped, as an editor's export might be
+        let mut stripped = vec![0xFF, SOI];
+        for segment in segments.iter().filter(|s| !SegmentKind::Exif.matches(s)) {
+            jpeg::push_segment(&mut stripped, segment).unwrap();
+        }
+        stripped.extend(&source[jpeg::header_len(&segments) as usize..]);
+        assert!(read_exif_metadata(&mut stripped.as_slice(), &ExtractOptions::default()).unwrap().camera_model.is_none());
+
+        let (restored, copied) = copy_segments(&source, &stripped, &[SegmentKind::Exif, SegmentKind::Icc]).unwrap();
+        let expected: Vec<SegmentKind> = [SegmentKind::Exif, SegmentKind::Icc].into_iter()
+            .filter(|kind| segments.iter().any(|s| kind.matches(s)))
+            .collect();
+        assert_eq!(copied, expected);
+        assert_eq!(restored.len(), source.len());
+        let exif = read_exif_metadata(&mut restored.as_slice(), &ExtractOptions::default()).unwrap();
+        assert_eq!(exif.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+
+        // Copying again replaces the segments rather than adding more
+        let (again, _) = copy_segments(&source, &restored, &[SegmentKind::Exif]).unwrap();
+        assert_eq!(again, restored);
+        assert_eq!("xmp".parse(), Ok(SegmentKind::Xmp));
+
+        // The orientation describes the target's pixels, so it is not copied
+        let rotate = |bytes: &[u8], value| exif_write::rewrite(bytes,
+            &[Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![value]) }], None).unwrap();
+        let orientation_of = |bytes: &[u8]| orientation(&jpeg::read_segments(&mut Cursor::new(bytes)).unwrap());
+        let (upright, _) = copy_segments(&rotate(&source, 6), &stripped, &[SegmentKind::Exif]).unwrap();
+        assert_eq!(orientation_of(&upright), 1);
+        let (rotated, _) = copy_segments(&rotate(&source, 3), &rotate(&source, 8), &[SegmentKind::Exif]).unwrap();
+        assert_eq!(orientation_of(&rotated), 8);
+        assert!("iptc".parse::<SegmentKind>().is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+/// One input file and its metadata as `(dotted field, display value)` pairs,
+/// or the reason it could not be read
+pub struct Entry {
+    pub path: PathBuf,
+    pub fields: Result<Vec<(String, String)>, String>,
+}
+
+impl Entry {
+    pub fn new(path: PathBuf, metadata: Result<Value>) -> Self {
+        let fields = metadata.map(|value| {
+            let mut fields = Vec::new();
+            flatten("", &value, &mut fields);
+            fields
+        });
+        Entry { path, fields: fields.map_err(|e| format!("{:#}", e)) }
+    }
+
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.as_ref().ok()?.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the entry matches a filter: `field:text` looks in one field (or
+    /// fields under it), anything else in the path and every value. Case-insensitive.
+    fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        let contains = |text: &str| text.to_lowercase().contains(&filter);
+        let fields = self.fields.as_ref().map(Vec::as_slice).unwrap_or_default();
+        if let Some((name, text)) = filter.split_once(':') {
+            let in_field = |key: &str| key == name || key.starts_with(&format!("{}.", name));
+            if fields.iter().any(|(key, _)| in_field(key)) {
+                return fields.iter().any(|(key, value)| in_field(key) && value.to_lowercase().contains(text));
+            }
+        }
+        contains(&self.path.to_string_lossy()) || fields.iter().any(|(_, value)| contains(value))
+    }
+}
+
+/// Nested objects become dotted keys and arrays indexed ones, e.g. `regions.0.name`
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    let key = |name: &str| if prefix.is_empty() { name.to_string() } else { format!("{}.{}", prefix, name) };
+    match value {
+        Value::Object(object) => object.iter().for_each(|(name, v)| flatten(&key(name), v, out)),
+        Value::Array(items) => items.iter().enumerate().for_each(|(i, v)| flatten(&key(&i.to_string()), v, out)),
+        Value::String(text) => out.push((prefix.to_string(), text.clone())),
+        other => out.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// Numbers compare numerically, anything else as text; missing values sort last
+fn compare_values(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(x), Ok(y)) => x.total_cmp(&y),
+            _ => a.cmp(b),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Input {
+    None,
+    Filter,
+    Sort,
+}
+
+/// State of the browser, separate from drawing so it can be tested
+pub struct Browser {
+    entries: Vec<Entry>,
+    /// Indices into `entries` that pass the filter, in display order
+    visible: Vec<usize>,
+    filter: String,
+    sort_field: String,
+    descending: bool,
+    input: Input,
+    list: ListState,
+    detail_scroll: u16,
+}
+
+impl Browser {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        let mut browser = Browser {
+            entries,
+            visible: Vec::new(),
+            filter: String::new(),
+            sort_field: String::new(),
+            descending: false,
+            input: Input::None,
+            list: ListState::default(),
+            detail_scroll: 0,
+        };
+        browser.refresh();
+        browser
+    }
+
+    /// Reapply the filter and sort, keeping the selection where possible
+    fn refresh(&mut self) {
+        let selected = self.selected_index();
+        self.visible = (0..self.entries.len()).filter(|&i| self.entries[i].matches(&self.filter)).collect();
+        if !self.sort_field.is_empty() {
+            let entries = &self.entries;
+            let field = &self.sort_field;
+            self.visible.sort_by(|&a, &b| compare_values(entries[a].field(field), entries[b].field(field)));
+            if self.descending {
+                self.visible.reverse();
+            }
+        }
+        let position = selected.and_then(|s| self.visible.iter().position(|&i| i == s));
+        self.list.select(position.or((!self.visible.is_empty()).then_some(0)));
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.list.selected().and_then(|i| self.visible.get(i)).copied()
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        self.list.select(Some((current + by).clamp(0, self.visible.len() as isize - 1) as usize));
+        self.detail_scroll = 0;
+    }
+
+    /// Handle a key press, returning false to quit
+    fn key(&mut self, code: KeyCode) -> bool {
+        if self.input != Input::None {
+            let text = if self.input == Input::Filter { &mut self.filter } else { &mut self.sort_field };
+            match code {
+                KeyCode::Enter | KeyCode::Esc => self.input = Input::None,
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) => text.push(c),
+                _ => {}
+            }
+            self.refresh();
+            return true;
+        }
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Home => self.move_selection(-(self.visible.len() as isize)),
+            KeyCode::End => self.move_selection(self.visible.len() as isize),
+            KeyCode::PageDown => self.detail_scroll = self.detail_scroll.saturating_add(10),
+            KeyCode::PageUp => self.detail_scroll = self.detail_scroll.saturating_sub(10),
+            KeyCode::Char('/') => self.input = Input::Filter,
+            KeyCode::Char('s') => self.input = Input::Sort,
+            KeyCode::Char('r') => {
+                self.descending = !self.descending;
+                self.refresh();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [files, detail] = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(main);
+
+        let items: Vec<ListItem> = self.visible.iter()
+            .map(|&i| {
+                let entry = &self.entries[i];
+                let name = entry.path.display().to_string();
+                match (&entry.fields, self.sort_field.as_str()) {
+                    (Err(_), _) => ListItem::new(format!("{} (unreadable)", name)),
+                    (Ok(_), "") => ListItem::new(name),
+                    (Ok(_), field) => ListItem::new(format!("{}  {}", name, entry.field(field).unwrap_or("-"))),
+                }
+            })
+            .collect();
+        let title = format!(" Files {}/{} ", self.visible.len(), self.entries.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, files, &mut self.list);
+
+        let lines: Vec<Line> = match self.selected_index().map(|i| &self.entries[i].fields) {
+            Some(Ok(fields)) => {
+                let width = fields.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+                fields.iter().map(|(key, value)| Line::from(format!("{:<width$}  {}", key, value, width = width))).collect()
+            }
+            Some(Err(error)) => vec![Line::from(error.as_str())],
+            None => Vec::new(),
+        };
+        let detail_title = self.selected_index().map(|i| format!(" {} ", self.entries[i].path.display())).unwrap_or_default();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(detail_title)).scroll((self.detail_scroll, 0)), detail);
+
+        let order = if self.descending { "desc" } else { "asc" };
+        let status_line = match self.input {
+            Input::Filter => format!("Filter (field:text or text): {}_", self.filter),
+            Input::Sort => format!("Sort by field: {}_", self.sort_field),
+            Input::None => format!(
+                "/ filter [{}]  s sort [{} {}]  r reverse  PgUp/PgDn scroll  q quit",
+                self.filter, if self.sort_field.is_empty() { "input" } else { &self.sort_field }, order,
+            ),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+}
+
+/// Browse `entries` until the user quits, restoring the terminal afterwards
+pub fn run(entries: Vec<Entry>) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, Browser::new(entries));
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, mut browser: Browser) -> Result<()> {
+    loop {
+        terminal.draw(|frame| browser.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !browser.key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_filter_and_sort() {
+        let entry = |name: &str, value: Value| Entry::new(PathBuf::from(name), Ok(value));
+        let mut browser = Browser::new(vec![
+            entry("a.jpg", json!({"size": 900, "camera_model": "X-T5", "gps": {"latitude": 48.1}})),
+            entry("b.jpg", json!({"size": 50, "camera_model": "Canon EOS R5"})),
+            entry("c.jpg", json!({"size": 1200, "camera_model": "X-T5"})),
+            Entry::new(PathBuf::from("d.jpg"), Err(anyhow::anyhow!("Not a JPEG"))),
+        ]);
+        let names = |b: &Browser| b.visible.iter().map(|&i| b.entries[i].path.display().to_string()).collect::<Vec<_>>();
+
+        browser.sort_field = "size".to_string();
+        browser.refresh();
+        assert_eq!(names(&browser), ["b.jpg", "a.jpg", "c.jpg", "d.jpg"]);
+
+        for c in "/camera_model:x-t".chars() {
+            browser.key(KeyCode::Char(c));
+        }
+        browser.key(KeyCode::Enter);
+        browser.key(KeyCode::Char('r'));
+        assert_eq!(names(&browser), ["c.jpg", "a.jpg"]);
+
+        browser.filter = "gps:48".to_string();
+        browser.refresh();
+        assert_eq!(names(&browser), ["a.jpg"]);
+        assert!(!browser.key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,11 @@
+//! Browser bindings: image bytes in, JSON out.
+
+use wasm_bindgen::prelude::*;
+
+/// Extract content metadata from an image buffer and return it as a JSON string
+#[wasm_bindgen(js_name = extractMetadata)]
+pub fn extract_metadata(bytes: &[u8]) -> Result<String, JsError> {
+    let metadata = crate::content::extract_from_bytes(bytes, &Default::default())
+        .map_err(|e| JsError::new(&e.to_string()))?;
+    serde_json::to_string(&metadata).map_err(|e| JsError::new(&e.to_string()))
+}
@@ -0,0 +1,117 @@
+use crate::jpeg::{Segment, XMP_SIGNATURE};
+use roxmltree::{Document, Node};
+
+/// RDF syntax namespace
+pub const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+/// Dublin Core namespace, which holds keywords and captions
+pub const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+/// Adobe's auxiliary EXIF namespace, where maker note fields such as the firmware are kept
+pub const AUX_NS: &str = "http://ns.adobe.com/exif/1.0/aux/";
+
+/// The standard XMP packet from the APP1 segments, if any
+pub fn packet(segments: &[Segment]) -> Option<String> {
+    let segment = segments.iter().find(|s| s.is_app(1, XMP_SIGNATURE))?;
+    let data = &segment.data[XMP_SIGNATURE.len()..];
+    Some(String::from_utf8_lossy(data).trim_end_matches('\0').to_string())
+}
+
+/// Parse an XMP packet, tolerating the `<?xpacket?>` wrapper and trailing padding
+pub fn parse(packet: &str) -> Option<Document<'_>> {
+    Document::parse(body(packet)?.1).ok()
+}
+
+/// The XML document inside a packet's `<?xpacket?>` wrapper, and its offset in the packet
+pub fn body(packet: &str) -> Option<(usize, &str)> {
+    let start = packet.find("<x:xmpmeta").or_else(|| packet.find("<rdf:RDF"))?;
+    let end = packet.rfind("</x:xmpmeta>").map(|i| i + "</x:xmpmeta>".len())
+        .or_else(|| packet.rfind("</rdf:RDF>").map(|i| i + "</rdf:RDF>".len()))?;
+    Some((start, packet.get(start..end)?))
+}
+
+/// Value of a simple property, written either as an attribute of an
+/// `rdf:Description` or as a child element with text content
+pub fn property(doc: &Document, namespace: &str, name: &str) -> Option<String> {
+    property_node(doc, namespace, name).map(|value| value.trim().to_string())
+}
+
+fn property_node<'a>(doc: &'a Document, namespace: &str, name: &str) -> Option<&'a str> {
+    doc.descendants()
+        .filter(|node| node.has_tag_name((RDF_NS, "Description")))
+        .find_map(|node| node.attribute((namespace, name)))
+        .or_else(|| {
+            doc.descendants()
+                .find(|node| node.has_tag_name((namespace, name)))
+                .and_then(|node| node.text())
+        })
+}
+
+/// The node holding a struct value's fields: an explicit `rdf:Description`
+/// child if there is one, otherwise the property element itself
+/// (`rdf:parseType="Resource"` or fields written as attributes)
+pub fn resource<'a, 'input>(node: Node<'a, 'input>) -> Node<'a, 'input> {
+    node.children()
+        .find(|child| child.has_tag_name((RDF_NS, "Description")))
+        .unwrap_or(node)
+}
+
+/// Child property element of a struct value
+pub fn child<'a, 'input>(node: Node<'a, 'input>, namespace: &str, name: &str) -> Option<Node<'a, 'input>> {
+    resource(node).children().find(|child| child.has_tag_name((namespace, name)))
+}
+
+/// Simple field of a struct value, as an attribute or as a child element's text
+pub fn field(node: Node, namespace: &str, name: &str) -> Option<String> {
+    let resource = resource(node);
+    resource.attribute((namespace, name))
+        .or_else(|| child(node, namespace, name).and_then(|child| child.text()))
+        .map(|value| value.trim().to_string())
+}
+
+/// Items of an array property (`rdf:Bag`, `rdf:Seq` or `rdf:Alt`)
+pub fn list_items<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(|child| child.is_element())
+        .flat_map(|array| array.children())
+        .filter(|item| item.has_tag_name((RDF_NS, "li")))
+}
+
+/// First item of an array property, such as the first `dc:creator` or the default `dc:rights`
+pub fn first_item(doc: &Document, namespace: &str, name: &str) -> Option<String> {
+    let node = doc.descendants().find(|node| node.has_tag_name((namespace, name)))?;
+    list_items(node)
+        .filter_map(|item| item.text())
+        .map(|text| text.trim().to_string())
+        .find(|text| !text.is_empty())
+}
+
+/// Keywords from the `dc:subject` bag, trimmed, with blanks dropped
+pub fn keywords(doc: &Document) -> Vec<String> {
+    doc.descendants()
+        .find(|node| node.has_tag_name((DC_NS, "subject")))
+        .map(|subject| {
+            list_items(subject)
+                .filter_map(|item| item.text())
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_attribute_and_element_forms() {
+        let packet = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description xmlns:a="urn:a" a:One="1"><a:Two> 2 </a:Two></rdf:Description>
+</rdf:RDF></x:xmpmeta>
+<?xpacket end="w"?>   "#;
+        let doc = parse(packet).unwrap();
+        assert_eq!(property(&doc, "urn:a", "One").as_deref(), Some("1"));
+        assert_eq!(property(&doc, "urn:a", "Two").as_deref(), Some("2"));
+        assert_eq!(property(&doc, "urn:a", "Three"), None);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::error::{ExtractError, Result};
+use crate::jpeg::{self, Segment, SOI, XMP_EXTENSION_SIGNATURE, XMP_SIGNATURE};
+use crate::keywords::LR_NS;
+use crate::lighting::CRS_NS;
+use crate::xmp::{self, DC_NS, RDF_NS};
+use chrono::DateTime;
+use roxmltree::{Document, Node};
+use serde_json::Value;
+use std::io::Cursor;
+use std::ops::Range;
+
+/// Namespace prefixes declared on the sidecar's `rdf:Description`
+const NAMESPACES: &[(&str, &str)] = &[
+    ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+    ("aux", "http://ns.adobe.com/exif/1.0/aux/"),
+    ("dc", DC_NS),
+    ("crs", CRS_NS),
+    ("lr", LR_NS),
+];
+
+/// An XMP sidecar packet holding the camera, capture, GPS and keyword data of
+/// a serialized metadata record, in the properties Lightroom and Darktable read
+pub fn sidecar(metadata: &Value) -> String {
+    let text = |pointer| metadata.pointer(pointer).and_then(Value::as_str);
+    let number = |pointer| metadata.pointer(pointer).and_then(Value::as_f64);
+    let mut properties: Vec<(&str, String)> = Vec::new();
+
+    if let Some(model) = text("/camera_model") {
+        properties.push(("tiff:Model", model.to_string()));
+    }
+    for (name, pointer) in [("tiff:Orientation", "/orientation"), ("tiff:ImageWidth", "/width"), ("tiff:ImageLength", "/height")] {
+        if let Some(value) = metadata.pointer(pointer).and_then(Value::as_u64) {
+            properties.push((name, value.to_string()));
+        }
+    }
+    // EXIF times are local without a zone, so the XMP date carries none either
+    if let Some(time) = text("/capture_time").and_then(|t| DateTime::parse_from_rfc3339(t).ok()) {
+        properties.push(("exif:DateTimeOriginal", time.format("%Y-%m-%dT%H:%M:%S%.f").to_string()));
+    }
+    if let Some(serial) = text("/camera_serial") {
+        properties.push(("aux:SerialNumber", serial.to_string()));
+    }
+    if let Some(image_number) = metadata.pointer("/sequence_number").and_then(Value::as_u64) {
+        properties.push(("aux:ImageNumber", image_number.to_string()));
+    }
+    for (name, pointer) in [
+        ("exif:FocalLength", "/focus/focal_length"),
+        ("exif:FNumber", "/focus/aperture"),
+        ("exif:SubjectDistance", "/focus/subject_distance"),
+    ] {
+        if let Some(value) = number(pointer) {
+            properties.push((name, rational(value)));
+        }
+    }
+    if let Some(focal_length) = number("/enrichment/focal_length_35mm") {
+        properties.push(("exif:FocalLengthIn35mmFilm", format!("{}", focal_length.round())));
+    }
+    match text("/white_balance/mode") {
+        Some("auto") => properties.push(("exif:WhiteBalance", "0".to_string())),
+        Some("manual") => properties.push(("exif:WhiteBalance", "1".to_string())),
+        _ => {}
+    }
+    if let Some(temperature) = metadata.pointer("/white_balance/color_temperature").and_then(Value::as_u64) {
+        properties.push(("crs:Temperature", temperature.to_string()));
+    }
+    if let (Some(latitude), Some(longitude)) = (number("/gps/latitude"), number("/gps/longitude")) {
+        properties.push(("exif:GPSVersionID", "2.3.0.0".to_string()));
+        properties.push(("exif:GPSLatitude", coordinate(latitude, ['N', 'S'])));
+        properties.push(("exif:GPSLongitude", coordinate(longitude, ['E', 'W'])));
+        if let Some(altitude) = number("/gps/altitude") {
+            properties.push(("exif:GPSAltitudeRef", u8::from(altitude < 0.0).to_string()));
+            properties.push(("exif:GPSAltitude", rational(altitude.abs())));
+        }
+    }
+
+    let mut out = String::from("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    out.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    out.push_str(&format!(" <rdf:RDF xmlns:rdf=\"{}\">\n", RDF_NS));
+    out.push_str("  <rdf:Description rdf:about=\"\"");
+    for (prefix, namespace) in NAMESPACES {
+        out.push_str(&format!("\n    xmlns:{}=\"{}\"", prefix, namespace));
+    }
+    out.push_str(">\n");
+    for (name, value) in &properties {
+        out.push_str(&format!("   <{0}>{1}</{0}>\n", name, escape(value)));
+    }
+    if let Some(flash) = metadata.get("flash") {
+        out.push_str(&flash_struct(flash));
+    }
+    for (property, field) in [("dc:subject", "keywords"), ("lr:hierarchicalSubject", "hierarchical_keywords")] {
+        let keywords: Vec<&str> = metadata.get(field)
+            .and_then(Value::as_array)
+            .map(|keywords| keywords.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        if !keywords.is_empty() {
+            out.push_str(&format!("   <{}>\n    <rdf:Bag>\n", property));
+            for keyword in keywords {
+                out.push_str(&format!("     <rdf:li>{}</rdf:li>\n", escape(keyword)));
+            }
+            out.push_str(&format!("    </rdf:Bag>\n   </{}>\n", property));
+        }
+    }
+    if let Some(caption) = text("/description/image_description") {
+        out.push_str("   <dc:description>\n    <rdf:Alt>\n");
+        out.push_str(&format!("     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n", escape(caption)));
+        out.push_str("    </rdf:Alt>\n   </dc:description>\n");
+    }
+    out.push_str("  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n");
+    out
+}
+
+/// Merge a record into an existing sidecar, such as one written by Lightroom or
+/// darktable: the properties [`sidecar`] writes replace those of the same name,
+/// keyword arrays keep the existing items followed by new ones, each once, and
+/// every other property of the existing packet is kept. `None` if it cannot be parsed.
+pub fn merge(existing: &str, metadata: &Value) -> Option<String> {
+    let doc = xmp::parse(existing)?;
+    let mut metadata = metadata.clone();
+    for (field, namespace, name) in [("keywords", DC_NS, "subject"), ("hierarchical_keywords", LR_NS, "hierarchicalSubject")] {
+        let Some(node) = doc.descendants().find(|node| node.has_tag_name((namespace, name))) else {
+            continue;
+        };
+        let existing_items = xmp::list_items(node)
+            .filter_map(|item| item.text())
+            .map(|text| Value::from(text.trim()));
+        let new_items = metadata.get(field).and_then(Value::as_array).into_iter().flatten().cloned();
+        let mut items: Vec<Value> = Vec::new();
+        for keyword in existing_items.chain(new_items) {
+            if !items.contains(&keyword) {
+                items.push(keyword);
+            }
+        }
+        if let Some(object) = metadata.as_object_mut() {
+            object.insert(field.to_string(), Value::Array(items));
+        }
+    }
+
+    let ours = sidecar(&metadata);
+    let ours_doc = xmp::parse(&ours)?;
+    let (ours_start, _) = xmp::body(&ours)?;
+    let description = ours_doc.descendants().find(|n| n.has_tag_name((RDF_NS, "Description")))?;
+    let written: Vec<(&str, &str)> = description.children()
+        .filter(Node::is_element)
+        .filter_map(|property| Some((property.tag_name().namespace()?, property.tag_name().name())))
+        .collect();
+    let mut merged = remove_properties(existing, |namespace, name| written.contains(&(namespace, name)))?;
+    // XMP allows several descriptions of the same resource, so ours goes in whole
+    // with its own namespace declarations
+    let end = merged[..merged.rfind("</rdf:RDF>")?].trim_end().len();
+    let range = description.range();
+    merged.insert_str(end, &format!("\n {}", &ours[ours_start + range.start..ours_start + range.end]));
+    Some(merged)
+}
+
+/// A packet without the top-level properties `remove` matches by namespace and
+/// name, whether written as elements or as attributes of an `rdf:Description`.
+/// Everything else is kept byte for byte. `None` if the packet cannot be parsed.
+pub fn remove_properties(packet: &str, remove: impl Fn(&str, &str) -> bool) -> Option<String> {
+    let (start, body) = xmp::body(packet)?;
+    let doc = Document::parse(body).ok()?;
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    // With the whitespace before it, so the packet stays tidy
+    let with_space = |range: Range<usize>| body[..range.start].trim_end().len()..range.end;
+    for description in doc.descendants().filter(|n| n.has_tag_name((RDF_NS, "Description"))) {
+        let (mut removed, mut kept) = (Vec::new(), 0);
+        for attribute in description.attributes().filter(|a| a.namespace() != Some(RDF_NS)) {
+            match attribute.namespace() {
+                Some(ns) if remove(ns, attribute.name()) => removed.push(with_space(attribute.range())),
+                _ => kept += 1,
+            }
+        }
+        for property in description.children().filter(Node::is_element) {
+            match property.tag_name().namespace() {
+                Some(ns) if remove(ns, property.tag_name().name()) => removed.push(with_space(property.range())),
+                _ => kept += 1,
+            }
+        }
+        // A description left without properties goes too
+        if kept == 0 && !removed.is_empty() {
+            ranges.push(with_space(description.range()));
+        } else {
+            ranges.extend(removed);
+        }
+    }
+    ranges.sort_by_key(|range| range.start);
+    let mut out = packet[..start].to_string();
+    let mut at = 0;
+    for range in ranges {
+        out.push_str(&body[at..range.start.max(at)]);
+        at = at.max(range.end);
+    }
+    out.push_str(&packet[start + at..]);
+    Some(out)
+}
+
+/// Rewrite a JPEG's XMP packet without the properties `remove` matches, see
+/// [`remove_properties`]. Fails rather than leave them in place when the packet
+/// cannot be parsed or continues in extended XMP segments, which are not rewritten.
+pub fn remove_jpeg_properties(bytes: &[u8], remove: impl Fn(&str, &str) -> bool) -> Result<Vec<u8>> {
+    let segments = jpeg::read_segments(&mut Cursor::new(bytes))?;
+    let Some(packet) = xmp::packet(&segments) else {
+        return Ok(bytes.to_vec());
+    };
+    if segments.iter().any(|s| s.is_app(1, XMP_EXTENSION_SIGNATURE)) {
+        return Err(ExtractError::Encode("XMP data: extended XMP segments cannot be rewritten".to_string()));
+    }
+    let rewritten = remove_properties(&packet, remove)
+        .ok_or_else(|| ExtractError::Encode("XMP data: the packet could not be parsed".to_string()))?;
+    if rewritten == packet {
+        return Ok(bytes.to_vec());
+    }
+
+    let mut out = vec![0xFF, SOI];
+    for segment in &segments {
+        if segment.is_app(1, XMP_SIGNATURE) {
+            let data = [XMP_SIGNATURE, rewritten.as_bytes()].concat();
+            jpeg::push_segment(&mut out, &Segment { marker: segment.marker, data, offset: None })?;
+        } else {
+            jpeg::push_segment(&mut out, segment)?;
+        }
+    }
+    out.extend(&bytes[jpeg::header_len(&segments) as usize..]);
+    Ok(out)
+}
+
+/// The `exif:Flash` struct from our decoded `flash` object
+fn flash_struct(flash: &Value) -> String {
+    let flag = |key| if flash.get(key).and_then(Value::as_bool).unwrap_or(false) { "True" } else { "False" };
+    let return_light = match flash.get("return").and_then(Value::as_str) {
+        Some("reserved") => 1,
+        Some("not_detected") => 2,
+        Some("detected") => 3,
+        _ => 0,
+    };
+    let mode = match flash.get("mode").and_then(Value::as_str) {
+        Some("compulsory_firing") => 1,
+        Some("compulsory_suppression") => 2,
+        Some("auto") => 3,
+        _ => 0,
+    };
+    // XMP's Function flag is set when the flash function is absent
+    let function = match flash.get("function_present").and_then(Value::as_bool) {
+        Some(false) => "True",
+        _ => "False",
+    };
+    format!(
+        "   <exif:Flash rdf:parseType=\"Resource\">\n    <exif:Fired>{}</exif:Fired>\n    <exif:Return>{}</exif:Return>\n    <exif:Mode>{}</exif:Mode>\n    <exif:Function>{}</exif:Function>\n    <exif:RedEyeMode>{}</exif:RedEyeMode>\n   </exif:Flash>\n",
+        flag("fired"), return_light, mode, function, flag("red_eye_reduction"),
+    )
+}
+
+/// XMP rational text, e.g. `28/10` for 2.8, in lowest terms
+fn rational(value: f64) -> String {
+    let mut num = (value * 1000.0).round() as u64;
+    let mut denom = 1000;
+    while denom > 1 && num.is_multiple_of(10) {
+        num /= 10;
+        denom /= 10;
+    }
+    format!("{}/{}", num, denom)
+}
+
+/// XMP GPS coordinate, `DDD,MM.mmmmmmK` with `hemispheres` as (positive, negative)
+fn coordinate(degrees: f64, hemispheres: [char; 2]) -> String {
+    let hemisphere = if degrees < 0.0 { hemispheres[1] } else { hemispheres[0] };
+    let whole = degrees.abs().trunc();
+    format!("{},{:.6}{}", whole, (degrees.abs() - whole) * 60.0, hemisphere)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keywords, xmp};
+    use serde_json::json;
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let metadata = json!({
+            "camera_model": "Canon EOS 5D Mark IV",
+            "orientation": 1,
+            "capture_time": "2024-05-10T14:03:21.250Z",
+            "focus": {"focal_length": 35.0, "aperture": 2.8},
+            "flash": {"fired": true, "return": "detected", "mode": "auto", "function_present": true, "red_eye_reduction": false},
+            "gps": {"latitude": -33.8568, "longitude": 151.2153, "altitude": -4.5},
+            "keywords": ["harbour", "R&D <night>", "Places", "Sydney"],
+            "hierarchical_keywords": ["Places", "Places|Sydney"],
+            "description": {"image_description": "Opera House"},
+        });
+        let packet = sidecar(&metadata);
+        let doc = xmp::parse(&packet).unwrap();
+        let exif_ns = NAMESPACES[1].1;
+        assert_eq!(xmp::property(&doc, NAMESPACES[0].1, "Model").as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert_eq!(xmp::property(&doc, exif_ns, "DateTimeOriginal").as_deref(), Some("2024-05-10T14:03:21.250"));
+        assert_eq!(xmp::property(&doc, exif_ns, "FNumber").as_deref(), Some("28/10"));
+        assert_eq!(xmp::property(&doc, exif_ns, "GPSLatitude").as_deref(), Some("33,51.408000S"));
+        assert_eq!(xmp::property(&doc, exif_ns, "GPSAltitudeRef").as_deref(), Some("1"));
+        assert_eq!(xmp::keywords(&doc), ["harbour", "R&D <night>", "Places", "Sydney"]);
+        assert_eq!(keywords::from_xmp(&doc, true).flat, ["Sydney", "harbour", "R&D <night>"]);
+
+        let flash = doc.descendants().find(|n| n.has_tag_name((exif_ns, "Flash"))).unwrap();
+        assert_eq!(xmp::field(flash, exif_ns, "Mode").as_deref(), Some("3"));
+        assert_eq!(xmp::field(flash, exif_ns, "Function").as_deref(), Some("False"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let existing = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:tiff="http://ns.adobe.com/tiff/1.0/" xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+  xmlns:dc="http://purl.org/dc/elements/1.1/" tiff:Model="Old Model" crs:Exposure2012="+0.35">
+ <dc:subject><rdf:Bag><rdf:li>family</rdf:li><rdf:li>family </rdf:li></rdf:Bag></dc:subject>
+</rdf:Description>
+</rdf:RDF></x:xmpmeta>"#;
+        let metadata = json!({"camera_model": "Canon EOS 5D Mark IV", "keywords": ["harbour", "family", "harbour"]});
+        let merged = merge(existing, &metadata).unwrap();
+        let doc = xmp::parse(&merged).unwrap();
+        assert_eq!(xmp::property(&doc, NAMESPACES[0].1, "Model").as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert_eq!(xmp::property(&doc, CRS_NS, "Exposure2012").as_deref(), Some("+0.35"));
+        assert_eq!(xmp::keywords(&doc), ["family", "harbour"]);
+        // Merging again changes nothing
+        assert_eq!(merge(&merged, &metadata).unwrap(), merged);
+        assert_eq!(merge("not xmp", &metadata), None);
+    }
+
+    #[test]
+    fn test_remove_properties() {
+        let packet = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:aux="http://ns.adobe.com/exif/1.0/aux/" xmlns:dc="http://purl.org/dc/elements/1.1/"
+  aux:SerialNumber="025021000535" aux:Firmware="1.0.1">
+ <dc:creator><rdf:Seq><rdf:li>Jane Doe</rdf:li></rdf:Seq></dc:creator>
+ <dc:subject><rdf:Bag><rdf:li>harbour</rdf:li></rdf:Bag></dc:subject>
+</rdf:Description>
+</rdf:RDF></x:xmpmeta>
+<?xpacket end="w"?>"#;
+        let aux = NAMESPACES[2].1;
+        let removed = remove_properties(packet, |ns, name| (ns, name) == (aux, "SerialNumber") || (ns, name) == (DC_NS, "creator")).unwrap();
+        assert!(!removed.contains("025021000535") && !removed.contains("Jane Doe"));
+        assert!(removed.starts_with("<?xpacket") && removed.ends_with("<?xpacket end=\"w\"?>"));
+        let doc = xmp::parse(&removed).unwrap();
+        assert_eq!(xmp::property(&doc, aux, "Firmware").as_deref(), Some("1.0.1"));
+        assert_eq!(xmp::keywords(&doc), ["harbour"]);
+        assert_eq!(remove_properties(packet, |_, _| false).as_deref(), Some(packet));
+        assert_eq!(remove_properties("<x:xmpmeta><broken", |_, _| true), None);
+    }
+
+    #[test]
+    fn test_rational() {
+        assert_eq!(rational(50.0), "50/1");
+        assert_eq!(rational(0.125), "125/1000");
+    }
+}